@@ -0,0 +1,892 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! A Debug Adapter Protocol server, it lets editors like VS Code launch and debug
+//! Go programs with `goscript dap`.
+//!
+//! The messages are read on a thread of their own, while the program runs on the
+//! main thread. Whenever a request comes while the program is running, the VM is
+//! paused to handle it, and it's resumed right after unless the request is `pause`.
+//!
+//! Every fiber is a thread of the protocol. Only the stopped fiber can be
+//! inspected, the other ones show no stack frames.
+
+use crate::{compile_errors, source_reader, Args};
+use go_engine::ffi::{
+    CallError, CancellationHandle, DebugAction, DebugView, Debugger, GosValue, PauseHandle,
+    StopReason,
+};
+use go_engine::{Engine, EngineError};
+use serde_json::{json, Value};
+use std::collections::BTreeSet;
+use std::env;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+/// Serves one debug session on the standard input and output.
+pub fn serve_stdio(args: &Args) -> Result<(), String> {
+    serve(args, io::stdin(), io::stdout())
+}
+
+/// Serves one debug session, which ends with the `disconnect` request or
+/// when the input is closed.
+pub fn serve(
+    args: &Args,
+    input: impl Read + Send + 'static,
+    output: impl Write + Send + 'static,
+) -> Result<(), String> {
+    let client = Arc::new(Client::new(Box::new(output)));
+    let control = Arc::new(Control::default());
+    let requests = read_requests(input, control.clone());
+
+    // the program can only be compiled once it's known from the launch request
+    let launch = loop {
+        let req = match requests.recv() {
+            Ok(req) => req,
+            Err(_) => return Ok(()),
+        };
+        match command(&req) {
+            "initialize" => client.respond(
+                &req,
+                json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsEvaluateForHovers": true,
+                    "supportsTerminateRequest": true,
+                }),
+            ),
+            "launch" => break req,
+            "disconnect" => {
+                client.respond(&req, Value::Null);
+                return Ok(());
+            }
+            _ => client.fail(&req, "the program is not launched yet"),
+        }
+    };
+    let program = match launch["arguments"]["program"].as_str() {
+        Some(p) => PathBuf::from(p),
+        None => {
+            client.fail(&launch, "the program to launch is missing");
+            client.event("terminated", Value::Null);
+            return Ok(());
+        }
+    };
+    let stop_on_entry = launch["arguments"]["stopOnEntry"].as_bool() == Some(true);
+    let mut args = Args {
+        file: Some(program),
+        output: None,
+        std_dir: args.std_dir.clone(),
+        std_zip: args.std_zip.clone(),
+        pprof: None,
+        folded: None,
+        ..*args
+    };
+    if let Some(std) = launch["arguments"]["std"].as_str() {
+        args.std_dir = Some(PathBuf::from(std));
+    }
+
+    let engine = Engine::new();
+    engine.set_std_io(
+        None,
+        Some(Box::new(OutputWriter::new(client.clone(), "stdout"))),
+        Some(Box::new(OutputWriter::new(client.clone(), "stderr"))),
+    );
+    let compiled = source_reader(&args).and_then(|(reader, path)| {
+        engine
+            .compile(&reader, &path, true, false, false)
+            .map_err(compile_errors)
+    });
+    let code = match compiled {
+        Ok(code) => code,
+        Err(msg) => {
+            client.fail(&launch, &msg);
+            client.event("terminated", Value::Null);
+            return Ok(());
+        }
+    };
+    let mut vm = engine.new_vm(&code);
+    let cancel = vm.cancellation_handle();
+    let requests = Rc::new(requests);
+    vm.set_debugger(Box::new(Adapter {
+        client: client.clone(),
+        control: control.clone(),
+        requests: requests.clone(),
+        cancel: cancel.clone(),
+        fibers: BTreeSet::new(),
+        entry: stop_on_entry,
+        disconnected: false,
+    }));
+    let pause = vm.pause_handle().unwrap();
+    *control.pause.lock().unwrap() = Some(pause.clone());
+    *control.cancel.lock().unwrap() = Some(cancel.clone());
+    client.respond(&launch, Value::Null);
+    client.event("initialized", Value::Null);
+
+    // the breakpoints are set before the program starts
+    loop {
+        let req = match requests.recv() {
+            Ok(req) => req,
+            Err(_) => return Ok(()),
+        };
+        match command(&req) {
+            "setBreakpoints" => {
+                let body = set_breakpoints(
+                    &req,
+                    |file, line| vm.set_breakpoint(file, line),
+                    |file| vm.clear_breakpoints(file),
+                );
+                client.respond(&req, body);
+            }
+            "setExceptionBreakpoints" => client.respond(&req, Value::Null),
+            "threads" => client.respond(&req, json!({ "threads": [] })),
+            "configurationDone" => {
+                client.respond(&req, Value::Null);
+                break;
+            }
+            "disconnect" | "terminate" => {
+                client.respond(&req, Value::Null);
+                client.event("terminated", Value::Null);
+                return Ok(());
+            }
+            _ => client.fail(&req, "the program is not started yet"),
+        }
+    }
+
+    if stop_on_entry {
+        pause.pause();
+    }
+    control.running.store(true, Ordering::SeqCst);
+    let result = vm.run_main();
+    control.running.store(false, Ordering::SeqCst);
+    let exit_code = match result {
+        Ok(()) => 0,
+        Err(CallError::Interrupted(_)) => 0,
+        Err(e) => {
+            let err = EngineError::from_call_error(e, &code);
+            let mut msg = format!("{}\n", err);
+            for frame in err.call_stack().unwrap_or_default() {
+                msg.push_str(&format!("\t{}\n", frame));
+            }
+            client.event("output", json!({ "category": "stderr", "output": msg }));
+            1
+        }
+    };
+    client.event("exited", json!({ "exitCode": exit_code }));
+    client.event("terminated", Value::Null);
+
+    // waits for the client to disconnect
+    while let Ok(req) = requests.recv() {
+        match command(&req) {
+            "disconnect" => {
+                client.respond(&req, Value::Null);
+                break;
+            }
+            "threads" => client.respond(&req, json!({ "threads": [] })),
+            _ => client.fail(&req, "the program has exited"),
+        }
+    }
+    Ok(())
+}
+
+/// What the reader thread needs to interrupt the running program
+#[derive(Default)]
+struct Control {
+    running: AtomicBool,
+    pause: Mutex<Option<PauseHandle>>,
+    cancel: Mutex<Option<CancellationHandle>>,
+}
+
+/// Reads the messages on a new thread, and sends on the requests.
+fn read_requests(
+    input: impl Read + Send + 'static,
+    control: Arc<Control>,
+) -> mpsc::Receiver<Value> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut reader = BufReader::new(input);
+        while let Some(msg) = read_message(&mut reader) {
+            if msg["type"] != "request" {
+                continue;
+            }
+            let stop = matches!(command(&msg), "disconnect" | "terminate");
+            if tx.send(msg).is_err() {
+                break;
+            }
+            if control.running.load(Ordering::SeqCst) {
+                if stop {
+                    if let Some(cancel) = &*control.cancel.lock().unwrap() {
+                        cancel.cancel();
+                    }
+                }
+                if let Some(pause) = &*control.pause.lock().unwrap() {
+                    pause.pause();
+                }
+            }
+        }
+        // the client is gone
+        if let Some(cancel) = &*control.cancel.lock().unwrap() {
+            cancel.cancel();
+        }
+    });
+    rx
+}
+
+/// The largest message accepted, the length comes from the client
+const MAX_MESSAGE_LEN: usize = 16 << 20;
+
+/// Reads a message with its `Content-Length` header, `None` at the end of
+/// the input or if the message is broken or too large.
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("Content-Length") {
+                len = value.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut buf = vec![0; len.filter(|l| *l <= MAX_MESSAGE_LEN)?];
+    reader.read_exact(&mut buf).ok()?;
+    serde_json::from_slice(&buf).ok()
+}
+
+fn command(req: &Value) -> &str {
+    req["command"].as_str().unwrap_or_default()
+}
+
+/// The index of the stack frame of the request, the frame ids start at 1
+fn frame_index(args: &Value) -> Option<usize> {
+    let id = u32::try_from(args["frameId"].as_u64().unwrap_or(1)).ok()?;
+    id.checked_sub(1).map(|i| i as usize)
+}
+
+/// The sending side of the protocol, shared by the threads
+struct Client {
+    out: Mutex<Box<dyn Write + Send>>,
+    seq: AtomicI64,
+}
+
+impl Client {
+    fn new(out: Box<dyn Write + Send>) -> Client {
+        Client {
+            out: Mutex::new(out),
+            seq: AtomicI64::new(1),
+        }
+    }
+
+    fn send(&self, mut msg: Value) {
+        msg["seq"] = json!(self.seq.fetch_add(1, Ordering::SeqCst));
+        let body = msg.to_string();
+        let mut out = self.out.lock().unwrap();
+        // nothing can be done if the client is gone
+        let _ = write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = out.flush();
+    }
+
+    fn respond(&self, req: &Value, body: Value) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    fn fail(&self, req: &Value, msg: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": req["seq"],
+            "command": req["command"],
+            "success": false,
+            "message": msg,
+        }));
+    }
+
+    fn event(&self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+}
+
+/// Sends what the program prints as `output` events
+struct OutputWriter {
+    client: Arc<Client>,
+    category: &'static str,
+}
+
+impl OutputWriter {
+    fn new(client: Arc<Client>, category: &'static str) -> OutputWriter {
+        OutputWriter { client, category }
+    }
+}
+
+impl Write for OutputWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.client.event(
+            "output",
+            json!({
+                "category": self.category,
+                "output": String::from_utf8_lossy(buf),
+            }),
+        );
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The debugger attached to the VM, it handles the requests while a fiber is stopped
+struct Adapter {
+    client: Arc<Client>,
+    control: Arc<Control>,
+    requests: Rc<mpsc::Receiver<Value>>,
+    cancel: CancellationHandle,
+    /// The fibers alive
+    fibers: BTreeSet<usize>,
+    /// Whether the first pause is the one for stopping on entry
+    entry: bool,
+    disconnected: bool,
+}
+
+/// The variables of a scope are referred to by the frame and the kind of the scope
+const SCOPES: [&str; 3] = ["Locals", "Closure", "Globals"];
+
+impl Adapter {
+    /// Tells the client about the stop and handles its requests until it asks
+    /// to go on, a pause only for handling the requests is not told.
+    fn stop(&mut self, reason: StopReason, view: &DebugView) -> DebugAction {
+        let requests = self.requests.clone();
+        let reason = match reason {
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Step => "step",
+            StopReason::Pause if self.entry => {
+                self.entry = false;
+                "entry"
+            }
+            StopReason::Pause => {
+                // the pause may be only for handling the requests
+                let mut paused = false;
+                for req in requests.try_iter() {
+                    paused |= command(&req) == "pause";
+                    if let Some(action) = self.handle(&req, view) {
+                        return action;
+                    }
+                }
+                match paused {
+                    true => "pause",
+                    false => return DebugAction::Resume,
+                }
+            }
+        };
+        self.client.event(
+            "stopped",
+            json!({
+                "reason": reason,
+                "threadId": view.fiber_id() + 1,
+                "allThreadsStopped": true,
+            }),
+        );
+        loop {
+            let req = match requests.recv() {
+                Ok(req) => req,
+                Err(_) => {
+                    self.cancel.cancel();
+                    self.disconnected = true;
+                    return DebugAction::Continue;
+                }
+            };
+            if let Some(action) = self.handle(&req, view) {
+                return action;
+            }
+        }
+    }
+}
+
+impl Debugger for Adapter {
+    fn on_stop(&mut self, reason: StopReason, view: &DebugView) -> DebugAction {
+        if self.disconnected {
+            return DebugAction::Continue;
+        }
+        // the requests coming while stopped need no pause
+        self.control.running.store(false, Ordering::SeqCst);
+        let action = self.stop(reason, view);
+        self.control.running.store(true, Ordering::SeqCst);
+        action
+    }
+
+    fn on_fiber_start(&mut self, fiber: usize) {
+        self.fibers.insert(fiber);
+        self.client.event(
+            "thread",
+            json!({ "reason": "started", "threadId": fiber + 1 }),
+        );
+    }
+
+    fn on_fiber_end(&mut self, fiber: usize) {
+        self.fibers.remove(&fiber);
+        self.client.event(
+            "thread",
+            json!({ "reason": "exited", "threadId": fiber + 1 }),
+        );
+    }
+}
+
+impl Adapter {
+    /// Handles a request while stopped, and returns how to go on if it's a
+    /// request for that.
+    fn handle(&mut self, req: &Value, view: &DebugView) -> Option<DebugAction> {
+        let args = &req["arguments"];
+        let action = match command(req) {
+            "continue" => {
+                self.client
+                    .respond(req, json!({ "allThreadsContinued": true }));
+                return Some(DebugAction::Continue);
+            }
+            "next" => DebugAction::StepOver,
+            "stepIn" => DebugAction::StepIn,
+            "stepOut" => DebugAction::StepOut,
+            "disconnect" | "terminate" => {
+                self.cancel.cancel();
+                self.disconnected = true;
+                self.client.respond(req, Value::Null);
+                return Some(DebugAction::Continue);
+            }
+            "threads" => {
+                let mut fibers = self.fibers.clone();
+                fibers.insert(view.fiber_id());
+                let threads: Vec<Value> = fibers
+                    .iter()
+                    .map(|f| json!({ "id": f + 1, "name": format!("goroutine {}", f + 1) }))
+                    .collect();
+                self.client.respond(req, json!({ "threads": threads }));
+                return None;
+            }
+            "stackTrace" => {
+                let frames: Vec<Value> = match args["threadId"].as_u64() {
+                    Some(id) if id as usize == view.fiber_id() + 1 => view
+                        .stack_frames()
+                        .iter()
+                        .enumerate()
+                        .map(|(i, f)| {
+                            let mut frame = json!({
+                                "id": i + 1,
+                                "name": f.func,
+                                "line": f.line,
+                                "column": f.column,
+                            });
+                            if !f.file.is_empty() {
+                                frame["source"] = source(&f.file);
+                            }
+                            frame
+                        })
+                        .collect(),
+                    _ => vec![],
+                };
+                let total = frames.len();
+                self.client
+                    .respond(req, json!({ "stackFrames": frames, "totalFrames": total }));
+                return None;
+            }
+            "scopes" => {
+                let frame = match frame_index(args) {
+                    Some(frame) => frame,
+                    None => {
+                        self.client.fail(req, "bad frameId");
+                        return None;
+                    }
+                };
+                let scopes: Vec<Value> = SCOPES
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        json!({
+                            "name": name,
+                            "variablesReference": frame * SCOPES.len() + i + 1,
+                            "expensive": false,
+                        })
+                    })
+                    .collect();
+                self.client.respond(req, json!({ "scopes": scopes }));
+                return None;
+            }
+            "variables" => {
+                let r = args["variablesReference"].as_u64().unwrap_or(0) as usize;
+                let vars = match r {
+                    0 => vec![],
+                    _ => {
+                        let frame = (r - 1) / SCOPES.len();
+                        match (r - 1) % SCOPES.len() {
+                            0 => view.locals(frame),
+                            1 => view.up_values(frame),
+                            _ => view.globals(frame),
+                        }
+                    }
+                };
+                let vars: Vec<Value> = vars
+                    .iter()
+                    .map(|(name, val)| {
+                        json!({
+                            "name": name,
+                            "value": val.to_string(),
+                            "type": type_name(val),
+                            "variablesReference": 0,
+                        })
+                    })
+                    .collect();
+                self.client.respond(req, json!({ "variables": vars }));
+                return None;
+            }
+            "evaluate" => {
+                let frame = match frame_index(args) {
+                    Some(frame) => frame,
+                    None => {
+                        self.client.fail(req, "bad frameId");
+                        return None;
+                    }
+                };
+                let expr = args["expression"].as_str().unwrap_or_default().trim();
+                match view.lookup(frame, expr) {
+                    Some(val) => self.client.respond(
+                        req,
+                        json!({
+                            "result": val.to_string(),
+                            "type": type_name(&val),
+                            "variablesReference": 0,
+                        }),
+                    ),
+                    None => self
+                        .client
+                        .fail(req, &format!("{} is not a variable in scope", expr)),
+                }
+                return None;
+            }
+            "setBreakpoints" => {
+                let body = set_breakpoints(
+                    req,
+                    |file, line| view.set_breakpoint(file, line),
+                    |file| view.clear_breakpoints(file),
+                );
+                self.client.respond(req, body);
+                return None;
+            }
+            "pause" | "setExceptionBreakpoints" | "configurationDone" => {
+                self.client.respond(req, Value::Null);
+                return None;
+            }
+            _ => {
+                self.client.fail(req, "not supported");
+                return None;
+            }
+        };
+        self.client.respond(req, Value::Null);
+        Some(action)
+    }
+}
+
+/// Replaces the breakpoints of the source of the request
+fn set_breakpoints(
+    req: &Value,
+    set: impl Fn(&str, usize) -> Option<usize>,
+    clear: impl Fn(&str),
+) -> Value {
+    let args = &req["arguments"];
+    let file = args["source"]["path"].as_str().unwrap_or_default();
+    clear(file);
+    let lines = args["breakpoints"]
+        .as_array()
+        .map(|bps| {
+            bps.iter()
+                .map(|bp| bp["line"].as_u64().unwrap_or(0))
+                .collect()
+        })
+        .or_else(|| {
+            args["lines"]
+                .as_array()
+                .map(|ls| ls.iter().map(|l| l.as_u64().unwrap_or(0)).collect())
+        })
+        .unwrap_or_else(Vec::new);
+    let bps: Vec<Value> = lines
+        .iter()
+        .map(|line| match set(file, *line as usize) {
+            Some(l) => json!({ "verified": true, "line": l }),
+            None => json!({ "verified": false, "line": line, "message": "no code at the line" }),
+        })
+        .collect();
+    json!({ "breakpoints": bps })
+}
+
+/// The source of a frame, the relative paths are relative to the working directory
+fn source(file: &str) -> Value {
+    let path = match Path::new(file).is_absolute() {
+        true => PathBuf::from(file),
+        false => env::current_dir()
+            .map(|wd| wd.join(file.trim_start_matches("./")))
+            .unwrap_or_else(|_| PathBuf::from(file)),
+    };
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    json!({ "name": name, "path": path.to_string_lossy() })
+}
+
+fn type_name(val: &GosValue) -> String {
+    format!("{:?}", val.typ())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Command;
+    use std::time::Duration;
+
+    /// The reading end of a channel of bytes, it ends if nothing comes in time
+    struct Pipe {
+        rx: mpsc::Receiver<Vec<u8>>,
+        buf: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            if self.buf.is_empty() {
+                match self.rx.recv_timeout(Duration::from_secs(20)) {
+                    Ok(data) => self.buf = data,
+                    Err(_) => return Ok(0),
+                }
+            }
+            let n = out.len().min(self.buf.len());
+            out[..n].copy_from_slice(&self.buf[..n]);
+            self.buf.drain(..n);
+            Ok(n)
+        }
+    }
+
+    struct Sink(mpsc::Sender<Vec<u8>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let _ = self.0.send(buf.to_vec());
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    struct TestClient {
+        to_server: mpsc::Sender<Vec<u8>>,
+        from_server: BufReader<Pipe>,
+        seq: i64,
+        /// The output events of the program
+        output: String,
+    }
+
+    impl TestClient {
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            let resp = self.send_request(command, arguments);
+            assert_eq!(resp["success"], true, "{}", resp);
+            resp["body"].clone()
+        }
+
+        /// Returns the whole response, which may be a failure
+        fn send_request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let body = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            })
+            .to_string();
+            let msg = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+            self.to_server.send(msg.into_bytes()).unwrap();
+            let seq = self.seq;
+            self.expect(|m| m["type"] == "response" && m["request_seq"] == seq)
+        }
+
+        /// Skips the messages until the one `pred` accepts
+        fn expect(&mut self, pred: impl Fn(&Value) -> bool) -> Value {
+            loop {
+                let msg = read_message(&mut self.from_server).expect("no message");
+                if msg["event"] == "output" {
+                    self.output
+                        .push_str(msg["body"]["output"].as_str().unwrap());
+                }
+                if pred(&msg) {
+                    return msg;
+                }
+            }
+        }
+
+        fn expect_event(&mut self, event: &str) -> Value {
+            self.expect(|m| m["event"] == event)["body"].clone()
+        }
+    }
+
+    #[test]
+    fn test_debug_session() {
+        let source = "package main
+
+import \"fmt\"
+
+var total int
+
+func add(a, b int) int {
+\tsum := a + b
+\treturn sum
+}
+
+func main() {
+\tfor i := 0; i < 2; i++ {
+\t\ttotal = add(total, i+1)
+\t}
+\tfmt.Println(total)
+}
+";
+        let dir = env::temp_dir().join(format!("goscript_dap_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let program = dir.join("main.gos");
+        std::fs::write(&program, source).unwrap();
+        let program = program.to_str().unwrap().to_owned();
+
+        let (to_server, server_in) = mpsc::channel();
+        let (server_out, from_server) = mpsc::channel();
+        let server = thread::spawn(move || {
+            let args = Args {
+                command: Command::Dap,
+                file: None,
+                output: None,
+                std_dir: Some(PathBuf::from("../std/")),
+                std_zip: None,
+                trace_parser: false,
+                trace_checker: false,
+                pprof: None,
+                folded: None,
+                sample_every: None,
+            };
+            let input = Pipe {
+                rx: server_in,
+                buf: vec![],
+            };
+            serve(&args, input, Sink(server_out))
+        });
+        let mut client = TestClient {
+            to_server,
+            from_server: BufReader::new(Pipe {
+                rx: from_server,
+                buf: vec![],
+            }),
+            seq: 0,
+            output: String::new(),
+        };
+
+        let caps = client.request("initialize", json!({ "adapterID": "goscript" }));
+        assert_eq!(caps["supportsConfigurationDoneRequest"], true);
+        client.request("launch", json!({ "program": program }));
+        client.expect_event("initialized");
+        let body = client.request(
+            "setBreakpoints",
+            json!({
+                "source": { "path": program },
+                // the blank line resolves to the next one
+                "breakpoints": [{ "line": 7 }, { "line": 100 }],
+            }),
+        );
+        assert_eq!(
+            body["breakpoints"][0],
+            json!({ "verified": true, "line": 8 })
+        );
+        assert_eq!(body["breakpoints"][1]["verified"], false);
+        client.request("configurationDone", json!({}));
+
+        let stopped = client.expect_event("stopped");
+        assert_eq!(stopped["reason"], "breakpoint");
+        let thread = stopped["threadId"].clone();
+        let threads = client.request("threads", json!({}));
+        assert_eq!(threads["threads"][0]["id"], thread);
+        let body = client.request("stackTrace", json!({ "threadId": thread }));
+        let frames = body["stackFrames"].as_array().unwrap();
+        assert_eq!(frames[0]["name"], "main.add");
+        assert_eq!(frames[0]["line"], 8);
+        assert!(frames[0]["source"]["path"]
+            .as_str()
+            .unwrap()
+            .ends_with("main.gos"));
+        assert_eq!(frames[1]["name"], "main.main");
+        assert_eq!(frames[1]["line"], 14);
+
+        let scopes = client.request("scopes", json!({ "frameId": 1 }));
+        let locals = scopes["scopes"][0]["variablesReference"].clone();
+        let vars = client.request("variables", json!({ "variablesReference": locals }));
+        let vars: Vec<(String, String)> = vars["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|v| {
+                (
+                    v["name"].as_str().unwrap().to_owned(),
+                    v["value"].as_str().unwrap().to_owned(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            vars,
+            vec![
+                ("a".to_owned(), "0".to_owned()),
+                ("b".to_owned(), "1".to_owned())
+            ]
+        );
+        let globals = scopes["scopes"][2]["variablesReference"].clone();
+        let vars = client.request("variables", json!({ "variablesReference": globals }));
+        assert_eq!(vars["variables"][0]["name"], "total");
+        let i = client.request("evaluate", json!({ "expression": "i", "frameId": 2 }));
+        assert_eq!(i["result"], "0");
+        for command in ["scopes", "evaluate"] {
+            let resp = client.send_request(command, json!({ "expression": "i", "frameId": 0 }));
+            assert_eq!(resp["success"], false, "{}", resp);
+        }
+
+        client.request("next", json!({ "threadId": thread }));
+        assert_eq!(client.expect_event("stopped")["reason"], "step");
+        let sum = client.request("evaluate", json!({ "expression": "sum", "frameId": 1 }));
+        assert_eq!(sum["result"], "1");
+
+        // no more stops
+        client.request(
+            "setBreakpoints",
+            json!({ "source": { "path": program }, "breakpoints": [] }),
+        );
+        client.request("continue", json!({ "threadId": thread }));
+        assert_eq!(client.expect_event("exited")["exitCode"], 0);
+        client.expect_event("terminated");
+        assert_eq!(client.output, "3\n");
+        client.request("disconnect", json!({}));
+        server.join().unwrap().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn test_message_len() {
+        let read = |len: usize| {
+            let msg = format!("Content-Length: {}\r\n\r\n{{}}", len);
+            read_message(&mut io::Cursor::new(msg))
+        };
+        assert_eq!(read(2), Some(json!({})));
+        assert_eq!(read(usize::MAX), None);
+    }
+}
@@ -0,0 +1,510 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! The `goscript` command, it runs Go programs, compiles them to bytecode files and
+//! runs those, evaluates Go code interactively and serves debuggers, see `USAGE`.
+
+mod dap;
+
+use go_engine::ffi::{Profile, Sampling};
+use go_engine::{Bytecode, Config, Engine, EngineError, ErrorList, Repl, SourceReader, VfsZip};
+use std::borrow::Cow;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::{env, fs};
+
+const USAGE: &str = "\
+Usage: goscript <command> [options] <file>
+
+Commands:
+    run <file.gos>                compile and run a program
+    build <file.gos> [-o <file>]  compile a program to a bytecode file,
+                                  <file>.gosb by default
+    exec <file.gosb>              run a bytecode file
+    check <file.gos>              parse and type check a program
+    disasm <file.gos|file.gosb>   print the bytecode of a program
+    repl                          evaluate Go code interactively
+    dap                           serve the Debug Adapter Protocol on the
+                                  standard input and output, for debugging
+                                  in editors
+
+Options:
+    --std <dir>        the directory of the standard library, by default
+                       $GOSCRIPT_STD or ./std/, or std/ in the zip archive
+    --std-zip <file>   read the standard library from a zip archive
+    --trace-parser     print the debug info of the parser
+    --trace-checker    print the debug info of the type checker
+    -o <file>          the output file of build
+    --pprof <file>     write the CPU profile of run or exec in the pprof format
+    --folded <file>    write the CPU profile of run or exec as folded stacks,
+                       for flamegraph tools
+    --sample-every <n> sample the call stacks for the profile every n
+                       instructions, by default whenever a goroutine yields
+    -h, --help         print this help
+";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Command {
+    Run,
+    Build,
+    Exec,
+    Check,
+    Disasm,
+    Repl,
+    Dap,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Args {
+    command: Command,
+    /// None only for repl and dap
+    file: Option<PathBuf>,
+    output: Option<PathBuf>,
+    std_dir: Option<PathBuf>,
+    std_zip: Option<PathBuf>,
+    trace_parser: bool,
+    trace_checker: bool,
+    pprof: Option<PathBuf>,
+    folded: Option<PathBuf>,
+    sample_every: Option<u64>,
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args(env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            print!("{}", USAGE);
+            return ExitCode::SUCCESS;
+        }
+        Err(msg) => {
+            eprintln!("goscript: {}\nRun 'goscript --help' for the usage.", msg);
+            return ExitCode::from(2);
+        }
+    };
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(msg) => {
+            eprintln!("{}", msg);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Returns None if the help is asked for
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Option<Args>, String> {
+    let mut command = None;
+    let mut file = None;
+    let mut output = None;
+    let mut std_dir = None;
+    let mut std_zip = None;
+    let mut trace_parser = false;
+    let mut trace_checker = false;
+    let mut pprof = None;
+    let mut folded = None;
+    let mut sample_every = None;
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| {
+            args.next()
+                .map(PathBuf::from)
+                .ok_or_else(|| format!("{} needs a value", name))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(None),
+            "-o" => output = Some(value("-o")?),
+            "--std" => std_dir = Some(value("--std")?),
+            "--std-zip" => std_zip = Some(value("--std-zip")?),
+            "--trace-parser" => trace_parser = true,
+            "--trace-checker" => trace_checker = true,
+            "--pprof" => pprof = Some(value("--pprof")?),
+            "--folded" => folded = Some(value("--folded")?),
+            "--sample-every" => {
+                let n = value("--sample-every")?;
+                match n.to_str().and_then(|n| n.parse().ok()) {
+                    Some(n) if n > 0 => sample_every = Some(n),
+                    _ => return Err("--sample-every needs a positive number".to_owned()),
+                }
+            }
+            a if a.starts_with('-') => return Err(format!("unknown option {}", a)),
+            a if command.is_none() => {
+                command = Some(match a {
+                    "run" => Command::Run,
+                    "build" => Command::Build,
+                    "exec" => Command::Exec,
+                    "check" => Command::Check,
+                    "disasm" => Command::Disasm,
+                    "repl" => Command::Repl,
+                    "dap" => Command::Dap,
+                    _ => return Err(format!("unknown command {}", a)),
+                })
+            }
+            a if file.is_none() => file = Some(PathBuf::from(a)),
+            a => return Err(format!("unexpected argument {}", a)),
+        }
+    }
+    let command = command.ok_or("missing command")?;
+    if output.is_some() && command != Command::Build {
+        return Err("-o is only for build".to_owned());
+    }
+    let profiling = pprof.is_some() || folded.is_some() || sample_every.is_some();
+    if profiling && !matches!(command, Command::Run | Command::Exec) {
+        return Err("profiling is only for run and exec".to_owned());
+    }
+    match (command, &file) {
+        (Command::Repl | Command::Dap, Some(f)) => {
+            return Err(format!("unexpected argument {}", f.display()))
+        }
+        (Command::Repl | Command::Dap, None) | (_, Some(_)) => {}
+        (_, None) => return Err("missing file".to_owned()),
+    }
+    Ok(Some(Args {
+        command,
+        file,
+        output,
+        std_dir,
+        std_zip,
+        trace_parser,
+        trace_checker,
+        pprof,
+        folded,
+        sample_every,
+    }))
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let engine = Engine::new();
+    match args.command {
+        Command::Run => {
+            let code = compile(&engine, args)?;
+            run_bytecode(&engine, &code, args)
+        }
+        Command::Build => {
+            let code = compile(&engine, args)?;
+            let data = engine.encode_bytecode(&code).map_err(|e| e.to_string())?;
+            let out = match &args.output {
+                Some(out) => out.clone(),
+                None => file(args).with_extension("gosb"),
+            };
+            fs::write(&out, data).map_err(|e| format!("cannot write {}: {}", out.display(), e))
+        }
+        Command::Exec => {
+            let code = load(&engine, file(args))?;
+            run_bytecode(&engine, &code, args)
+        }
+        Command::Check => {
+            let (reader, path) = source_reader(args)?;
+            engine
+                .compile_module(
+                    &reader,
+                    &[path.to_str().unwrap()],
+                    args.trace_parser,
+                    args.trace_checker,
+                )
+                .map(|_| ())
+                .map_err(compile_errors)
+        }
+        Command::Disasm => {
+            let code = match file(args).extension().map_or(false, |ext| ext == "gosb") {
+                true => load(&engine, file(args))?,
+                false => compile(&engine, args)?,
+            };
+            // the output may be piped to a command like head, which
+            // doesn't read all of it
+            let _ = io::stdout().write_all(code.disassemble().as_bytes());
+            Ok(())
+        }
+        Command::Repl => repl(args),
+        Command::Dap => dap::serve_stdio(args),
+    }
+}
+
+/// The file argument, which all the commands but repl and dap have
+fn file(args: &Args) -> &Path {
+    args.file.as_deref().unwrap()
+}
+
+/// Reads the inputs from the standard input until it's closed. An input ends
+/// with a line where all the brackets are closed.
+fn repl(args: &Args) -> Result<(), String> {
+    let mut cfg = Config::default();
+    cfg.trace_parser = args.trace_parser;
+    cfg.trace_checker = args.trace_checker;
+    let reader = library_reader(args, PathBuf::from("./"))?;
+    let mut repl = Repl::new(cfg, reader).map_err(compile_errors)?;
+    let mut input = String::new();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        print!("{}", if input.is_empty() { ">>> " } else { "... " });
+        let _ = io::stdout().flush();
+        let line = match lines.next() {
+            Some(line) => line.map_err(|e| e.to_string())?,
+            None => {
+                println!();
+                return Ok(());
+            }
+        };
+        input.push_str(&line);
+        input.push('\n');
+        if !is_complete(&input) {
+            continue;
+        }
+        match repl.eval(&input) {
+            Ok(Some(value)) => println!("{}", value),
+            Ok(None) => {}
+            Err(EngineError::Compile(el)) => {
+                el.sort();
+                for e in el.borrow().iter() {
+                    eprint!("{}", e);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                for frame in e.call_stack().unwrap_or_default() {
+                    eprintln!("\t{}", frame);
+                }
+            }
+        }
+        input.clear();
+    }
+}
+
+/// Whether all the brackets in the input are closed, the ones in the literals
+/// and the comments don't count.
+fn is_complete(input: &str) -> bool {
+    let mut depth = 0;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            '"' | '\'' | '`' => {
+                let mut escaped = false;
+                for x in chars.by_ref() {
+                    match x {
+                        '\\' if c != '`' => escaped = !escaped,
+                        x if x == c && !escaped => break,
+                        _ => escaped = false,
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                chars.by_ref().find(|x| *x == '\n');
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut star = false;
+                for x in chars.by_ref() {
+                    if star && x == '/' {
+                        break;
+                    }
+                    star = x == '*';
+                }
+            }
+            _ => {}
+        }
+    }
+    depth <= 0
+}
+
+fn compile(engine: &Engine, args: &Args) -> Result<Bytecode, String> {
+    let (reader, path) = source_reader(args)?;
+    engine
+        .compile(&reader, &path, true, args.trace_parser, args.trace_checker)
+        .map_err(compile_errors)
+}
+
+fn load(engine: &Engine, file: &Path) -> Result<Bytecode, String> {
+    let data = fs::read(file).map_err(|e| format!("cannot read {}: {}", file.display(), e))?;
+    engine
+        .load_bytecode(&data)
+        .map_err(|e| format!("cannot load {}: {}", file.display(), e))
+}
+
+fn run_bytecode(engine: &Engine, code: &Bytecode, args: &Args) -> Result<(), String> {
+    let result = match args.pprof.is_some() || args.folded.is_some() {
+        true => {
+            let mut vm = engine.new_vm(code);
+            vm.set_profiler(match args.sample_every {
+                Some(n) => Sampling::Every(n),
+                None => Sampling::Yield,
+            });
+            let result = vm.run_main();
+            #[cfg(feature = "stats")]
+            engine.print_stats(&vm);
+            // the profile of a run that panics is still useful
+            write_profile(&vm.profile().unwrap(), args)?;
+            result
+        }
+        false => engine.try_run_bytecode(code),
+    };
+    result.map_err(|e| {
+        let err = EngineError::from_call_error(e, code);
+        let mut msg = err.to_string();
+        for frame in err.call_stack().unwrap_or_default() {
+            msg.push_str(&format!("\n\t{}", frame));
+        }
+        msg
+    })
+}
+
+/// Writes the profile files the options ask for
+fn write_profile(profile: &Profile, args: &Args) -> Result<(), String> {
+    let write = |path: &Path, data: Vec<u8>| {
+        fs::write(path, data).map_err(|e| format!("cannot write {}: {}", path.display(), e))
+    };
+    // writing to a Vec doesn't fail
+    if let Some(path) = &args.pprof {
+        let mut data = vec![];
+        profile.write_pprof(&mut data).unwrap();
+        write(path, data)?;
+    }
+    if let Some(path) = &args.folded {
+        let mut data = vec![];
+        profile.write_folded(&mut data).unwrap();
+        write(path, data)?;
+    }
+    Ok(())
+}
+
+fn compile_errors(el: ErrorList) -> String {
+    el.sort();
+    el.to_string().trim_end().to_owned()
+}
+
+/// The source reader for the standard library the options ask for, and the path
+/// of the file to read with it.
+fn source_reader(args: &Args) -> Result<(SourceReader, PathBuf), String> {
+    let file = file(args);
+    match args.std_zip.is_some() {
+        true => {
+            // the local files are read through a virtual file system mounted at
+            // the working directory, which can't be the root
+            let file = match file.is_absolute() {
+                true => env::current_dir()
+                    .ok()
+                    .and_then(|wd| file.strip_prefix(wd).ok().map(Path::to_owned))
+                    .ok_or_else(|| {
+                        format!(
+                            "{} has to be in the working directory with --std-zip",
+                            file.display()
+                        )
+                    })?,
+                false => file.to_owned(),
+            };
+            let reader = library_reader(args, PathBuf::from("./"))?;
+            Ok((reader, local_path(&file)))
+        }
+        false => {
+            let working_dir = match file.is_absolute() {
+                true => PathBuf::from("/"),
+                false => PathBuf::from("./"),
+            };
+            let reader = library_reader(args, working_dir)?;
+            Ok((reader, local_path(file)))
+        }
+    }
+}
+
+/// The source reader for the standard library the options ask for, the local
+/// files are read from `working_dir`, which has to be "./" with --std-zip.
+fn library_reader(args: &Args, working_dir: PathBuf) -> Result<SourceReader, String> {
+    match &args.std_zip {
+        Some(zip) => {
+            let archive =
+                fs::read(zip).map_err(|e| format!("cannot read {}: {}", zip.display(), e))?;
+            VfsZip::new(Cow::Owned(archive.clone()))
+                .map_err(|e| format!("cannot open {}: {}", zip.display(), e))?;
+            let base_dir = args
+                .std_dir
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("std/"));
+            Ok(SourceReader::zip_lib_and_local_fs(
+                Cow::Owned(archive),
+                base_dir,
+                working_dir,
+            ))
+        }
+        None => {
+            let base_dir = args
+                .std_dir
+                .clone()
+                .or_else(|| env::var_os("GOSCRIPT_STD").map(PathBuf::from))
+                .unwrap_or_else(|| PathBuf::from("./std/"));
+            Ok(SourceReader::local_fs(base_dir, working_dir))
+        }
+    }
+}
+
+/// A path that starts with `.` or `..`, other paths are import paths in the
+/// standard library. An absolute path is made relative to the root.
+fn local_path(file: &Path) -> PathBuf {
+    let rel = file.strip_prefix("/").unwrap_or(file);
+    match rel.starts_with(".") || rel.starts_with("..") {
+        true => rel.to_owned(),
+        false => Path::new(".").join(rel),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Option<Args>, String> {
+        parse_args(args.split_whitespace().map(str::to_owned))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = parse("build --std lib/ main.gos -o out.gosb --trace-parser")
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.command, Command::Build);
+        assert_eq!(args.file, Some(PathBuf::from("main.gos")));
+        assert_eq!(args.output, Some(PathBuf::from("out.gosb")));
+        assert_eq!(args.std_dir, Some(PathBuf::from("lib/")));
+        assert!(args.trace_parser && !args.trace_checker);
+
+        assert_eq!(parse("run -h").unwrap(), None);
+        assert_eq!(parse("run").unwrap_err(), "missing file");
+        assert_eq!(parse("run a.gos -o b").unwrap_err(), "-o is only for build");
+        assert_eq!(parse("fly a.gos").unwrap_err(), "unknown command fly");
+        assert_eq!(parse("run a.gos --std").unwrap_err(), "--std needs a value");
+        assert_eq!(parse("repl --std lib/").unwrap().unwrap().file, None);
+        assert_eq!(
+            parse("repl a.gos").unwrap_err(),
+            "unexpected argument a.gos"
+        );
+
+        let args = parse("exec a.gosb --pprof a.pb --sample-every 100")
+            .unwrap()
+            .unwrap();
+        assert_eq!(args.pprof, Some(PathBuf::from("a.pb")));
+        assert_eq!((args.folded, args.sample_every), (None, Some(100)));
+        assert_eq!(
+            parse("run a.gos --sample-every 0").unwrap_err(),
+            "--sample-every needs a positive number"
+        );
+        assert_eq!(
+            parse("check a.gos --folded a.txt").unwrap_err(),
+            "profiling is only for run and exec"
+        );
+    }
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete("x := 1\n"));
+        assert!(!is_complete("func f() {\n"));
+        assert!(is_complete("func f() {\n}\n"));
+        assert!(is_complete("s := \"{\" + `(` // [\n"));
+        assert!(!is_complete("m := map[string]int{ /* } */\n"));
+        assert!(is_complete("r := '\\''\n"));
+    }
+
+    #[test]
+    fn test_local_path() {
+        assert_eq!(local_path(Path::new("a/b.gos")), Path::new("./a/b.gos"));
+        assert_eq!(local_path(Path::new("../b.gos")), Path::new("../b.gos"));
+        assert_eq!(local_path(Path::new("/a/b.gos")), Path::new("./a/b.gos"));
+    }
+}
@@ -0,0 +1,489 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Caching the packages a program is built from one by one.
+//!
+//! The artifact of a package is the `PackageData` exported from the type checker,
+//! along with a copy of the code generated for it, the `PackageCode`. The keys in
+//! the code are replaced by the indices of the objects among the ones the
+//! bytecode starts with, the ones of the package and the ones of the packages it
+//! imports, in that order, like the keys in `PackageData`. A package in the store
+//! is imported from its artifact instead of being checked, and its code is moved
+//! to where it would be generated.
+//!
+//! The code only fits the code of the packages it imports as it was generated,
+//! so an artifact holds the fingerprints of the artifacts of the packages it
+//! imports, and it's only loaded if they are loaded too.
+//!
+//! An artifact is a header followed by the Borsh encoded `Artifact`:
+//!
+//! | bytes | content |
+//! |-------|---------|
+//! | 4     | magic number `GOSP` |
+//! | 4     | format version of go-vm, little endian |
+//! | 4     | the features of go-vm that affect the layout, see `FeatureFlags` |
+//! | 8     | checksum of the payload, which is the fingerprint of the artifact |
+//! | ...   | payload, the Borsh encoded `Artifact` |
+
+use super::entry::{parse_check_gen, State};
+use borsh::{BorshDeserialize, BorshSerialize};
+use go_parser::{ErrorList, FileSet, Map, PiggyVecKey, RangeMap};
+use go_types::{
+    check::TypeInfo, ImportKey, Owned, PackageCache, PackageData, PackageKey as TCPackageKey,
+    PackageSource, SourceRead, TCObjects, TraceConfig, TypeKey as TCTypeKey,
+};
+use go_vm::types::*;
+use go_vm::{Bytecode, FeatureFlags, Relocation, FORMAT_VERSION};
+use std::io;
+use std::ops::Range;
+use std::path::Path;
+
+const MAGIC: [u8; 4] = *b"GOSP";
+const HEADER_LEN: usize = 20;
+
+/// PackageStore keeps the artifacts of the packages for `parse_check_gen_cached`.
+pub trait PackageStore {
+    /// The imports of the package with the files, as they were when an artifact
+    /// of it was written, None if there is none.
+    fn imports(&mut self, import_path: &str, files: &[(String, String)]) -> Option<Vec<ImportKey>>;
+
+    /// The artifact of the package written for the same files, and for the same
+    /// files of the packages it imports.
+    fn read(&mut self, source: &PackageSource) -> Option<Vec<u8>>;
+
+    fn write(&mut self, source: &PackageSource, artifact: &[u8]) -> io::Result<()>;
+}
+
+/// Same as `parse_check_gen`, but the packages with artifacts in the store are
+/// loaded from them, and the artifacts of the ones checked and generated are
+/// written to it. If anything is loaded the bytecode is verified, and it's built
+/// again without reading the store if it's bad.
+pub fn parse_check_gen_cached<S: SourceRead>(
+    path: &Path,
+    tconfig: &TraceConfig,
+    reader: &S,
+    debug_info: bool,
+    store: &mut dyn PackageStore,
+) -> Result<Bytecode, ErrorList> {
+    match build(path, tconfig, reader, debug_info, store, true)? {
+        (code, false) => Ok(code),
+        (code, true) => match code.verify() {
+            Ok(()) => Ok(code),
+            Err(_) => match build(path, tconfig, reader, debug_info, store, false) {
+                Ok((code, _)) => Ok(code),
+                // it can't fail unless the store changes the sources
+                Err(_) => parse_check_gen(path, tconfig, reader, debug_info),
+            },
+        },
+    }
+}
+
+/// Builds the program, returns the bytecode and whether any package is loaded
+fn build<S: SourceRead>(
+    path: &Path,
+    tconfig: &TraceConfig,
+    reader: &S,
+    debug_info: bool,
+    store: &mut dyn PackageStore,
+    read: bool,
+) -> Result<(Bytecode, bool), ErrorList> {
+    let mut state = State::new();
+    let mut artifacts = Artifacts {
+        store,
+        read,
+        base: state.base.sizes(),
+        loaded: Map::new(),
+        code: Map::new(),
+    };
+    let paths = [path.to_str().unwrap()];
+    let (keys, el) = state.import(&paths, None, tconfig, reader, Some(&mut artifacts));
+    if el.len() > 0 {
+        return Err(el);
+    }
+    let loaded = !artifacts.code.is_empty();
+    state.loaded = std::mem::take(&mut artifacts.code);
+    let pkgs = state.new_packages(TCPackageKey::from(0));
+    state.gen(&pkgs, None);
+    artifacts.write(&state, &state.import_order(&pkgs));
+
+    let entry = (state.pkg_map[&keys[0]], state.main_ident);
+    state.gen(&[], Some(entry));
+    let mut code = state.code;
+    if !debug_info {
+        code.file_set = None;
+    }
+    Ok((code, loaded))
+}
+
+/// Span is where the code of a package is in `Bytecode`
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Span {
+    metas: Range<usize>,
+    functions: Range<usize>,
+    packages: Range<usize>,
+    consts: Range<usize>,
+    ifaces: Range<usize>,
+    indices: Range<usize>,
+}
+
+impl Span {
+    pub fn start(code: &Bytecode) -> Span {
+        Span::default().end(code).after()
+    }
+
+    pub fn end(self, code: &Bytecode) -> Span {
+        let objs = &code.objects;
+        Span {
+            metas: self.metas.start..objs.metas.vec().len(),
+            functions: self.functions.start..objs.functions.vec().len(),
+            packages: self.packages.start..objs.packages.vec().len(),
+            consts: self.consts.start..code.consts.len(),
+            ifaces: self.ifaces.start..code.ifaces.len(),
+            indices: self.indices.start..code.indices.len(),
+        }
+    }
+
+    /// The empty span right after this one
+    fn after(self) -> Span {
+        let end = |r: Range<usize>| r.end..r.end;
+        Span {
+            metas: end(self.metas),
+            functions: end(self.functions),
+            packages: end(self.packages),
+            consts: end(self.consts),
+            ifaces: end(self.ifaces),
+            indices: end(self.indices),
+        }
+    }
+
+    /// The numbers of the metadata, functions and packages
+    pub fn sizes(&self) -> [usize; 3] {
+        [self.metas.len(), self.functions.len(), self.packages.len()]
+    }
+
+    /// The relocation of the code of a package from the objects of the owners, the
+    /// base of the bytecode, the package and the packages it imports, to their
+    /// indices if `export`, or back otherwise. The code of a package only refers
+    /// to its own consts, interface bindings and struct indices.
+    fn relocation(owners: &[&Span], pos: RangeMap, export: bool) -> Relocation {
+        let map = |f: fn(&Span) -> &Range<usize>, own: bool| {
+            let owners = if own { &owners[1..2] } else { owners };
+            let ranges = owners.iter().map(|s| f(s));
+            match export {
+                true => RangeMap::flatten(ranges),
+                false => RangeMap::unflatten(ranges),
+            }
+        };
+        Relocation {
+            metas: map(|s| &s.metas, false),
+            functions: map(|s| &s.functions, false),
+            packages: map(|s| &s.packages, false),
+            consts: map(|s| &s.consts, true),
+            ifaces: map(|s| &s.ifaces, true),
+            indices: map(|s| &s.indices, true),
+            pos,
+        }
+    }
+}
+
+/// PackageCode is the code generated for a package, see `Span::relocation`
+#[derive(BorshDeserialize, BorshSerialize)]
+pub(crate) struct PackageCode {
+    metas: Vec<MetadataType>,
+    functions: Vec<FunctionObj>,
+    package: PackageObj,
+    consts: Vec<GosValue>,
+    ifaces: Vec<(Meta, Vec<Binding4Runtime>)>,
+    indices: Vec<Vec<OpIndex>>,
+    /// the metadata of the types the package owns, by the indices of the types
+    /// among them
+    types: Vec<(usize, Meta)>,
+}
+
+impl PackageCode {
+    /// Copies the code of the package out of the code of the state
+    fn export(state: &State, pkg: TCPackageKey, deps: &[&Span]) -> Option<PackageCode> {
+        let span = state.spans.get(&pkg)?;
+        if span.packages.len() != 1 {
+            return None;
+        }
+        let owned = Owned::of(pkg, &state.results);
+        let owned_types = RangeMap::flatten(&owned.types);
+        let mut types: Vec<(usize, Meta)> = state
+            .type_cache
+            .iter()
+            .filter_map(|(k, m)| Some((owned_types.get(k.as_usize())?, m.clone())))
+            .collect();
+        types.sort_by_key(|(i, _)| *i);
+        let code = &state.code;
+        let objs = &code.objects;
+        let mut result = PackageCode {
+            metas: objs.metas.vec()[span.metas.clone()].to_vec(),
+            functions: objs.functions.vec()[span.functions.clone()].to_vec(),
+            package: objs.packages.vec()[span.packages.start].clone(),
+            consts: code.consts[span.consts.clone()].to_vec(),
+            ifaces: code.ifaces[span.ifaces.clone()].to_vec(),
+            indices: code.indices[span.indices.clone()].to_vec(),
+            types,
+        };
+        let pos = owned.pos.first().cloned().unwrap_or(0..0);
+        let mut owners = vec![&state.base, span];
+        owners.extend_from_slice(deps);
+        result.relocate(&Span::relocation(&owners, RangeMap::flatten([&pos]), true))?;
+        Some(result)
+    }
+
+    fn relocate(&mut self, r: &Relocation) -> Option<()> {
+        self.metas.iter_mut().try_for_each(|m| r.metadata(m))?;
+        self.functions.iter_mut().try_for_each(|f| r.function(f))?;
+        r.package_obj(&mut self.package)?;
+        r.values(&mut self.consts)?;
+        for (meta, bindings) in self.ifaces.iter_mut() {
+            r.meta(meta)?;
+            bindings.iter_mut().try_for_each(|b| r.binding(b))?;
+        }
+        self.types.iter_mut().try_for_each(|(_, m)| r.meta(m))
+    }
+}
+
+/// The code of a package loaded from its artifact, along with the import paths
+/// of the packages it imports, directly or not
+pub(crate) struct LoadedCode {
+    code: PackageCode,
+    deps: Vec<String>,
+}
+
+impl State {
+    /// Adds the code of a package loaded from its artifact where it would be
+    /// generated
+    pub(crate) fn add_loaded(&mut self, pkg: TCPackageKey, loaded: LoadedCode) {
+        let LoadedCode { mut code, deps } = loaded;
+        let at = Span::start(&self.code);
+        let appended = |r: &Range<usize>, len: usize| r.start..r.start + len;
+        let span = Span {
+            metas: appended(&at.metas, code.metas.len()),
+            functions: appended(&at.functions, code.functions.len()),
+            packages: appended(&at.packages, 1),
+            consts: appended(&at.consts, code.consts.len()),
+            ifaces: appended(&at.ifaces, code.ifaces.len()),
+            indices: appended(&at.indices, code.indices.len()),
+        };
+        let owned = Owned::of(pkg, &self.results);
+        let pos = owned.pos.first().cloned().unwrap_or(0..0);
+        let mut owners = vec![&self.base, &span];
+        owners.extend(deps.iter().map(|p| &self.spans[&self.pkgs[p]]));
+        code.relocate(&Span::relocation(
+            &owners,
+            RangeMap::unflatten([&pos]),
+            false,
+        ))
+        .expect("the code is checked when it's loaded");
+
+        let objs = &mut self.code.objects;
+        objs.metas.append(&mut code.metas);
+        objs.functions.append(&mut code.functions);
+        let key = objs.packages.insert(code.package);
+        self.pkg_map.insert(pkg, key);
+        self.code.consts.append(&mut code.consts);
+        // the interface bindings and struct indices are not shared by the packages
+        for _ in code.ifaces.iter() {
+            let null = TCTypeKey::null();
+            self.iface_selector.push((null, null));
+        }
+        self.code.ifaces.append(&mut code.ifaces);
+        for indices in code.indices.iter() {
+            self.struct_selector.push(indices.clone());
+        }
+        self.code.indices.append(&mut code.indices);
+        let types = RangeMap::unflatten(&owned.types);
+        for (i, meta) in code.types.into_iter() {
+            if let Some(k) = types.get(i) {
+                self.type_cache.insert(k.into(), meta);
+            }
+        }
+    }
+}
+
+#[derive(BorshDeserialize, BorshSerialize)]
+struct Artifact {
+    /// the numbers of the metadata, functions and packages the bytecode starts with
+    base: [usize; 3],
+    /// the import paths of the packages it imports, directly or not, and the
+    /// fingerprints of their artifacts, in the order of `PackageData::dep_paths`
+    deps: Vec<(String, u64)>,
+    data: PackageData,
+    code: PackageCode,
+}
+
+impl Artifact {
+    fn encode(&self) -> Option<(Vec<u8>, u64)> {
+        let payload = self.try_to_vec().ok()?;
+        let fingerprint = fnv1a(&payload);
+        let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+        buf.extend_from_slice(&MAGIC);
+        buf.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        buf.extend_from_slice(&FeatureFlags::current().bits().to_le_bytes());
+        buf.extend_from_slice(&fingerprint.to_le_bytes());
+        buf.extend_from_slice(&payload);
+        Some((buf, fingerprint))
+    }
+
+    fn decode(data: &[u8]) -> Option<(Artifact, u64)> {
+        if data.len() < HEADER_LEN || data[..4] != MAGIC {
+            return None;
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        let fingerprint = u64::from_le_bytes(data[12..20].try_into().unwrap());
+        let payload = &data[HEADER_LEN..];
+        if u32_at(4) != FORMAT_VERSION
+            || u32_at(8) != FeatureFlags::current().bits()
+            || fnv1a(payload) != fingerprint
+        {
+            return None;
+        }
+        let artifact = Artifact::try_from_slice(payload).ok()?;
+        Some((artifact, fingerprint))
+    }
+}
+
+/// Artifacts loads the packages from the artifacts in the store for the Importer
+struct Artifacts<'a> {
+    store: &'a mut dyn PackageStore,
+    /// whether the artifacts are read, or only written
+    read: bool,
+    base: [usize; 3],
+    /// the fingerprints of the artifacts loaded, and the numbers of the metadata,
+    /// functions and packages in their code, by import path
+    loaded: Map<String, (u64, [usize; 3])>,
+    code: Map<TCPackageKey, LoadedCode>,
+}
+
+impl<'a> Artifacts<'a> {
+    fn load_artifact(
+        &mut self,
+        pkg: TCPackageKey,
+        source: &PackageSource,
+        objs: &mut TCObjects,
+        fset: &mut FileSet,
+        pkgs: &Map<String, TCPackageKey>,
+        results: &Map<TCPackageKey, TypeInfo>,
+    ) -> Option<()> {
+        let (artifact, fingerprint) = Artifact::decode(&self.store.read(source)?)?;
+        let Artifact {
+            base,
+            deps,
+            data,
+            mut code,
+        } = artifact;
+        if base != self.base || !data.dep_paths().eq(deps.iter().map(|(p, _)| p.as_str())) {
+            return None;
+        }
+        let mut sizes = self.base;
+        for (path, fingerprint) in deps.iter() {
+            let (f, s) = self.loaded.get(path)?;
+            if f != fingerprint {
+                return None;
+            }
+            sizes.iter_mut().zip(s.iter()).for_each(|(x, y)| *x += y);
+        }
+        let own = [code.metas.len(), code.functions.len(), 1];
+        // the keys in the code have to be of the objects it may refer to, which
+        // is checked by relocating it to where it is
+        let all = |i: usize| RangeMap::flatten([&(0..sizes[i] + own[i])]);
+        let own_only = |len: usize| RangeMap::flatten([&(0..len)]);
+        let checked = Relocation {
+            metas: all(0),
+            functions: all(1),
+            packages: all(2),
+            consts: own_only(code.consts.len()),
+            ifaces: own_only(code.ifaces.len()),
+            indices: own_only(code.indices.len()),
+            pos: own_only(data.pos_size()),
+        };
+        code.relocate(&checked)?;
+        data.import(pkg, objs, fset, pkgs, results)?;
+
+        self.loaded
+            .insert(source.import_path.clone(), (fingerprint, own));
+        let deps = deps.into_iter().map(|(p, _)| p).collect();
+        self.code.insert(pkg, LoadedCode { code, deps });
+        Some(())
+    }
+
+    /// Writes the artifacts of the packages generated, in the order, the ones
+    /// importing packages without artifacts are skipped
+    fn write(&mut self, state: &State, order: &[TCPackageKey]) {
+        let mut fingerprints: Map<String, u64> = self
+            .loaded
+            .iter()
+            .map(|(path, (f, _))| (path.clone(), *f))
+            .collect();
+        for pkg in order.iter() {
+            let source = match &state.results[pkg].source {
+                Some(source) if !self.loaded.contains_key(&source.import_path) => source,
+                _ => continue,
+            };
+            let encoded = Self::export(state, *pkg, &fingerprints).and_then(|x| x.encode());
+            if let Some((data, fingerprint)) = encoded {
+                // failing to write only costs a compile next time
+                if self.store.write(source, &data).is_ok() {
+                    fingerprints.insert(source.import_path.clone(), fingerprint);
+                }
+            }
+        }
+    }
+
+    fn export(
+        state: &State,
+        pkg: TCPackageKey,
+        fingerprints: &Map<String, u64>,
+    ) -> Option<Artifact> {
+        let fset = state.code.file_set.as_ref()?;
+        let data = PackageData::export(pkg, &state.tc_objs, fset, &state.results)?;
+        let deps: Vec<(String, u64)> = data
+            .dep_paths()
+            .map(|p| Some((p.to_owned(), *fingerprints.get(p)?)))
+            .collect::<Option<_>>()?;
+        let spans: Vec<&Span> = deps
+            .iter()
+            .map(|(p, _)| state.spans.get(state.pkgs.get(p)?))
+            .collect::<Option<_>>()?;
+        let code = PackageCode::export(state, pkg, &spans)?;
+        Some(Artifact {
+            base: state.base.sizes(),
+            deps,
+            data,
+            code,
+        })
+    }
+}
+
+impl<'a> PackageCache for Artifacts<'a> {
+    fn imports(&mut self, import_path: &str, files: &[(String, String)]) -> Option<Vec<ImportKey>> {
+        match self.read {
+            true => self.store.imports(import_path, files),
+            false => None,
+        }
+    }
+
+    fn load(
+        &mut self,
+        pkg: TCPackageKey,
+        source: &PackageSource,
+        objs: &mut TCObjects,
+        fset: &mut FileSet,
+        pkgs: &Map<String, TCPackageKey>,
+        results: &Map<TCPackageKey, TypeInfo>,
+    ) -> bool {
+        self.load_artifact(pkg, source, objs, fset, pkgs, results)
+            .is_some()
+    }
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is stable across Rust versions
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
@@ -288,13 +288,13 @@ impl<'a, 'c> CodeGen<'a, 'c> {
                                     struct_addr = self.gen_load_pointer(struct_addr, Some(pos));
                                 }
                                 (
-                                    VirtualAddr::StructMember(struct_addr, Addr::Imm(index)),
+                                    VirtualAddr::StructMember(struct_addr, Addr::Imm(index), t),
                                     typ,
                                     pos,
                                 )
                             } else {
                                 (
-                                    VirtualAddr::StructEmbedded(struct_addr, Addr::Imm(index)),
+                                    VirtualAddr::StructEmbedded(struct_addr, Addr::Imm(index), t),
                                     typ,
                                     pos,
                                 )
@@ -622,6 +622,7 @@ impl<'a, 'c> CodeGen<'a, 'c> {
 
     fn gen_func_def(
         &mut self,
+        name: String,
         tc_type: TCTypeKey, // Meta,
         f_type_key: FuncTypeKey,
         recv: Option<FieldList>,
@@ -633,6 +634,7 @@ impl<'a, 'c> CodeGen<'a, 'c> {
             .vmctx
             .function_with_meta(Some(self.pkg_key), fmeta, FuncFlag::Default);
         let fkey = *f.as_function();
+        self.vmctx.functions_mut()[fkey].name = name;
         let mut fctx = FuncCtx::new(fkey, Some(tc_type), self.consts);
         if let Some(fl) = &typ.results {
             fctx.add_params(&fl, self.ast_objs, &self.t);
@@ -657,6 +659,26 @@ impl<'a, 'c> CodeGen<'a, 'c> {
         (fkey, cls)
     }
 
+    /// Names the function like Go does in stack traces
+    fn func_decl_name(&self, decl: &FuncDecl) -> String {
+        let pkg = self.vmctx.packages()[self.pkg_key].name();
+        let name = &self.ast_objs.idents[decl.name].name;
+        match &decl.recv {
+            Some(recv) => {
+                let typ = &self.ast_objs.fields[recv.list[0]].typ;
+                let recv_name = |expr: &Expr| match expr {
+                    Expr::Ident(i) => self.ast_objs.idents[*i].name.clone(),
+                    _ => "?".to_owned(),
+                };
+                match typ {
+                    Expr::Star(s) => format!("{}.(*{}).{}", pkg, recv_name(&s.expr), name),
+                    _ => format!("{}.{}.{}", pkg, recv_name(typ), name),
+                }
+            }
+            None => format!("{}.{}", pkg, name),
+        }
+    }
+
     fn gen_builtin_call(
         &mut self,
         func_expr: &Expr,
@@ -1213,6 +1235,7 @@ impl<'a, 'c> CodeGen<'a, 'c> {
                 }
                 None => {
                     let mut struct_addr = self.load_mode_call(|g| g.gen_expr(&sexpr.expr));
+                    let meta = self.t.node_meta(sexpr.expr.id(), self.vmctx);
                     let (_, _, indices, _) = self.t.selection_vtypes_indices_sel_typ(sexpr.id());
                     let rt_indices = indices.iter().map(|x| *x as OpIndex).collect();
                     let (op, index) =
@@ -1224,7 +1247,7 @@ impl<'a, 'c> CodeGen<'a, 'c> {
                     }
                     self.cur_expr_emit_assign(ref_tc_type, pos, |f, d, p| {
                         let inst = InterInst::with_op_index(op, d, struct_addr, Addr::Imm(index));
-                        f.emit_inst(inst, p);
+                        f.emit_inst_meta(inst, meta, p);
                     });
                 }
             },
@@ -1592,6 +1615,8 @@ impl<'a, 'c> CodeGen<'a, 'c> {
             .vmctx
             .function_with_meta(Some(pkey), fmeta, FuncFlag::PkgCtor);
         let fkey = *f.as_function();
+        let name = format!("{}.init", self.vmctx.packages()[pkey].name());
+        self.vmctx.functions_mut()[fkey].name = name;
         // the 0th member is the constructor
         self.vmctx.packages_mut()[pkey].add_member(
             String::new(),
@@ -1661,7 +1686,14 @@ impl<'a, 'c> ExprVisitor for CodeGen<'a, 'c> {
     /// Add function as a const and then generate a closure of it
     fn visit_expr_func_lit(&mut self, this: &Expr, flit: &FuncLit) {
         let tc_type = self.t.expr_tc_type(this);
-        let (fkey, _) = self.gen_func_def(tc_type, flit.typ, None, &flit.body);
+        let parent = func_ctx!(self);
+        parent.func_lits += 1;
+        let name = format!(
+            "{}.func{}",
+            self.vmctx.functions()[parent.f_key].name,
+            parent.func_lits
+        );
+        let (fkey, _) = self.gen_func_def(name, tc_type, flit.typ, None, &flit.body);
         let fctx = func_ctx!(self);
         let addr = fctx.add_comparable(FfiCtx::new_function(fkey));
         let pos = Some(flit.body.l_brace);
@@ -1721,7 +1753,7 @@ impl<'a, 'c> ExprVisitor for CodeGen<'a, 'c> {
                         let result_addr = expr_ctx!(self).inc_cur_reg();
                         let inst =
                             InterInst::with_op_index(op, result_addr, lhs_addr, Addr::Imm(index));
-                        func_ctx!(self).emit_inst(inst, pos);
+                        func_ctx!(self).emit_inst_meta(inst, lhs_meta, pos);
                         result_addr
                     }
                 } else {
@@ -1736,7 +1768,7 @@ impl<'a, 'c> ExprVisitor for CodeGen<'a, 'c> {
                         let addr = expr_ctx!(self).inc_cur_reg();
                         let inst =
                             InterInst::with_op_index(op, addr, struct_addr, Addr::Imm(index));
-                        func_ctx!(self).emit_inst(inst, pos);
+                        func_ctx!(self).emit_inst_meta(inst, lhs_meta, pos);
                         struct_addr = addr;
                     }
                     if final_lhs_type == ValueType::Pointer
@@ -1755,7 +1787,7 @@ impl<'a, 'c> ExprVisitor for CodeGen<'a, 'c> {
                             recv_addr,
                             Addr::Imm(final_index as OpIndex),
                         );
-                        f.emit_inst(inst, p);
+                        f.emit_inst_meta(inst, final_lhs_meta, p);
                     });
                 } else {
                     self.cur_expr_emit_assign(expr_type, pos, |f, d, p| {
@@ -1778,7 +1810,7 @@ impl<'a, 'c> ExprVisitor for CodeGen<'a, 'c> {
                 }
                 self.cur_expr_emit_assign(expr_type, pos, |f, d, p| {
                     let inst = InterInst::with_op_index(op, d, lhs_addr, Addr::Imm(index));
-                    f.emit_inst(inst, p);
+                    f.emit_inst_meta(inst, lhs_meta, p);
                 });
             }
         }
@@ -2030,7 +2062,8 @@ impl<'a, 'c> StmtVisitor for CodeGen<'a, 'c> {
         }
         let tc_type = self.t.obj_def_tc_type(decl.name);
         let stmt = decl.body.as_ref().unwrap();
-        let (fkey, cls) = self.gen_func_def(tc_type, decl.typ, decl.recv.clone(), stmt);
+        let name = self.func_decl_name(decl);
+        let (fkey, cls) = self.gen_func_def(name, tc_type, decl.typ, decl.recv.clone(), stmt);
         // this is a struct method
         if let Some(self_ident) = &decl.recv {
             let field = &self.ast_objs.fields[self_ident.list[0]];
@@ -2475,6 +2508,7 @@ pub type IfaceSelector = Selector<(TCTypeKey, TCTypeKey)>;
 
 pub type StructSelector = Selector<Vec<OpIndex>>;
 
+#[derive(Clone)]
 pub struct Selector<K: Eq + Hash + Ord + Clone> {
     vec: Vec<K>,
     mapping: Map<K, OpIndex>,
@@ -2488,8 +2522,28 @@ impl<K: Eq + Hash + Ord + Clone> Selector<K> {
         }
     }
 
-    pub fn result(self) -> Vec<K> {
-        self.vec
+    pub fn keys(&self) -> &[K] {
+        &self.vec
+    }
+
+    /// Drops the keys added after the first `len` ones
+    pub fn truncate(&mut self, len: usize) {
+        for key in self.vec.drain(len..) {
+            self.mapping.remove(&key);
+        }
+    }
+
+    /// Forgets the keys added so far, the next ones get new indices even if
+    /// they are added again
+    pub fn reset(&mut self) {
+        self.mapping.clear();
+    }
+
+    /// Adds a key that's never returned by `add`, it only keeps the indices of
+    /// the keys in step with the ones they are for
+    #[cfg(feature = "serde_borsh")]
+    pub fn push(&mut self, key: K) {
+        self.vec.push(key);
     }
 
     pub fn add(&mut self, key: K) -> OpIndex {
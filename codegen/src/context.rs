@@ -6,7 +6,7 @@ use super::consts::Consts;
 use super::types::TypeLookup;
 use go_parser::ast::*;
 use go_parser::{AstObjects, IdentKey, Map, Pos};
-use go_types::{ObjKey as TCObjKey, TypeKey as TCTypeKey};
+use go_types::{ObjKey as TCObjKey, TCObjects, TypeKey as TCTypeKey};
 use go_vm::types::*;
 use go_vm::*;
 use std::convert::TryFrom;
@@ -75,8 +75,9 @@ pub enum VirtualAddr {
     SliceEntry(Addr, Addr),
     ArrayEntry(Addr, Addr),
     MapEntry(Addr, Addr, Addr),
-    StructMember(Addr, Addr),
-    StructEmbedded(Addr, Addr),
+    /// The struct, the field index, and the type of the struct
+    StructMember(Addr, Addr, Meta),
+    StructEmbedded(Addr, Addr, Meta),
     PackageMember(Addr, Addr),
     Pointee(Addr),
     Blank,
@@ -361,6 +362,9 @@ pub(crate) struct FuncCtx<'c> {
     pos: Vec<Option<usize>>,
     pub up_ptrs: Vec<ValueDesc>,
     local_zeros: Vec<GosValue>,
+    operand_metas: Map<usize, Meta>,
+    /// the number of function literals in it so far, for naming them
+    pub func_lits: usize,
 
     entities: Map<TCObjKey, Addr>,
     uv_entities: Map<TCObjKey, Addr>,
@@ -377,6 +381,8 @@ impl<'a> FuncCtx<'a> {
             pos: vec![],
             up_ptrs: vec![],
             local_zeros: vec![],
+            operand_metas: Map::new(),
+            func_lits: 0,
             entities: Map::new(),
             uv_entities: Map::new(),
             local_alloc: 0,
@@ -497,6 +503,7 @@ impl<'a> FuncCtx<'a> {
         }
         let mut direct = false;
         let mut inst_ex = None;
+        let mut operand_meta = None;
         let mut inst = match lhs {
             VirtualAddr::Direct(l) => {
                 direct = true;
@@ -544,10 +551,12 @@ impl<'a> FuncCtx<'a> {
                 ));
                 InterInst::with_op_index(Opcode::STORE_MAP, m, k, rhs)
             }
-            VirtualAddr::StructMember(s, i) => {
+            VirtualAddr::StructMember(s, i, meta) => {
+                operand_meta = Some(meta);
                 InterInst::with_op_index(Opcode::STORE_STRUCT, s, i, rhs)
             }
-            VirtualAddr::StructEmbedded(s, i) => {
+            VirtualAddr::StructEmbedded(s, i, meta) => {
+                operand_meta = Some(meta);
                 InterInst::with_op_index(Opcode::STORE_EMBEDDED, s, i, rhs)
             }
             VirtualAddr::PackageMember(p, i) => {
@@ -567,7 +576,10 @@ impl<'a> FuncCtx<'a> {
                 }
             };
         }
-        self.emit_inst(inst, pos);
+        match operand_meta {
+            Some(meta) => self.emit_inst_meta(inst, meta, pos),
+            None => self.emit_inst(inst, pos),
+        }
         if let Some(i) = inst_ex {
             self.emit_inst(i, pos);
         }
@@ -720,10 +732,41 @@ impl<'a> FuncCtx<'a> {
     pub fn into_runtime_func(
         self,
         asto: &AstObjects,
+        tco: &TCObjects,
         vmctx: &mut CodeGenVMCtx,
         labels: &Map<TCObjKey, usize>,
         cst_map: &Map<usize, usize>,
     ) {
+        let mut var_names: Vec<VarName> = self
+            .entities
+            .iter()
+            .filter_map(|(key, addr)| match addr {
+                Addr::LocalVar(i) => {
+                    let obj = &tco.lobjs[*key];
+                    let start = match *obj.scope_pos() {
+                        0 => obj.pos(),
+                        p => p,
+                    };
+                    let end = obj
+                        .parent()
+                        .map_or(u32::MAX as usize, |s| tco.scopes[s].end());
+                    Some(VarName {
+                        name: obj.name().clone(),
+                        index: *i as OpIndex,
+                        scope: (start as u32, end as u32),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        var_names.sort_by_key(|x| x.index);
+        let mut up_names = vec![String::new(); self.up_ptrs.len()];
+        for (key, addr) in self.uv_entities.iter() {
+            if let Addr::Imm(i) = addr {
+                up_names[*i as usize] = tco.lobjs[*key].name().clone();
+            }
+        }
+
         let code: Vec<Instruction> = self
             .code
             .into_iter()
@@ -746,6 +789,13 @@ impl<'a> FuncCtx<'a> {
         func.up_ptrs = self.up_ptrs;
         func.max_write_index = Instruction::max_write_index(&code);
         func.local_zeros = self.local_zeros;
+        func.operand_metas = self
+            .operand_metas
+            .into_iter()
+            .map(|(pc, meta)| (pc as OpIndex, meta))
+            .collect();
+        func.var_names = var_names;
+        func.up_names = up_names;
         func.code = code;
     }
 
@@ -753,4 +803,11 @@ impl<'a> FuncCtx<'a> {
         self.code.push(i);
         self.pos.push(pos);
     }
+
+    /// Emits an instruction accessing a field or an interface method by index, `meta`
+    /// is the type of the struct or the interface it's applied to
+    pub fn emit_inst_meta(&mut self, i: InterInst, meta: Meta, pos: Option<usize>) {
+        self.operand_metas.insert(self.code.len(), meta);
+        self.emit_inst(i, pos);
+    }
 }
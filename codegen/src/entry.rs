@@ -3,21 +3,25 @@
 // license that can be found in the LICENSE file.
 
 use super::branch::BranchHelper;
+#[cfg(feature = "serde_borsh")]
+use super::cache::{LoadedCode, Span};
 use super::codegen::*;
 use super::consts::*;
 use super::context::*;
 use super::package::PkgHelper;
 use super::types::{TypeCache, TypeLookup};
 use go_parser::ast::Ident;
-use go_parser::{AstObjects, ErrorList, FileSet, IdentKey, Map};
+use go_parser::{AstObjects, ErrorList, File, FileSet, IdentKey, Map, PiggyVecKey, RangeMap};
 use go_types::{
-    check::TypeInfo, ImportKey, Importer, PackageKey as TCPackageKey, SourceRead, TCObjects,
-    TraceConfig,
+    check::TypeInfo, ImportKey, Importer, Owned, Package, PackageCache, PackageKey as TCPackageKey,
+    SourceRead, TCObjects, TraceConfig, TypeKey as TCTypeKey,
 };
 use go_vm::types::*;
 use go_vm::*;
+use std::borrow::Cow;
+use std::cell::{Ref, RefCell};
 use std::path::Path;
-use std::vec;
+use std::rc::Rc;
 
 pub fn parse_check_gen<S: SourceRead>(
     path: &Path,
@@ -25,126 +29,593 @@ pub fn parse_check_gen<S: SourceRead>(
     reader: &S,
     debug_info: bool,
 ) -> Result<Bytecode, ErrorList> {
-    let mut fset = FileSet::new();
-
-    let ast_objs = &mut AstObjects::new();
-    let tc_objs = &mut TCObjects::new();
-    let results = &mut Map::new();
-    let pkgs = &mut Map::new();
-    let el = ErrorList::new();
-
-    let importer = &mut Importer::new(
-        &tconfig, reader, &mut fset, pkgs, results, ast_objs, tc_objs, &el, 0,
-    );
-    let key = ImportKey::new(
-        path.to_str().unwrap(),
-        reader.working_dir().to_str().unwrap(),
-    );
-    let main_pkg = importer.import(&key);
-    if el.len() > 0 {
-        Err(el)
-    } else {
-        let blank_ident = ast_objs.idents.insert(Ident::blank(0));
-        let main_ident = ast_objs.idents.insert(Ident::with_str(0, "main"));
-        Ok(gen_byte_code(
-            ast_objs,
-            tc_objs,
-            results,
-            main_pkg.unwrap(),
-            main_ident,
-            blank_ident,
-            debug_info.then_some(fset),
-        ))
+    State::new().build(path, tconfig, reader, debug_info)
+}
+
+/// Packages parsed, type checked and generated once, like the standard library, so
+/// that the programs importing them are compiled against them without doing that
+/// again.
+///
+/// A program is checked and generated on top of the module's objects, in place,
+/// then its own packages, with the functions, metadata, consts and files of them,
+/// are split off and the module is left as it was, so it's shared by any number of
+/// programs without being copied. The bytecode of a program is linked from a copy
+/// of the code of the module, which holds the state of the packages when it runs,
+/// and the part split off, the keys and const indices of which go on from the
+/// ones of the module.
+pub struct Module {
+    state: RefCell<State>,
+}
+
+impl Module {
+    /// Parses, type checks and generates the packages with the import paths, along
+    /// with all the packages they import.
+    pub fn new<S: SourceRead>(
+        paths: &[&str],
+        tconfig: &TraceConfig,
+        reader: &S,
+    ) -> Result<Module, ErrorList> {
+        let mut state = State::new();
+        state.add(paths, None, true, tconfig, reader)?;
+        Ok(Module {
+            state: RefCell::new(state),
+        })
+    }
+
+    /// The import paths of the packages in the module, sorted
+    pub fn packages(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.state.borrow().pkgs.keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// Compiles the program at `path` against the module, only the packages that
+    /// are not in the module are parsed, type checked and generated.
+    pub fn parse_check_gen<S: SourceRead>(
+        &self,
+        path: &Path,
+        tconfig: &TraceConfig,
+        reader: &S,
+        debug_info: bool,
+    ) -> Result<Bytecode, ErrorList> {
+        self.state
+            .borrow_mut()
+            .build(path, tconfig, reader, debug_info)
+    }
+
+    /// Imports the packages with the import paths, along with the packages they
+    /// import, and generates the ones that are new to the module. If it fails, the
+    /// module is left as it was.
+    pub fn import<S: SourceRead>(
+        &mut self,
+        paths: &[&str],
+        tconfig: &TraceConfig,
+        reader: &S,
+    ) -> Result<(), ErrorList> {
+        self.state
+            .get_mut()
+            .add(paths, None, true, tconfig, reader)
+            .map(|_| ())
+    }
+
+    /// Imports and generates the package at `path` like `import`, but the package
+    /// scope of it is nested in the one of the package `outer`, so that the package
+    /// level objects of `outer` are visible in it. That's how an interactive session
+    /// builds on its earlier inputs.
+    pub fn add_nested<S: SourceRead>(
+        &mut self,
+        path: &str,
+        outer: &str,
+        tconfig: &TraceConfig,
+        reader: &S,
+    ) -> Result<(), ErrorList> {
+        self.state
+            .get_mut()
+            .add(&[path], Some(outer), true, tconfig, reader)
+            .map(|_| ())
+    }
+
+    /// Parses and type checks the package at `path` like `add_nested`, and calls `f`
+    /// with the results, instead of adding the package to the module.
+    pub fn check_nested<S: SourceRead, R>(
+        &mut self,
+        path: &str,
+        outer: &str,
+        tconfig: &TraceConfig,
+        reader: &S,
+        f: impl FnOnce(&AstObjects, &TCObjects, &TypeInfo) -> R,
+    ) -> Result<R, ErrorList> {
+        let state = self.state.get_mut();
+        let key = state.add(&[path], Some(outer), false, tconfig, reader)?[0];
+        Ok(f(&state.ast_objs, &state.tc_objs, &state.results[&key]))
+    }
+
+    /// Sets how the packages are written in the types of the messages of the type
+    /// checker, it's the package path by default.
+    pub fn set_fmt_qualifier(&mut self, qualifier: impl Fn(&Package) -> Cow<str> + 'static) {
+        self.state.get_mut().tc_objs.fmt_qualifier = Rc::new(qualifier);
+    }
+
+    /// Makes the bytecode run the function `func` of the package at `path`, which
+    /// is initialized first if it's not yet.
+    pub fn set_entry(&mut self, path: &str, func: &str) {
+        let state = self.state.get_mut();
+        let pkg = state.pkg_map[&state.pkgs[path]];
+        let ident = state.ast_objs.idents.insert(Ident::with_str(0, func));
+        state.gen(&[], Some((pkg, ident)));
+    }
+
+    /// The code generated so far, along with the file set. The values of the
+    /// package members are kept in it when it runs.
+    pub fn bytecode(&self) -> Ref<'_, Bytecode> {
+        Ref::map(self.state.borrow(), |x| &x.code)
     }
 }
 
-fn gen_byte_code(
-    ast_objs: &AstObjects,
-    tc_objs: &TCObjects,
-    checker_result: &Map<TCPackageKey, TypeInfo>,
-    tc_main_pkg: TCPackageKey,
-    main_ident: IdentKey,
+/// The objects the packages of a module are checked and generated with
+pub(crate) struct State {
+    ast_objs: AstObjects,
+    pub(crate) tc_objs: TCObjects,
+    pub(crate) pkgs: Map<String, TCPackageKey>,
+    pub(crate) results: Map<TCPackageKey, TypeInfo>,
+    /// The code generated so far, the file set is always there while building
+    pub(crate) code: Bytecode,
+    pub(crate) pkg_map: Map<TCPackageKey, PackageKey>,
+    pub(crate) type_cache: TypeCache,
+    pub(crate) iface_selector: IfaceSelector,
+    pub(crate) struct_selector: StructSelector,
     blank_ident: IdentKey,
-    fset: Option<FileSet>,
-) -> Bytecode {
-    let vm_objs = VMObjects::new();
-    let mut vmctx = CodeGenVMCtx::new(vm_objs);
-    let consts = Consts::new();
-    let mut iface_selector = IfaceSelector::new();
-    let mut struct_selector = StructSelector::new();
-    let mut pkg_map = Map::new();
-    let mut type_cache: TypeCache = Map::new();
-    let mut branch_helper = BranchHelper::new();
-    let mut result_funcs = vec![];
-
-    for (&tcpkg, _) in checker_result.iter() {
-        let name = tc_objs.pkgs[tcpkg].name().clone().unwrap();
-        let pkey = vmctx.packages_mut().insert(PackageObj::new(name));
-        pkg_map.insert(tcpkg, pkey);
-    }
-
-    let main_pkg = pkg_map[&tc_main_pkg];
-    let entry = gen_entry_func(&mut vmctx, &consts, main_pkg, main_ident);
-    let entry_key = entry.f_key;
-    result_funcs.push(entry);
-
-    for (tcpkg, ti) in checker_result.iter() {
-        let mut pkg_helper = PkgHelper::new(ast_objs, tc_objs, &pkg_map);
-        let cgen = CodeGen::new(
-            &mut vmctx,
-            &consts,
+    pub(crate) main_ident: IdentKey,
+    /// The code of the packages loaded from a cache, it's added when they would
+    /// be generated
+    #[cfg(feature = "serde_borsh")]
+    pub(crate) loaded: Map<TCPackageKey, LoadedCode>,
+    /// Where the code of every package generated or loaded is in `code`
+    #[cfg(feature = "serde_borsh")]
+    pub(crate) spans: Map<TCPackageKey, Span>,
+    /// The objects the code starts with, before any package is generated
+    #[cfg(feature = "serde_borsh")]
+    pub(crate) base: Span,
+}
+
+/// The part of the bytecode of a program that is not in the module: its own
+/// packages, and the functions, metadata, consts, interface bindings, struct
+/// indices and files generated for them, along with its entry function.
+struct Program {
+    metas: Vec<MetadataType>,
+    functions: Vec<FunctionObj>,
+    packages: Vec<PackageObj>,
+    consts: Vec<GosValue>,
+    ifaces: Vec<(Meta, Vec<Binding4Runtime>)>,
+    indices: Vec<Vec<OpIndex>>,
+    files: Vec<File>,
+    entry: FunctionObj,
+    main_pkg: PackageKey,
+}
+
+/// The sizes of what a program adds to the objects of a module, what's after
+/// them is dropped once the program is built.
+struct Mark {
+    ast_objs: [usize; 9],
+    tc_objs: [usize; 5],
+    vm_objs: [usize; 3],
+    consts: usize,
+    ifaces: usize,
+    indices: usize,
+    files: usize,
+    iface_selector: usize,
+    struct_selector: usize,
+    entry: FunctionObj,
+    main_pkg: PackageKey,
+}
+
+impl State {
+    pub(crate) fn new() -> State {
+        let mut ast_objs = AstObjects::new();
+        let blank_ident = ast_objs.idents.insert(Ident::blank(0));
+        let main_ident = ast_objs.idents.insert(Ident::with_str(0, "main"));
+        // the entry function is generated again for every program
+        let mut vmctx = CodeGenVMCtx::new(VMObjects::new());
+        let fmeta = vmctx.prim_meta().default_sig;
+        let fobj = vmctx.function_with_meta(None, fmeta, FuncFlag::Default);
+        let entry = *fobj.as_function();
+        vmctx.functions_mut()[entry].name = "main".to_owned();
+        let code = Bytecode::new(
+            vmctx.into_vmo(),
+            vec![],
+            vec![],
+            vec![],
+            entry,
+            PackageKey::null(),
+            Some(FileSet::new()),
+        );
+        #[cfg(feature = "serde_borsh")]
+        let base = Span::default().end(&code);
+        State {
             ast_objs,
-            tc_objs,
-            &ti,
-            &mut type_cache,
-            &mut iface_selector,
-            &mut struct_selector,
-            &mut branch_helper,
-            &mut pkg_helper,
-            pkg_map[tcpkg],
+            tc_objs: TCObjects::new(),
+            pkgs: Map::new(),
+            results: Map::new(),
+            code,
+            pkg_map: Map::new(),
+            type_cache: Map::new(),
+            iface_selector: IfaceSelector::new(),
+            struct_selector: StructSelector::new(),
             blank_ident,
-        );
-        result_funcs.append(&mut cgen.gen_with_files(&ti.ast_files, *tcpkg));
-    }
-
-    let (consts, cst_map) = consts.get_runtime_consts(&mut vmctx);
-    for f in result_funcs.into_iter() {
-        f.into_runtime_func(ast_objs, &mut vmctx, branch_helper.labels(), &cst_map);
-    }
-
-    let dummy_ti = TypeInfo::new();
-    let mut lookup = TypeLookup::new(tc_objs, &dummy_ti, &mut type_cache);
-    let iface_binding = iface_selector
-        .result()
-        .into_iter()
-        .map(|x| lookup.iface_binding_info(x, &mut vmctx))
-        .collect();
-
-    Bytecode::new(
-        vmctx.into_vmo(),
-        consts,
-        iface_binding,
-        struct_selector.result(),
-        entry_key,
-        main_pkg,
-        fset,
-    )
+            main_ident,
+            #[cfg(feature = "serde_borsh")]
+            loaded: Map::new(),
+            #[cfg(feature = "serde_borsh")]
+            spans: Map::new(),
+            #[cfg(feature = "serde_borsh")]
+            base,
+        }
+    }
+
+    /// Imports the packages, nested in the package `outer` if it's given, and
+    /// generates the new ones if `gen` is set. The packages that are not generated
+    /// are dropped from the module, whether they fail or not, so that importing
+    /// them again checks them again.
+    fn add<S: SourceRead>(
+        &mut self,
+        paths: &[&str],
+        outer: Option<&str>,
+        gen: bool,
+        tconfig: &TraceConfig,
+        reader: &S,
+    ) -> Result<Vec<TCPackageKey>, ErrorList> {
+        let first_new = TCPackageKey::from(self.tc_objs.pkgs.vec().len());
+        let outer = outer.map(|x| self.pkgs[x]);
+        let (keys, el) = self.import(paths, outer, tconfig, reader, None);
+        let new_pkgs = self.new_packages(first_new);
+        if el.len() > 0 || !gen {
+            self.pkgs.retain(|_, k| *k < first_new);
+        }
+        if el.len() > 0 {
+            return Err(el);
+        }
+        if gen {
+            self.gen(&new_pkgs, None);
+        }
+        Ok(keys)
+    }
+
+    /// Imports the packages, nested in the package `outer` if it's given, the ones
+    /// in the cache are loaded from it. Returns the keys of the packages imported
+    /// and the errors.
+    pub(crate) fn import<S: SourceRead>(
+        &mut self,
+        paths: &[&str],
+        outer: Option<TCPackageKey>,
+        tconfig: &TraceConfig,
+        reader: &S,
+        mut cache: Option<&mut dyn PackageCache>,
+    ) -> (Vec<TCPackageKey>, ErrorList) {
+        let el = ErrorList::new();
+        let keys: Vec<TCPackageKey> = paths
+            .iter()
+            .filter_map(|path| {
+                let key = ImportKey::new(path, reader.working_dir().to_str().unwrap());
+                let importer = &mut Importer::new(
+                    tconfig,
+                    reader,
+                    self.code.file_set.as_mut().unwrap(),
+                    &mut self.pkgs,
+                    &mut self.results,
+                    &mut self.ast_objs,
+                    &mut self.tc_objs,
+                    &el,
+                    0,
+                )
+                .with_cache(match &mut cache {
+                    Some(cache) => Some(&mut **cache),
+                    None => None,
+                });
+                match outer {
+                    Some(outer) => importer.import_nested(&key, outer),
+                    None => importer.import(&key),
+                }
+                .ok()
+            })
+            .collect();
+        (keys, el)
+    }
+
+    /// The packages imported from the key `first_new` on, sorted
+    pub(crate) fn new_packages(&self, first_new: TCPackageKey) -> Vec<TCPackageKey> {
+        let mut pkgs: Vec<TCPackageKey> = self
+            .pkgs
+            .values()
+            .filter(|k| **k >= first_new)
+            .copied()
+            .collect();
+        pkgs.sort();
+        pkgs
+    }
+
+    /// Generates the bytecode of the program with the main package at `path`.
+    /// The objects of the program are dropped from the module afterwards, whether
+    /// it fails or not.
+    fn build<S: SourceRead>(
+        &mut self,
+        path: &Path,
+        tconfig: &TraceConfig,
+        reader: &S,
+        debug_info: bool,
+    ) -> Result<Bytecode, ErrorList> {
+        let mark = self.mark();
+        let result = self.add(&[path.to_str().unwrap()], None, true, tconfig, reader);
+        let result = result.map(|keys| {
+            let entry = (self.pkg_map[&keys[0]], self.main_ident);
+            self.gen(&[], Some(entry));
+            self.split_off(&mark)
+        });
+        self.rollback(mark);
+        result.map(|program| self.link(program, debug_info))
+    }
+
+    fn mark(&self) -> Mark {
+        let a = &self.ast_objs;
+        let t = &self.tc_objs;
+        let v = &self.code.objects;
+        Mark {
+            ast_objs: [
+                a.l_stmts.vec().len(),
+                a.a_stmts.vec().len(),
+                a.specs.vec().len(),
+                a.fdecls.vec().len(),
+                a.ftypes.vec().len(),
+                a.idents.vec().len(),
+                a.fields.vec().len(),
+                a.entities.vec().len(),
+                a.scopes.vec().len(),
+            ],
+            tc_objs: [
+                t.lobjs.vec().len(),
+                t.types.vec().len(),
+                t.pkgs.vec().len(),
+                t.decls.vec().len(),
+                t.scopes.vec().len(),
+            ],
+            vm_objs: [
+                v.metas.vec().len(),
+                v.functions.vec().len(),
+                v.packages.vec().len(),
+            ],
+            consts: self.code.consts.len(),
+            ifaces: self.code.ifaces.len(),
+            indices: self.code.indices.len(),
+            files: self.code.file_set.as_ref().unwrap().iter().count(),
+            iface_selector: self.iface_selector.keys().len(),
+            struct_selector: self.struct_selector.keys().len(),
+            entry: v.functions[self.code.entry].clone(),
+            main_pkg: self.code.main_pkg,
+        }
+    }
+
+    /// Takes what's generated after the mark out of the code
+    fn split_off(&mut self, mark: &Mark) -> Program {
+        let code = &mut self.code;
+        let objs = &mut code.objects;
+        Program {
+            metas: objs.metas.split_off(mark.vm_objs[0]),
+            functions: objs.functions.split_off(mark.vm_objs[1]),
+            packages: objs.packages.split_off(mark.vm_objs[2]),
+            consts: code.consts.split_off(mark.consts),
+            ifaces: code.ifaces.split_off(mark.ifaces),
+            indices: code.indices.split_off(mark.indices),
+            files: code.file_set.as_mut().unwrap().split_off(mark.files),
+            entry: objs.functions[code.entry].clone(),
+            main_pkg: code.main_pkg,
+        }
+    }
+
+    /// Drops what's added after the mark
+    fn rollback(&mut self, mark: Mark) {
+        let a = &mut self.ast_objs;
+        a.l_stmts.truncate(mark.ast_objs[0]);
+        a.a_stmts.truncate(mark.ast_objs[1]);
+        a.specs.truncate(mark.ast_objs[2]);
+        a.fdecls.truncate(mark.ast_objs[3]);
+        a.ftypes.truncate(mark.ast_objs[4]);
+        a.idents.truncate(mark.ast_objs[5]);
+        a.fields.truncate(mark.ast_objs[6]);
+        a.entities.truncate(mark.ast_objs[7]);
+        a.scopes.truncate(mark.ast_objs[8]);
+        let t = &mut self.tc_objs;
+        t.lobjs.truncate(mark.tc_objs[0]);
+        t.types.truncate(mark.tc_objs[1]);
+        t.pkgs.truncate(mark.tc_objs[2]);
+        t.decls.truncate(mark.tc_objs[3]);
+        t.scopes.truncate(mark.tc_objs[4]);
+
+        let first_new = TCPackageKey::from(mark.tc_objs[2]);
+        self.pkgs.retain(|_, k| *k < first_new);
+        self.results.retain(|k, _| *k < first_new);
+        self.pkg_map.retain(|k, _| *k < first_new);
+        let first_type = TCTypeKey::from(mark.tc_objs[1]);
+        let first_meta = MetadataKey::from(mark.vm_objs[0]);
+        self.type_cache
+            .retain(|k, v| *k < first_type && v.key < first_meta);
+        self.iface_selector.truncate(mark.iface_selector);
+        self.struct_selector.truncate(mark.struct_selector);
+
+        let code = &mut self.code;
+        let objs = &mut code.objects;
+        objs.metas.truncate(mark.vm_objs[0]);
+        objs.functions.truncate(mark.vm_objs[1]);
+        objs.packages.truncate(mark.vm_objs[2]);
+        objs.functions[code.entry] = mark.entry;
+        code.main_pkg = mark.main_pkg;
+        code.consts.truncate(mark.consts);
+        code.ifaces.truncate(mark.ifaces);
+        code.indices.truncate(mark.indices);
+        code.file_set.as_mut().unwrap().split_off(mark.files);
+    }
+
+    /// Links the program split off the module to a copy of the code of the module
+    fn link(&self, mut program: Program, debug_info: bool) -> Bytecode {
+        let mut code = self.code.clone();
+        let objs = &mut code.objects;
+        objs.metas.append(&mut program.metas);
+        objs.functions.append(&mut program.functions);
+        objs.packages.append(&mut program.packages);
+        objs.functions[code.entry] = program.entry;
+        code.main_pkg = program.main_pkg;
+        code.consts.append(&mut program.consts);
+        code.ifaces.append(&mut program.ifaces);
+        code.indices.append(&mut program.indices);
+        match debug_info {
+            true => code.file_set.as_mut().unwrap().append(program.files),
+            false => code.file_set = None,
+        }
+        code
+    }
+
+    /// Generates the packages, every one after the ones it imports, and then the
+    /// entry function calling the given function of a package, on top of the code
+    /// generated before. The packages loaded from a cache are added instead.
+    pub(crate) fn gen(&mut self, packages: &[TCPackageKey], entry: Option<(PackageKey, IdentKey)>) {
+        for tcpkg in self.import_order(packages) {
+            #[cfg(feature = "serde_borsh")]
+            {
+                let span = Span::start(&self.code);
+                match self.loaded.remove(&tcpkg) {
+                    Some(loaded) => self.add_loaded(tcpkg, loaded),
+                    None => self.gen_package(tcpkg),
+                }
+                self.spans.insert(tcpkg, span.end(&self.code));
+            }
+            #[cfg(not(feature = "serde_borsh"))]
+            self.gen_package(tcpkg);
+        }
+        if entry.is_some() {
+            self.gen_code(&[], entry);
+        }
+    }
+
+    /// The packages sorted so that every one is after the ones it imports
+    pub(crate) fn import_order(&self, packages: &[TCPackageKey]) -> Vec<TCPackageKey> {
+        fn visit(
+            pkg: TCPackageKey,
+            packages: &[TCPackageKey],
+            tc_objs: &TCObjects,
+            order: &mut Vec<TCPackageKey>,
+        ) {
+            if order.contains(&pkg) || !packages.contains(&pkg) {
+                return;
+            }
+            for imp in tc_objs.pkgs[pkg].imports().iter() {
+                visit(*imp, packages, tc_objs, order);
+            }
+            order.push(pkg);
+        }
+        let mut order = vec![];
+        for pkg in packages.iter() {
+            visit(*pkg, packages, &self.tc_objs, &mut order);
+        }
+        order
+    }
+
+    /// Generates a package. Its code only refers to the metadata, consts, interface
+    /// bindings and struct indices generated for itself and the packages it imports,
+    /// so that it can be moved to another bytecode with them.
+    fn gen_package(&mut self, tcpkg: TCPackageKey) {
+        let first_meta = MetadataKey::from(self.code.objects.metas.vec().len());
+        self.iface_selector.reset();
+        self.struct_selector.reset();
+        self.gen_code(&[tcpkg], None);
+        // the metadata of the types owned by other packages is created again by
+        // every package that needs it
+        let owned = RangeMap::flatten(&Owned::of(tcpkg, &self.results).types);
+        self.type_cache
+            .retain(|k, m| m.key < first_meta || owned.contains(k.as_usize()));
+    }
+
+    /// Generates the packages, and the entry function calling the given function of
+    /// a package, on top of the code generated before. The consts of the new code go
+    /// after the existing ones, and only the new interface bindings and struct
+    /// indices are added.
+    fn gen_code(&mut self, packages: &[TCPackageKey], entry: Option<(PackageKey, IdentKey)>) {
+        let vm_objs = std::mem::replace(&mut self.code.objects, VMObjects::new());
+        let mut vmctx = CodeGenVMCtx::new(vm_objs);
+        let consts = Consts::new();
+        let mut branch_helper = BranchHelper::new();
+        let mut result_funcs = vec![];
+
+        for tcpkg in packages.iter() {
+            let name = self.tc_objs.pkgs[*tcpkg].name().clone().unwrap();
+            let pkey = vmctx.packages_mut().insert(PackageObj::new(name));
+            self.pkg_map.insert(*tcpkg, pkey);
+        }
+
+        if let Some((pkg, func_ident)) = entry {
+            let f = gen_entry_func(&mut vmctx, &consts, self.code.entry, pkg, func_ident);
+            result_funcs.push(f);
+            self.code.main_pkg = pkg;
+        }
+
+        for tcpkg in packages.iter() {
+            let ti = &self.results[tcpkg];
+            let mut pkg_helper = PkgHelper::new(&self.ast_objs, &self.tc_objs, &self.pkg_map);
+            let cgen = CodeGen::new(
+                &mut vmctx,
+                &consts,
+                &self.ast_objs,
+                &self.tc_objs,
+                ti,
+                &mut self.type_cache,
+                &mut self.iface_selector,
+                &mut self.struct_selector,
+                &mut branch_helper,
+                &mut pkg_helper,
+                self.pkg_map[tcpkg],
+                self.blank_ident,
+            );
+            result_funcs.append(&mut cgen.gen_with_files(&ti.ast_files, *tcpkg));
+        }
+
+        let (mut new_consts, cst_map) = consts.get_runtime_consts(&mut vmctx);
+        let offset = self.code.consts.len();
+        let cst_map = cst_map.into_iter().map(|(i, j)| (i, j + offset)).collect();
+        self.code.consts.append(&mut new_consts);
+        for f in result_funcs.into_iter() {
+            f.into_runtime_func(
+                &self.ast_objs,
+                &self.tc_objs,
+                &mut vmctx,
+                branch_helper.labels(),
+                &cst_map,
+            );
+        }
+
+        let dummy_ti = TypeInfo::new();
+        let mut lookup = TypeLookup::new(&self.tc_objs, &dummy_ti, &mut self.type_cache);
+        let ifaces = &mut self.code.ifaces;
+        for x in self.iface_selector.keys()[ifaces.len()..].iter() {
+            let (meta, binding) = lookup.iface_binding_info(*x, &mut vmctx);
+            ifaces.push((meta, binding.into_iter().map(|x| x.into()).collect()));
+        }
+        let indices = &mut self.code.indices;
+        indices.extend_from_slice(&self.struct_selector.keys()[indices.len()..]);
+
+        self.code.objects = vmctx.into_vmo();
+    }
 }
 
-// generate the entry function for Bytecode
+// generate the code of the entry function, which imports the package and calls
+// the function
 fn gen_entry_func<'a, 'c>(
     vmctx: &'a mut CodeGenVMCtx,
     consts: &'c Consts,
+    fkey: FunctionKey,
     pkg: PackageKey,
-    main_ident: IdentKey,
+    func_ident: IdentKey,
 ) -> FuncCtx<'c> {
-    let fmeta = vmctx.prim_meta().default_sig;
-    let fobj = vmctx.function_with_meta(None, fmeta.clone(), FuncFlag::Default);
-    let fkey = *fobj.as_function();
     let mut fctx = FuncCtx::new(fkey, None, consts);
     fctx.emit_import(pkg, None);
     let pkg_addr = fctx.add_package(pkg);
-    let index = Addr::PkgMemberIndex(pkg, main_ident);
+    let index = Addr::PkgMemberIndex(pkg, func_ident);
     fctx.emit_load_pkg(Addr::Regsiter(0), pkg_addr, index, None);
     fctx.emit_call(Addr::Regsiter(0), 0, CallStyle::Default, None);
     fctx.emit_return(None, None, vmctx.functions());
@@ -7,8 +7,11 @@
 //! # Feature
 //! - `async`: Channel and goroutine support
 //! - `btree_map`: Make it use BTreeMap instead of HashMap
+//! - `serde_borsh`: Caching the packages with `parse_check_gen_cached`
 
 mod branch;
+#[cfg(feature = "serde_borsh")]
+mod cache;
 mod consts;
 mod context;
 //mod emit;
@@ -18,5 +21,7 @@ mod codegen;
 mod entry;
 mod types;
 
-pub use entry::parse_check_gen;
+#[cfg(feature = "serde_borsh")]
+pub use cache::{parse_check_gen_cached, PackageStore};
+pub use entry::{parse_check_gen, Module};
 pub use go_types::{SourceRead, TraceConfig};
@@ -8,7 +8,7 @@ extern crate go_engine as engine;
 use std::path::{Path, PathBuf};
 
 #[cfg(feature = "go_std")]
-fn run(path: &str, trace: bool) -> Result<(), engine::ErrorList> {
+fn run(path: &str, trace: bool) -> Result<(), engine::EngineError> {
     let mut cfg = engine::Config::default();
     cfg.trace_parser = trace;
     cfg.trace_checker = trace;
@@ -17,7 +17,7 @@ fn run(path: &str, trace: bool) -> Result<(), engine::ErrorList> {
 }
 
 #[cfg(not(feature = "go_std"))]
-fn run(_path: &str, _trace: bool) -> Result<(), engine::ErrorList> {
+fn run(_path: &str, _trace: bool) -> Result<(), engine::EngineError> {
     unimplemented!()
 }
 
@@ -0,0 +1,169 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! The Go packages generated from the signatures of the FFIs, so that a Rust
+//! module registered as an FFI can be imported without a hand-written Go stub.
+
+use crate::engine::{ImportKey, SourceRead};
+use crate::vfs::{vfs_map::VfsMap, VirtualFs};
+use go_parser::Map;
+use go_vm::FfiSignature;
+use std::borrow::Cow;
+use std::fmt::Write;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const BINDINGS_DIR: &str = "__ffi_bindings__";
+
+/// Generates the Go package wrapping the FFI `name`: the interface declaration of
+/// the FFI and an exported function for each of its functions, e.g. `add` of
+/// "mymod" becomes `mymod.Add`.
+pub(crate) fn go_binding(name: &str, sigs: &[FfiSignature]) -> String {
+    let pkg = name.rsplit('/').next().unwrap();
+    let iface = format!("ffi{}", export_name(pkg));
+    let mut src = String::new();
+    writeln!(
+        src,
+        "// Code generated from the FFI {:?}. DO NOT EDIT.\n",
+        name
+    )
+    .unwrap();
+    writeln!(src, "package {}\n", pkg).unwrap();
+    writeln!(src, "type {} interface {{", iface).unwrap();
+    for sig in sigs.iter() {
+        writeln!(src, "\t{}{}", sig.name, go_func_type(sig)).unwrap();
+    }
+    writeln!(src, "}}\n").unwrap();
+    writeln!(src, "var inst = ffi({}, {:?})", iface, name).unwrap();
+    for sig in sigs.iter() {
+        let args: Vec<String> = (0..sig.params.len()).map(|i| format!("p{}", i)).collect();
+        let call = format!("inst.{}({})", sig.name, args.join(", "));
+        writeln!(
+            src,
+            "\nfunc {}{} {{",
+            export_name(sig.name),
+            go_func_type(sig)
+        )
+        .unwrap();
+        match sig.results.len() {
+            0 => writeln!(src, "\t{}", call).unwrap(),
+            1 => writeln!(src, "\treturn {}", call).unwrap(),
+            // returning a multi-value FFI call directly is not supported by codegen
+            n => {
+                let rets: Vec<String> = (0..n).map(|i| format!("r{}", i)).collect();
+                writeln!(src, "\t{} := {}", rets.join(", "), call).unwrap();
+                writeln!(src, "\treturn {}", rets.join(", ")).unwrap();
+            }
+        }
+        writeln!(src, "}}").unwrap();
+    }
+    src
+}
+
+/// The parameters and the results of the signature in Go syntax
+fn go_func_type(sig: &FfiSignature) -> String {
+    let params: Vec<String> = sig
+        .params
+        .iter()
+        .enumerate()
+        .map(|(i, t)| format!("p{} {}", i, t))
+        .collect();
+    let results = match sig.results.len() {
+        0 => String::new(),
+        1 => format!(" {}", sig.results[0]),
+        _ => format!(" ({})", sig.results.join(", ")),
+    };
+    format!("({}){}", params.join(", "), results)
+}
+
+/// "async_fetch" -> "AsyncFetch"
+fn export_name(name: &str) -> String {
+    name.split('_')
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            let mut chars = s.chars();
+            let first = chars.next().unwrap();
+            first.to_uppercase().chain(chars).collect::<String>()
+        })
+        .collect()
+}
+
+/// A `SourceRead` that serves the generated packages and reads everything else
+/// from `inner`. The generated packages take precedence over the library packages
+/// with the same import paths.
+pub(crate) struct BindingReader<'a, S: SourceRead> {
+    inner: &'a S,
+    vfs: VfsMap,
+}
+
+impl<'a, S: SourceRead> BindingReader<'a, S> {
+    pub(crate) fn new(inner: &'a S, sigs: &Map<&'static str, Vec<FfiSignature>>) -> Self {
+        let files = sigs
+            .iter()
+            .map(|(name, sigs)| {
+                let pkg = name.rsplit('/').next().unwrap();
+                let path = Self::package_dir(name).join(format!("{}.gos", pkg));
+                (path, Cow::Owned(go_binding(name, sigs)))
+            })
+            .collect();
+        BindingReader {
+            inner,
+            vfs: VfsMap::new(files),
+        }
+    }
+
+    fn package_dir(name: &str) -> PathBuf {
+        Path::new(BINDINGS_DIR).join(name)
+    }
+
+    fn is_binding(path: &Path) -> bool {
+        path.starts_with(BINDINGS_DIR)
+    }
+}
+
+impl<'a, S: SourceRead> SourceRead for BindingReader<'a, S> {
+    fn working_dir(&self) -> &Path {
+        self.inner.working_dir()
+    }
+
+    fn base_dir(&self) -> Option<&Path> {
+        self.inner.base_dir()
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+        match Self::is_binding(path) {
+            true => self.vfs.read_file(path),
+            false => self.inner.read_file(path),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        match Self::is_binding(path) {
+            true => self.vfs.read_dir(path),
+            false => self.inner.read_dir(path),
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        match Self::is_binding(path) {
+            true => self.vfs.is_file(path),
+            false => self.inner.is_file(path),
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        match Self::is_binding(path) {
+            true => self.vfs.is_dir(path),
+            false => self.inner.is_dir(path),
+        }
+    }
+
+    fn canonicalize_import(&self, key: &ImportKey) -> io::Result<(PathBuf, String)> {
+        let dir = Self::package_dir(&key.path);
+        match !key.path.starts_with('.') && self.vfs.read_dir(&dir).is_ok() {
+            true => Ok((dir, key.path.clone())),
+            false => self.inner.canonicalize_import(key),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! A cache of the packages programs are built from, in a directory.
+//!
+//! Every package is identified by a hash of its import path and its files, and is
+//! keyed by that hash combined with the keys of the packages it imports, so the key
+//! of a package changes whenever any package it's built from does.
+//! The cache directory holds two kinds of files:
+//! - `<source hash>.imports`: the packages imported by the package with the source
+//!   hash, one `path<TAB>dir` import key per line. This is what allows working out
+//!   the key of a package without parsing it.
+//! - `<package key>.gosp`: the type info and the code generated for the package,
+//!   see `go_codegen::parse_check_gen_cached`.
+//!
+//! A program that changes only has its changed packages, and the ones importing
+//! them, checked and generated again. The cache is best effort, a file that's
+//! missing, unreadable or out of date results in compiling the package, which
+//! rewrites it.
+
+use crate::engine::{ImportKey, SourceRead};
+use go_codegen as cg;
+use go_parser::{ErrorList, Map};
+use go_types::{PackageSource, TraceConfig};
+use go_vm::Bytecode;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub(crate) struct CompileCache {
+    dir: PathBuf,
+}
+
+impl CompileCache {
+    pub fn new(dir: PathBuf) -> CompileCache {
+        CompileCache { dir }
+    }
+
+    /// Compiles the program at `path`, the packages cached are loaded instead of
+    /// compiled, and the ones compiled are cached.
+    pub fn compile<S: SourceRead>(
+        &self,
+        reader: &S,
+        path: &Path,
+        tconfig: &TraceConfig,
+        debug_info: bool,
+    ) -> Result<Bytecode, ErrorList> {
+        let mut store = PackageFiles {
+            dir: &self.dir,
+            reader,
+            keys: Map::new(),
+        };
+        cg::parse_check_gen_cached(path, tconfig, reader, debug_info, &mut store)
+    }
+}
+
+/// The files of the packages in the cache directory
+struct PackageFiles<'a, S: SourceRead> {
+    dir: &'a Path,
+    reader: &'a S,
+    /// the keys of the packages worked out so far, by import path
+    keys: Map<String, u64>,
+}
+
+impl<'a, S: SourceRead> PackageFiles<'a, S> {
+    /// Works out the key of the package, the keys of the packages it imports have
+    /// to be worked out before
+    fn package_key(&mut self, source: &PackageSource) -> Option<u64> {
+        let mut hash = fnv1a(
+            FNV_OFFSET,
+            &source_hash(&source.import_path, &source.files).to_le_bytes(),
+        );
+        for imp in source.imports.iter() {
+            let dep = match imp.path.as_str() {
+                "unsafe" => fnv1a(FNV_OFFSET, imp.path.as_bytes()),
+                _ => {
+                    let (_, import_path) = self.reader.canonicalize_import(imp).ok()?;
+                    *self.keys.get(&import_path)?
+                }
+            };
+            hash = fnv1a(hash, &dep.to_le_bytes());
+        }
+        self.keys.insert(source.import_path.clone(), hash);
+        Some(hash)
+    }
+
+    fn package_file(&self, key: u64) -> PathBuf {
+        // the compiler itself is part of the key, as its output may change
+        // without changing the encoding
+        let hash = fnv1a(key, env!("CARGO_PKG_VERSION").as_bytes());
+        self.file(hash, "gosp")
+    }
+
+    fn file(&self, hash: u64, ext: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.{}", hash, ext))
+    }
+}
+
+impl<'a, S: SourceRead> cg::PackageStore for PackageFiles<'a, S> {
+    fn imports(&mut self, import_path: &str, files: &[(String, String)]) -> Option<Vec<ImportKey>> {
+        let src = source_hash(import_path, files);
+        let imports = fs::read_to_string(self.file(src, "imports")).ok()?;
+        parse_imports(&imports)
+    }
+
+    fn read(&mut self, source: &PackageSource) -> Option<Vec<u8>> {
+        let key = self.package_key(source)?;
+        fs::read(self.package_file(key)).ok()
+    }
+
+    fn write(&mut self, source: &PackageSource, artifact: &[u8]) -> io::Result<()> {
+        let key = self
+            .package_key(source)
+            .ok_or_else(|| io::Error::other("the imported packages are not cached"))?;
+        fs::create_dir_all(self.dir)?;
+        let src = source_hash(&source.import_path, &source.files);
+        let imports: String = source
+            .imports
+            .iter()
+            .map(|k| format!("{}\t{}\n", k.path, k.dir))
+            .collect();
+        write_file(&self.file(src, "imports"), imports.as_bytes())?;
+        write_file(&self.package_file(key), artifact)
+    }
+}
+
+fn source_hash(import_path: &str, files: &[(String, String)]) -> u64 {
+    let mut hash = fnv1a(FNV_OFFSET, import_path.as_bytes());
+    for (name, content) in files.iter() {
+        hash = fnv1a(hash, &(name.len() as u64).to_le_bytes());
+        hash = fnv1a(hash, name.as_bytes());
+        hash = fnv1a(hash, &(content.len() as u64).to_le_bytes());
+        hash = fnv1a(hash, content.as_bytes());
+    }
+    hash
+}
+
+fn parse_imports(content: &str) -> Option<Vec<ImportKey>> {
+    content
+        .lines()
+        .map(|line| {
+            line.split_once('\t')
+                .map(|(path, dir)| ImportKey::new(path, dir))
+        })
+        .collect()
+}
+
+/// Writes to a temporary file first, so that a reader never sees a partial file
+fn write_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)
+}
+
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is stable across Rust versions
+fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    data.iter()
+        .fold(hash, |h, b| (h ^ *b as u64).wrapping_mul(0x100000001b3))
+}
@@ -2,16 +2,27 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use crate::error::EngineError;
 use crate::ffi::Ffi;
-#[cfg(feature = "go_std")]
-use crate::std::os;
-#[cfg(feature = "serde_borsh")]
-use borsh::BorshSerialize;
+use std::any::Any;
+#[cfg(feature = "stats")]
+use std::io::Write;
 use std::path::Path;
+#[cfg(all(feature = "codegen", feature = "serde_borsh"))]
+use std::path::PathBuf;
 use std::rc::Rc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::mpsc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::thread;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
 
 #[cfg(feature = "codegen")]
-pub use {cg::SourceRead, types::ImportKey};
+pub use {
+    cg::{Module, SourceRead},
+    types::ImportKey,
+};
 #[cfg(feature = "codegen")]
 extern crate go_codegen as cg;
 #[cfg(feature = "codegen")]
@@ -32,10 +43,23 @@ pub struct Config {
     pub std_out: Option<Box<dyn std::io::Write + Sync + Send>>,
     /// custom std err
     pub std_err: Option<Box<dyn std::io::Write + Sync + Send>>,
+    /// max number of instructions to execute, no limit if None
+    pub instruction_budget: Option<u64>,
+    /// max time to run, no limit if None, not available on wasm
+    #[cfg(not(target_arch = "wasm32"))]
+    pub timeout: Option<Duration>,
+    /// approximate max number of bytes the script may allocate, no limit if None
+    pub memory_limit: Option<usize>,
 }
 
 pub struct Engine {
     ffi: vm::FfiFactory,
+    instruction_budget: Option<u64>,
+    #[cfg(not(target_arch = "wasm32"))]
+    timeout: Option<Duration>,
+    memory_limit: Option<usize>,
+    #[cfg(all(feature = "codegen", feature = "serde_borsh"))]
+    cache: Option<crate::cache::CompileCache>,
 }
 
 impl Engine {
@@ -44,6 +68,12 @@ impl Engine {
         {
             Engine {
                 ffi: vm::FfiFactory::new(),
+                instruction_budget: None,
+                #[cfg(not(target_arch = "wasm32"))]
+                timeout: None,
+                memory_limit: None,
+                #[cfg(all(feature = "codegen", feature = "serde_borsh"))]
+                cache: None,
             }
         }
 
@@ -51,17 +81,31 @@ impl Engine {
         {
             let mut e = Engine {
                 ffi: vm::FfiFactory::new(),
+                instruction_budget: None,
+                #[cfg(not(target_arch = "wasm32"))]
+                timeout: None,
+                memory_limit: None,
+                #[cfg(all(feature = "codegen", feature = "serde_borsh"))]
+                cache: None,
             };
             crate::std::register(&mut e.ffi);
             e
         }
     }
 
-    pub fn with_user_data(data: usize) -> Engine {
+    /// Creates an engine with some host state that the FFIs can reach through
+    /// `FfiCtx::user_data`.
+    pub fn with_user_data(data: Rc<dyn Any>) -> Engine {
         #[cfg(not(feature = "go_std"))]
         {
             Engine {
                 ffi: vm::FfiFactory::with_user_data(data),
+                instruction_budget: None,
+                #[cfg(not(target_arch = "wasm32"))]
+                timeout: None,
+                memory_limit: None,
+                #[cfg(all(feature = "codegen", feature = "serde_borsh"))]
+                cache: None,
             }
         }
 
@@ -69,6 +113,12 @@ impl Engine {
         {
             let mut e = Engine {
                 ffi: vm::FfiFactory::with_user_data(data),
+                instruction_budget: None,
+                #[cfg(not(target_arch = "wasm32"))]
+                timeout: None,
+                memory_limit: None,
+                #[cfg(all(feature = "codegen", feature = "serde_borsh"))]
+                cache: None,
             };
             crate::std::register(&mut e.ffi);
             e
@@ -82,13 +132,80 @@ impl Engine {
         std_out: Option<Box<dyn std::io::Write + Sync + Send>>,
         std_err: Option<Box<dyn std::io::Write + Sync + Send>>,
     ) {
-        os::set_std_io(std_in, std_out, std_err);
+        self.ffi.set_std_io(vm::StdIoApi {
+            std_in,
+            std_out,
+            std_err,
+        });
+    }
+
+    /// Limits the number of instructions the VMs created by this engine may execute.
+    /// When the budget runs out, `try_run_bytecode` stops with `CallError::BudgetExhausted`,
+    /// while a `Vm` suspends the call and it can be resumed after adding more budget.
+    pub fn set_instruction_budget(&mut self, budget: Option<u64>) {
+        self.instruction_budget = budget;
+    }
+
+    /// Limits the time `try_run_bytecode` and `run_vm` may take, the run is interrupted
+    /// when it's over.
+    /// It uses a timer thread, so it's not available on targets without threads, like wasm.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Limits the memory the VMs created by this engine may allocate, see
+    /// `GcContainer::set_memory_limit`.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        self.memory_limit = limit;
+    }
+
+    /// Caches the packages the compiled programs are built from in `dir`, so that
+    /// compiling a program again only loads the packages that haven't changed
+    /// since, and compiles the rest.
+    #[cfg(all(feature = "codegen", feature = "serde_borsh"))]
+    pub fn set_cache_dir(&mut self, dir: Option<PathBuf>) {
+        self.cache = dir.map(crate::cache::CompileCache::new);
+    }
+
+    /// Replaces the host state that the FFIs can reach through `FfiCtx::user_data`.
+    pub fn set_user_data(&mut self, data: Option<Rc<dyn Any>>) {
+        self.ffi.set_user_data(data);
     }
 
     pub fn register_extension(&mut self, name: &'static str, proto: Rc<dyn Ffi>) {
         self.ffi.register(name, proto);
     }
 
+    /// Registers an FFI along with the Go signatures of its functions, so that
+    /// the Go code can import it as a package, like `import "mymod"`, without
+    /// a Go stub. `#[ffi_impl]` generates `auto_gen_ffi_signatures` for this.
+    pub fn register_package(
+        &mut self,
+        name: &'static str,
+        proto: Rc<dyn Ffi>,
+        signatures: Vec<vm::FfiSignature>,
+    ) {
+        self.ffi.register_with_signatures(name, proto, signatures);
+    }
+
+    /// Registers a closure as an FFI function, like `register_fn("mymod.add", |a: i64, b: i64| a + b)`,
+    /// see `FfiFactory::register_fn`.
+    pub fn register_fn<Args>(&mut self, name: &'static str, f: impl vm::IntoFfiFn<Args>) {
+        self.ffi.register_fn(name, f);
+    }
+
+    /// Registers a closure returning a future as an async FFI function,
+    /// see `FfiFactory::register_async_fn`.
+    #[cfg(feature = "async")]
+    pub fn register_async_fn<Args>(
+        &mut self,
+        name: &'static str,
+        f: impl vm::IntoAsyncFfiFn<Args>,
+    ) {
+        self.ffi.register_async_fn(name, f);
+    }
+
     #[cfg(feature = "codegen")]
     pub fn compile<S: SourceRead>(
         &self,
@@ -102,7 +219,71 @@ impl Engine {
             trace_parser,
             trace_checker,
         };
-        cg::parse_check_gen(path, &cfg, reader, debug_info)
+        let reader = crate::bindings::BindingReader::new(reader, self.ffi.go_signatures());
+        #[cfg(feature = "serde_borsh")]
+        if let Some(cache) = &self.cache {
+            return cache.compile(&reader, path, &cfg, debug_info);
+        }
+        cg::parse_check_gen(path, &cfg, &reader, debug_info)
+    }
+
+    /// Parses, type checks and generates the packages with the import paths once, like
+    /// `&["fmt", "strings"]`, so that programs can be compiled against them with
+    /// `compile_with_module`.
+    #[cfg(feature = "codegen")]
+    pub fn compile_module<S: SourceRead>(
+        &self,
+        reader: &S,
+        paths: &[&str],
+        trace_parser: bool,
+        trace_checker: bool,
+    ) -> Result<Module, parser::ErrorList> {
+        let cfg = types::TraceConfig {
+            trace_parser,
+            trace_checker,
+        };
+        let reader = crate::bindings::BindingReader::new(reader, self.ffi.go_signatures());
+        Module::new(paths, &cfg, &reader)
+    }
+
+    /// Compiles the program like `compile`, but the packages it imports from the
+    /// module are not parsed, type checked and generated again.
+    #[cfg(feature = "codegen")]
+    pub fn compile_with_module<S: SourceRead>(
+        &self,
+        module: &Module,
+        reader: &S,
+        path: &Path,
+        debug_info: bool,
+        trace_parser: bool,
+        trace_checker: bool,
+    ) -> Result<vm::Bytecode, parser::ErrorList> {
+        let cfg = types::TraceConfig {
+            trace_parser,
+            trace_checker,
+        };
+        let reader = crate::bindings::BindingReader::new(reader, self.ffi.go_signatures());
+        module.parse_check_gen(path, &cfg, &reader, debug_info)
+    }
+
+    /// Wraps `reader` so that it serves the Go packages of the FFIs as well
+    #[cfg(feature = "codegen")]
+    pub(crate) fn binding_reader<'a, S: SourceRead>(
+        &self,
+        reader: &'a S,
+    ) -> crate::bindings::BindingReader<'a, S> {
+        crate::bindings::BindingReader::new(reader, self.ffi.go_signatures())
+    }
+
+    /// Returns the Go package generated for the FFI `name`, which the Go code
+    /// imports with `import "name"`. Only the FFIs registered with `register_fn`
+    /// or `register_package` have one.
+    #[cfg(feature = "codegen")]
+    pub fn go_binding(&self, name: &str) -> Option<String> {
+        self.ffi
+            .go_signatures()
+            .get(name)
+            .map(|sigs| crate::bindings::go_binding(name, sigs))
     }
 
     #[cfg(all(feature = "codegen", feature = "serde_borsh"))]
@@ -113,15 +294,128 @@ impl Engine {
         debug_info: bool,
         trace_parser: bool,
         trace_checker: bool,
-    ) -> Result<Vec<u8>, parser::ErrorList> {
-        self.compile(reader, path, debug_info, trace_parser, trace_checker)
-            .map(|code| code.try_to_vec().unwrap())
+    ) -> Result<Vec<u8>, EngineError> {
+        let code = self.compile(reader, path, debug_info, trace_parser, trace_checker)?;
+        self.encode_bytecode(&code).map_err(EngineError::Bytecode)
     }
 
+    /// Encodes the bytecode to be loaded with `load_bytecode`, the header records
+    /// how the FFIs it needs are registered here.
+    #[cfg(feature = "serde_borsh")]
+    pub fn encode_bytecode(&self, code: &vm::Bytecode) -> Result<Vec<u8>, vm::BytecodeError> {
+        code.encode(&self.ffi)
+    }
+
+    /// Loads the bytecode returned by `compile_serialize`. It fails if the bytecode
+    /// is built by an incompatible go-vm, if it needs FFIs not registered here, or
+    /// registered with other Go signatures than where it's encoded.
+    #[cfg(feature = "serde_borsh")]
+    pub fn load_bytecode(&self, data: &[u8]) -> Result<vm::Bytecode, vm::BytecodeError> {
+        vm::Bytecode::decode(data, &self.ffi)
+    }
+
+    /// Runs the program and returns the unrecovered panic if there is one.
+    #[deprecated(
+        note = "use `try_run_bytecode`, this one doesn't report running out of budget, being interrupted or deadlocks"
+    )]
     pub fn run_bytecode(&self, bc: &vm::Bytecode) -> Option<vm::PanicData> {
-        vm::run(bc, &self.ffi)
+        match self.try_run_bytecode(bc) {
+            Err(vm::CallError::Panic(p)) => Some(p),
+            _ => None,
+        }
+    }
+
+    /// Runs the program, with the `stats` feature a report of what it has
+    /// executed is written to the stderr of the engine afterwards.
+    pub fn try_run_bytecode(&self, bc: &vm::Bytecode) -> Result<(), vm::CallError> {
+        let vm = self.new_vm(bc);
+        let result = self.run_vm(&vm);
+        #[cfg(feature = "stats")]
+        self.print_stats(&vm);
+        result
+    }
+
+    /// Writes the report of what the VM has executed to the stderr set with
+    /// `set_std_io`, or to the stderr of the process.
+    #[cfg(feature = "stats")]
+    pub fn print_stats(&self, vm: &vm::Vm) {
+        let report = vm.stats().to_string();
+        match &mut self.ffi.std_io().borrow_mut().std_err {
+            Some(w) => {
+                let _ = w.write_all(report.as_bytes());
+            }
+            None => eprint!("{}", report),
+        }
+    }
+
+    /// Runs `main.main` on a VM created by `new_vm`, with the timeout of the engine.
+    /// The host can stop it with the handle of the VM, `Vm::cancellation_handle`,
+    /// which stays cancelled after a timeout, until it's reset.
+    pub fn run_vm(&self, vm: &vm::Vm) -> Result<(), vm::CallError> {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(timeout) = self.timeout {
+            let (done, timer) = Engine::start_timer(timeout, vm.cancellation_handle());
+            let result = vm.run_main();
+            drop(done);
+            timer.join().unwrap();
+            return result;
+        }
+        vm.run_main()
+    }
+
+    /// Creates a VM that keeps the state of the program alive, so that the exported
+    /// Go functions can be called repeatedly with `Vm::call`.
+    /// Every VM has its own cancellation handle.
+    pub fn new_vm<'a>(&'a self, bc: &'a vm::Bytecode) -> vm::Vm<'a> {
+        self.new_vm_with_gc(bc, vm::gc::GcContainer::new())
+    }
+
+    /// Same as `new_vm`, but the VM allocates in `gcc`, see `Vm::with_gc_container`.
+    pub(crate) fn new_vm_with_gc<'a>(
+        &'a self,
+        bc: &'a vm::Bytecode,
+        gcc: vm::gc::GcContainer,
+    ) -> vm::Vm<'a> {
+        let vm = vm::Vm::with_gc_container(bc, &self.ffi, gcc);
+        vm.set_instruction_budget(self.instruction_budget);
+        vm.gc_container().set_memory_limit(self.memory_limit);
+        vm
+    }
+
+    /// Starts a thread that cancels the run when `timeout` is reached, unless the
+    /// returned sender is dropped before that.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn start_timer(
+        timeout: Duration,
+        cancel: vm::CancellationHandle,
+    ) -> (mpsc::Sender<()>, thread::JoinHandle<()>) {
+        let (done, wait) = mpsc::channel::<()>();
+        let timer = thread::spawn(move || {
+            if let Err(mpsc::RecvTimeoutError::Timeout) = wait.recv_timeout(timeout) {
+                cancel.cancel();
+            }
+        });
+        (done, timer)
     }
 
+    /// Compiles and runs the source, like `run_source`, but returns the panics
+    /// too instead of printing them.
+    #[cfg(feature = "codegen")]
+    pub fn try_run_source<S: SourceRead>(
+        &self,
+        trace_parser: bool,
+        trace_checker: bool,
+        reader: &S,
+        path: &Path,
+    ) -> Result<(), EngineError> {
+        let code = self.compile(reader, path, true, trace_parser, trace_checker)?;
+        self.try_run_bytecode(&code)
+            .map_err(|e| EngineError::from_call_error(e, &code))
+    }
+
+    /// Compiles and runs the source. An unrecovered panic is passed to
+    /// `panic_handler`, or printed without one, the other runtime errors, like
+    /// running out of budget, are returned.
     #[cfg(feature = "codegen")]
     pub fn run_source<S: SourceRead>(
         &self,
@@ -130,29 +424,30 @@ impl Engine {
         reader: &S,
         path: &Path,
         panic_handler: Option<Rc<dyn Fn(String, String)>>,
-    ) -> Result<(), parser::ErrorList> {
-        self.compile(reader, path, true, trace_parser, trace_checker)
-            .map(|code| {
-                // let mut decoded;
-                // #[cfg(feature = "serde_borsh")]
-                // {
-                //     let encoded = code.try_to_vec().unwrap();
-                //     decoded = go_vm::Bytecode::try_from_slice(&encoded).unwrap();
-                // }
-                // #[cfg(not(feature = "serde_borsh"))]
-                // {
-                //     decoded = code;
-                // }
-                let pdata = vm::run(&code, &self.ffi);
-                if let Some(pdata) = pdata {
-                    let call_stack = vm::CallStackDisplay::new(&pdata, &code);
-                    if let Some(handler) = panic_handler {
-                        handler(format!("{}", pdata.msg), format!("{}", call_stack));
-                    } else {
-                        eprintln!("{}\n", pdata.msg);
-                        eprintln!("{}\n", call_stack);
-                    }
+    ) -> Result<(), EngineError> {
+        let code = self.compile(reader, path, true, trace_parser, trace_checker)?;
+        // let mut decoded;
+        // #[cfg(feature = "serde_borsh")]
+        // {
+        //     let encoded = code.try_to_vec().unwrap();
+        //     decoded = go_vm::Bytecode::try_from_slice(&encoded).unwrap();
+        // }
+        // #[cfg(not(feature = "serde_borsh"))]
+        // {
+        //     decoded = code;
+        // }
+        match self.try_run_bytecode(&code) {
+            Err(vm::CallError::Panic(pdata)) => {
+                let call_stack = vm::CallStackDisplay::new(&pdata, &code);
+                if let Some(handler) = panic_handler {
+                    handler(format!("{}", pdata.msg), format!("{}", call_stack));
+                } else {
+                    eprintln!("{}\n", pdata.msg);
+                    eprintln!("{}\n", call_stack);
                 }
-            })
+                Ok(())
+            }
+            result => result.map_err(|e| EngineError::from_call_error(e, &code)),
+        }
     }
 }
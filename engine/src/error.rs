@@ -0,0 +1,92 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+use crate::ErrorList;
+use go_vm::{Bytecode, CallError, StackFrame};
+use std::fmt;
+
+/// Everything that can go wrong when compiling and running a Go program.
+#[derive(Debug)]
+pub enum EngineError {
+    /// The source code failed to parse or type check.
+    Compile(ErrorList),
+    /// The Go code panicked and the panic was not recovered.
+    Panic {
+        msg: String,
+        call_stack: Vec<StackFrame>,
+    },
+    /// An FFI function returned an error, which turned into an unrecovered panic.
+    Ffi {
+        msg: String,
+        call_stack: Vec<StackFrame>,
+    },
+    /// The run was cancelled or timed out, the call stack is where it stopped.
+    Interrupted { call_stack: Vec<StackFrame> },
+    /// The instruction budget is used up.
+    BudgetExhausted,
+    /// All the goroutines are blocked.
+    Deadlock,
+    /// The function called by the host cannot be found, or the arguments don't match.
+    BadCall(String),
+    /// The bytecode cannot be encoded or loaded.
+    #[cfg(feature = "serde_borsh")]
+    Bytecode(go_vm::BytecodeError),
+}
+
+impl EngineError {
+    /// Converts the error of a run or a `Vm::call`, resolving the call stacks with
+    /// the debug info of `bc`.
+    pub fn from_call_error(err: CallError, bc: &Bytecode) -> EngineError {
+        match err {
+            CallError::Panic(data) => {
+                let msg = data.msg.to_string();
+                let call_stack = StackFrame::resolve(&data.call_stack, bc);
+                match data.ffi_error {
+                    true => EngineError::Ffi { msg, call_stack },
+                    false => EngineError::Panic { msg, call_stack },
+                }
+            }
+            CallError::Interrupted(call_stack) => EngineError::Interrupted {
+                call_stack: StackFrame::resolve(&call_stack, bc),
+            },
+            CallError::BudgetExhausted => EngineError::BudgetExhausted,
+            CallError::Deadlock => EngineError::Deadlock,
+            CallError::BadCall(msg) => EngineError::BadCall(msg),
+        }
+    }
+
+    /// The call stack of a panic or an interruption, innermost frame first.
+    pub fn call_stack(&self) -> Option<&[StackFrame]> {
+        match self {
+            EngineError::Panic { call_stack, .. }
+            | EngineError::Ffi { call_stack, .. }
+            | EngineError::Interrupted { call_stack } => Some(call_stack),
+            _ => None,
+        }
+    }
+}
+
+impl From<ErrorList> for EngineError {
+    fn from(el: ErrorList) -> Self {
+        EngineError::Compile(el)
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Compile(el) => write!(f, "{}", el),
+            EngineError::Panic { msg, .. } => write!(f, "panic: {}", msg),
+            EngineError::Ffi { msg, .. } => write!(f, "ffi error: {}", msg),
+            EngineError::Interrupted { .. } => f.write_str("interrupted"),
+            EngineError::BudgetExhausted => f.write_str("instruction budget exhausted"),
+            EngineError::Deadlock => f.write_str("all goroutines are asleep - deadlock!"),
+            EngineError::BadCall(msg) => write!(f, "bad call: {}", msg),
+            #[cfg(feature = "serde_borsh")]
+            EngineError::Bytecode(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
@@ -10,15 +10,15 @@
 //! # Example:
 //! ```
 //! use std::path::{Path, PathBuf};
-//! use go_engine::{Config, ErrorList, SourceReader, run};
+//! use go_engine::{Config, EngineError, SourceReader, run};
 //!
-//!fn run_file(path: &str, trace: bool) -> Result<(), ErrorList> {
+//!fn run_file(path: &str, trace: bool) -> Result<(), EngineError> {
 //!    let mut cfg = Config::default();
 //!    cfg.trace_parser = trace;
 //!    cfg.trace_checker = trace;
 //!    let sr = SourceReader::local_fs(PathBuf::from("../std/"), PathBuf::from("./"));
 //!    let result = run(cfg, &sr, Path::new(path), None);
-//!    if let Err(el) = &result {
+//!    if let Err(EngineError::Compile(el)) = &result {
 //!        el.sort();
 //!        eprint!("{}", el);
 //!    }
@@ -36,11 +36,25 @@
 //! - `codegen`: Enable codegen
 //! - `instruction_pos`: Add instruction position to bytecode for debugging
 //! - `serde_borsh`: Serde support for bytecode using Borsh
+//! - `serde`: Convert between Rust data and Go values with serde
+//! - `stats`: Count the executed instructions, clones and allocations, and write a report to stderr after running
+//! - `trace`: Execution tracing with `Vm::set_tracer`
 //! - `wasm`: Enable wasm support
 //!
 
 mod engine;
 
+#[cfg(feature = "codegen")]
+mod bindings;
+
+#[cfg(all(feature = "codegen", feature = "serde_borsh"))]
+mod cache;
+
+mod error;
+
+#[cfg(feature = "codegen")]
+mod repl;
+
 #[cfg(feature = "go_std")]
 mod std;
 
@@ -51,12 +65,14 @@ mod source;
 #[macro_use]
 pub mod ffi;
 
-#[cfg(feature = "go_std")]
-#[macro_use]
-extern crate lazy_static;
-
 pub use engine::*;
+pub use error::EngineError;
 pub use go_parser::{ErrorList, FileSet};
+pub use go_vm::{Bytecode, StackFrame};
+#[cfg(feature = "serde_borsh")]
+pub use go_vm::{BytecodeError, FeatureFlags};
+#[cfg(feature = "codegen")]
+pub use repl::Repl;
 pub use source::*;
 
 pub use crate::vfs::{compound::CompoundFs, vfs_map::VfsMap, VirtualFs};
@@ -0,0 +1,1117 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! An interactive session that evaluates Go code one input at a time.
+//!
+//! Every input becomes a package of its own, with the package scope nested in the
+//! one of the last input that ran, so that it sees what the earlier inputs declared
+//! and may declare the same names again. The packages are type checked, generated
+//! and linked into one `Module`, the bytecode of which keeps the values of the
+//! package members from one input to the next, so the earlier inputs never run
+//! again.
+//!
+//! The statements of an input run in a function of its package. The variables,
+//! constants and types declared at the top level of it are made package level
+//! declarations, which takes two passes: the first one type checks the input as it
+//! is, to get the types of the variables, the second one checks and generates the
+//! package with the declarations moved out of the function.
+
+use crate::engine::{Config, Engine, ImportKey, Module, SourceRead};
+use crate::error::EngineError;
+use crate::vfs::{vfs_map::VfsMap, VirtualFs};
+use crate::ErrorList;
+use go_parser::ast::{BlockStmt, Decl, Expr, Node, Spec, Stmt};
+use go_parser::{AstObjects, FilePos, FileSet, IdentKey, Map, Token};
+use go_types::check::TypeInfo;
+use go_types::{Displayer, EntityType, ObjKey, OperandMode, Package, TCObjects, TraceConfig};
+use go_vm::gc::GcContainer;
+use go_vm::types::{GosElem, GosValue, RuntimeResult};
+use go_vm::{Ffi, FfiCtx};
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::fmt::Write;
+#[cfg(feature = "async")]
+use std::future::Future;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "async")]
+use std::pin::Pin;
+use std::rc::Rc;
+
+/// The directory of the packages of the inputs
+const REPL_DIR: &str = "__repl__";
+/// The helper package the inputs import
+const HELPER: &str = "__repl";
+const HELPER_SOURCE: &str = r#"package __repl
+
+type ffiRepl interface {
+    show(a ...interface{})
+}
+
+var inst = ffi(ffiRepl, "__repl")
+
+func Show(a ...interface{}) {
+    inst.show(a...)
+}
+"#;
+/// The function of the package of an input that runs its statements
+const INPUT_FUNC: &str = "__input";
+/// The file name the positions in the current input are reported with
+const INPUT_NAME: &str = "input";
+/// The prefix of the aliases of the packages imported for the types of variables
+const ALIAS: &str = "__p";
+/// The prefix of what refers to an object of the earlier inputs that the input
+/// declares again
+const OUTER: &str = "__outer_";
+/// What the packages of the earlier inputs are written as in the types, it's not
+/// valid Go, so that the names after it are written again
+const OUTER_MARK: &str = "\0";
+
+/// An interactive session, like a REPL, on top of the parser, the type checker and codegen.
+///
+/// Every input is a statement list, an expression, an import or a declaration
+/// of functions and types. The variables, constants and types declared by the
+/// statements are kept for the inputs that come later. An input that fails to
+/// compile is dropped. One that fails to run is dropped too, but what it did before
+/// failing, like assigning to the variables of the earlier inputs, stays.
+///
+/// -- Note --
+/// The methods of a type can only be declared in the input that declares the type.
+/// The goroutines that are still blocked when an input is done are dropped.
+pub struct Repl<S: SourceRead> {
+    engine: Engine,
+    reader: S,
+    trace_parser: bool,
+    trace_checker: bool,
+    /// The helper package, the imports and the inputs of the session, generated
+    /// into one bytecode, which holds the values of the variables
+    module: Module,
+    /// Where the VMs allocate, the values left in the packages are there
+    gcc: GcContainer,
+    imports: Vec<Import>,
+    /// The package of the last input that ran, the next one is nested in it
+    last: String,
+    /// The number of packages made for the inputs, for naming them
+    count: usize,
+    qualifier: Rc<Qualifier>,
+    shown: Rc<RefCell<Option<String>>>,
+}
+
+impl<S: SourceRead> Repl<S> {
+    /// Starts a session, the library packages are read with `reader`.
+    pub fn new(config: Config, reader: S) -> Result<Repl<S>, ErrorList> {
+        let mut engine = Engine::new();
+        engine.set_instruction_budget(config.instruction_budget);
+        #[cfg(not(target_arch = "wasm32"))]
+        engine.set_timeout(config.timeout);
+        engine.set_memory_limit(config.memory_limit);
+        #[cfg(feature = "go_std")]
+        engine.set_std_io(config.std_in, config.std_out, config.std_err);
+        let shown = Rc::new(RefCell::new(None));
+        engine.register_extension(
+            HELPER,
+            Rc::new(ReplFfi {
+                shown: shown.clone(),
+            }),
+        );
+        // the package the first input is nested in
+        let root = input_path(0);
+        let mut module = engine.compile_module(
+            &ReplReader::new(&reader, &root, "package main\n".to_owned()),
+            &[HELPER, &root],
+            config.trace_parser,
+            config.trace_checker,
+        )?;
+        let qualifier = Rc::new(Qualifier {
+            aliases: RefCell::new(None),
+            current: Cell::new(std::ptr::null()),
+        });
+        let q = qualifier.clone();
+        module.set_fmt_qualifier(move |pkg| q.qualify(pkg));
+        Ok(Repl {
+            engine,
+            reader,
+            trace_parser: config.trace_parser,
+            trace_checker: config.trace_checker,
+            module,
+            gcc: GcContainer::new(),
+            imports: vec![],
+            last: root,
+            count: 0,
+            qualifier,
+            shown,
+        })
+    }
+
+    /// The engine that compiles and runs the inputs, e.g. to register FFIs.
+    pub fn engine_mut(&mut self) -> &mut Engine {
+        &mut self.engine
+    }
+
+    /// Evaluates an input, returns the value if it's an expression that has one.
+    /// Multiple values, like the results of a call, are separated by ", ".
+    ///
+    /// The positions of the compile errors and of the call stacks are in the input,
+    /// with the file name "input".
+    pub fn eval(&mut self, input: &str) -> Result<Option<String>, EngineError> {
+        let input = input.trim_end();
+        match Input::parse(input) {
+            Input::Empty => Ok(None),
+            Input::Imports(imports) => self.add_imports(imports).map(|_| None),
+            Input::Decls => {
+                let source = Source {
+                    decls: input.to_owned(),
+                    ..Source::default()
+                };
+                let (path, lines) = self.compile(&source, &[])?;
+                self.run(path, lines)
+            }
+            Input::Expr { is_call: false } => {
+                let source = Source {
+                    body: format!("{}.Show({})", HELPER, input),
+                    ..Source::default()
+                };
+                let (path, lines) = self.compile(&source, &[])?;
+                self.run(path, lines)
+            }
+            Input::Expr { is_call: true } => {
+                // it fails to check as an argument if the call has no value, then
+                // the call is compiled as it is, which reports what's wrong with it
+                let source = Source {
+                    body: format!("{}.Show({})", HELPER, input),
+                    ..Source::default()
+                };
+                let count = self.check(&source, result_count).unwrap_or(0);
+                let body = match count {
+                    0 => input.to_owned(),
+                    1 => format!("{}.Show({})", HELPER, input),
+                    // passing the results of a call as the arguments of another is not supported by codegen
+                    n => {
+                        let vars: Vec<String> = (0..n).map(|i| format!("__v{}", i)).collect();
+                        let vars = vars.join(", ");
+                        format!("{} := {}\n{}.Show({})", vars, input, HELPER, vars)
+                    }
+                };
+                let source = Source {
+                    body,
+                    ..Source::default()
+                };
+                let (path, lines) = self.compile(&source, &[])?;
+                self.run(path, lines)
+            }
+            Input::Stmts(stmts) if stmts.is_empty() => {
+                let source = Source {
+                    body: input.to_owned(),
+                    ..Source::default()
+                };
+                let (path, lines) = self.compile(&source, &[])?;
+                self.run(path, lines).map(|_| None)
+            }
+            Input::Stmts(stmts) => self.eval_decl_stmts(input, &stmts).map(|_| None),
+        }
+    }
+
+    /// Evaluates statements that declare variables, constants or types at the top
+    /// level, they are made package level declarations.
+    fn eval_decl_stmts(&mut self, input: &str, stmts: &Stmts) -> Result<(), EngineError> {
+        let mut body = input.to_owned();
+        // the variables would not be used otherwise
+        for var in stmts.vars.iter() {
+            write!(body, "\n_ = {}", var).unwrap();
+        }
+        let source = Source {
+            body,
+            ..Source::default()
+        };
+        let len = input.chars().count();
+        let qualifier = self.qualifier.clone();
+        let checked = self.check(&source, |ast_objs, tc_objs, ti, start| {
+            Checked::new(ast_objs, tc_objs, ti, start..start + len, &qualifier)
+        })?;
+        if !checked.errors.is_empty() {
+            let chars: Vec<char> = input.chars().collect();
+            let el = ErrorList::new();
+            for (offset, msg) in checked.errors.into_iter() {
+                el.add(Some(input_pos(&chars, offset)), msg, false, false);
+            }
+            return Err(EngineError::Compile(el));
+        }
+        let aliases: Vec<Import> = checked
+            .aliases
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| Import {
+                spec: format!("{}{} {:?}", ALIAS, i, path),
+                path,
+            })
+            .collect();
+
+        // the package the input is nested in, it's the one with the declarations
+        // that refer to the objects the input declares again, if there are any
+        let outer = match checked.outer.is_empty() {
+            true => None,
+            false => {
+                let decls: Vec<&str> = checked.outer.iter().map(|(_, d)| d.as_str()).collect();
+                let source = Source {
+                    decls: decls.join("\n"),
+                    ..Source::default()
+                };
+                Some(self.compile(&source, &aliases)?.0)
+            }
+        };
+        let (body, hoisted) = stmts.rewrite(input, &checked.renames);
+        let mut tail = checked.vars.join("\n");
+        for decl in hoisted.iter() {
+            write!(tail, "\n{}", decl).unwrap();
+        }
+        let source = Source {
+            body,
+            tail,
+            ..Source::default()
+        };
+        let last = outer.map(|outer| std::mem::replace(&mut self.last, outer));
+        let result = self.compile(&source, &aliases);
+        if let Some(last) = last {
+            self.last = last;
+        }
+        let (path, lines) = result?;
+        self.run(path, lines).map(|_| ())
+    }
+
+    /// Checks and generates the imported packages
+    fn add_imports(&mut self, imports: Vec<Import>) -> Result<(), EngineError> {
+        let imports: Vec<Import> = imports
+            .into_iter()
+            .filter(|i| !self.imports.iter().any(|x| x.spec == i.spec))
+            .collect();
+        let paths: Vec<&str> = imports.iter().map(|i| i.path.as_str()).collect();
+        let reader = self.engine.binding_reader(&self.reader);
+        self.module.import(&paths, &self.trace_config(), &reader)?;
+        self.imports.extend(imports);
+        Ok(())
+    }
+
+    /// Type checks the package of an input, and calls `f` with the results and
+    /// the position the input starts at.
+    fn check<R>(
+        &mut self,
+        source: &Source,
+        f: impl Fn(&AstObjects, &TCObjects, &TypeInfo, usize) -> R,
+    ) -> Result<R, EngineError> {
+        self.compile_with(source, &[], Some(&f))
+            .map(|(r, _, _)| r.unwrap())
+    }
+
+    /// Checks and generates the package of an input, returns its path and the
+    /// lines of the input in its file.
+    fn compile(
+        &mut self,
+        source: &Source,
+        aliases: &[Import],
+    ) -> Result<(String, Range<usize>), EngineError> {
+        let gen: Option<&dyn Fn(&AstObjects, &TCObjects, &TypeInfo, usize)> = None;
+        self.compile_with(source, aliases, gen)
+            .map(|(_, path, lines)| (path, lines))
+    }
+
+    /// Checks the package of an input, nested in the last one, and generates it
+    /// unless `check` is given. The imports of the session that are not used are
+    /// left out, instead of being reported.
+    fn compile_with<R>(
+        &mut self,
+        source: &Source,
+        aliases: &[Import],
+        check: Option<&dyn Fn(&AstObjects, &TCObjects, &TypeInfo, usize) -> R>,
+    ) -> Result<(Option<R>, String, Range<usize>), EngineError> {
+        let imports: Vec<Import> = self
+            .all_imports()
+            .into_iter()
+            .chain(aliases.iter().cloned())
+            .collect();
+        let mut skipped = vec![];
+        loop {
+            self.count += 1;
+            let path = input_path(self.count);
+            let src = source.write(&imports, &skipped);
+            let bindings = self.engine.binding_reader(&self.reader);
+            let reader = ReplReader::new(&bindings, &path, src.text);
+            let tconfig = self.trace_config();
+            let result = match check {
+                Some(f) => self
+                    .module
+                    .check_nested(&path, &self.last, &tconfig, &reader, |a, t, ti| {
+                        f(a, t, ti, ti.ast_files[0].package + src.offset)
+                    })
+                    .map(Some),
+                None => self
+                    .module
+                    .add_nested(&path, &self.last, &tconfig, &reader)
+                    .map(|_| None),
+            };
+            let el = match result {
+                Ok(r) => return Ok((r, path, src.lines)),
+                Err(el) => el,
+            };
+            let file = file_name(&path);
+            let unused: Vec<usize> = src
+                .import_lines
+                .iter()
+                .filter(|(_, line)| {
+                    el.borrow()
+                        .iter()
+                        .any(|e| e.soft && *e.pos.filename == file && e.pos.line == *line)
+                })
+                .map(|(i, _)| *i)
+                .collect();
+            if unused.is_empty() || !skipped.is_empty() {
+                return Err(input_errors(el, &file, &src.lines));
+            }
+            skipped = unused;
+        }
+    }
+
+    /// Runs the statements of the package at `path`, it's the one the next input
+    /// is nested in if it doesn't fail.
+    fn run(&mut self, path: String, lines: Range<usize>) -> Result<Option<String>, EngineError> {
+        self.module.set_entry(&path, INPUT_FUNC);
+        self.shown.replace(None);
+        let code = self.module.bytecode();
+        // not try_run_bytecode, which would report the stats of every input
+        let vm = self.engine.new_vm_with_gc(&code, self.gcc.clone());
+        let result = self.engine.run_vm(&vm);
+        drop(vm);
+        match result {
+            Ok(()) => {
+                self.last = path;
+                Ok(self.shown.take())
+            }
+            Err(e) => {
+                let mut err = EngineError::from_call_error(e, &code);
+                let file = file_name(&path);
+                let frames = match &mut err {
+                    EngineError::Panic { call_stack, .. }
+                    | EngineError::Ffi { call_stack, .. }
+                    | EngineError::Interrupted { call_stack } => call_stack.iter_mut(),
+                    _ => [].iter_mut(),
+                };
+                for frame in frames.filter(|f| f.file == file) {
+                    if lines.contains(&frame.line) {
+                        frame.file = INPUT_NAME.to_owned();
+                        frame.line -= lines.start - 1;
+                    }
+                }
+                Err(err)
+            }
+        }
+    }
+
+    /// The imports of the session, the helper package first
+    fn all_imports(&self) -> Vec<Import> {
+        let helper = Import {
+            path: HELPER.to_owned(),
+            spec: format!("{:?}", HELPER),
+        };
+        std::iter::once(helper)
+            .chain(self.imports.iter().cloned())
+            .collect()
+    }
+
+    fn trace_config(&self) -> TraceConfig {
+        TraceConfig {
+            trace_parser: self.trace_parser,
+            trace_checker: self.trace_checker,
+        }
+    }
+}
+
+/// The import path of the package of the `n`th input
+fn input_path(n: usize) -> String {
+    format!("{}/{}", REPL_DIR, n)
+}
+
+/// The name of the file of the package at `path`
+fn file_name(path: &str) -> String {
+    format!("{}/{}.gos", path, INPUT_NAME)
+}
+
+/// The compile errors with the positions in `lines` of `file` made relative to them
+fn input_errors(el: ErrorList, file: &str, lines: &Range<usize>) -> EngineError {
+    let result = ErrorList::new();
+    el.sort();
+    for e in el.borrow().iter() {
+        let pos = match *e.pos.filename == file && lines.contains(&e.pos.line) {
+            true => FilePos {
+                filename: Rc::new(INPUT_NAME.to_owned()),
+                offset: e.pos.offset,
+                line: e.pos.line - lines.start + 1,
+                column: e.pos.column,
+            },
+            false => e.pos.clone(),
+        };
+        result.add(Some(pos), e.msg.clone(), e.soft, e.by_parser);
+    }
+    EngineError::Compile(result)
+}
+
+/// The position of the char at `offset` in the input
+fn input_pos(chars: &[char], offset: usize) -> FilePos {
+    let before = &chars[..offset];
+    let line_start = before.iter().rposition(|c| *c == '\n').map_or(0, |i| i + 1);
+    FilePos {
+        filename: Rc::new(INPUT_NAME.to_owned()),
+        offset,
+        line: before.iter().filter(|c| **c == '\n').count() + 1,
+        column: offset - line_start + 1,
+    }
+}
+
+/// The number of results of the call the input is made of, it's checked as the
+/// argument of `Show`
+fn result_count(ast_objs: &AstObjects, tc_objs: &TCObjects, ti: &TypeInfo, _: usize) -> usize {
+    let tv = input_func(ast_objs, ti).and_then(|(_, body)| match body.list.as_slice() {
+        [Stmt::Expr(expr)] => match expr.as_ref() {
+            Expr::Call(show) => ti.types.get(&show.args.first()?.id()),
+            _ => None,
+        },
+        _ => None,
+    });
+    match tv {
+        Some(tv) if tv.mode == OperandMode::NoValue => 0,
+        Some(tv) => tc_objs.types[tv.typ]
+            .try_as_tuple()
+            .map_or(1, |tuple| tuple.vars().len()),
+        None => 0,
+    }
+}
+
+/// The name of the input function of the checked package, with its body
+fn input_func<'a>(ast_objs: &'a AstObjects, ti: &TypeInfo) -> Option<(IdentKey, &'a BlockStmt)> {
+    ti.ast_files[0].decls.iter().find_map(|decl| match decl {
+        Decl::Func(key) => {
+            let fdecl = &ast_objs.fdecls[*key];
+            match ast_objs.idents[fdecl.name].name == INPUT_FUNC {
+                true => Some((fdecl.name, fdecl.body.as_deref()?)),
+                false => None,
+            }
+        }
+        _ => None,
+    })
+}
+
+/// An imported package, `spec` is the import spec as written, like `m "math"`
+#[derive(Clone)]
+struct Import {
+    path: String,
+    spec: String,
+}
+
+/// The source of the package of an input
+#[derive(Default)]
+struct Source {
+    /// Package level declarations, before the input function
+    decls: String,
+    /// The statements of the input function
+    body: String,
+    /// Package level declarations, after the input function
+    tail: String,
+}
+
+/// The text of a package written from a `Source`
+struct SourceText {
+    text: String,
+    /// The imports that are written, and their lines
+    import_lines: Vec<(usize, usize)>,
+    /// The lines of the input, in the declarations or in the body
+    lines: Range<usize>,
+    /// The offset of the input in chars
+    offset: usize,
+}
+
+impl Source {
+    fn write(&self, imports: &[Import], skipped: &[usize]) -> SourceText {
+        let mut text = String::from("package main\n");
+        let mut import_lines = vec![];
+        for (i, import) in imports.iter().enumerate() {
+            if !skipped.contains(&i) {
+                writeln!(text, "import {}", import.spec).unwrap();
+                import_lines.push((i, text.lines().count()));
+            }
+        }
+        let mut lines = input_lines(&text, &self.decls);
+        let mut offset = text.chars().count();
+        writeln!(text, "{}", self.decls).unwrap();
+        writeln!(text, "func {}() {{", INPUT_FUNC).unwrap();
+        if self.decls.is_empty() {
+            lines = input_lines(&text, &self.body);
+            offset = text.chars().count();
+        }
+        writeln!(text, "{}\n}}\n{}", self.body, self.tail).unwrap();
+        SourceText {
+            text,
+            import_lines,
+            lines,
+            offset,
+        }
+    }
+}
+
+/// The lines `input` takes when it's written right after `src`
+fn input_lines(src: &str, input: &str) -> Range<usize> {
+    let start = src.lines().count() + 1;
+    start..start + input.lines().count()
+}
+
+/// What the type checker tells about the top level declarations of an input
+#[derive(Default)]
+struct Checked {
+    /// The package level declarations of the variables
+    vars: Vec<String>,
+    /// The declarations that refer to the objects of the earlier inputs that the
+    /// input declares again, by name
+    outer: Vec<(String, String)>,
+    /// The uses of those objects in the input, with what they are replaced by
+    renames: Vec<(Range<usize>, String)>,
+    /// The names that can't be declared again, as the input uses what they are in
+    /// the universe, by position in the input
+    errors: Vec<(usize, String)>,
+    /// The import paths of the packages written with aliases in the declarations
+    aliases: Vec<String>,
+}
+
+impl Checked {
+    /// The input is at `input` in the checked package
+    fn new(
+        ast_objs: &AstObjects,
+        tc_objs: &TCObjects,
+        ti: &TypeInfo,
+        input: Range<usize>,
+        qualifier: &Qualifier,
+    ) -> Checked {
+        let mut checked = Checked::default();
+        let func = match input_func(ast_objs, ti).and_then(|(name, _)| ti.defs[&name]) {
+            Some(func) => &tc_objs.lobjs[func],
+            None => return checked,
+        };
+        let scope = func
+            .typ()
+            .and_then(|sig| tc_objs.types[sig].try_as_signature()?.scope());
+        let elems = match scope {
+            Some(scope) => tc_objs.scopes[scope].elems(),
+            None => return checked,
+        };
+        qualifier.aliases.replace(Some(vec![]));
+        qualifier
+            .current
+            .set(func.pkg().map_or(std::ptr::null(), |p| &tc_objs.pkgs[p]));
+        let type_str = |okey: ObjKey| {
+            let typ = tc_objs.lobjs[okey].typ().unwrap();
+            Displayer::new(&typ, None, Some(tc_objs)).to_string()
+        };
+
+        let mut uses: Vec<(usize, ObjKey, &str)> = ti
+            .uses
+            .iter()
+            .map(|(ident, obj)| (&ast_objs.idents[*ident], *obj))
+            .filter(|(ident, _)| input.contains(&ident.pos))
+            .map(|(ident, obj)| (ident.pos - input.start, obj, ident.name.as_str()))
+            .collect();
+        uses.sort_by_key(|(pos, _, _)| *pos);
+        let universe = *tc_objs.universe().scope();
+        for (pos, obj, name) in uses.into_iter() {
+            match elems.get(name) {
+                Some(o) if *o != obj => {}
+                _ => continue,
+            }
+            let lobj = &tc_objs.lobjs[obj];
+            let outer_pkg = lobj
+                .pkg()
+                .map(|p| &tc_objs.pkgs[p])
+                .filter(|p| p.path().starts_with(REPL_DIR) && lobj.parent() == Some(*p.scope()));
+            if lobj.parent() == Some(universe) {
+                checked.errors.push((
+                    pos,
+                    format!("predeclared {} used before it is declared again", name),
+                ));
+                continue;
+            } else if outer_pkg.is_none() {
+                // it's local to a block
+                continue;
+            }
+            // the shims are nested in the earlier inputs, where the names are the same
+            let outer_type = || unmark(&type_str(obj), |n| n.to_owned());
+            let (decl, rename) = match lobj.entity_type() {
+                EntityType::Var(_) => (
+                    format!(
+                        "func {}{}() *{} {{ return &{} }}",
+                        OUTER,
+                        name,
+                        outer_type(),
+                        name
+                    ),
+                    format!("(*{}{}())", OUTER, name),
+                ),
+                EntityType::Const(_) => (
+                    format!("const {}{} = {}", OUTER, name, name),
+                    format!("{}{}", OUTER, name),
+                ),
+                EntityType::TypeName => (
+                    format!("type {}{} = {}", OUTER, name, name),
+                    format!("{}{}", OUTER, name),
+                ),
+                EntityType::Func(_) => (
+                    format!(
+                        "func {}{}() {} {{ return {} }}",
+                        OUTER,
+                        name,
+                        outer_type(),
+                        name
+                    ),
+                    format!("{}{}()", OUTER, name),
+                ),
+                _ => continue,
+            };
+            checked.add_outer(name, decl);
+            let len = name.chars().count();
+            checked.renames.push((pos..pos + len, rename));
+        }
+
+        let mut vars: Vec<ObjKey> = elems
+            .values()
+            .filter(|o| tc_objs.lobjs[**o].entity_type().is_var())
+            .copied()
+            .collect();
+        vars.sort_by_key(|o| tc_objs.lobjs[*o].pos());
+        for var in vars.into_iter() {
+            // the types of the earlier inputs may be declared again by the input
+            let mut types = vec![];
+            let typ = unmark(&type_str(var), |n| match elems.contains_key(n) {
+                true => {
+                    types.push(n.to_owned());
+                    format!("{}{}", OUTER, n)
+                }
+                false => n.to_owned(),
+            });
+            for name in types.into_iter() {
+                let decl = format!("type {}{} = {}", OUTER, name, name);
+                checked.add_outer(&name, decl);
+            }
+            let name = tc_objs.lobjs[var].name();
+            checked.vars.push(format!("var {} {}", name, typ));
+        }
+        checked.aliases = qualifier.aliases.replace(None).unwrap();
+        checked
+    }
+
+    fn add_outer(&mut self, name: &str, decl: String) {
+        if !self.outer.iter().any(|(n, _)| n == name) {
+            self.outer.push((name.to_owned(), decl));
+        }
+    }
+}
+
+/// Writes the names after `OUTER_MARK` in the type `typ` with `f`
+fn unmark(typ: &str, mut f: impl FnMut(&str) -> String) -> String {
+    let mut parts = typ.split(OUTER_MARK);
+    let mut result = parts.next().unwrap_or_default().to_owned();
+    for part in parts {
+        let part = part.strip_prefix('.').unwrap_or(part);
+        let end = part
+            .find(|c: char| !c.is_alphanumeric() && c != '_')
+            .unwrap_or(part.len());
+        result.push_str(&f(&part[..end]));
+        result.push_str(&part[end..]);
+    }
+    result
+}
+
+/// How the types are written in the messages of the type checker and in the
+/// declarations of the variables. The packages of the inputs are left out, as
+/// what they declare is in scope. While `aliases` is set, the other packages are
+/// written with aliases, which are imported by the packages of the inputs, and the
+/// packages of the inputs other than `current` are written as `OUTER_MARK`.
+struct Qualifier {
+    aliases: RefCell<Option<Vec<String>>>,
+    current: Cell<*const Package>,
+}
+
+impl Qualifier {
+    fn qualify<'p>(&self, pkg: &'p Package) -> Cow<'p, str> {
+        let mut aliases = self.aliases.borrow_mut();
+        let path = pkg.path();
+        match aliases.as_mut() {
+            Some(_) if path.starts_with(REPL_DIR) => match std::ptr::eq(pkg, self.current.get()) {
+                true => Cow::Borrowed(""),
+                false => Cow::Borrowed(OUTER_MARK),
+            },
+            None if path.starts_with(REPL_DIR) => Cow::Borrowed(""),
+            Some(aliases) => {
+                let i = match aliases.iter().position(|x| x == path) {
+                    Some(i) => i,
+                    None => {
+                        aliases.push(path.clone());
+                        aliases.len() - 1
+                    }
+                };
+                Cow::Owned(format!("{}{}", ALIAS, i))
+            }
+            None => Cow::Borrowed(path),
+        }
+    }
+}
+
+/// What an input is, as far as the parser can tell
+enum Input {
+    Empty,
+    Imports(Vec<Import>),
+    /// Functions and types, they are package level declarations
+    Decls,
+    /// An expression statement
+    Expr {
+        is_call: bool,
+    },
+    /// Statements, along with their top level declarations
+    Stmts(Stmts),
+}
+
+/// The top level declarations of an input made of statements, the positions are
+/// in chars of the input
+#[derive(Default)]
+struct Stmts {
+    /// The positions of the `:=` of the short variable declarations
+    defines: Vec<usize>,
+    vars: Vec<String>,
+    var_decls: Vec<VarDecl>,
+    /// The `const` and `type` declarations
+    hoisted: Vec<Range<usize>>,
+}
+
+/// A `var` declaration
+struct VarDecl {
+    /// From the `var` keyword to the first spec, along with the closing paren
+    /// if there is one
+    head: Range<usize>,
+    r_paren: Option<usize>,
+    specs: Vec<VarSpec>,
+}
+
+struct VarSpec {
+    /// Up to the first value, or the end if there is none
+    head: Range<usize>,
+    names: String,
+    has_values: bool,
+}
+
+impl Stmts {
+    fn is_empty(&self) -> bool {
+        self.defines.is_empty() && self.var_decls.is_empty() && self.hoisted.is_empty()
+    }
+
+    /// Turns the declarations into assignments, and returns the statements along
+    /// with the declarations to be moved to the package level. The lines stay where
+    /// they are. The `renames` are applied too.
+    fn rewrite(&self, input: &str, renames: &[(Range<usize>, String)]) -> (String, Vec<String>) {
+        let chars: Vec<char> = input.chars().collect();
+        let text = |range: Range<usize>| -> String {
+            let mut s = String::new();
+            let mut i = range.start;
+            for (r, name) in renames.iter().filter(|(r, _)| range.contains(&r.start)) {
+                s.extend(&chars[i..r.start]);
+                s.push_str(name);
+                i = r.end;
+            }
+            s.extend(&chars[i..range.end]);
+            s
+        };
+        let newlines = |range: Range<usize>| -> String {
+            chars[range].iter().filter(|c| **c == '\n').collect()
+        };
+
+        let mut edits: Vec<(Range<usize>, String)> = vec![];
+        for pos in self.defines.iter() {
+            edits.push((*pos..*pos + 1, " ".to_owned()));
+        }
+        for decl in self.var_decls.iter() {
+            edits.push((decl.head.clone(), newlines(decl.head.clone())));
+            for spec in decl.specs.iter() {
+                let mut s = String::new();
+                if spec.has_values {
+                    write!(s, "{} = ", spec.names).unwrap();
+                }
+                s.push_str(&newlines(spec.head.clone()));
+                edits.push((spec.head.clone(), s));
+            }
+            if let Some(p) = decl.r_paren {
+                edits.push((p..p + 1, String::new()));
+            }
+        }
+        let mut hoisted = vec![];
+        for range in self.hoisted.iter() {
+            hoisted.push(text(range.clone()));
+            edits.push((range.clone(), newlines(range.clone())));
+        }
+        edits.sort_by_key(|(r, _)| r.start);
+
+        let mut body = String::new();
+        let mut i = 0;
+        for (range, s) in edits.into_iter() {
+            body.push_str(&text(i..range.start));
+            body.push_str(&s);
+            i = range.end;
+        }
+        body.push_str(&text(i..chars.len()));
+        (body, hoisted)
+    }
+}
+
+impl Input {
+    fn parse(input: &str) -> Input {
+        if input.trim().is_empty() {
+            return Input::Empty;
+        }
+        if let Some(input) = Input::parse_decls(input) {
+            return input;
+        }
+        // the errors are reported when it's compiled
+        let src = format!("package main\nfunc _() {{\n{}\n}}", input);
+        let (objs, file) = Input::parse_file(&src);
+        let body = match file.as_ref().and_then(|f| f.decls.first()) {
+            Some(Decl::Func(key)) => objs.fdecls[*key].body.clone(),
+            _ => None,
+        };
+        let body = match &body {
+            Some(body) => body,
+            None => return Input::Stmts(Stmts::default()),
+        };
+        if let [Stmt::Expr(expr)] = body.list.as_slice() {
+            return Input::Expr {
+                is_call: matches!(expr.as_ref(), Expr::Call(_)),
+            };
+        }
+        // the input starts after the brace and the line break
+        let start = body.l_brace + 2;
+        let mut stmts = Stmts::default();
+        for stmt in body.list.iter() {
+            match stmt {
+                Stmt::Assign(key) => {
+                    let assign = &objs.a_stmts[*key];
+                    if assign.token == Token::DEFINE {
+                        stmts.defines.push(assign.token_pos - start);
+                        for expr in assign.lhs.iter() {
+                            if let Expr::Ident(ident) = expr {
+                                stmts.vars.push(objs.idents[*ident].name.clone());
+                            }
+                        }
+                    }
+                }
+                Stmt::Decl(decl) => match decl.as_ref() {
+                    Decl::Gen(gen) if gen.token == Token::VAR => {
+                        let mut specs = vec![];
+                        for spec in gen.specs.iter() {
+                            if let Spec::Value(value) = &objs.specs[*spec] {
+                                let names: Vec<String> = value
+                                    .names
+                                    .iter()
+                                    .map(|i| objs.idents[*i].name.clone())
+                                    .collect();
+                                let from = objs.specs[*spec].pos(&objs) - start;
+                                let to = match value.values.first() {
+                                    Some(v) => v.pos(&objs),
+                                    None => objs.specs[*spec].end(&objs),
+                                } - start;
+                                specs.push(VarSpec {
+                                    head: from..to,
+                                    names: names.join(", "),
+                                    has_values: !value.values.is_empty(),
+                                });
+                                stmts.vars.extend(names);
+                            }
+                        }
+                        let head_end = match gen.l_paran {
+                            Some(p) => p + 1 - start,
+                            None => specs
+                                .first()
+                                .map_or(gen.token_pos - start, |s| s.head.start),
+                        };
+                        stmts.var_decls.push(VarDecl {
+                            head: gen.token_pos - start..head_end,
+                            r_paren: gen.r_paren.map(|p| p - start),
+                            specs,
+                        });
+                    }
+                    Decl::Gen(gen) if gen.token == Token::CONST || gen.token == Token::TYPE => {
+                        stmts
+                            .hoisted
+                            .push(decl.pos(&objs) - start..decl.end(&objs) - start);
+                    }
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        stmts.vars.retain(|v| v != "_");
+        Input::Stmts(stmts)
+    }
+
+    /// Returns `Imports` or `Decls` if the input is made of package level declarations only
+    fn parse_decls(input: &str) -> Option<Input> {
+        let src = format!("package main\n{}", input);
+        let (objs, file) = Input::parse_file(&src);
+        let decls = file?.decls;
+        let mut imports = vec![];
+        for decl in decls.iter() {
+            match decl {
+                Decl::Gen(gen) if gen.token == Token::IMPORT => {
+                    for spec in gen.specs.iter() {
+                        if let Spec::Import(import) = &objs.specs[*spec] {
+                            let path = import.path.token.get_literal();
+                            let name = import.name.map(|i| objs.idents[i].name.clone());
+                            imports.push(Import {
+                                path: path.trim_matches(|c| c == '"' || c == '`').to_owned(),
+                                spec: match name {
+                                    Some(name) => format!("{} {}", name, path),
+                                    None => path.to_owned(),
+                                },
+                            });
+                        }
+                    }
+                }
+                Decl::Gen(gen) if gen.token == Token::TYPE => {}
+                Decl::Func(_) => {}
+                _ => return None,
+            }
+        }
+        match imports.len() {
+            0 => Some(Input::Decls),
+            n if n == decls.len() => Some(Input::Imports(imports)),
+            // imports have to come first in a file, mixing them is left to the checker
+            _ => Some(Input::Decls),
+        }
+    }
+
+    /// Returns the file if it parses without errors
+    fn parse_file(src: &str) -> (AstObjects, Option<go_parser::ast::File>) {
+        let mut objs = AstObjects::new();
+        let mut fs = FileSet::new();
+        let el = ErrorList::new();
+        let (_, file) = go_parser::parse_file(&mut objs, &mut fs, &el, "input", src, false);
+        let file = file.filter(|_| el.len() == 0);
+        (objs, file)
+    }
+}
+
+/// The FFI of the helper package
+struct ReplFfi {
+    shown: Rc<RefCell<Option<String>>>,
+}
+
+impl Ffi for ReplFfi {
+    fn call(&self, ctx: &mut FfiCtx, params: Vec<GosValue>) -> RuntimeResult<Vec<GosValue>> {
+        match ctx.func_name {
+            "show" => {
+                let vals = FfiCtx::slice_as_rust_slice::<GosElem>(&params[0])?;
+                let strs = vals
+                    .iter()
+                    .map(|x| {
+                        let val = x.borrow();
+                        match val.is_nil() {
+                            true => Ok("<nil>".to_owned()),
+                            false => Ok(match val.iface_underlying()? {
+                                Some(v) => v.to_string(),
+                                None => "<ffi>".to_owned(),
+                            }),
+                        }
+                    })
+                    .collect::<RuntimeResult<Vec<String>>>()?;
+                self.shown.replace(Some(strs.join(", ")));
+            }
+            name => return Err(format!("ffi function '{}' not found!", name).into()),
+        }
+        Ok(vec![])
+    }
+
+    #[cfg(feature = "async")]
+    fn async_call(
+        &self,
+        ctx: &mut FfiCtx,
+        _params: Vec<GosValue>,
+    ) -> Pin<Box<dyn Future<Output = RuntimeResult<Vec<GosValue>>> + '_>> {
+        let err = Err(format!("ffi function '{}' not found!", ctx.func_name).into());
+        Box::pin(async move { err })
+    }
+}
+
+/// A `SourceRead` that serves the package of an input and the helper package,
+/// and reads everything else from `inner`.
+struct ReplReader<'a, S: SourceRead> {
+    inner: &'a S,
+    vfs: VfsMap,
+}
+
+impl<'a, S: SourceRead> ReplReader<'a, S> {
+    /// Serves `source` as the package at `path`
+    fn new(inner: &'a S, path: &str, source: String) -> Self {
+        let files = Map::from([
+            (PathBuf::from(file_name(path)), Cow::Owned(source)),
+            (
+                Path::new(REPL_DIR).join(HELPER).join("repl.gos"),
+                Cow::Borrowed(HELPER_SOURCE),
+            ),
+        ]);
+        ReplReader {
+            inner,
+            vfs: VfsMap::new(files),
+        }
+    }
+
+    fn is_repl(path: &Path) -> bool {
+        path.starts_with(REPL_DIR)
+    }
+}
+
+impl<'a, S: SourceRead> SourceRead for ReplReader<'a, S> {
+    fn working_dir(&self) -> &Path {
+        self.inner.working_dir()
+    }
+
+    fn base_dir(&self) -> Option<&Path> {
+        self.inner.base_dir()
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+        match Self::is_repl(path) {
+            true => self.vfs.read_file(path),
+            false => self.inner.read_file(path),
+        }
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<PathBuf>> {
+        match Self::is_repl(path) {
+            true => self.vfs.read_dir(path),
+            false => self.inner.read_dir(path),
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        match Self::is_repl(path) {
+            true => self.vfs.is_file(path),
+            false => self.inner.is_file(path),
+        }
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        match Self::is_repl(path) {
+            true => self.vfs.is_dir(path),
+            false => self.inner.is_dir(path),
+        }
+    }
+
+    fn canonicalize_import(&self, key: &ImportKey) -> io::Result<(PathBuf, String)> {
+        match key.path.as_str() {
+            HELPER => Ok((Path::new(REPL_DIR).join(HELPER), HELPER.to_owned())),
+            path if path.starts_with(REPL_DIR) => Ok((PathBuf::from(path), path.to_owned())),
+            _ => self.inner.canonicalize_import(key),
+        }
+    }
+}
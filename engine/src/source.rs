@@ -3,8 +3,8 @@
 // license that can be found in the LICENSE file.
 
 use crate::engine::{Config, Engine, ImportKey, SourceRead};
+use crate::error::EngineError;
 use crate::vfs::VirtualFs;
-use crate::ErrorList;
 use go_parser::Map;
 use std::io;
 use std::path::{Path, PathBuf};
@@ -15,8 +15,12 @@ pub fn run(
     source: &SourceReader,
     path: &Path,
     panic_handler: Option<Rc<dyn Fn(String, String)>>,
-) -> Result<(), ErrorList> {
-    let engine = Engine::new();
+) -> Result<(), EngineError> {
+    let mut engine = Engine::new();
+    engine.set_instruction_budget(config.instruction_budget);
+    #[cfg(not(target_arch = "wasm32"))]
+    engine.set_timeout(config.timeout);
+    engine.set_memory_limit(config.memory_limit);
     #[cfg(feature = "go_std")]
     engine.set_std_io(config.std_in, config.std_out, config.std_err);
     engine.run_source(
@@ -28,6 +32,18 @@ pub fn run(
     )
 }
 
+/// Same as `run`, but returns the panics too, instead of printing them.
+pub fn try_run(config: Config, source: &SourceReader, path: &Path) -> Result<(), EngineError> {
+    let mut engine = Engine::new();
+    engine.set_instruction_budget(config.instruction_budget);
+    #[cfg(not(target_arch = "wasm32"))]
+    engine.set_timeout(config.timeout);
+    engine.set_memory_limit(config.memory_limit);
+    #[cfg(feature = "go_std")]
+    engine.set_std_io(config.std_in, config.std_out, config.std_err);
+    engine.try_run_source(config.trace_parser, config.trace_checker, source, path)
+}
+
 pub struct SourceReader {
     /// base directory for non-local imports(library files)
     base_dir: Option<PathBuf>,
@@ -4,6 +4,7 @@
 
 extern crate self as go_engine;
 use crate::ffi::*;
+use crate::std::os::StdIo;
 use go_vm::types::{GosElem, GosValue};
 
 #[derive(Ffi)]
@@ -11,7 +12,7 @@ pub struct Fmt2Ffi;
 
 #[ffi_impl]
 impl Fmt2Ffi {
-    fn ffi_println(args: GosValue) -> RuntimeResult<()> {
+    fn ffi_println(ctx: &FfiCtx, args: GosValue) -> RuntimeResult<()> {
         let vec = FfiCtx::slice_as_rust_slice::<GosElem>(&args)?;
         let strs: Vec<String> = vec
             .iter()
@@ -30,10 +31,10 @@ impl Fmt2Ffi {
             })
             .map(|x: RuntimeResult<String>| x.unwrap())
             .collect();
-        #[cfg(target_arch = "wasm32")]
-        crate::std::wasm::console_log(&strs.join(", "));
-        #[cfg(not(target_arch = "wasm32"))]
-        println!("{}", strs.join(", "));
+        let line = format!("{}\n", strs.join(", "));
+        StdIo::StdOut
+            .write(ctx.std_io, line.as_bytes())
+            .map_err(|e| e.to_string())?;
         Ok(())
     }
 }
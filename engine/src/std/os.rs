@@ -10,8 +10,6 @@ use std::fs;
 use std::io;
 use std::io::prelude::*;
 use std::rc::Rc;
-use std::sync::Arc;
-use std::sync::Mutex;
 
 // Flags to OpenFile
 const O_RDONLY: usize = 0x00000;
@@ -22,28 +20,6 @@ const O_CREATE: usize = 0x00040;
 const O_EXCL: usize = 0x00080;
 const O_TRUNC: usize = 0x00200;
 
-lazy_static! {
-    static ref STD_IO_API: Arc<Mutex<StdIoApi>> = Arc::new(Mutex::new(StdIoApi::default()));
-}
-
-pub fn set_std_io(
-    std_in: Option<Box<dyn std::io::Read + Sync + Send>>,
-    std_out: Option<Box<dyn std::io::Write + Sync + Send>>,
-    std_err: Option<Box<dyn std::io::Write + Sync + Send>>,
-) {
-    let mut api = STD_IO_API.lock().unwrap();
-    api.std_in = std_in;
-    api.std_out = std_out;
-    api.std_err = std_err;
-}
-
-#[derive(Default)]
-pub struct StdIoApi {
-    pub(crate) std_in: Option<Box<dyn io::Read + Sync + Send>>,
-    pub(crate) std_out: Option<Box<dyn io::Write + Sync + Send>>,
-    pub(crate) std_err: Option<Box<dyn io::Write + Sync + Send>>,
-}
-
 #[derive(Ffi)]
 pub struct FileFfi;
 
@@ -82,19 +58,27 @@ impl FileFfi {
         })
     }
 
-    fn ffi_read(fp: GosValue, buffer: GosValue) -> RuntimeResult<(isize, isize, GosValue)> {
+    fn ffi_read(
+        ctx: &FfiCtx,
+        fp: GosValue,
+        buffer: GosValue,
+    ) -> RuntimeResult<(isize, isize, GosValue)> {
         let file = fp.as_non_nil_unsafe_ptr()?.downcast_ref::<VirtualFile>()?;
         let slice = &buffer.as_non_nil_slice::<Elem8>()?.0;
         let mut buf = slice.as_raw_slice_mut();
-        let r = file.read(&mut buf);
+        let r = file.read(ctx.std_io, &mut buf);
         Ok(FileFfi::result_to_go(r, |opt| opt.unwrap_or(0) as isize))
     }
 
-    fn ffi_write(fp: GosValue, buffer: GosValue) -> RuntimeResult<(isize, isize, GosValue)> {
+    fn ffi_write(
+        ctx: &FfiCtx,
+        fp: GosValue,
+        buffer: GosValue,
+    ) -> RuntimeResult<(isize, isize, GosValue)> {
         let file = fp.as_non_nil_unsafe_ptr()?.downcast_ref::<VirtualFile>()?;
         let slice = &buffer.as_non_nil_slice::<Elem8>()?.0;
         let buf = slice.as_raw_slice();
-        let r = file.write(&buf);
+        let r = file.write(ctx.std_io, &buf);
         Ok(FileFfi::result_to_go(r, |opt| opt.unwrap_or(0) as isize))
     }
 
@@ -132,8 +116,8 @@ pub enum StdIo {
 }
 
 impl StdIo {
-    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
-        let mut api = STD_IO_API.lock().unwrap();
+    fn read(&self, api: &RefCell<StdIoApi>, buf: &mut [u8]) -> io::Result<usize> {
+        let mut api = api.borrow_mut();
         match self {
             Self::StdIn => match &mut api.std_in {
                 Some(r) => r.read(buf),
@@ -150,8 +134,8 @@ impl StdIo {
         }
     }
 
-    fn write(&self, buf: &[u8]) -> io::Result<usize> {
-        let mut api = STD_IO_API.lock().unwrap();
+    pub(crate) fn write(&self, api: &RefCell<StdIoApi>, buf: &[u8]) -> io::Result<usize> {
+        let mut api = api.borrow_mut();
         match self {
             Self::StdOut => match &mut api.std_out {
                 Some(r) => r.write(buf),
@@ -200,17 +184,17 @@ impl VirtualFile {
         VirtualFile::StdIo(io)
     }
 
-    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+    fn read(&self, api: &RefCell<StdIoApi>, buf: &mut [u8]) -> io::Result<usize> {
         match self {
             Self::File(f) => f.borrow_mut().read(buf),
-            Self::StdIo(io) => io.read(buf),
+            Self::StdIo(io) => io.read(api, buf),
         }
     }
 
-    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+    fn write(&self, api: &RefCell<StdIoApi>, buf: &[u8]) -> io::Result<usize> {
         match self {
             Self::File(f) => f.borrow_mut().write(buf),
-            Self::StdIo(io) => io.write(buf),
+            Self::StdIo(io) => io.write(api, buf),
         }
     }
 
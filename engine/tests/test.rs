@@ -1,6 +1,8 @@
 #![allow(dead_code)]
 
 use std::borrow::Cow;
+#[cfg(all(feature = "go_std", feature = "instruction_pos"))]
+use std::cell::RefCell;
 #[cfg(feature = "read_zip")]
 use std::fs;
 use std::io;
@@ -8,31 +10,33 @@ use std::io::Write;
 #[cfg(any(feature = "read_zip", feature = "go_std"))]
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::{Arc, Mutex};
 
 #[macro_use]
 extern crate time_test;
 extern crate go_engine as engine;
 
+/// The clones share the same buffer
 #[derive(Clone)]
 struct WriteBuf {
-    buffer: io::Cursor<Vec<u8>>,
+    buffer: Arc<Mutex<io::Cursor<Vec<u8>>>>,
 }
 
 impl WriteBuf {
     fn new() -> WriteBuf {
         WriteBuf {
-            buffer: io::Cursor::new(vec![]),
+            buffer: Arc::new(Mutex::new(io::Cursor::new(vec![]))),
         }
     }
 
     fn into_string(self) -> String {
-        String::from_utf8_lossy(&self.buffer.into_inner()).into_owned()
+        String::from_utf8_lossy(self.buffer.lock().unwrap().get_ref()).into_owned()
     }
 }
 
 impl Write for WriteBuf {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.buffer.write(buf)
+        self.buffer.lock().unwrap().write(buf)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -40,12 +44,12 @@ impl Write for WriteBuf {
     }
 }
 
-fn run(path: &str, trace: bool) -> Result<(), engine::ErrorList> {
+fn run(path: &str, trace: bool) -> Result<(), engine::EngineError> {
     run_path(path, trace, true)
 }
 
 #[cfg(feature = "go_std")]
-fn run_path(path: &str, trace: bool, fail_on_panic: bool) -> Result<(), engine::ErrorList> {
+fn run_path(path: &str, trace: bool, fail_on_panic: bool) -> Result<(), engine::EngineError> {
     let mut cfg = engine::Config::default();
     cfg.trace_parser = trace;
     cfg.trace_checker = trace;
@@ -59,7 +63,7 @@ fn run_path(path: &str, trace: bool, fail_on_panic: bool) -> Result<(), engine::
             }
         }));
     let result = engine::run(cfg, &sr, Path::new(path), ph);
-    if let Err(el) = &result {
+    if let Err(engine::EngineError::Compile(el)) = &result {
         el.sort();
         eprint!("{}", el);
     }
@@ -67,12 +71,12 @@ fn run_path(path: &str, trace: bool, fail_on_panic: bool) -> Result<(), engine::
 }
 
 #[cfg(not(feature = "go_std"))]
-fn run_path(_path: &str, _trace: bool, fail_on_panic: bool) -> Result<(), engine::ErrorList> {
+fn run_path(_path: &str, _trace: bool, fail_on_panic: bool) -> Result<(), engine::EngineError> {
     unimplemented!()
 }
 
 #[cfg(all(feature = "read_zip", feature = "go_std"))]
-fn run_zip(zip: &str, path: &str, trace: bool) -> Result<(), engine::ErrorList> {
+fn run_zip(zip: &str, path: &str, trace: bool) -> Result<(), engine::EngineError> {
     let zip = fs::read(Path::new(zip)).unwrap();
 
     let mut cfg = engine::Config::default();
@@ -90,7 +94,7 @@ fn run_zip(zip: &str, path: &str, trace: bool) -> Result<(), engine::ErrorList>
             panic!("test panicked");
         }));
     let result = engine::run(cfg, &sr, Path::new(path), ph);
-    if let Err(el) = &result {
+    if let Err(engine::EngineError::Compile(el)) = &result {
         el.sort();
         eprint!("{}", el);
     }
@@ -98,16 +102,16 @@ fn run_zip(zip: &str, path: &str, trace: bool) -> Result<(), engine::ErrorList>
 }
 
 #[cfg(not(feature = "go_std"))]
-fn run_zip(_zip: &str, _path: &str, _trace: bool) -> Result<(), engine::ErrorList> {
+fn run_zip(_zip: &str, _path: &str, _trace: bool) -> Result<(), engine::EngineError> {
     unimplemented!()
 }
 
 #[cfg(feature = "go_std")]
-fn run_string(source: Cow<'static, str>, trace: bool) -> Result<(), engine::ErrorList> {
+fn run_string(source: Cow<'static, str>, trace: bool) -> Result<(), engine::EngineError> {
     let mut cfg = engine::Config::default();
     cfg.trace_parser = trace;
     cfg.trace_checker = trace;
-    let (sr, path) = engine::SourceReader::fs_lib_and_string(PathBuf::from("../std/"), source);
+    let (sr, path) = string_reader(source);
     let ph: Option<Rc<dyn Fn(String, String)>> =
         Some(Rc::new(move |msg: String, stack: String| {
             eprintln!("{}\n", msg);
@@ -115,7 +119,7 @@ fn run_string(source: Cow<'static, str>, trace: bool) -> Result<(), engine::Erro
             panic!("test panicked");
         }));
     let result = engine::run(cfg, &sr, &path, ph);
-    if let Err(el) = &result {
+    if let Err(engine::EngineError::Compile(el)) = &result {
         el.sort();
         eprint!("{}", el);
     }
@@ -123,16 +127,36 @@ fn run_string(source: Cow<'static, str>, trace: bool) -> Result<(), engine::Erro
 }
 
 #[cfg(not(feature = "go_std"))]
-fn run_string(_source: &str, _trace: bool) -> Result<(), engine::ErrorList> {
+fn run_string(_source: &str, _trace: bool) -> Result<(), engine::EngineError> {
     unimplemented!()
 }
 
+/// The std library and a Go source as `temp_file.gos`
+#[cfg(feature = "go_std")]
+fn string_reader(source: impl Into<Cow<'static, str>>) -> (engine::SourceReader, PathBuf) {
+    engine::SourceReader::fs_lib_and_string(PathBuf::from("../std/"), source.into())
+}
+
+/// Compiles a Go source with debug info, for the tests driving the VM from the host
+#[cfg(feature = "go_std")]
+fn compile_with(engine: &engine::Engine, source: &'static str) -> engine::Bytecode {
+    let (sr, path) = string_reader(source);
+    engine.compile(&sr, &path, true, false, false).unwrap()
+}
+
+#[cfg(feature = "go_std")]
+fn compile_string(source: &'static str) -> (engine::Engine, engine::Bytecode) {
+    let engine = engine::Engine::new();
+    let code = compile_with(&engine, source);
+    (engine, code)
+}
+
 #[cfg(feature = "read_zip")]
 fn run_zip_and_string(
     file: &str,
     source: Cow<'static, str>,
     trace: bool,
-) -> Result<(), engine::ErrorList> {
+) -> Result<(), engine::EngineError> {
     let zip = fs::read(Path::new(file)).unwrap();
 
     let mut cfg = engine::Config::default();
@@ -150,7 +174,7 @@ fn run_zip_and_string(
             panic!("test panicked");
         }));
     let result = engine::run(cfg, &sr, &path, ph);
-    if let Err(el) = &result {
+    if let Err(engine::EngineError::Compile(el)) = &result {
         el.sort();
         eprint!("{}", el);
     }
@@ -173,6 +197,1857 @@ fn test_source() {
     assert!(result.is_ok());
 }
 
+#[test]
+#[cfg(feature = "go_std")]
+fn test_call_exported() {
+    let source = r#"
+    package main
+
+    var count int
+
+    func Add(a, b int) int {
+        count++
+        return a + b
+    }
+
+    func Count() int {
+        return count
+    }
+
+    func Twice(n int) int {
+        acc := 0
+        inc := func() {
+            acc += n
+        }
+        inc()
+        inc()
+        return acc
+    }
+
+    func Fail() {
+        panic("fail")
+    }
+
+    func main() {
+    }
+    "#;
+    let (engine, code) = compile_string(source);
+    let vm = engine.new_vm(&code);
+    for i in 0..3isize {
+        let result = vm.call("Add", vec![i.into(), 2isize.into()]).unwrap();
+        assert_eq!(*result[0].as_int(), i + 2);
+    }
+    let result = vm.call("main.Count", vec![]).unwrap();
+    assert_eq!(*result[0].as_int(), 3);
+    let result = vm.call("Twice", vec![21isize.into()]).unwrap();
+    assert_eq!(*result[0].as_int(), 42);
+    assert!(matches!(
+        vm.call("Fail", vec![]),
+        Err(engine::ffi::CallError::Panic(_))
+    ));
+    assert!(matches!(
+        vm.call("Add", vec![1isize.into()]),
+        Err(engine::ffi::CallError::BadCall(_))
+    ));
+    assert!(vm.call("main.main", vec![]).is_err());
+}
+
+#[test]
+#[cfg(feature = "go_std")]
+fn test_call_exported_closure() {
+    // the function called by the host is the bottom frame, the upvalues of the
+    // closures it creates must be found there and closed when it returns
+    let source = r#"
+    package main
+
+    var keep func() int
+
+    func Keep(n int) {
+        acc := n
+        keep = func() int {
+            acc++
+            return acc
+        }
+        acc *= 10
+    }
+
+    func Next() int {
+        return keep()
+    }
+
+    func main() {
+    }
+    "#;
+    let (engine, code) = compile_string(source);
+    let vm = engine.new_vm(&code);
+    vm.call("Keep", vec![4isize.into()]).unwrap();
+    for i in 41..44isize {
+        let result = vm.call("Next", vec![]).unwrap();
+        assert_eq!(*result[0].as_int(), i);
+    }
+}
+
+#[test]
+#[cfg(feature = "go_std")]
+fn test_instruction_budget() {
+    let source = r#"
+    package main
+
+    func Sum(n int) int {
+        s := 0
+        for i := 0; i < n; i++ {
+            s += i
+        }
+        return s
+    }
+
+    func main() {
+        for {
+        }
+    }
+    "#;
+    let (sr, path) = string_reader(source);
+    let mut engine = engine::Engine::new();
+    engine.set_instruction_budget(Some(100_000));
+    let code = compile_with(&engine, source);
+    assert!(matches!(
+        engine.try_run_bytecode(&code),
+        Err(engine::ffi::CallError::BudgetExhausted)
+    ));
+    let mut cfg = engine::Config::default();
+    cfg.instruction_budget = Some(100_000);
+    assert!(matches!(
+        engine::run(cfg, &sr, &path, None),
+        Err(engine::EngineError::BudgetExhausted)
+    ));
+
+    let code = compile_with(&engine, source);
+    let vm = engine.new_vm(&code);
+    vm.set_instruction_budget(Some(10));
+    let mut result = vm.call("Sum", vec![10000isize.into()]);
+    assert!(matches!(
+        vm.call("Sum", vec![1isize.into()]),
+        Err(engine::ffi::CallError::BadCall(_))
+    ));
+    let mut resumed = 0;
+    while let Err(engine::ffi::CallError::BudgetExhausted) = result {
+        assert_eq!(vm.instruction_budget(), Some(0));
+        vm.set_instruction_budget(Some(5000));
+        result = vm.resume();
+        resumed += 1;
+    }
+    assert!(resumed > 1);
+    assert_eq!(*result.unwrap()[0].as_int(), 10000 * 9999 / 2);
+    vm.set_instruction_budget(None);
+    let result = vm.call("Sum", vec![100isize.into()]).unwrap();
+    assert_eq!(*result[0].as_int(), 4950);
+}
+
+#[test]
+#[cfg(all(feature = "go_std", not(target_arch = "wasm32")))]
+fn test_interrupt() {
+    let source = r#"
+    package main
+
+    func Spin() {
+        for {
+        }
+    }
+
+    func Answer() int {
+        return 42
+    }
+
+    func main() {
+        Spin()
+    }
+    "#;
+    let mut engine = engine::Engine::new();
+    engine.set_timeout(Some(std::time::Duration::from_millis(100)));
+    let code = compile_with(&engine, source);
+    match engine.try_run_bytecode(&code) {
+        Err(engine::ffi::CallError::Interrupted(call_stack)) => {
+            assert!(call_stack.len() >= 2);
+            let display = engine::ffi::CallStackDisplay::with_call_stack(&call_stack, &code);
+            assert!(format!("{}", display).contains("temp_file.gos"));
+        }
+        _ => panic!("should be interrupted"),
+    }
+    // the timeout only stops that run
+    let vm = engine.new_vm(&code);
+    assert!(matches!(
+        engine.run_vm(&vm),
+        Err(engine::ffi::CallError::Interrupted(_))
+    ));
+    assert!(vm.cancellation_handle().is_cancelled());
+    let other = engine.new_vm(&code);
+    assert!(!other.cancellation_handle().is_cancelled());
+    let result = other.call("Answer", vec![]).unwrap();
+    assert_eq!(*result[0].as_int(), 42);
+
+    let engine = engine::Engine::new();
+    let code = compile_with(&engine, source);
+    let vm = engine.new_vm(&code);
+    let other = engine.new_vm(&code);
+    let handle = vm.cancellation_handle();
+    let canceller = std::thread::spawn(move || {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        handle.cancel();
+    });
+    assert!(matches!(
+        vm.call("Spin", vec![]),
+        Err(engine::ffi::CallError::Interrupted(_))
+    ));
+    canceller.join().unwrap();
+    // cancelling a VM doesn't stop the others of the engine
+    let result = other.call("Answer", vec![]).unwrap();
+    assert_eq!(*result[0].as_int(), 42);
+    vm.cancellation_handle().reset();
+    let result = vm.call("Answer", vec![]).unwrap();
+    assert_eq!(*result[0].as_int(), 42);
+}
+
+#[test]
+#[cfg(feature = "go_std")]
+fn test_memory_limit() {
+    let source = r#"
+    package main
+
+    var kept [][]int
+
+    func try(f func()) (ok bool) {
+        defer func() {
+            if r := recover(); r != nil {
+                ok = false
+            }
+        }()
+        f()
+        return true
+    }
+
+    func Make(n int) {
+        s := make([]int, n)
+        s[0] = 1
+    }
+
+    func TryMake(n int) bool {
+        return try(func() {
+            Make(n)
+        })
+    }
+
+    func Keep(n int) bool {
+        return try(func() {
+            kept = append(kept, make([]int, n))
+        })
+    }
+
+    func Concat(n int) bool {
+        return try(func() {
+            s := "0123456789"
+            for i := 0; i < n; i++ {
+                s += s
+            }
+        })
+    }
+
+    func Fill(n int) bool {
+        return try(func() {
+            m := map[int]int{}
+            for i := 0; i < n; i++ {
+                m[i] = i
+            }
+        })
+    }
+
+    func main() {
+    }
+    "#;
+    let mut engine = engine::Engine::new();
+    engine.set_memory_limit(Some(1 << 20));
+    let code = compile_with(&engine, source);
+    let vm = engine.new_vm(&code);
+    let call = |name: &str, n: isize| -> bool {
+        let result = vm.call(name, vec![n.into()]).unwrap();
+        *result[0].as_bool()
+    };
+    match vm.call("Make", vec![(1isize << 30).into()]) {
+        Err(e) => assert!(format!("{}", e).contains("out of memory")),
+        Ok(_) => panic!("should run out of memory"),
+    }
+    assert!(!call("TryMake", 1 << 30));
+    // the memory of the slices that are freed can be used again
+    for _ in 0..10 {
+        assert!(call("TryMake", 100_000));
+    }
+    assert!(call("Keep", 80_000));
+    assert!(!call("Keep", 80_000));
+    assert!(vm.gc_container().memory_used() <= 1 << 20);
+    assert!(!call("Concat", 20));
+    assert!(!call("Fill", 100_000));
+    assert!(call("TryMake", 10));
+}
+
+#[test]
+#[cfg(feature = "go_std")]
+fn test_engine_error() {
+    let run = |source: &'static str| {
+        let (sr, path) = string_reader(source);
+        engine::try_run(engine::Config::default(), &sr, &path)
+    };
+
+    match run("package main\n\nfunc main() {\n\tundefined()\n}\n") {
+        Err(engine::EngineError::Compile(el)) => assert!(el.len() > 0),
+        _ => panic!("should fail to compile"),
+    }
+
+    let source = r#"package main
+
+type T struct{}
+
+func (t *T) Run(f func()) {
+    f()
+}
+
+func fail(n int) {
+    panic(n)
+}
+
+func main() {
+    t := &T{}
+    t.Run(func() {
+        fail(42)
+    })
+}
+"#;
+    match run(source) {
+        Err(engine::EngineError::Panic { msg, call_stack }) => {
+            assert_eq!(msg, "42");
+            let funcs: Vec<&str> = call_stack.iter().map(|f| f.func.as_str()).collect();
+            assert_eq!(
+                funcs,
+                [
+                    "main.fail",
+                    "main.main.func1",
+                    "main.(*T).Run",
+                    "main.main",
+                    "main"
+                ]
+            );
+            assert!(call_stack[0].file.ends_with("temp_file.gos"));
+            assert_eq!((call_stack[0].line, call_stack[0].column), (10, 5));
+            assert_eq!(call_stack[3].line, 15);
+        }
+        _ => panic!("should panic"),
+    }
+
+    let source = r#"package main
+
+type ffiMissing interface {
+    missing()
+}
+
+func main() {
+    native := ffi(ffiMissing, "missing")
+    native.missing()
+}
+"#;
+    match run(source) {
+        Err(e @ engine::EngineError::Ffi { .. }) => {
+            assert!(e.to_string().contains("missing"));
+            assert_eq!(e.call_stack().unwrap()[0].func, "main.main");
+        }
+        _ => panic!("should fail in ffi"),
+    }
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "async"))]
+fn test_std_io_per_engine() {
+    let source = r#"
+    package main
+
+    import (
+        "fmt"
+        "fmt2"
+        "os"
+    )
+
+    var name string
+
+    func SetName(n string) {
+        name = n
+    }
+
+    func Greet() {
+        fmt.Println("hello", name)
+        fmt2.Println("bye", name)
+        fmt.Fprintln(os.Stderr, "oops", name)
+    }
+
+    func main() {
+    }
+    "#;
+    let outputs: Vec<(WriteBuf, WriteBuf)> =
+        (0..2).map(|_| (WriteBuf::new(), WriteBuf::new())).collect();
+    let engines: Vec<engine::Engine> = outputs
+        .iter()
+        .map(|(out, err)| {
+            let engine = engine::Engine::new();
+            engine.set_std_io(
+                None,
+                Some(Box::new(out.clone())),
+                Some(Box::new(err.clone())),
+            );
+            engine
+        })
+        .collect();
+    // the package variables live in the bytecode, so each VM gets its own
+    let codes: Vec<_> = engines.iter().map(|e| compile_with(e, source)).collect();
+    let vms: Vec<_> = engines
+        .iter()
+        .zip(codes.iter())
+        .map(|(e, code)| e.new_vm(code))
+        .collect();
+    vms[0]
+        .call("SetName", vec![engine::ffi::FfiCtx::new_string("a")])
+        .unwrap();
+    vms[1]
+        .call("SetName", vec![engine::ffi::FfiCtx::new_string("b")])
+        .unwrap();
+    // interleave the calls
+    for vm in vms.iter().chain(vms.iter()) {
+        vm.call("Greet", vec![]).unwrap();
+    }
+    drop(vms);
+    drop(codes);
+    drop(engines);
+    let expected = |n: &str| {
+        (
+            format!("hello {}\nbye, {}\nhello {}\nbye, {}\n", n, n, n, n),
+            format!("oops {}\noops {}\n", n, n),
+        )
+    };
+    for ((out, err), n) in outputs.into_iter().zip(["a", "b"]) {
+        assert_eq!((out.into_string(), err.into_string()), expected(n));
+    }
+}
+
+struct Counter {
+    count: std::cell::Cell<isize>,
+}
+
+struct CounterFfi;
+
+impl engine::ffi::Ffi for CounterFfi {
+    fn call(
+        &self,
+        ctx: &mut engine::ffi::FfiCtx,
+        params: Vec<engine::ffi::GosValue>,
+    ) -> engine::ffi::RuntimeResult<Vec<engine::ffi::GosValue>> {
+        let counter = ctx
+            .user_data::<Counter>()
+            .ok_or_else(|| "no counter".to_owned())?;
+        assert_eq!(ctx.func_name, "add");
+        counter.count.set(counter.count.get() + *params[0].as_int());
+        Ok(vec![counter.count.get().into()])
+    }
+
+    #[cfg(feature = "async")]
+    fn async_call(
+        &self,
+        _ctx: &mut engine::ffi::FfiCtx,
+        _params: Vec<engine::ffi::GosValue>,
+    ) -> std::pin::Pin<
+        Box<
+            dyn std::future::Future<Output = engine::ffi::RuntimeResult<Vec<engine::ffi::GosValue>>>
+                + '_,
+        >,
+    > {
+        unreachable!()
+    }
+}
+
+#[test]
+#[cfg(feature = "go_std")]
+fn test_ffi_user_data() {
+    let source = r#"
+    package main
+
+    type ffiCounter interface {
+        add(n int) int
+    }
+
+    func Add(n int) int {
+        c := ffi(ffiCounter, "counter")
+        return c.add(n)
+    }
+
+    func main() {
+    }
+    "#;
+    let counter = Rc::new(Counter {
+        count: std::cell::Cell::new(0),
+    });
+    let mut engine = engine::Engine::with_user_data(counter.clone());
+    engine.register_extension("counter", Rc::new(CounterFfi));
+    let code = compile_with(&engine, source);
+    let vm = engine.new_vm(&code);
+    vm.call("Add", vec![2isize.into()]).unwrap();
+    let result = vm.call("Add", vec![3isize.into()]).unwrap();
+    assert_eq!(*result[0].as_int(), 5);
+    assert_eq!(counter.count.get(), 5);
+    drop(vm);
+
+    // the wrong type is not reachable
+    engine.set_user_data(Some(Rc::new(5u8)));
+    let vm = engine.new_vm(&code);
+    match vm.call("Add", vec![1isize.into()]) {
+        Err(engine::ffi::CallError::Panic(data)) => assert!(data.ffi_error),
+        _ => panic!("should fail in ffi"),
+    }
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "async"))]
+fn test_register_fn() {
+    let source = r#"
+    package main
+
+    type ffiMymod interface {
+        add(a, b int64) int64
+        greet(name string) string
+        divmod(a, b int) (int, int)
+        check(n int)
+        async_double(n int) int
+    }
+
+    var m = ffi(ffiMymod, "mymod")
+
+    func Add(a, b int64) int64 {
+        return m.add(a, b)
+    }
+
+    func Greet(name string) string {
+        return m.greet(name)
+    }
+
+    func DivMod(a, b int) (int, int) {
+        q, r := m.divmod(a, b)
+        return q, r
+    }
+
+    func Check(n int) {
+        m.check(n)
+    }
+
+    func Double(n int) int {
+        return m.async_double(n)
+    }
+
+    func main() {
+    }
+    "#;
+    let mut engine = engine::Engine::new();
+    engine.register_fn("mymod.add", |a: i64, b: i64| a + b);
+    engine.register_fn("mymod.greet", |name: String| format!("hello {}", name));
+    engine.register_fn("mymod.divmod", |a: isize, b: isize| (a / b, a % b));
+    engine.register_fn(
+        "mymod.check",
+        |n: isize| -> engine::ffi::RuntimeResult<()> {
+            match n < 0 {
+                true => Err("negative".to_owned().into()),
+                false => Ok(()),
+            }
+        },
+    );
+    engine.register_async_fn("mymod.async_double", |n: isize| async move { n * 2 });
+    let code = compile_with(&engine, source);
+    let vm = engine.new_vm(&code);
+
+    let result = vm.call("Add", vec![2i64.into(), 3i64.into()]).unwrap();
+    assert_eq!(*result[0].as_int64(), 5);
+    let result = vm.call("Greet", vec!["go".to_owned().into()]).unwrap();
+    assert_eq!(result[0].to_string(), "hello go");
+    let result = vm
+        .call("DivMod", vec![7isize.into(), 2isize.into()])
+        .unwrap();
+    assert_eq!((*result[0].as_int(), *result[1].as_int()), (3, 1));
+    let result = vm.call("Double", vec![21isize.into()]).unwrap();
+    assert_eq!(*result[0].as_int(), 42);
+
+    assert!(vm.call("Check", vec![1isize.into()]).is_ok());
+    match vm.call("Check", vec![(-1isize).into()]) {
+        Err(engine::ffi::CallError::Panic(data)) => {
+            assert!(data.ffi_error);
+            assert!(data.msg.to_string().contains("negative"));
+        }
+        _ => panic!("should fail in ffi"),
+    }
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "async"))]
+fn test_go_binding() {
+    let source = r#"
+    package main
+
+    import "mymod"
+
+    func Run() (int64, string, int, int, int) {
+        q, r := mymod.DivMod(7, 2)
+        return mymod.Add(2, 3), mymod.Greet("go"), q, r, mymod.AsyncDouble(21)
+    }
+
+    func main() {
+    }
+    "#;
+    let mut engine = engine::Engine::new();
+    engine.register_fn("mymod.add", |a: i64, b: i64| a + b);
+    engine.register_fn("mymod.greet", |name: String| format!("hello {}", name));
+    engine.register_fn("mymod.div_mod", |a: isize, b: isize| (a / b, a % b));
+    engine.register_async_fn("mymod.async_double", |n: isize| async move { n * 2 });
+
+    let binding = engine.go_binding("mymod").unwrap();
+    assert!(binding.contains("package mymod"));
+    assert!(binding.contains("\tadd(p0 int64, p1 int64) int64\n"));
+    assert!(binding.contains("func DivMod(p0 int, p1 int) (int, int) {"));
+    assert!(engine.go_binding("fmt2").is_none());
+
+    let code = compile_with(&engine, source);
+    let vm = engine.new_vm(&code);
+    let result = vm.call("Run", vec![]).unwrap();
+    assert_eq!(*result[0].as_int64(), 5);
+    assert_eq!(result[1].to_string(), "hello go");
+    assert_eq!((*result[2].as_int(), *result[3].as_int()), (3, 1));
+    assert_eq!(*result[4].as_int(), 42);
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "async"))]
+fn test_ffi_conversions() {
+    use engine::ffi::FromGos;
+    use go_vm::types::GosValue;
+
+    let source = r#"
+    package main
+
+    import "conv"
+
+    func Run() ([]uint8, int, int, int64, []string) {
+        m := map[string]int64{"a": 1, "b": 2}
+        return conv.Reverse([]uint8{1, 2, 3}), conv.Len(nil), conv.Len([]uint8{}),
+            conv.Sum(m), conv.Words("hello ffi")
+    }
+
+    func Nothing() []string {
+        return conv.Words("")
+    }
+
+    func Values(n int) int {
+        return len(conv.Values(n))
+    }
+
+    func main() {
+    }
+    "#;
+    let mut engine = engine::Engine::new();
+    engine.register_fn("conv.reverse", |mut v: Vec<u8>| {
+        v.reverse();
+        v
+    });
+    engine.register_fn("conv.len", |b: Option<Vec<u8>>| {
+        b.map_or(-1, |b| b.len() as isize)
+    });
+    engine.register_fn("conv.sum", |m: std::collections::HashMap<String, i64>| {
+        m.values().sum::<i64>()
+    });
+    engine.register_fn("conv.words", |s: String| {
+        let words: Vec<String> = s.split_whitespace().map(|x| x.to_owned()).collect();
+        (!words.is_empty()).then_some(words)
+    });
+    engine.register_fn("conv.values", |n: isize| {
+        (0..n).map(GosValue::from).collect::<Vec<GosValue>>()
+    });
+
+    let binding = engine.go_binding("conv").unwrap();
+    assert!(binding.contains("func Reverse(p0 []uint8) []uint8 {"));
+    assert!(binding.contains("func Sum(p0 map[string]int64) int64 {"));
+
+    let code = compile_with(&engine, source);
+    let vm = engine.new_vm(&code);
+    let result = vm.call("Run", vec![]).unwrap();
+    let mut result = result.into_iter();
+    let mut next = || result.next().unwrap();
+    assert_eq!(Vec::<u8>::from_gos(next()).unwrap(), vec![3, 2, 1]);
+    assert_eq!(isize::from_gos(next()).unwrap(), -1);
+    assert_eq!(isize::from_gos(next()).unwrap(), 0);
+    assert_eq!(i64::from_gos(next()).unwrap(), 3);
+    assert_eq!(
+        Vec::<String>::from_gos(next()).unwrap(),
+        vec!["hello", "ffi"]
+    );
+    let result = vm.call("Nothing", vec![]).unwrap();
+    assert!(result[0].is_nil());
+    // the element type of an empty Vec<GosValue> is not known
+    for n in [0, 2] {
+        let result = vm.call("Values", vec![GosValue::from(n)]).unwrap();
+        assert_eq!(*result[0].as_int(), n);
+    }
+}
+
+#[cfg(all(feature = "go_std", feature = "async"))]
+mod typed_ffi {
+    use engine::ffi::*;
+    use go_vm::types::GosValue;
+
+    #[derive(Ffi)]
+    pub struct TwiceFfi;
+
+    #[ffi_impl(rename = "twice")]
+    impl TwiceFfi {
+        fn ffi_twice(n: i64) -> i64 {
+            n * 2
+        }
+
+        fn ffi_or_zero(n: Option<i64>) -> i64 {
+            n.unwrap_or(0)
+        }
+
+        async fn ffi_async_twice(n: i64) -> i64 {
+            n * 2
+        }
+    }
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "async"))]
+fn test_ffi_impl_binding() {
+    use typed_ffi::TwiceFfi;
+
+    let source = r#"
+    package main
+
+    import "opt"
+    import "twice"
+
+    func Run() (int64, int64, int64, int64, int, int) {
+        return twice.Twice(1), twice.OrZero(nil), twice.OrZero(int64(5)), twice.AsyncTwice(21),
+            opt.OrOne(nil), opt.OrOne(3)
+    }
+
+    func main() {
+    }
+    "#;
+    let mut engine = engine::Engine::new();
+    engine.register_package(
+        TwiceFfi::auto_gen_ffi_id(),
+        TwiceFfi::auto_gen_ffi_new(),
+        TwiceFfi::auto_gen_ffi_signatures(),
+    );
+    engine.register_fn("opt.or_one", |n: Option<isize>| n.unwrap_or(1));
+
+    let binding = engine.go_binding("twice").unwrap();
+    assert!(binding.contains("\tasync_twice(p0 int64) int64\n"));
+    assert!(binding.contains("\tor_zero(p0 interface{}) int64\n"));
+    let binding = engine.go_binding("opt").unwrap();
+    assert!(binding.contains("\tor_one(p0 interface{}) int\n"));
+
+    let code = compile_with(&engine, source);
+    let vm = engine.new_vm(&code);
+    let result = vm.call("Run", vec![]).unwrap();
+    let result: Vec<i64> = result
+        .iter()
+        .map(|x| match x.typ() {
+            engine::ffi::ValueType::Int => *x.as_int() as i64,
+            _ => *x.as_int64(),
+        })
+        .collect();
+    assert_eq!(result, vec![2, 0, 5, 42, 1, 3]);
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "async"))]
+fn test_ffi_type_mismatch() {
+    // the Go declarations don't match the Rust functions
+    let source = r#"
+    package main
+
+    type ffiMymod interface {
+        greet(name int) string
+    }
+
+    type ffiTwice interface {
+        twice(n string) int64
+    }
+
+    func Greet() string {
+        return ffi(ffiMymod, "mymod").greet(42)
+    }
+
+    func Twice() int64 {
+        return ffi(ffiTwice, "twice").twice("21")
+    }
+
+    func main() {
+    }
+    "#;
+    let mut engine = engine::Engine::new();
+    engine.register_fn("mymod.greet", |name: String| format!("hello {}", name));
+    engine.register_extension(
+        typed_ffi::TwiceFfi::auto_gen_ffi_id(),
+        typed_ffi::TwiceFfi::auto_gen_ffi_new(),
+    );
+    let code = compile_with(&engine, source);
+    let vm = engine.new_vm(&code);
+    for (func, msg) in [
+        ("Greet", "FFI: expect String, got Int"),
+        ("Twice", "FFI: expect Int64, got String"),
+    ] {
+        match vm.call(func, vec![]) {
+            Err(engine::ffi::CallError::Panic(data)) => {
+                assert!(data.ffi_error);
+                assert!(data.msg.to_string().contains(msg), "{}", data.msg);
+            }
+            _ => panic!("{} should fail in ffi", func),
+        }
+    }
+}
+
+#[test]
+#[cfg(feature = "go_std")]
+fn test_verify() {
+    use engine::ffi::{Instruction, Opcode, ValueType};
+
+    let source = r#"
+    package main
+
+    func main() {
+        s := 0
+        for i := 0; i < 10; i++ {
+            s += i
+        }
+        assert(s == 45)
+    }
+    "#;
+    let (_, mut code) = compile_string(source);
+    assert!(code.verify().is_ok());
+
+    let bad_const = -(code.consts.len() as i32) - 1;
+    let entry = &mut code.objects.functions[code.entry];
+    let ret = entry.code.len() - 1;
+    let inst = |op0, d, s0| Instruction {
+        op0,
+        op1: Opcode::VOID,
+        t0: ValueType::Int,
+        t1: ValueType::Void,
+        d,
+        s0,
+        s1: 0,
+    };
+    entry
+        .code
+        .insert(ret, inst(Opcode::DUPLICATE, 1000, bad_const));
+    entry.code.insert(ret, inst(Opcode::JUMP, 100, 0));
+    entry.pos.insert(ret, None);
+    entry.pos.insert(ret, None);
+    let errors = code.verify().unwrap_err();
+    let msgs: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+    assert_eq!(msgs.len(), 3, "{:?}", msgs);
+    assert!(msgs[0].ends_with(&format!(
+        " at {}: JUMP: bad jump target {} from {}",
+        ret,
+        ret + 101,
+        ret
+    )));
+    assert!(msgs[1].contains("DUPLICATE: register 1000 out of the frame"));
+    assert!(msgs[2].contains("DUPLICATE: bad constant"));
+}
+
+#[test]
+#[cfg(feature = "go_std")]
+fn test_verify_indices() {
+    use engine::ffi::{Bytecode, Opcode};
+
+    let source = r#"
+    package main
+
+    type Inner struct {
+        a int
+    }
+
+    type Outer struct {
+        Inner
+        b int
+    }
+
+    type I interface {
+        F() int
+    }
+
+    func (o Outer) F() int {
+        return o.b
+    }
+
+    var g int
+
+    func main() {
+        o := Outer{}
+        o.b = 1
+        o.a = 2
+        p := &o.b
+        q := &o.a
+        g = o.b + o.a
+        r := &g
+        var i I = o
+        f := i.F
+        assert(*p+*q+*r+f()+g == 10)
+    }
+    "#;
+    let engine = engine::Engine::new();
+    let compile = || compile_with(&engine, source);
+    let code = compile();
+    assert!(code.verify().is_ok(), "{:?}", code.verify());
+
+    fn func(code: &mut Bytecode) -> &mut engine::ffi::FunctionObj {
+        let functions = &mut code.objects.functions;
+        let main = functions.vec().iter().position(|f| f.name == "main.main");
+        &mut functions[main.unwrap().into()]
+    }
+    // corrupts the first `op` of main.main with `f`, and returns the error
+    let corrupt = |op: Opcode, f: &dyn Fn(&mut Bytecode, usize)| -> String {
+        let mut code = compile();
+        let pc = func(&mut code).code.iter().position(|x| x.op0 == op);
+        let pc = pc.unwrap();
+        f(&mut code, pc);
+        let errors = code.verify().unwrap_err();
+        assert_eq!(errors.len(), 1, "{:?}", errors);
+        assert_eq!(errors[0].pc, Some(pc));
+        errors[0].msg.clone()
+    };
+
+    // Outer has a field for Inner.a too
+    for op in [Opcode::LOAD_STRUCT, Opcode::REF_STRUCT_FIELD] {
+        let msg = corrupt(op, &|c, pc| func(c).code[pc].s1 = 3);
+        assert_eq!(msg, format!("{}: bad field 3", op));
+    }
+    let msg = corrupt(Opcode::STORE_STRUCT, &|c, pc| func(c).code[pc].s0 = 3);
+    assert_eq!(msg, "STORE_STRUCT: bad field 3");
+
+    // the path goes through Inner, which has only one field
+    for op in [
+        Opcode::LOAD_EMBEDDED,
+        Opcode::REF_EMBEDDED,
+        Opcode::STORE_EMBEDDED,
+    ] {
+        let msg = corrupt(op, &|c, pc| {
+            c.indices.push(vec![0, 1]);
+            let path = c.indices.len() as i32 - 1;
+            match op {
+                Opcode::STORE_EMBEDDED => func(c).code[pc].s0 = path,
+                _ => func(c).code[pc].s1 = path,
+            }
+        });
+        assert!(msg.starts_with(&format!("{}: bad field 1 in embedded field path", op)));
+    }
+
+    for op in [Opcode::LOAD_PKG, Opcode::REF_PKG_MEMBER] {
+        let msg = corrupt(op, &|c, pc| func(c).code[pc].s1 = 1000);
+        assert!(msg.starts_with(&format!("{}: bad member 1000 of package", op)));
+        // the package has to be a constant
+        let msg = corrupt(op, &|c, pc| func(c).code[pc].s0 = 0);
+        assert!(msg.starts_with(&format!("{}: bad constant", op)), "{}", msg);
+    }
+    let msg = corrupt(Opcode::STORE_PKG, &|c, pc| func(c).code[pc].s0 = 1000);
+    assert!(msg.starts_with("STORE_PKG: bad member 1000 of package"));
+
+    let msg = corrupt(Opcode::BIND_I_METHOD, &|c, pc| func(c).code[pc].s1 = 1);
+    assert_eq!(msg, "BIND_I_METHOD: bad method 1");
+
+    // the types of the operands can't be known without the compiler's
+    let msg = corrupt(Opcode::LOAD_STRUCT, &|c, pc| {
+        func(c).operand_metas.remove(&(pc as i32));
+    });
+    assert_eq!(msg, "LOAD_STRUCT: the type of the operand is unknown");
+}
+
+#[test]
+#[cfg(feature = "go_std")]
+fn test_disassemble() {
+    let source = r#"
+    package main
+
+    func add(a, b int) int {
+        return a + b
+    }
+
+    func main() {
+        s := "go"
+        for i := 0; i < 3; i++ {
+            s += "!"
+        }
+        assert(add(len(s), 40) == 45)
+    }
+    "#;
+    let (_, code) = compile_string(source);
+    let text = code.disassemble();
+    assert!(text.contains("func main.add (package main, 2 params, 1 results"));
+    assert!(text.contains("ADD<Int>  r0, r1, r2"));
+    // the loop jumps back to its condition
+    assert!(text.contains("L0:"));
+    assert!(text.contains("JUMP  L0, _, _"));
+    assert!(text.contains("ADD_ASSIGN<String>  r0, k"));
+    assert!(text.contains(" = \"!\""));
+    #[cfg(feature = "instruction_pos")]
+    assert!(text.contains(":5:16"));
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "instruction_pos"))]
+fn test_debugger() {
+    use engine::ffi::{DebugAction, DebugView, Debugger, StopReason};
+
+    let source = r#"
+    package main
+
+    var total int
+
+    func add(a, b int) int {
+        sum := a + b // add
+        return sum // ret
+    }
+
+    func main() {
+        x := 1
+        f := func() int {
+            return x * 2 // closure
+        }
+        for i := 0; i < 2; i++ {
+            x := add(x, i)
+            total += x
+        }
+        total += f()
+    }
+    "#;
+    let line = |mark: &str| source.lines().position(|l| l.contains(mark)).unwrap() + 1;
+
+    struct Script {
+        actions: Vec<DebugAction>,
+        log: Rc<RefCell<Vec<String>>>,
+        closure_line: usize,
+    }
+
+    impl Debugger for Script {
+        fn on_stop(&mut self, reason: StopReason, view: &DebugView) -> DebugAction {
+            let frames = view.stack_frames();
+            let vars = |vars: Vec<(String, engine::ffi::GosValue)>| {
+                vars.iter()
+                    .map(|(n, v)| format!("{}={}", n, v))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            self.log.borrow_mut().push(format!(
+                "{:?} {}:{} [{}] [{}]",
+                reason,
+                frames[0].func,
+                frames[0].line,
+                vars(view.locals(0)),
+                vars(view.up_values(0)),
+            ));
+            if self.log.borrow().len() == 5 {
+                assert_eq!(frames[1].func, "main.main");
+                assert_eq!(*view.lookup(1, "i").unwrap().as_int(), 1);
+                assert_eq!(*view.lookup(0, "total").unwrap().as_int(), 1);
+                let globals: Vec<String> = view.globals(0).into_iter().map(|x| x.0).collect();
+                assert_eq!(globals, vec!["total"]);
+                view.clear_breakpoints("temp_file.gos");
+                view.set_breakpoint("temp_file.gos", self.closure_line);
+            }
+            self.actions.pop().unwrap_or(DebugAction::Continue)
+        }
+    }
+
+    let (engine, code) = compile_string(source);
+    let mut vm = engine.new_vm(&code);
+    assert_eq!(vm.set_breakpoint("temp_file.gos", 1), None);
+    let log = Rc::new(RefCell::new(vec![]));
+    vm.set_debugger(Box::new(Script {
+        actions: vec![
+            DebugAction::Continue,
+            DebugAction::StepOver,
+            DebugAction::StepIn,
+            DebugAction::StepOver,
+        ],
+        log: log.clone(),
+        closure_line: line("// closure"),
+    }));
+    // the line of the signature resolves to the first line of the body
+    assert_eq!(
+        vm.set_breakpoint("temp_file.gos", line("// add") - 1),
+        Some(line("// add"))
+    );
+    assert_eq!(vm.set_breakpoint("other.gos", line("// add")), None);
+    vm.run_main().unwrap();
+
+    let (add, ret, closure) = (line("// add"), line("// ret"), line("// closure"));
+    let call = line("add(x, i)");
+    let expected = vec![
+        format!("Breakpoint main.add:{} [a=1 b=0] []", add),
+        format!("Step main.add:{} [a=1 b=0 sum=1] []", ret),
+        format!("Step main.main:{} [x=1 f=<closure> i=0] []", call),
+        format!("Step main.main:{} [f=<closure> i=0 x=1] []", call + 1),
+        format!("Breakpoint main.add:{} [a=1 b=1] []", add),
+        format!("Breakpoint main.main.func1:{} [] [x=1]", closure),
+    ];
+    assert_eq!(*log.borrow(), expected);
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "instruction_pos", feature = "trace"))]
+fn test_tracer() {
+    use engine::ffi::{Bytecode, TraceEvent, Tracer};
+
+    let source = r#"
+    package main
+
+    func safe(n int) (r int) {
+        defer func() {
+            if e := recover(); e != nil { // recover
+                r = -1
+            }
+        }()
+        if n == 0 {
+            panic("zero") // panic
+        }
+        return n // ret
+    } // end
+
+    func main() {
+        done := make(chan int)
+        go func() {
+            done <- safe(0) // go
+        }()
+        assert(<-done == -1)
+        assert(safe(2) == 2) // call
+    }
+    "#;
+    let line = |mark: &str| source.lines().position(|l| l.contains(mark)).unwrap() + 1;
+
+    struct Log<'a> {
+        code: &'a Bytecode,
+        events: Rc<RefCell<Vec<String>>>,
+        instructions: Rc<RefCell<usize>>,
+    }
+
+    impl<'a> Tracer for Log<'a> {
+        fn on_event(&mut self, event: TraceEvent) {
+            let name = |f| &self.code.objects.functions[f].name;
+            let line = |pos: Option<u32>| {
+                let fs = self.code.file_set.as_ref().unwrap();
+                pos.and_then(|p| fs.position(p as usize))
+                    .map_or(0, |p| p.line)
+            };
+            let text = match event {
+                TraceEvent::Instruction { .. } => {
+                    *self.instructions.borrow_mut() += 1;
+                    return;
+                }
+                TraceEvent::Call { fiber, func, pos } if name(func).starts_with("main.") => {
+                    format!("{} call {}:{}", fiber, name(func), line(pos))
+                }
+                TraceEvent::Return { fiber, func, .. } if name(func).starts_with("main.") => {
+                    format!("{} return {}", fiber, name(func))
+                }
+                TraceEvent::Spawn { fiber, func } => format!("{} spawn {}", fiber, name(func)),
+                TraceEvent::Exit { fiber } => format!("{} exit", fiber),
+                TraceEvent::Panic {
+                    fiber, pos, value, ..
+                } => format!("{} panic {}:{}", fiber, value, line(pos)),
+                TraceEvent::Recover {
+                    fiber,
+                    func,
+                    pos,
+                    value,
+                } => format!("{} recover {} {}:{}", fiber, value, name(func), line(pos)),
+                _ => return,
+            };
+            self.events.borrow_mut().push(text);
+        }
+    }
+
+    let (engine, code) = compile_string(source);
+    let mut vm = engine.new_vm(&code);
+    let events = Rc::new(RefCell::new(vec![]));
+    let instructions = Rc::new(RefCell::new(0));
+    vm.set_tracer(Box::new(Log {
+        code: &code,
+        events: events.clone(),
+        instructions: instructions.clone(),
+    }));
+    vm.run_main().unwrap();
+
+    assert!(*instructions.borrow() > 0);
+    // the entry function calls main.init and main.main, not from the source
+    let expected = vec![
+        "0 spawn main".to_owned(),
+        "0 call main.init:0".to_owned(),
+        "0 return main.init".to_owned(),
+        "0 call main.main:0".to_owned(),
+        "1 spawn main.main.func1".to_owned(),
+        format!("1 call main.safe:{}", line("// go")),
+        format!("1 panic zero:{}", line("// panic")),
+        format!("1 call main.safe.func1:{}", line("// end")),
+        format!("1 recover zero main.safe.func1:{}", line("// recover")),
+        "1 return main.safe.func1".to_owned(),
+        "1 return main.safe".to_owned(),
+        "1 return main.main.func1".to_owned(),
+        "1 exit".to_owned(),
+        format!("0 call main.safe:{}", line("// call")),
+        format!("0 call main.safe.func1:{}", line("// ret")),
+        "0 return main.safe.func1".to_owned(),
+        "0 return main.safe".to_owned(),
+        "0 return main.main".to_owned(),
+        "0 exit".to_owned(),
+    ];
+    assert_eq!(*events.borrow(), expected);
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "instruction_pos"))]
+fn test_profiler() {
+    use engine::ffi::Sampling;
+
+    let source = r#"
+    package main
+
+    func sum(n int) int {
+        s := 0
+        for i := 0; i < n; i++ {
+            s += i // loop
+        }
+        return s
+    }
+
+    func main() {
+        assert(sum(5000) == 12497500) // call
+    }
+    "#;
+    let line = |mark: &str| source.lines().position(|l| l.contains(mark)).unwrap() + 1;
+
+    let (engine, code) = compile_string(source);
+    for sampling in [Sampling::Every(10), Sampling::Yield] {
+        let mut vm = engine.new_vm(&code);
+        assert!(vm.profile().is_none());
+        vm.set_profiler(sampling);
+        vm.run_main().unwrap();
+        let profile = vm.profile().unwrap();
+        assert_eq!(profile.sampling, sampling);
+
+        let total: u64 = profile.samples.iter().map(|s| s.instructions).sum();
+        assert!(total > 5000 * 3, "{}", total);
+        // most of the time is spent in the loop, called by main
+        let lines = profile.lines();
+        assert_eq!((lines[0].0.as_str(), lines[0].2), ("main", 0));
+        assert_eq!(
+            (lines[1].0.as_str(), lines[1].2),
+            ("main.main", line("// call"))
+        );
+        assert!(lines[1].3 * 10 > total * 9);
+        let hot = &profile.samples[0];
+        assert_eq!(hot.stack[0].func, "main.sum");
+        assert!(hot.stack[0].line >= line("for i") && hot.stack[0].line <= line("// loop"));
+
+        let mut folded = vec![];
+        profile.write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        let prefix = format!("main;main.main:{};main.sum:", line("// call"));
+        assert!(folded.lines().next().unwrap().starts_with(&prefix));
+
+        let mut pprof = vec![];
+        profile.write_pprof(&mut pprof).unwrap();
+        let text = String::from_utf8_lossy(&pprof);
+        assert!(text.contains("main.sum") && text.contains("instructions"));
+    }
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "instruction_pos", feature = "stats"))]
+fn test_stats() {
+    let source = r#"
+    package main
+
+    type point struct {
+        x, y int
+    }
+
+    func sum(n int) int {
+        s := 0
+        p := point{1, 2}
+        for i := 0; i < n; i++ {
+            q := p // copy
+            s += q.x // loop
+        }
+        return s
+    }
+
+    func main() {
+        assert(sum(5000) == 5000)
+    }
+    "#;
+    let line = |mark: &str| source.lines().position(|l| l.contains(mark)).unwrap() + 1;
+
+    let (engine, code) = compile_string(source);
+    let vm = engine.new_vm(&code);
+    vm.run_main().unwrap();
+    let stats = vm.stats();
+    assert!(stats.instructions > 5000 * 4, "{}", stats.instructions);
+    let by_op: u64 = stats.opcodes.iter().map(|(_, n)| n).sum();
+    assert_eq!(by_op, stats.instructions);
+    assert_eq!(stats.functions[0].0, "main.sum");
+    assert!(stats.functions[0].1 > 5000 * 4);
+    // the loop is the hottest, the copy takes a clone and an allocation each time
+    let hot: Vec<usize> = stats.lines.iter().take(3).map(|l| l.2).collect();
+    assert!(hot.contains(&line("// copy")) && hot.contains(&line("// loop")));
+    assert!(stats.lines.iter().all(|l| l.3 <= stats.lines[0].3));
+    let clones = stats
+        .clones
+        .iter()
+        .find(|(t, _)| *t == engine::ffi::types::ValueType::Struct);
+    assert!(clones.unwrap().1 >= 5000);
+    let structs = stats.allocations.iter().find(|(k, _)| *k == "struct");
+    assert!(structs.unwrap().1 >= 5000);
+    assert!(stats
+        .to_string()
+        .starts_with(&format!("instructions: {}\n", stats.instructions)));
+
+    // the engine writes the report to its own stderr, not the process's
+    let err = WriteBuf::new();
+    engine.set_std_io(None, None, Some(Box::new(err.clone())));
+    engine.try_run_bytecode(&code).unwrap();
+    assert!(err.into_string().starts_with("instructions: "));
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "serde_borsh"))]
+fn test_bytecode_container() {
+    let source = r#"
+    package main
+
+    import "mymod"
+
+    func Run() int64 {
+        return mymod.Add(2, 3)
+    }
+
+    func main() {
+    }
+    "#;
+    let (sr, path) = string_reader(source);
+    let mut engine = engine::Engine::new();
+    engine.register_fn("mymod.add", |a: i64, b: i64| a + b);
+    let data = engine
+        .compile_serialize(&sr, &path, true, false, false)
+        .unwrap();
+    let code = engine.load_bytecode(&data).unwrap();
+    assert_eq!(code.ffi_names(), vec!["mymod"]);
+    let result = engine.new_vm(&code).call("Run", vec![]).unwrap();
+    assert_eq!(*result[0].as_int64(), 5);
+
+    let load = |f: &dyn Fn(&mut Vec<u8>)| {
+        let mut data = data.clone();
+        f(&mut data);
+        engine.load_bytecode(&data).err().unwrap()
+    };
+    assert!(matches!(
+        load(&|d| d[0] = b'X'),
+        engine::BytecodeError::BadMagic
+    ));
+    assert!(matches!(
+        load(&|d| d[4] += 1),
+        engine::BytecodeError::Version { .. }
+    ));
+    assert!(matches!(
+        load(&|d| d[8] ^= 1),
+        engine::BytecodeError::Features { .. }
+    ));
+    assert!(matches!(
+        load(&|d| d[12] ^= 1),
+        engine::BytecodeError::FfiHash
+    ));
+    assert!(matches!(
+        load(&|d| *d.last_mut().unwrap() ^= 1),
+        engine::BytecodeError::Checksum
+    ));
+    assert!(matches!(
+        engine::Engine::new().load_bytecode(&data),
+        Err(engine::BytecodeError::MissingFfi(name)) if name == "mymod"
+    ));
+
+    // the FFI is registered, but not with the Go signatures it's built against
+    let mut other = engine::Engine::new();
+    other.register_fn("mymod.add", |a: isize, b: isize| a + b);
+    assert!(matches!(
+        other.load_bytecode(&data),
+        Err(engine::BytecodeError::FfiHash)
+    ));
+    let mut same = engine::Engine::new();
+    same.register_fn("mymod.add", |a: i64, b: i64| a - b);
+    same.register_fn("other.add", |a: isize, b: isize| a + b);
+    assert!(same.load_bytecode(&data).is_ok());
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "serde_borsh"))]
+fn test_compile_cache() {
+    let source = |n: i64| {
+        format!(
+            r#"
+    package main
+
+    import "strconv"
+
+    func Run() string {{
+        return strconv.Itoa({})
+    }}
+
+    func main() {{
+    }}
+    "#,
+            n
+        )
+    };
+    let dir = std::env::temp_dir().join(format!("goscript_cache_{}", std::process::id()));
+    let mut engine = engine::Engine::new();
+    engine.set_cache_dir(Some(dir.clone()));
+    let run = |n: i64| {
+        let (sr, path) = string_reader(source(n));
+        let code = engine.compile(&sr, &path, false, false, false).unwrap();
+        let result = engine.new_vm(&code).call("Run", vec![]).unwrap();
+        let s = result[0].as_string().as_str().to_string();
+        s
+    };
+    let artifacts = || {
+        let mut files: Vec<(PathBuf, std::time::SystemTime)> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().unwrap() == "gosp")
+            .map(|p| {
+                let modified = std::fs::metadata(&p).unwrap().modified().unwrap();
+                (p, modified)
+            })
+            .collect();
+        files.sort();
+        files
+    };
+
+    assert_eq!(run(1), "1");
+    let first = artifacts();
+    // the packages strconv imports are cached one by one too
+    assert!(first.len() > 2);
+    // all of them are loaded next time, none is written again
+    assert_eq!(run(1), "1");
+    assert_eq!(artifacts(), first);
+    // only the main package is compiled when it changes
+    assert_eq!(run(2), "2");
+    let second = artifacts();
+    assert_eq!(second.len(), first.len() + 1);
+    assert!(first.iter().all(|f| second.contains(f)));
+
+    // and bad ones are replaced
+    for (path, _) in second.iter() {
+        std::fs::write(path, b"bad").unwrap();
+    }
+    assert_eq!(run(1), "1");
+    assert_eq!(run(2), "2");
+    let third = artifacts();
+    assert_eq!(third.len(), second.len());
+    assert!(third
+        .iter()
+        .all(|(p, _)| std::fs::read(p).unwrap() != b"bad"));
+    assert_eq!(run(2), "2");
+    assert_eq!(artifacts(), third);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "async"))]
+fn test_compile_with_module() {
+    let engine = engine::Engine::new();
+    let sr = engine::SourceReader::local_fs(PathBuf::from("../std/"), PathBuf::from("./"));
+    let module = engine
+        .compile_module(&sr, &["fmt", "strconv"], false, false)
+        .unwrap();
+    assert!(module.packages().contains(&"fmt".to_owned()));
+    assert!(module.packages().contains(&"strconv".to_owned()));
+    assert!(!module.packages().contains(&"strings".to_owned()));
+    let sizes = |code: &engine::Bytecode| {
+        let objs = &code.objects;
+        let (funcs, pkgs) = (objs.functions.vec().len(), objs.packages.vec().len());
+        (objs.metas.vec().len(), funcs, pkgs, code.consts.len())
+    };
+    let module_sizes = sizes(&module.bytecode());
+
+    let compile = |source: &'static str| {
+        let (sr, path) = string_reader(source);
+        engine.compile_with_module(&module, &sr, &path, true, false, false)
+    };
+    let code = compile(
+        r#"
+    package main
+
+    import "strconv"
+
+    func Run() string {
+        return strconv.Itoa(42)
+    }
+
+    func main() {
+    }
+    "#,
+    )
+    .unwrap();
+    let result = engine.new_vm(&code).call("Run", vec![]).unwrap();
+    assert_eq!(*result[0].as_string().as_str(), *"42");
+
+    // the packages not in the module are compiled from source
+    let code2 = compile(
+        r#"
+    package main
+
+    import (
+        "fmt"
+        "strings"
+    )
+
+    func Run() string {
+        return fmt.Sprintf("%d-%d", 7, strings.Index("gopher", "ph"))
+    }
+
+    func main() {
+    }
+    "#,
+    )
+    .unwrap();
+    let result = engine.new_vm(&code2).call("Run", vec![]).unwrap();
+    assert_eq!(*result[0].as_string().as_str(), *"7-2");
+
+    // the code of the module is generated once, programs are generated on top of it
+    let packages = |code: &engine::Bytecode| -> Vec<String> {
+        let pkgs = code.objects.packages.vec().iter();
+        pkgs.map(|p| p.name().to_owned()).collect()
+    };
+    let (pkgs, pkgs2) = (packages(&code), packages(&code2));
+    assert_eq!(pkgs.last().unwrap(), "main");
+    assert!(pkgs2.contains(&"strings".to_owned()));
+    assert_eq!(pkgs[..pkgs.len() - 1], pkgs2[..pkgs.len() - 1]);
+    let funcs = code.objects.functions.vec();
+    let module_funcs = funcs.iter().position(|f| f.name == "main.init").unwrap();
+    assert!(code2.objects.functions.vec()[..module_funcs]
+        .iter()
+        .zip(funcs.iter())
+        .all(|(a, b)| a.name == b.name));
+
+    let errs = compile(
+        r#"
+    package main
+
+    import "strconv"
+
+    func main() {
+        var s int = strconv.Itoa(1)
+        _ = s
+    }
+    "#,
+    )
+    .err()
+    .unwrap();
+    assert_eq!(errs.len(), 1);
+    assert!(!module.packages().contains(&"strings".to_owned()));
+
+    // the programs are split off the module, which is left as it was
+    assert_eq!(sizes(&module.bytecode()), module_sizes);
+    let code3 = compile(
+        r#"
+    package main
+
+    import "strings"
+
+    func Run() string {
+        return strings.TrimSpace(" go ")
+    }
+
+    func main() {
+    }
+    "#,
+    )
+    .unwrap();
+    let result = engine.new_vm(&code3).call("Run", vec![]).unwrap();
+    assert_eq!(*result[0].as_string().as_str(), *"go");
+    assert_eq!(sizes(&module.bytecode()), module_sizes);
+    assert!(code3.verify().is_ok());
+    let result = engine.new_vm(&code2).call("Run", vec![]).unwrap();
+    assert_eq!(*result[0].as_string().as_str(), *"7-2");
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "async"))]
+fn test_repl() {
+    let out = WriteBuf::new();
+    let mut cfg = engine::Config::default();
+    cfg.std_out = Some(Box::new(out.clone()));
+    let sr = engine::SourceReader::local_fs(PathBuf::from("../std/"), PathBuf::from("./"));
+    let mut repl = engine::Repl::new(cfg, sr).unwrap();
+    let mut eval = |input: &str| repl.eval(input).map_err(|e| e.to_string());
+
+    assert_eq!(eval("import \"fmt\""), Ok(None));
+    assert_eq!(eval("import s \"strings\""), Ok(None));
+    assert_eq!(eval("x := 40"), Ok(None));
+    assert_eq!(eval("x + 2"), Ok(Some("42".to_owned())));
+    assert_eq!(
+        eval("type P struct { a int }\n\nfunc (p P) Twice() int {\n    return p.a * 2\n}"),
+        Ok(None)
+    );
+    assert_eq!(eval("p := P{x}\nx = 1"), Ok(None));
+    assert_eq!(eval("p.Twice()"), Ok(Some("80".to_owned())));
+    assert!(eval("x, s.ToUpper(\"go\")").is_err());
+    assert_eq!(eval("s.Index(\"go\", \"o\")"), Ok(Some("1".to_owned())));
+    let err = eval("s.Cut(\"a=b\", \"=\")").unwrap_err();
+    assert!(err.contains("input:1:3  Cut not declared"), "{}", err);
+    // the earlier inputs don't print again
+    assert_eq!(eval("func two() (int, bool) { return 3, true }"), Ok(None));
+    assert_eq!(eval("two()"), Ok(Some("3, true".to_owned())));
+    assert_eq!(
+        eval("fmt.Println(\"hi\", x)"),
+        Ok(Some("5, <nil>".to_owned()))
+    );
+    assert!(eval("fmt.Println(\"hi again\")").is_ok());
+
+    // the errors are reported with the positions in the input
+    let err = eval("y := 1\nvar z string = x").unwrap_err();
+    assert!(err.contains("input:2:16"), "{}", err);
+    assert!(eval("z").is_err());
+    // shadowing the earlier variables
+    assert_eq!(eval("x := \"one\""), Ok(None));
+    assert_eq!(eval("x"), Ok(Some("one".to_owned())));
+    assert_eq!(eval("import \"nonexistent\"").is_err(), true);
+    assert_eq!(eval("p.a"), Ok(Some("40".to_owned())));
+
+    let err = repl.eval("x += \"!\"\npanic(x)").unwrap_err();
+    assert_eq!(err.to_string(), "panic: one!");
+    let frame = &err.call_stack().unwrap()[0];
+    assert_eq!((frame.file.as_str(), frame.line), ("input", 2));
+    // what the input that panicked did before it stays
+    assert_eq!(repl.eval("x").unwrap(), Some("one!".to_owned()));
+    // declaring again what the input uses
+    assert_eq!(repl.eval("x := len(x)\nconst c = 2").unwrap(), None);
+    assert_eq!(repl.eval("x * c").unwrap(), Some("8".to_owned()));
+
+    assert_eq!(out.into_string(), "hi 1\nhi again\n");
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone)]
+struct Limits {
+    max_size: isize,
+    tags: Vec<String>,
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize, Debug, PartialEq, Clone)]
+struct Config {
+    name: String,
+    verbose: bool,
+    limits: Limits,
+    ratio: f64,
+    extra: std::collections::HashMap<String, i32>,
+    parent: Option<Box<Config>>,
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "serde"))]
+fn test_serde() {
+    let source = r#"
+    package main
+
+    type Limits struct {
+        MaxSize int
+        Tags []string
+    }
+
+    type Config struct {
+        Name string
+        Verbose bool
+        Limits Limits
+        Ratio float64
+        Extra map[string]int32
+        Parent *Config
+    }
+
+    func Bump(c Config) Config {
+        c.Name += "!"
+        c.Limits.MaxSize *= 2
+        c.Limits.Tags = append(c.Limits.Tags, "bumped")
+        c.Extra["count"] += 1
+        if c.Parent != nil {
+            c.Parent.Verbose = true
+        }
+        return c
+    }
+
+    func main() {
+    }
+    "#;
+    let (engine, code) = compile_string(source);
+    let vm = engine.new_vm(&code);
+
+    let parent = Config {
+        name: "parent".to_owned(),
+        verbose: false,
+        limits: Limits {
+            max_size: 1,
+            tags: vec![],
+        },
+        ratio: 0.0,
+        extra: Default::default(),
+        parent: None,
+    };
+    let config = Config {
+        name: "config".to_owned(),
+        verbose: false,
+        limits: Limits {
+            max_size: 21,
+            tags: vec!["a".to_owned()],
+        },
+        ratio: 0.5,
+        extra: [("count".to_owned(), 1)].into_iter().collect(),
+        parent: Some(Box::new(parent.clone())),
+    };
+    let (params, results) = vm.signature("Bump").unwrap();
+    let arg = vm.to_gos(&config, &params[0]).unwrap();
+    let result = vm.call("Bump", vec![arg]).unwrap();
+    let bumped: Config = vm.from_gos(&result[0], &results[0]).unwrap();
+
+    let mut expected = config.clone();
+    expected.name = "config!".to_owned();
+    expected.limits.max_size = 42;
+    expected.limits.tags.push("bumped".to_owned());
+    expected.extra.insert("count".to_owned(), 2);
+    expected.parent.as_mut().unwrap().verbose = true;
+    assert_eq!(bumped, expected);
+
+    // the Go struct has no such field
+    #[derive(serde::Serialize)]
+    struct Other {
+        color: String,
+    }
+    let other = Other {
+        color: "red".to_owned(),
+    };
+    assert!(vm.to_gos(&other, &params[0]).is_err());
+    assert!(vm.to_gos(&"a string", &params[0]).is_err());
+
+    // the value is not of the Go type
+    let not_config: engine::ffi::GosValue = 42isize.into();
+    assert!(vm.from_gos::<Config>(&not_config, &results[0]).is_err());
+    assert!(vm
+        .from_gos::<Option<Config>>(&not_config, &results[0])
+        .is_err());
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "async"))]
+fn test_vm_goroutines() {
+    let source = r#"
+    package main
+
+    var events = make(chan int, 16)
+    var never = make(chan int)
+    var total int
+
+    func Start() {
+        go func() {
+            for {
+                n := <-events
+                if n < 0 {
+                    panic("negative")
+                }
+                total += n
+            }
+        }()
+    }
+
+    func Push(n int) {
+        events <- n
+    }
+
+    func Total() int {
+        return total
+    }
+
+    func Block() {
+        <-never
+    }
+
+    func main() {
+    }
+    "#;
+    let (engine, code) = compile_string(source);
+    let vm = engine.new_vm(&code);
+    vm.init_packages().unwrap();
+    vm.call("Start", vec![]).unwrap();
+    for frame in 1..=3isize {
+        vm.call("Push", vec![frame.into()]).unwrap();
+        vm.call("Push", vec![frame.into()]).unwrap();
+        vm.run_until_idle().unwrap();
+        let result = vm.call("Total", vec![]).unwrap();
+        assert_eq!(*result[0].as_int(), frame * (frame + 1));
+    }
+    assert!(matches!(
+        vm.call("Block", vec![]),
+        Err(engine::ffi::CallError::Deadlock)
+    ));
+    vm.call("Push", vec![(-1isize).into()]).unwrap();
+    assert!(matches!(
+        vm.run_until_idle(),
+        Err(engine::ffi::CallError::Panic(_))
+    ));
+    vm.shutdown();
+}
+
+#[test]
+#[cfg(all(feature = "go_std", feature = "async"))]
+fn test_goroutine_before_block() {
+    // main blocks right after spawning the goroutine it waits for, which has
+    // to get to run before the VM tells that all the goroutines are blocked
+    let source = r#"
+    package main
+
+    type ffiProbe interface {
+        received(n int)
+    }
+
+    var probe = ffi(ffiProbe, "probe")
+
+    func main() {
+        done := make(chan int)
+        go func() {
+            done <- 42
+        }()
+        probe.received(<-done)
+    }
+    "#;
+    let received = Arc::new(Mutex::new(None));
+    let mut engine = engine::Engine::new();
+    let r = received.clone();
+    engine.register_fn("probe.received", move |n: isize| {
+        *r.lock().unwrap() = Some(n);
+    });
+    let code = compile_with(&engine, source);
+    let vm = engine.new_vm(&code);
+    vm.run_main().unwrap();
+    assert_eq!(*received.lock().unwrap(), Some(42));
+}
+
 #[test]
 #[cfg(feature = "read_zip")]
 fn test_zip() {
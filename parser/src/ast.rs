@@ -484,7 +484,7 @@ impl Node for Decl {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct File {
     pub package: position::Pos,
     pub name: IdentKey,
@@ -523,7 +523,7 @@ impl Node for File {
 // A BadExpr node is a placeholder for expressions containing
 // syntax errors for which no correct expression nodes can be
 // created.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BadExpr {
     pub from: position::Pos,
     pub to: position::Pos,
@@ -596,28 +596,28 @@ impl Ident {
 
 // An Ellipsis node stands for the "..." type in a
 // parameter list or the "..." length in an array type.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Ellipsis {
     pub pos: position::Pos,
     pub elt: Option<Expr>, // ellipsis element type (parameter lists only)
 }
 
 // A BasicLit node represents a literal of basic type.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BasicLit {
     pub pos: position::Pos,
     pub token: token::Token,
 }
 
 // A FuncLit node represents a function literal.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct FuncLit {
     pub typ: FuncTypeKey,
     pub body: Rc<BlockStmt>,
 }
 
 // A CompositeLit node represents a composite literal.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CompositeLit {
     pub typ: Option<Expr>,
     pub l_brace: position::Pos,
@@ -627,14 +627,14 @@ pub struct CompositeLit {
 }
 
 // A ParenExpr node represents a parenthesized expression.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ParenExpr {
     pub l_paren: position::Pos,
     pub expr: Expr,
     pub r_paren: position::Pos,
 }
 // A SelectorExpr node represents an expression followed by a selector.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SelectorExpr {
     pub expr: Expr,
     pub sel: IdentKey,
@@ -647,7 +647,7 @@ impl SelectorExpr {
 }
 
 // An IndexExpr node represents an expression followed by an index.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct IndexExpr {
     pub expr: Expr,
     pub l_brack: position::Pos,
@@ -656,7 +656,7 @@ pub struct IndexExpr {
 }
 
 // An SliceExpr node represents an expression followed by slice indices.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SliceExpr {
     pub expr: Expr,
     pub l_brack: position::Pos,
@@ -669,7 +669,7 @@ pub struct SliceExpr {
 
 // A TypeAssertExpr node represents an expression followed by a
 // type assertion.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct TypeAssertExpr {
     pub expr: Expr,
     pub l_paren: position::Pos,
@@ -678,7 +678,7 @@ pub struct TypeAssertExpr {
 }
 
 // A CallExpr node represents an expression followed by an argument list.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CallExpr {
     pub func: Expr,
     pub l_paren: position::Pos,
@@ -695,7 +695,7 @@ impl CallExpr {
 
 // A StarExpr node represents an expression of the form "*" Expression.
 // Semantically it could be a unary "*" expression, or a pointer type.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct StarExpr {
     pub star: position::Pos,
     pub expr: Expr,
@@ -703,7 +703,7 @@ pub struct StarExpr {
 
 // A UnaryExpr node represents a unary expression.
 // Unary "*" expressions are represented via StarExpr nodes.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct UnaryExpr {
     pub op_pos: position::Pos,
     pub op: token::Token,
@@ -711,7 +711,7 @@ pub struct UnaryExpr {
 }
 
 // A BinaryExpr node represents a binary expression.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BinaryExpr {
     pub expr_a: Expr,
     pub op_pos: position::Pos,
@@ -721,7 +721,7 @@ pub struct BinaryExpr {
 
 // A KeyValueExpr node represents (key : value) pairs
 // in composite literals.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct KeyValueExpr {
     pub key: Expr,
     pub colon: position::Pos,
@@ -729,7 +729,7 @@ pub struct KeyValueExpr {
 }
 
 // An ArrayType node represents an array or slice type.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ArrayType {
     pub l_brack: position::Pos,
     pub len: Option<Expr>, // Ellipsis node for [...]T array types, None for slice types
@@ -737,7 +737,7 @@ pub struct ArrayType {
 }
 
 // A StructType node represents a struct type.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct StructType {
     pub struct_pos: position::Pos,
     pub fields: FieldList,
@@ -799,7 +799,7 @@ pub struct InterfaceType {
 }
 
 // A MapType node represents a map type.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct MapType {
     pub map: position::Pos,
     pub key: Expr,
@@ -823,7 +823,7 @@ pub struct ChanType {
 }
 
 // An ImportSpec node represents a single package import.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ImportSpec {
     pub name: Option<IdentKey>,
     pub path: BasicLit,
@@ -832,7 +832,7 @@ pub struct ImportSpec {
 
 // A ValueSpec node represents a constant or variable declaration
 // (ConstSpec or VarSpec production).
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ValueSpec {
     pub names: Vec<IdentKey>,
     pub typ: Option<Expr>,
@@ -840,14 +840,14 @@ pub struct ValueSpec {
 }
 
 // A TypeSpec node represents a type declaration (TypeSpec production).
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct TypeSpec {
     pub name: IdentKey,
     pub assign: position::Pos,
     pub typ: Expr,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BadDecl {
     pub from: position::Pos,
     pub to: position::Pos,
@@ -863,7 +863,7 @@ pub struct BadDecl {
 //	Token::CONST   ValueSpec
 //	Token::TYPE    TypeSpec
 //	Token::VAR     ValueSpec
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct GenDecl {
     pub token_pos: position::Pos,
     pub token: token::Token,
@@ -873,7 +873,7 @@ pub struct GenDecl {
 }
 
 // A FuncDecl node represents a function declaration.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct FuncDecl {
     pub recv: Option<FieldList>,
     pub name: IdentKey,
@@ -887,20 +887,20 @@ impl FuncDecl {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BadStmt {
     pub from: position::Pos,
     pub to: position::Pos,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct EmptyStmt {
     pub semi: position::Pos,
     pub implicit: bool,
 }
 
 // A LabeledStmt node represents a labeled statement.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct LabeledStmt {
     pub label: IdentKey,
     pub colon: position::Pos,
@@ -928,7 +928,7 @@ impl LabeledStmt {
 }
 
 // A SendStmt node represents a send statement.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SendStmt {
     pub chan: Expr,
     pub arrow: position::Pos,
@@ -936,7 +936,7 @@ pub struct SendStmt {
 }
 
 // An IncDecStmt node represents an increment or decrement statement.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct IncDecStmt {
     pub expr: Expr,
     pub token_pos: position::Pos,
@@ -945,7 +945,7 @@ pub struct IncDecStmt {
 
 // An AssignStmt node represents an assignment or
 // a short variable declaration.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct AssignStmt {
     pub lhs: Vec<Expr>,
     pub token_pos: position::Pos,
@@ -975,18 +975,18 @@ impl AssignStmt {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct GoStmt {
     pub go: position::Pos,
     pub call: Expr,
 }
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DeferStmt {
     pub defer: position::Pos,
     pub call: Expr,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ReturnStmt {
     pub ret: position::Pos,
     pub results: Vec<Expr>,
@@ -994,14 +994,14 @@ pub struct ReturnStmt {
 
 // A BranchStmt node represents a break, continue, goto,
 // or fallthrough statement.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BranchStmt {
     pub token_pos: position::Pos,
     pub token: token::Token,
     pub label: Option<IdentKey>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct BlockStmt {
     pub l_brace: position::Pos,
     pub list: Vec<Stmt>,
@@ -1026,7 +1026,7 @@ impl BlockStmt {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct IfStmt {
     pub if_pos: position::Pos,
     pub init: Option<Stmt>,
@@ -1036,7 +1036,7 @@ pub struct IfStmt {
 }
 
 // A CaseClause represents a case of an expression or type switch statement.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CaseClause {
     pub case: position::Pos,
     pub list: Option<Vec<Expr>>,
@@ -1044,7 +1044,7 @@ pub struct CaseClause {
     pub body: Vec<Stmt>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SwitchStmt {
     pub switch: position::Pos,
     pub init: Option<Stmt>,
@@ -1052,7 +1052,7 @@ pub struct SwitchStmt {
     pub body: Rc<BlockStmt>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct TypeSwitchStmt {
     pub switch: position::Pos,
     pub init: Option<Stmt>,
@@ -1061,7 +1061,7 @@ pub struct TypeSwitchStmt {
 }
 
 // A CommClause node represents a case of a select statement.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct CommClause {
     //communication
     pub case: position::Pos,
@@ -1070,13 +1070,13 @@ pub struct CommClause {
     pub body: Vec<Stmt>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct SelectStmt {
     pub select: position::Pos,
     pub body: Rc<BlockStmt>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct ForStmt {
     pub for_pos: position::Pos,
     pub init: Option<Stmt>,
@@ -1085,7 +1085,7 @@ pub struct ForStmt {
     pub body: Rc<BlockStmt>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RangeStmt {
     pub for_pos: position::Pos,
     pub key: Option<Expr>,
@@ -1096,7 +1096,7 @@ pub struct RangeStmt {
     pub body: Rc<BlockStmt>,
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Field {
     pub names: Vec<IdentKey>,
     pub typ: Expr,
@@ -1155,8 +1155,8 @@ impl FieldList {
 
     pub fn end(&self, objs: &AstObjects) -> position::Pos {
         match self.closing {
-            Some(c) => c,
-            None => self.list[self.list.len() - 1].pos(objs),
+            Some(c) => c + 1,
+            None => self.list[self.list.len() - 1].end(objs),
         }
     }
 }
@@ -17,7 +17,7 @@ pub trait PiggyVecKey {
 
 /// A vec that you can only insert into, so that the index can be used as a key
 ///
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct PiggyVec<K, V>
 where
     K: PiggyVecKey + From<usize>,
@@ -54,6 +54,24 @@ where
             phantom: PhantomData {},
         }
     }
+
+    /// Drops the values from the key `len` on
+    #[inline]
+    pub fn truncate(&mut self, len: usize) {
+        self.vec.truncate(len)
+    }
+
+    /// Removes the values from the key `at` on and returns them
+    #[inline]
+    pub fn split_off(&mut self, at: usize) -> Vec<V> {
+        self.vec.split_off(at)
+    }
+
+    /// Adds the values after the last one, the keys of them go on from there
+    #[inline]
+    pub fn append(&mut self, other: &mut Vec<V>) {
+        self.vec.append(other)
+    }
 }
 
 impl<K, V> Index<K> for PiggyVec<K, V>
@@ -132,6 +150,67 @@ where
     }
 }
 
+/// Maps the keys in some ranges to the keys in other ranges of the same sizes,
+/// it's how the values are moved from a PiggyVec to another
+#[derive(Clone, Debug, Default)]
+pub struct RangeMap {
+    ranges: Vec<(std::ops::Range<usize>, usize)>,
+}
+
+impl RangeMap {
+    pub fn new() -> RangeMap {
+        RangeMap { ranges: vec![] }
+    }
+
+    /// Maps the keys in the ranges, in order, to 0, 1, 2...
+    pub fn flatten<'a>(ranges: impl IntoIterator<Item = &'a std::ops::Range<usize>>) -> RangeMap {
+        let mut map = RangeMap::new();
+        let mut to = 0;
+        for r in ranges.into_iter() {
+            map.insert(r.clone(), to);
+            to += r.len();
+        }
+        map
+    }
+
+    /// The reverse of `flatten`, maps 0, 1, 2... to the keys in the ranges, in order
+    pub fn unflatten<'a>(ranges: impl IntoIterator<Item = &'a std::ops::Range<usize>>) -> RangeMap {
+        let mut map = RangeMap::new();
+        let mut from = 0;
+        for r in ranges.into_iter() {
+            map.insert(from..from + r.len(), r.start);
+            from += r.len();
+        }
+        map
+    }
+
+    /// Maps the keys in `from` to the ones from `to` on, `from` must not overlap
+    /// the ranges already in the map
+    pub fn insert(&mut self, from: std::ops::Range<usize>, to: usize) {
+        if from.is_empty() {
+            return;
+        }
+        let i = self.ranges.partition_point(|(r, _)| r.start < from.start);
+        self.ranges.insert(i, (from, to));
+    }
+
+    pub fn get(&self, k: usize) -> Option<usize> {
+        let i = self.ranges.partition_point(|(r, _)| r.start <= k);
+        let (r, to) = self.ranges.get(i.checked_sub(1)?)?;
+        r.contains(&k).then(|| to + k - r.start)
+    }
+
+    #[inline]
+    pub fn contains(&self, k: usize) -> bool {
+        self.get(k).is_some()
+    }
+
+    #[inline]
+    pub fn key<K: PiggyVecKey + From<usize>>(&self, k: K) -> Option<K> {
+        self.get(k.as_usize()).map(|x| x.into())
+    }
+}
+
 #[macro_export]
 macro_rules! piggy_key_type {
     ( $(#[$outer:meta])* $vis:vis struct $name:ident; $($rest:tt)* ) => {
@@ -191,6 +270,7 @@ pub type Fields = PiggyVec<FieldKey, ast::Field>;
 pub type Entitys = PiggyVec<EntityKey, scope::Entity>;
 pub type Scopes = PiggyVec<ScopeKey, scope::Scope>;
 
+#[derive(Clone)]
 pub struct AstObjects {
     pub l_stmts: LabeledStmts,
     pub a_stmts: AssignStmts,
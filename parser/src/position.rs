@@ -63,7 +63,7 @@ impl fmt::Display for FilePos {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct File {
     name: Rc<String>,
     base: usize,
@@ -224,7 +224,7 @@ impl BorshDeserialize for File {
 }
 
 #[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct FileSet {
     base: usize,
     files: Vec<File>,
@@ -267,6 +267,54 @@ impl FileSet {
         }
     }
 
+    /// Removes the files from the `at`th one on and returns them, the positions
+    /// of the files added next start where the ones left end
+    pub fn split_off(&mut self, at: usize) -> Vec<File> {
+        let files = self.files.split_off(at);
+        if let Some(f) = files.first() {
+            self.base = f.base;
+        }
+        files
+    }
+
+    /// Adds back the files split off a file set with the same files before them
+    pub fn append(&mut self, mut files: Vec<File>) {
+        if let Some(f) = files.last() {
+            if files[0].base < self.base {
+                panic!("illegal base");
+            }
+            self.base = f.base + f.size + 1;
+        }
+        self.files.append(&mut files);
+    }
+
+    /// Returns copies of the files in the range of positions, moved so that the
+    /// range starts at 0
+    pub fn files_in(&self, r: std::ops::Range<usize>) -> Vec<File> {
+        self.files
+            .iter()
+            .filter(|f| r.contains(&f.base))
+            .map(|f| File {
+                base: f.base - r.start,
+                ..f.clone()
+            })
+            .collect()
+    }
+
+    /// Adds the files returned by `files_in` after the last one, moved by the base
+    /// of the file set, which is returned
+    pub fn append_moved(&mut self, mut files: Vec<File>) -> usize {
+        let base = self.base;
+        for f in files.iter_mut() {
+            f.base += base;
+        }
+        if let Some(f) = files.last() {
+            self.base = f.base + f.size + 1;
+        }
+        self.files.append(&mut files);
+        base
+    }
+
     pub fn recent_file(&mut self) -> Option<&mut File> {
         let c = self.files.len();
         if c == 0 {
@@ -93,6 +93,7 @@ impl Entity {
     }
 }
 
+#[derive(Clone)]
 pub struct Scope {
     pub outer: Option<ScopeKey>,
     pub entities: Map<String, EntityKey>,
@@ -8,11 +8,10 @@ use quote::{quote, ToTokens};
 use syn::LitInt;
 use syn::NestedMeta;
 use syn::Token;
-use syn::TypeTuple;
 use syn::{
     parse_macro_input, parse_quote, punctuated::Punctuated, Arm, AttributeArgs, Block, Expr, FnArg,
     GenericArgument, Ident, ImplItem, ImplItemMethod, ItemImpl, Lit, Meta, PatType, PathArguments,
-    PathSegment, ReturnType, Stmt, Type,
+    PathSegment, ReturnType, Signature, Stmt, Type,
 };
 
 const TYPE_ERR_MSG: &str = "unexpected return type";
@@ -28,8 +27,8 @@ macro_rules! return_type_panic {
 #[derive(PartialEq)]
 enum FfiReturnType {
     ZeroVal,
-    OneVal(bool),
-    MultipleVal(Vec<bool>),
+    OneVal,
+    MultipleVal(usize),
     Vec,
     AlreadyBoxed,
 }
@@ -67,6 +66,8 @@ pub fn ffi_impl_implement(
     let type_name = get_last_segment(&impl_block.self_ty).unwrap().ident;
 
     let mut methods: Vec<ImplItem> = vec![
+        gen_signatures_method(&ffi_methods, &async_ffi_methods),
+        gen_register_package_method(),
         gen_dispatch_method(&impl_block.self_ty, ffi_methods, false),
         gen_dispatch_method(&impl_block.self_ty, async_ffi_methods, true),
         gen_new_method(&type_name, &new_method),
@@ -137,12 +138,11 @@ fn gen_dispatch_method(
                 panic!("FFI must be an associated function not method, i.e. without 'self'");
             }
             let mut args: Punctuated<Expr, Token![,]> = Punctuated::new();
+            let mut conversions: Vec<Stmt> = vec![];
             let mut param_count = 0;
             for (i, farg) in method.sig.inputs.iter().enumerate() {
-                let arg_name: &str = &get_last_segment(&fn_arg_as_pat_type(farg).ty)
-                    .unwrap()
-                    .ident
-                    .to_string();
+                let ty = &fn_arg_as_pat_type(farg).ty;
+                let arg_name: &str = &get_last_segment(ty).unwrap().ident.to_string();
                 match arg_name {
                     "FfiCtx" => {
                         if i == 0 {
@@ -152,17 +152,23 @@ fn gen_dispatch_method(
                             panic!("'FfiCtx' should be the first argument")
                         }
                     }
-                    "GosValue" => {
-                        args.push_value(parse_quote! {arg_iter.next().unwrap()});
-                        args.push_punct(Token![,](Span::call_site()));
-                        param_count += 1;
-                    }
-                    _ if is_primitive(arg_name) => {
-                        args.push_value(parse_quote! {arg_iter.next().unwrap().as_()});
+                    _ => {
+                        let arg = Ident::new(&format!("arg{}", param_count), Span::call_site());
+                        let on_err: Expr = if is_async {
+                            parse_quote! { return Box::pin(async move { Err(e) }) }
+                        } else {
+                            parse_quote! { return Err(e) }
+                        };
+                        conversions.push(parse_quote! {
+                            let #arg = match <#ty as go_vm::FromGos>::from_gos(arg_iter.next().unwrap()) {
+                                Ok(v) => v,
+                                Err(e) => #on_err,
+                            };
+                        });
+                        args.push_value(parse_quote! {#arg});
                         args.push_punct(Token![,](Span::call_site()));
                         param_count += 1;
                     }
-                    _ => panic!("Unexpected FFI parameter type, only primitive types and GosValue are supported"),
                 }
             }
             let count_lit = LitInt::new(&param_count.to_string(), Span::call_site());
@@ -176,6 +182,7 @@ fn gen_dispatch_method(
                         if arg_count != #count_lit {
                             Box::pin(async move { Err("FFI: bad argument count".to_owned().into()) })
                         } else {
+                            #(#conversions)*
                             #wrapper
                         }
                     },
@@ -186,6 +193,7 @@ fn gen_dispatch_method(
                         if arg_count != #count_lit {
                             Err("FFI: bad argument count".to_owned().into())
                         } else {
+                            #(#conversions)*
                             #wrapper
                         }
                     },
@@ -224,6 +232,67 @@ fn gen_register_method() -> ImplItemMethod {
         }
     }
 }
+fn gen_register_package_method() -> ImplItemMethod {
+    parse_quote! {
+        pub fn register_package(factory: &mut go_vm::FfiFactory) {
+            factory.register_with_signatures(
+                Self::auto_gen_ffi_id(),
+                Self::auto_gen_ffi_new(),
+                Self::auto_gen_ffi_signatures(),
+            );
+        }
+    }
+}
+
+/// The Go signatures of the FFI functions, from the `GoType` and `FfiReturn` of
+/// their Rust types. An async function returning the `Vec<GosValue>` itself or a
+/// boxed future is declared without results, as they are not known from its Rust
+/// signature.
+fn gen_signatures_method(
+    ffis: &Vec<&ImplItemMethod>,
+    async_ffis: &Vec<&ImplItemMethod>,
+) -> ImplItemMethod {
+    let sigs: Vec<TokenStream> = ffis
+        .iter()
+        .chain(async_ffis.iter())
+        .map(|method| {
+            let name = method.sig.ident.to_string();
+            let short_name = name.strip_prefix(FFI_FUNC_PREFIX).unwrap();
+            let params: Vec<TokenStream> = method
+                .sig
+                .inputs
+                .iter()
+                .filter_map(|farg| {
+                    let ty = &fn_arg_as_pat_type(farg).ty;
+                    let name = get_last_segment(ty).unwrap().ident.to_string();
+                    (name != "FfiCtx").then(|| quote! { <#ty as go_vm::GoType>::go_param_type() })
+                })
+                .collect();
+            let (_, r_type) = get_return_type_attributes(&method.sig.output);
+            let results = match (&method.sig.output, returns_gos_values(&method.sig)) {
+                (_, true) => quote! { vec![] },
+                _ if r_type == FfiReturnType::AlreadyBoxed => quote! { vec![] },
+                (ReturnType::Default, false) => quote! { vec![] },
+                (ReturnType::Type(_, t), false) => {
+                    quote! { <#t as go_vm::FfiReturn>::go_types() }
+                }
+            };
+            quote! {
+                go_vm::FfiSignature {
+                    name: #short_name,
+                    params: vec![#(#params),*],
+                    results: #results,
+                }
+            }
+        })
+        .collect();
+    parse_quote! {
+        pub fn auto_gen_ffi_signatures() -> Vec<go_vm::FfiSignature> {
+            vec![#(#sigs),*]
+        }
+    }
+}
+
 fn gen_id_method(type_name: &Ident, meta: &Vec<NestedMeta>) -> ImplItemMethod {
     let rname = meta
         .iter()
@@ -266,20 +335,26 @@ fn gen_wrapper_block(
     let is_async = m.sig.asyncness.is_some();
     let (is_result, r_type) = get_return_type_attributes(&m.sig.output);
     if is_async_call {
-        match (is_async, is_result, r_type) {
-            (true, true, FfiReturnType::Vec) => {
+        match (is_async, r_type) {
+            (_, FfiReturnType::AlreadyBoxed) => {
+                parse_quote! {{
+                    #self_ty::#callee(#args)
+                }}
+            }
+            (true, _) if returns_gos_values(&m.sig) => {
                 parse_quote! {{
                     let re = #self_ty::#callee(#args);
                     Box::pin( re )
                 }}
             }
-            (_, _, FfiReturnType::AlreadyBoxed) => {
+            (true, _) => {
                 parse_quote! {{
-                    #self_ty::#callee(#args)
+                    let gcc = ctx.gcc.clone();
+                    let re = #self_ty::#callee(#args);
+                    Box::pin(async move { go_vm::FfiReturn::into_returns(re.await, &gcc) })
                 }}
             }
-            (false, _, _) => unreachable!(),
-            (true, _, _) => panic!("async func can only return RuntimeResult<Vec<GosValue>>>"),
+            (false, _) => panic!("non-async ffi_async_ func can only return a boxed future"),
         }
     } else {
         match (is_async, is_result, r_type) {
@@ -288,16 +363,17 @@ fn gen_wrapper_block(
                     #self_ty::#callee(#args).map(|x| vec![])
                 }}
             }
-            (false, true, FfiReturnType::OneVal(primitive)) => {
-                let ret = one_return_value(primitive);
+            (false, true, FfiReturnType::OneVal | FfiReturnType::Vec) => {
                 parse_quote! {{
-                    #self_ty::#callee(#args).map(|input| vec![#ret])
+                    let input = #self_ty::#callee(#args)?;
+                    Ok(vec![go_vm::IntoGos::into_gos(input, ctx.gcc)?])
                 }}
             }
-            (false, true, FfiReturnType::MultipleVal(types)) => {
-                let ret = multiple_return_values(types);
+            (false, true, FfiReturnType::MultipleVal(count)) => {
+                let ret = multiple_return_values(count);
                 parse_quote! {{
-                    #self_ty::#callee(#args).map(|input| vec![#ret] )
+                    let input = #self_ty::#callee(#args)?;
+                    Ok(vec![#ret])
                 }}
             }
             (false, false, FfiReturnType::ZeroVal) => {
@@ -306,22 +382,20 @@ fn gen_wrapper_block(
                     Ok(vec![])
                 }}
             }
-            (false, false, FfiReturnType::OneVal(primitive)) => {
-                let ret = one_return_value(primitive);
+            (false, false, FfiReturnType::OneVal | FfiReturnType::Vec) => {
                 parse_quote! {{
                     let input = #self_ty::#callee(#args);
-                    Ok(vec![#ret])
+                    Ok(vec![go_vm::IntoGos::into_gos(input, ctx.gcc)?])
                 }}
             }
-            (false, false, FfiReturnType::MultipleVal(types)) => {
-                let ret = multiple_return_values(types);
+            (false, false, FfiReturnType::MultipleVal(count)) => {
+                let ret = multiple_return_values(count);
                 parse_quote! {{
                     let input = #self_ty::#callee(#args);
                     Ok(vec![#ret])
                 }}
             }
-            (false, _, FfiReturnType::Vec) => panic!("non-async func cannot return a vec"),
-            (false, _, _) => panic!("unsupported return type"),
+            (false, _, FfiReturnType::AlreadyBoxed) => panic!("unsupported return type"),
             (true, _, _) => unreachable!(),
         }
     }
@@ -333,45 +407,51 @@ fn get_return_type_attributes(rt: &ReturnType) -> (bool, FfiReturnType) {
         ReturnType::Type(_, t) => match &**t {
             Type::Path(tp) => {
                 let seg = tp.path.segments.last().unwrap();
-                let type_name = seg.ident.to_string();
-                match type_name.as_str() {
-                    "GosValue" => (false, FfiReturnType::OneVal(false)), // todo: futher validation
-                    _ if is_primitive(&type_name) => (false, FfiReturnType::OneVal(true)),
+                match seg.ident.to_string().as_str() {
                     "Pin" => (false, FfiReturnType::AlreadyBoxed), // todo: futher validation
                     "RuntimeResult" => {
-                        let inner_type = get_type_arg_type(&seg.arguments);
-                        match &inner_type {
-                            Type::Path(itp) => {
-                                let name = itp.path.segments.last().unwrap().ident.to_string();
-                                match name.as_str() {
-                                    "GosValue" => (true, FfiReturnType::OneVal(false)),
-                                    _ if is_primitive(&name) => (true, FfiReturnType::OneVal(true)),
-                                    "Vec" => (true, FfiReturnType::Vec), // todo: futher validation
-                                    _ => return_type_panic!(),
-                                }
-                            }
-                            Type::Tuple(tt) => {
-                                if tt.elems.is_empty() {
-                                    (true, FfiReturnType::ZeroVal)
-                                } else {
-                                    (true, FfiReturnType::MultipleVal(are_primitives(tt)))
-                                }
-                            }
-                            _ => return_type_panic!(),
-                        }
+                        (true, value_return_type(&get_type_arg_type(&seg.arguments)))
                     }
-                    _ => return_type_panic!(),
+                    _ => (false, FfiReturnType::OneVal),
                 }
             }
-            Type::Tuple(tt) => {
-                if tt.elems.is_empty() {
-                    (false, FfiReturnType::ZeroVal)
-                } else {
-                    (false, FfiReturnType::MultipleVal(are_primitives(tt)))
+            t => (false, value_return_type(t)),
+        },
+    }
+}
+
+/// Whether it's an async function returning `RuntimeResult<Vec<GosValue>>`, the
+/// values are returned as they are
+fn returns_gos_values(sig: &Signature) -> bool {
+    let t = match (&sig.asyncness, &sig.output) {
+        (Some(_), ReturnType::Type(_, t)) => t,
+        _ => return false,
+    };
+    match get_last_segment(t) {
+        Some(seg) if seg.ident == "RuntimeResult" => {
+            match get_last_segment(&get_type_arg_type(&seg.arguments)) {
+                Some(seg) if seg.ident == "Vec" => {
+                    get_last_segment(&get_type_arg_type(&seg.arguments))
+                        .is_some_and(|x| x.ident == "GosValue")
                 }
+                _ => false,
             }
-            _ => return_type_panic!(),
+        }
+        _ => false,
+    }
+}
+
+/// The returned values are converted with `IntoGos`, a `Vec` is a slice unless
+/// it's the `Vec<GosValue>` returned by an async function
+fn value_return_type(t: &Type) -> FfiReturnType {
+    match t {
+        Type::Path(tp) => match tp.path.segments.last().unwrap().ident.to_string().as_str() {
+            "Vec" => FfiReturnType::Vec,
+            _ => FfiReturnType::OneVal,
         },
+        Type::Tuple(tt) if tt.elems.is_empty() => FfiReturnType::ZeroVal,
+        Type::Tuple(tt) => FfiReturnType::MultipleVal(tt.elems.len()),
+        _ => return_type_panic!(),
     }
 }
 
@@ -410,46 +490,11 @@ fn get_type_arg_type(args: &PathArguments) -> Type {
     }
 }
 
-fn is_primitive(name: &str) -> bool {
-    match name {
-        "bool" | "isize" | "i8" | "i16" | "i32" | "i64" | "usize" | "u8" | "u16" | "u32"
-        | "u64" | "f32" | "f64" | "String" => true,
-        _ => false,
-    }
-}
-
-fn are_primitives(tuple: &TypeTuple) -> Vec<bool> {
-    tuple
-        .elems
-        .iter()
-        .map(|x| {
-            let name = get_last_segment(x).unwrap().ident.to_string();
-            let p = is_primitive(&name);
-            if !p && name != "GosValue" {
-                return_type_panic!()
-            }
-            p
-        })
-        .collect()
-}
-
-fn one_return_value(is_primitive: bool) -> TokenStream {
-    if is_primitive {
-        quote!(input.into())
-    } else {
-        quote!(input)
-    }
-}
-
-fn multiple_return_values(types: Vec<bool>) -> Punctuated<Expr, Token![,]> {
+fn multiple_return_values(count: usize) -> Punctuated<Expr, Token![,]> {
     let mut values: Punctuated<Expr, Token![,]> = Punctuated::new();
-    for (i, t) in types.into_iter().enumerate() {
+    for i in 0..count {
         let i_lit = Literal::usize_unsuffixed(i);
-        if t {
-            values.push_value(parse_quote! {input.#i_lit.into()});
-        } else {
-            values.push_value(parse_quote! {input.#i_lit});
-        }
+        values.push_value(parse_quote! {go_vm::IntoGos::into_gos(input.#i_lit, ctx.gcc)?});
         values.push_punct(Token![,](Span::call_site()));
     }
     values
@@ -11,7 +11,10 @@
 
 #![allow(dead_code)]
 use super::super::constant::Value;
-use super::super::importer::{ImportKey, Importer, SourceRead, TraceConfig};
+use super::super::export::Extent;
+use super::super::importer::{
+    ImportKey, Importer, PackageCache, PackageSource, SourceRead, TraceConfig,
+};
 use super::super::objects::{DeclInfoKey, ObjKey, PackageKey, ScopeKey, TCObjects, TypeKey};
 use super::super::operand::OperandMode;
 use super::super::selection::Selection;
@@ -43,14 +46,14 @@ impl TypeAndValue {
 /// An Initializer describes a package-level variable, or a list of variables in case
 /// of a multi-valued initialization expression, and the corresponding initialization
 /// expression.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Initializer {
     pub lhs: Vec<ObjKey>,
     pub rhs: Expr,
 }
 
 /// Types info holds the results of Type Checking
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct TypeInfo {
     /// 'types' maps expressions to their types, and for constant
     /// expressions, also their values. Invalid expressions are
@@ -126,6 +129,17 @@ pub struct TypeInfo {
     pub init_order: Vec<Initializer>,
     /// oxfeeefeee: parse result of the package, to be used by code gen
     pub ast_files: Vec<ast::File>,
+    /// 'imports' is the sorted list of the keys of the packages imported by
+    /// the files of the package.
+    pub imports: Vec<ImportKey>,
+    /// 'source' is the files the package is read from, it's set by the Importer.
+    pub source: Option<PackageSource>,
+    /// 'extent' is where the objects of the package are in TCObjects, it's set by
+    /// the Importer.
+    pub extent: Extent,
+    /// 'cached' is set if the package is loaded from a PackageCache instead of
+    /// checked, only 'imports', 'source' and 'extent' are set then.
+    pub cached: bool,
 }
 
 impl TypeInfo {
@@ -139,6 +153,10 @@ impl TypeInfo {
             scopes: Map::new(),
             init_order: Vec::new(),
             ast_files: Vec::new(),
+            imports: Vec::new(),
+            source: None,
+            extent: Extent::default(),
+            cached: false,
         }
     }
 }
@@ -224,6 +242,8 @@ pub struct Checker<'a, S: SourceRead> {
     trace_config: &'a TraceConfig,
 
     reader: &'a S,
+    // the cache of the packages it imports
+    cache: Option<&'a mut dyn PackageCache>,
     // result of type checking
     pub result: TypeInfo,
     // for debug
@@ -407,6 +427,7 @@ impl<'a, S: SourceRead> Checker<'a, S> {
         pkg: PackageKey,
         cfg: &'a TraceConfig,
         reader: &'a S,
+        cache: Option<&'a mut dyn PackageCache>,
     ) -> Checker<'a, S> {
         Checker {
             tc_objs: tc_objs,
@@ -421,6 +442,7 @@ impl<'a, S: SourceRead> Checker<'a, S> {
             octx: ObjContext::new(),
             trace_config: cfg,
             reader: reader,
+            cache: cache,
             result: TypeInfo::new(),
             indent: Rc::new(RefCell::new(0)),
         }
@@ -437,6 +459,8 @@ impl<'a, S: SourceRead> Checker<'a, S> {
         self.record_untyped(fctx);
 
         std::mem::swap(&mut self.result.ast_files, &mut files);
+        self.result.imports = self.imp_map.keys().cloned().collect();
+        self.result.imports.sort();
         self.all_results.insert(self.pkg, self.result);
         Ok(self.pkg)
     }
@@ -475,6 +499,10 @@ impl<'a, S: SourceRead> Checker<'a, S> {
             self.errors,
             pos,
         )
+        .with_cache(match &mut self.cache {
+            Some(cache) => Some(&mut **cache),
+            None => None,
+        })
     }
 
     /// check files' package name
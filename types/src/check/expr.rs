@@ -1151,7 +1151,7 @@ impl<'a, S: SourceRead> Checker<'a, S> {
                                         break; // cannot continue
                                     }
                                     let fld = self.lobj(fields[i]);
-                                    if !fld.exported() && fld.pkg() != Some(self.pkg) {
+                                    if !fld.same_id(Some(self.pkg), fld.name(), self.tc_objs) {
                                         let pos = x.pos(self.ast_objs);
                                         let (n, td) = (fld.name(), self.new_dis(&ty));
                                         let msg = format!(
@@ -21,7 +21,7 @@ use go_parser::ast::{self, Expr, Node};
 use go_parser::{FuncDeclKey, IdentKey, Pos, Token};
 use std::collections::HashSet;
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DeclInfoConst {
     pub file_scope: ScopeKey,  // scope of file containing this declaration
     pub typ: Option<Expr>,     // type, or None
@@ -29,7 +29,7 @@ pub struct DeclInfoConst {
     pub deps: HashSet<ObjKey>, // deps tracks initialization expression dependencies.
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DeclInfoVar {
     pub file_scope: ScopeKey,     // scope of file containing this declaration
     pub lhs: Option<Vec<ObjKey>>, // lhs of n:1 variable declarations, or None
@@ -38,14 +38,14 @@ pub struct DeclInfoVar {
     pub deps: HashSet<ObjKey>,    // deps tracks initialization expression dependencies.
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DeclInfoType {
     pub file_scope: ScopeKey, // scope of file containing this declaration
     pub typ: Expr,            // type
     pub alias: bool,          // type alias declaration
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct DeclInfoFunc {
     pub file_scope: ScopeKey,  // scope of file containing this declaration
     pub fdecl: FuncDeclKey,    // func declaration, or None
@@ -53,7 +53,7 @@ pub struct DeclInfoFunc {
 }
 
 /// DeclInfo describes a package-level const, type, var, or func declaration.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub enum DeclInfo {
     Const(DeclInfoConst),
     Var(DeclInfoVar),
@@ -678,7 +678,7 @@ impl<'a, S: SourceRead> Checker<'a, S> {
         None
     }
 
-    fn valid_import_path(&self, blit: &'a ast::BasicLit) -> Result<&'a str, String> {
+    fn valid_import_path<'b>(&self, blit: &'b ast::BasicLit) -> Result<&'b str, String> {
         let path = blit.token.get_literal();
         if path.len() < 3 || (!path.starts_with('"') || !path.ends_with('"')) {
             return Err("empty string".to_owned());
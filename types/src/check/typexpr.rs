@@ -70,11 +70,11 @@ impl<'a, S: SourceRead> Checker<'a, S> {
             // the map of unused dot imports for the respective file scope.
             // (This code is only needed for dot-imports. Without them,
             // we only have to mark variables, see Var case below).
+            // The objects of the package scope of an outer package are not imported.
             if pkg.is_some() && pkg != Some(self.pkg) {
-                fctx.unused_dot_imports
-                    .get_mut(&skey)
-                    .unwrap()
-                    .remove(&pkg.unwrap());
+                if let Some(imports) = fctx.unused_dot_imports.get_mut(&skey) {
+                    imports.remove(&pkg.unwrap());
+                }
             }
 
             let lobj = self.lobj(okey);
@@ -10,6 +10,11 @@
 // license that can be found in the LICENSE file.
 
 use super::typ::{BasicDetail, BasicInfo, BasicType};
+#[cfg(feature = "serde_borsh")]
+use borsh::{
+    maybestd::io::Error, maybestd::io::ErrorKind, maybestd::io::Result, maybestd::io::Write,
+    BorshDeserialize, BorshSerialize,
+};
 use go_parser::Token;
 use num_bigint::{BigInt, Sign};
 use num_rational::BigRational;
@@ -50,6 +55,72 @@ pub enum Value {
     Complex(Box<Value>, Box<Value>),
 }
 
+#[cfg(feature = "serde_borsh")]
+impl BorshSerialize for Value {
+    fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+        match self {
+            Value::Unknown => 0u8.serialize(writer),
+            Value::Bool(b) => {
+                1u8.serialize(writer)?;
+                b.serialize(writer)
+            }
+            Value::Str(s) => {
+                2u8.serialize(writer)?;
+                s.serialize(writer)
+            }
+            Value::Int(i) => {
+                3u8.serialize(writer)?;
+                i.to_signed_bytes_le().serialize(writer)
+            }
+            Value::Rat(r) => {
+                4u8.serialize(writer)?;
+                r.numer().to_signed_bytes_le().serialize(writer)?;
+                r.denom().to_signed_bytes_le().serialize(writer)
+            }
+            Value::Float(f) => {
+                5u8.serialize(writer)?;
+                f.into_inner().to_bits().serialize(writer)
+            }
+            Value::Complex(r, i) => {
+                6u8.serialize(writer)?;
+                r.serialize(writer)?;
+                i.serialize(writer)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde_borsh")]
+impl BorshDeserialize for Value {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> Result<Self> {
+        let big_int = |reader: &mut R| -> Result<BigInt> {
+            Ok(BigInt::from_signed_bytes_le(
+                &Vec::<u8>::deserialize_reader(reader)?,
+            ))
+        };
+        Ok(match u8::deserialize_reader(reader)? {
+            0 => Value::Unknown,
+            1 => Value::Bool(bool::deserialize_reader(reader)?),
+            2 => Value::Str(String::deserialize_reader(reader)?),
+            3 => Value::Int(big_int(reader)?),
+            4 => {
+                let numer = big_int(reader)?;
+                let denom = big_int(reader)?;
+                if denom == BigInt::from(0) {
+                    return Err(Error::new(ErrorKind::InvalidData, "zero denominator"));
+                }
+                Value::Rat(BigRational::new(numer, denom))
+            }
+            5 => Value::Float(f64::from_bits(u64::deserialize_reader(reader)?).into()),
+            6 => Value::Complex(
+                Box::new(Value::deserialize_reader(reader)?),
+                Box::new(Value::deserialize_reader(reader)?),
+            ),
+            _ => return Err(Error::new(ErrorKind::InvalidData, "invalid constant")),
+        })
+    }
+}
+
 impl fmt::Display for Value {
     /// For numeric values, the result may be an approximation;
     /// for String values the result may be a shortened string.
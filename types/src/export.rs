@@ -0,0 +1,370 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Exporting a checked package and importing it into another `TCObjects`.
+//!
+//! The objects a package adds to `TCObjects` while it's checked are its extent,
+//! those of them that are not added by the packages it imports are the ones it
+//! owns. An exported package is the objects it owns, with the keys in them
+//! replaced by the indices of the objects among the ones owned by the universe,
+//! the package and the packages it imports, in that order. Importing it
+//! replaces them back with where the objects are in the new `TCObjects`, so it
+//! only works if the universe and the imported packages are the same.
+
+use super::check::TypeInfo;
+use super::obj::LangObj;
+use super::objects::{ObjKey, PackageKey, ScopeKey, TCObjects, TypeKey};
+use super::package::Package;
+use super::scope::Scope;
+use super::typ::Type;
+#[cfg(feature = "serde_borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
+use go_parser::{File, FileSet, Map, PiggyVecKey, Pos, RangeMap};
+use std::ops::Range;
+
+/// Extent is where the objects a package adds to `TCObjects` are, including the
+/// ones added by the packages it imports, and the positions of the files added
+/// to the `FileSet`, see `TypeInfo::extent`.
+#[derive(Clone, Debug, Default)]
+pub struct Extent {
+    pub lobjs: Range<usize>,
+    pub types: Range<usize>,
+    pub scopes: Range<usize>,
+    pub pkgs: Range<usize>,
+    pub pos: Range<usize>,
+}
+
+impl Extent {
+    pub(crate) fn start(objs: &TCObjects, fset: &FileSet) -> Extent {
+        let empty = |at| at..at;
+        Extent {
+            lobjs: empty(objs.lobjs.vec().len()),
+            types: empty(objs.types.vec().len()),
+            scopes: empty(objs.scopes.vec().len()),
+            pkgs: empty(objs.pkgs.vec().len()),
+            pos: empty(fset.base()),
+        }
+    }
+
+    pub(crate) fn end(mut self, objs: &TCObjects, fset: &FileSet) -> Extent {
+        self.lobjs.end = objs.lobjs.vec().len();
+        self.types.end = objs.types.vec().len();
+        self.scopes.end = objs.scopes.vec().len();
+        self.pkgs.end = objs.pkgs.vec().len();
+        self.pos.end = fset.base();
+        self
+    }
+
+    /// Every package adds itself, so the extents nest like their packages
+    fn contains(&self, other: &Extent) -> bool {
+        self.pkgs.start <= other.pkgs.start && other.pkgs.end <= self.pkgs.end
+    }
+}
+
+/// Owned is the objects and the positions a package owns, they're in its extent
+/// but not in the extents of the packages it imports.
+#[derive(Clone, Debug, Default)]
+pub struct Owned {
+    pub lobjs: Vec<Range<usize>>,
+    pub types: Vec<Range<usize>>,
+    pub scopes: Vec<Range<usize>>,
+    pub pkgs: Vec<Range<usize>>,
+    pub pos: Vec<Range<usize>>,
+}
+
+impl Owned {
+    pub fn of(pkg: PackageKey, results: &Map<PackageKey, TypeInfo>) -> Owned {
+        let extent = &results[&pkg].extent;
+        let inner: Vec<&Extent> = results
+            .iter()
+            .filter(|(k, r)| **k != pkg && !r.extent.pkgs.is_empty())
+            .map(|(_, r)| &r.extent)
+            .filter(|e| extent.contains(e))
+            .collect();
+        let owned = |f: fn(&Extent) -> &Range<usize>| {
+            let mut ranges: Vec<&Range<usize>> = inner.iter().map(|e| f(e)).collect();
+            ranges.sort_by_key(|r| r.start);
+            let mut result = vec![];
+            let mut at = f(extent).start;
+            for r in ranges {
+                if r.start > at {
+                    result.push(at..r.start);
+                }
+                at = at.max(r.end);
+            }
+            if at < f(extent).end {
+                result.push(at..f(extent).end);
+            }
+            result
+        };
+        Owned {
+            lobjs: owned(|e| &e.lobjs),
+            types: owned(|e| &e.types),
+            scopes: owned(|e| &e.scopes),
+            pkgs: owned(|e| &e.pkgs),
+            pos: owned(|e| &e.pos),
+        }
+    }
+
+    fn universe(objs: &TCObjects) -> Owned {
+        let extent = objs.universe().extent();
+        Owned {
+            lobjs: vec![extent.lobjs.clone()],
+            types: vec![extent.types.clone()],
+            scopes: vec![extent.scopes.clone()],
+            pkgs: vec![extent.pkgs.clone()],
+            pos: vec![],
+        }
+    }
+
+    fn sizes(&self) -> [usize; 4] {
+        let size = |ranges: &Vec<Range<usize>>| ranges.iter().map(|r| r.len()).sum();
+        [
+            size(&self.lobjs),
+            size(&self.types),
+            size(&self.scopes),
+            size(&self.pkgs),
+        ]
+    }
+}
+
+/// Relocation maps the keys and the positions in the objects of a package when
+/// it's exported or imported.
+pub(crate) struct Relocation {
+    lobjs: RangeMap,
+    types: RangeMap,
+    scopes: RangeMap,
+    pkgs: RangeMap,
+    /// the positions out of the files of the package become 0
+    pos: RangeMap,
+}
+
+impl Relocation {
+    /// The relocation from the objects of the owners to their indices if `export`,
+    /// or back otherwise
+    fn new(owners: &[Owned], pos: RangeMap, export: bool) -> Relocation {
+        let map = |f: fn(&Owned) -> &Vec<Range<usize>>| {
+            let ranges = owners.iter().flat_map(|o| f(o).iter());
+            match export {
+                true => RangeMap::flatten(ranges),
+                false => RangeMap::unflatten(ranges),
+            }
+        };
+        Relocation {
+            lobjs: map(|o| &o.lobjs),
+            types: map(|o| &o.types),
+            scopes: map(|o| &o.scopes),
+            pkgs: map(|o| &o.pkgs),
+            pos,
+        }
+    }
+
+    #[inline]
+    pub fn obj(&self, k: &mut ObjKey) -> Option<()> {
+        *k = self.lobjs.key(*k)?;
+        Some(())
+    }
+
+    #[inline]
+    pub fn objs(&self, ks: &mut [ObjKey]) -> Option<()> {
+        ks.iter_mut().try_for_each(|k| self.obj(k))
+    }
+
+    #[inline]
+    pub fn typ(&self, k: &mut TypeKey) -> Option<()> {
+        *k = self.types.key(*k)?;
+        Some(())
+    }
+
+    #[inline]
+    pub fn scope(&self, k: &mut ScopeKey) -> Option<()> {
+        *k = self.scopes.key(*k)?;
+        Some(())
+    }
+
+    #[inline]
+    pub fn pkg(&self, k: &mut PackageKey) -> Option<()> {
+        *k = self.pkgs.key(*k)?;
+        Some(())
+    }
+
+    #[inline]
+    pub fn pos(&self, p: Pos) -> Pos {
+        self.pos.get(p).unwrap_or(0)
+    }
+}
+
+/// PackageData is a checked package exported from a `TCObjects`, it can be
+/// imported into another one which has the packages it imports.
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone)]
+pub struct PackageData {
+    /// the numbers of the objects owned by the universe
+    universe: [usize; 4],
+    /// the import paths of the packages it imports, directly or not, and the numbers
+    /// of the objects they own, sorted by import path
+    deps: Vec<(String, [usize; 4])>,
+    lobjs: Vec<LangObj>,
+    types: Vec<Type>,
+    scopes: Vec<Scope>,
+    pkgs: Vec<Package>,
+    files: Vec<File>,
+    /// the size of the positions of the files
+    size: usize,
+}
+
+impl PackageData {
+    /// Exports the package, returns None if it owns objects it can't export, e.g.
+    /// objects referring to objects owned by packages it doesn't import.
+    pub fn export(
+        pkg: PackageKey,
+        objs: &TCObjects,
+        fset: &FileSet,
+        results: &Map<PackageKey, TypeInfo>,
+    ) -> Option<PackageData> {
+        let owned = Owned::of(pkg, results);
+        // they are created first, and replaced when the package is imported
+        let scope = objs.pkgs[pkg].scope().as_usize();
+        if owned.pkgs.first()?.start != pkg.as_usize()
+            || owned.scopes.first()?.start != scope
+            || owned.pos.len() > 1
+        {
+            return None;
+        }
+        let deps = Self::deps(pkg, objs, results)?;
+        let universe = Owned::universe(objs);
+        let pos = owned.pos.first().cloned().unwrap_or(0..0);
+        let mut owners = vec![universe.clone(), owned.clone()];
+        owners.extend(deps.iter().map(|(_, k)| Owned::of(*k, results)));
+        let r = Relocation::new(&owners, RangeMap::flatten([&pos]), true);
+
+        fn export<T: Clone>(
+            ranges: &[Range<usize>],
+            vec: &[T],
+            relocate: impl Fn(&mut T) -> Option<()>,
+        ) -> Option<Vec<T>> {
+            ranges
+                .iter()
+                .flat_map(|r| vec[r.clone()].iter())
+                .map(|x| {
+                    let mut x = x.clone();
+                    relocate(&mut x)?;
+                    Some(x)
+                })
+                .collect()
+        }
+        Some(PackageData {
+            universe: universe.sizes(),
+            deps: deps
+                .iter()
+                .zip(owners[2..].iter())
+                .map(|((path, _), o)| (path.clone(), o.sizes()))
+                .collect(),
+            lobjs: export(&owned.lobjs, objs.lobjs.vec(), |o| o.relocate(&r))?,
+            types: export(&owned.types, objs.types.vec(), |t| t.relocate(&r))?,
+            scopes: export(&owned.scopes, objs.scopes.vec(), |s| s.relocate(&r))?,
+            pkgs: export(&owned.pkgs, objs.pkgs.vec(), |p| p.relocate(&r))?,
+            files: fset.files_in(pos.clone()),
+            size: pos.len(),
+        })
+    }
+
+    /// The import paths of the packages it imports, directly or not
+    pub fn dep_paths(&self) -> impl Iterator<Item = &str> {
+        self.deps.iter().map(|(p, _)| p.as_str())
+    }
+
+    /// The size of the positions of the files of the package
+    pub fn pos_size(&self) -> usize {
+        self.size
+    }
+
+    /// Imports the package as `pkg`, a package created with `TCObjects::new_package`
+    /// after the packages it imports are imported, `pkgs` maps their import paths to
+    /// them. Returns None and leaves the objects as they are if it doesn't fit.
+    pub fn import(
+        self,
+        pkg: PackageKey,
+        objs: &mut TCObjects,
+        fset: &mut FileSet,
+        pkgs: &Map<String, PackageKey>,
+        results: &Map<PackageKey, TypeInfo>,
+    ) -> Option<()> {
+        let universe = Owned::universe(objs);
+        if universe.sizes() != self.universe || self.scopes.is_empty() || self.pkgs.is_empty() {
+            return None;
+        }
+        let mut owners = vec![universe];
+        for (path, sizes) in self.deps.iter() {
+            let key = pkgs.get(path)?;
+            results.get(key)?;
+            let owned = Owned::of(*key, results);
+            if owned.sizes() != *sizes {
+                return None;
+            }
+            owners.push(owned);
+        }
+        let appended = |len: usize, count: usize| len..len + count;
+        let scope = objs.pkgs[pkg].scope().as_usize();
+        let owned = Owned {
+            lobjs: vec![appended(objs.lobjs.vec().len(), self.lobjs.len())],
+            types: vec![appended(objs.types.vec().len(), self.types.len())],
+            scopes: vec![
+                scope..scope + 1,
+                appended(objs.scopes.vec().len(), self.scopes.len() - 1),
+            ],
+            pkgs: vec![
+                pkg.as_usize()..pkg.as_usize() + 1,
+                appended(objs.pkgs.vec().len(), self.pkgs.len() - 1),
+            ],
+            pos: vec![],
+        };
+        owners.insert(1, owned);
+        let pos = appended(fset.base(), self.size);
+        let r = Relocation::new(&owners, RangeMap::unflatten([&pos]), false);
+
+        fn import<T>(mut vec: Vec<T>, relocate: impl Fn(&mut T) -> Option<()>) -> Option<Vec<T>> {
+            vec.iter_mut().try_for_each(relocate)?;
+            Some(vec)
+        }
+        let mut lobjs = import(self.lobjs, |o| o.relocate(&r))?;
+        let mut types = import(self.types, |t| t.relocate(&r))?;
+        let mut scopes = import(self.scopes, |s| s.relocate(&r))?;
+        let mut pkgs = import(self.pkgs, |p| p.relocate(&r))?;
+        objs.scopes[scope.into()] = scopes.remove(0);
+        objs.pkgs[pkg] = pkgs.remove(0);
+        objs.lobjs.append(&mut lobjs);
+        objs.types.append(&mut types);
+        objs.scopes.append(&mut scopes);
+        objs.pkgs.append(&mut pkgs);
+        fset.append_moved(self.files);
+        Some(())
+    }
+
+    /// The packages `pkg` imports, directly or not, sorted by import path
+    fn deps(
+        pkg: PackageKey,
+        objs: &TCObjects,
+        results: &Map<PackageKey, TypeInfo>,
+    ) -> Option<Vec<(String, PackageKey)>> {
+        let unsafe_ = *objs.universe().unsafe_pkg();
+        let mut deps: Map<String, PackageKey> = Map::new();
+        let mut stack = vec![pkg];
+        while let Some(p) = stack.pop() {
+            for imp in objs.pkgs[p].imports().iter() {
+                if *imp == unsafe_ {
+                    continue;
+                }
+                results.get(imp)?;
+                let path = objs.pkgs[*imp].path();
+                if deps.insert(path.clone(), *imp).is_none() {
+                    stack.push(*imp);
+                }
+            }
+        }
+        let mut deps: Vec<(String, PackageKey)> = deps.into_iter().collect();
+        deps.sort();
+        Some(deps)
+    }
+}
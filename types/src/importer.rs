@@ -10,6 +10,7 @@
 // license that can be found in the LICENSE file.
 
 use super::check::{Checker, TypeInfo};
+use super::export::Extent;
 use super::objects::{PackageKey, TCObjects};
 use go_parser::ast;
 use go_parser::{AstObjects, ErrorList, FileSet, Map, Parser, Pos};
@@ -45,7 +46,7 @@ pub trait SourceRead {
 /// importer must always return the same package (but given two different import paths,
 /// an importer may still return the same package by mapping them to the same package
 /// paths).
-#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Debug)]
 pub struct ImportKey {
     pub path: String,
     pub dir: String, // makes a difference only when importing local files
@@ -60,6 +61,39 @@ impl ImportKey {
     }
 }
 
+/// The files a package is read from, they identify the package when caching
+/// the compiled code, see `TypeInfo::source`.
+#[derive(Clone, Debug)]
+pub struct PackageSource {
+    /// the path returned by `SourceRead::canonicalize_import`
+    pub path: PathBuf,
+    pub import_path: String,
+    /// the display names and the contents of the files, as `read_package_files` returns
+    pub files: Vec<(String, String)>,
+    /// the same as `TypeInfo::imports`
+    pub imports: Vec<ImportKey>,
+}
+
+/// PackageCache lets the Importer load the packages checked before, instead of
+/// checking them again, see `Importer::with_cache`. Nested packages are always checked.
+pub trait PackageCache {
+    /// Returns the imports of the package read from the files, if it may be in the
+    /// cache. They are imported before the package is loaded.
+    fn imports(&mut self, import_path: &str, files: &[(String, String)]) -> Option<Vec<ImportKey>>;
+
+    /// Loads the package into `pkg`, which is created by the Importer, e.g. with
+    /// `PackageData::import`. Returns false if it's not in the cache.
+    fn load(
+        &mut self,
+        pkg: PackageKey,
+        source: &PackageSource,
+        objs: &mut TCObjects,
+        fset: &mut FileSet,
+        pkgs: &Map<String, PackageKey>,
+        results: &Map<PackageKey, TypeInfo>,
+    ) -> bool;
+}
+
 pub struct Importer<'a, S: SourceRead> {
     trace_config: &'a TraceConfig,
     reader: &'a S,
@@ -70,6 +104,7 @@ pub struct Importer<'a, S: SourceRead> {
     tc_objs: &'a mut TCObjects,
     errors: &'a ErrorList,
     pos: Pos,
+    cache: Option<&'a mut dyn PackageCache>,
 }
 
 impl<'a, S: SourceRead> Importer<'a, S> {
@@ -94,10 +129,29 @@ impl<'a, S: SourceRead> Importer<'a, S> {
             tc_objs: tc_objs,
             errors: errors,
             pos: pos,
+            cache: None,
         }
     }
 
-    pub fn import(&mut self, key: &'a ImportKey) -> Result<PackageKey, ()> {
+    /// Makes it load the packages from the cache if they are there, and the
+    /// packages they import too
+    pub fn with_cache(mut self, cache: Option<&'a mut dyn PackageCache>) -> Importer<'a, S> {
+        self.cache = cache;
+        self
+    }
+
+    pub fn import(&mut self, key: &ImportKey) -> Result<PackageKey, ()> {
+        self.import_in(key, None)
+    }
+
+    /// Imports the package like `import`, but with its package scope nested in the
+    /// one of `outer`, see `TCObjects::new_nested_package`. The package level objects
+    /// of `outer` are visible in it, and it may declare the same names again.
+    pub fn import_nested(&mut self, key: &ImportKey, outer: PackageKey) -> Result<PackageKey, ()> {
+        self.import_in(key, Some(outer))
+    }
+
+    fn import_in(&mut self, key: &ImportKey, outer: Option<PackageKey>) -> Result<PackageKey, ()> {
         if key.path == "unsafe" {
             return Ok(*self.tc_objs.universe().unsafe_pkg());
         }
@@ -106,57 +160,93 @@ impl<'a, S: SourceRead> Importer<'a, S> {
             Ok((path, import_path)) => match self.pkgs.get(&import_path) {
                 Some(key) => Ok(*key),
                 None => {
-                    let pkg = self.tc_objs.new_package(import_path.clone());
-                    self.pkgs.insert(import_path, pkg);
-                    let files = self.parse_path(&path)?;
-                    Checker::new(
-                        self.tc_objs,
-                        self.ast_objs,
-                        self.fset,
-                        self.errors,
-                        self.pkgs,
-                        self.all_results,
-                        pkg,
-                        self.trace_config,
-                        self.reader,
-                    )
-                    .check(files)
+                    let extent = Extent::start(self.tc_objs, self.fset);
+                    let pkg = match outer {
+                        Some(outer) => self.tc_objs.new_nested_package(outer),
+                        None => self.tc_objs.new_package(import_path.clone()),
+                    };
+                    self.pkgs.insert(import_path.clone(), pkg);
+                    let files = self.read_path(&path)?;
+                    let mut source = PackageSource {
+                        path,
+                        import_path,
+                        files,
+                        imports: vec![],
+                    };
+                    let loaded = match outer {
+                        Some(_) => false,
+                        None => self.load(pkg, &mut source)?,
+                    };
+                    if !loaded {
+                        let files = self.parse_files(&source.files)?;
+                        Checker::new(
+                            self.tc_objs,
+                            self.ast_objs,
+                            self.fset,
+                            self.errors,
+                            self.pkgs,
+                            self.all_results,
+                            pkg,
+                            self.trace_config,
+                            self.reader,
+                            match &mut self.cache {
+                                Some(cache) => Some(&mut **cache),
+                                None => None,
+                            },
+                        )
+                        .check(files)?;
+                    }
+                    let result = self.all_results.get_mut(&pkg).unwrap();
+                    source.imports = result.imports.clone();
+                    result.source = Some(source);
+                    result.extent = extent.end(self.tc_objs, self.fset);
+                    Ok(pkg)
                 }
             },
             Err(e) => self.error(format!("canonicalize import error: {}", e)),
         }
     }
 
-    fn parse_path(&mut self, path: &Path) -> Result<Vec<ast::File>, ()> {
-        match read_content(path, self.reader) {
+    /// Loads the package from the cache after importing the packages it imports,
+    /// returns false if it's not in the cache
+    fn load(&mut self, pkg: PackageKey, source: &mut PackageSource) -> Result<bool, ()> {
+        let imports = match self.cache.as_mut() {
+            Some(cache) => cache.imports(&source.import_path, &source.files),
+            None => None,
+        };
+        let imports = match imports {
+            Some(imports) => imports,
+            None => return Ok(false),
+        };
+        for key in imports.iter() {
+            self.import(key)?;
+        }
+        source.imports = imports;
+        let cache = self.cache.as_mut().unwrap();
+        if !cache.load(
+            pkg,
+            source,
+            self.tc_objs,
+            self.fset,
+            self.pkgs,
+            self.all_results,
+        ) {
+            return Ok(false);
+        }
+        let mut result = TypeInfo::new();
+        result.imports = source.imports.clone();
+        result.cached = true;
+        self.all_results.insert(pkg, result);
+        Ok(true)
+    }
+
+    fn read_path(&self, path: &Path) -> Result<Vec<(String, String)>, ()> {
+        match read_package_files(path, self.reader) {
             Ok(contents) => {
                 if contents.len() == 0 {
                     self.error(format!("no source file found in dir: {}", path.display()))
                 } else {
-                    let mut afiles = vec![];
-                    for (full_name, content) in contents.into_iter() {
-                        let mut pfile = self.fset.add_file(
-                            full_name,
-                            Some(self.fset.base()),
-                            content.chars().count(),
-                        );
-                        let afile = Parser::new(
-                            self.ast_objs,
-                            &mut pfile,
-                            self.errors,
-                            &content,
-                            self.trace_config.trace_parser,
-                        )
-                        .parse_file();
-                        if afile.is_none() {
-                            // parse error, the details should be in the errorlist already.
-                            // give up
-                            return Err(());
-                        } else {
-                            afiles.push(afile.unwrap());
-                        }
-                    }
-                    Ok(afiles)
+                    Ok(contents)
                 }
             }
             Err(e) => self.error(format!(
@@ -167,6 +257,33 @@ impl<'a, S: SourceRead> Importer<'a, S> {
         }
     }
 
+    fn parse_files(&mut self, contents: &[(String, String)]) -> Result<Vec<ast::File>, ()> {
+        let mut afiles = vec![];
+        for (full_name, content) in contents.iter() {
+            let mut pfile = self.fset.add_file(
+                full_name.clone(),
+                Some(self.fset.base()),
+                content.chars().count(),
+            );
+            let afile = Parser::new(
+                self.ast_objs,
+                &mut pfile,
+                self.errors,
+                content,
+                self.trace_config.trace_parser,
+            )
+            .parse_file();
+            if afile.is_none() {
+                // parse error, the details should be in the errorlist already.
+                // give up
+                return Err(());
+            } else {
+                afiles.push(afile.unwrap());
+            }
+        }
+        Ok(afiles)
+    }
+
     fn error<T>(&self, err: String) -> Result<T, ()> {
         self.errors
             .add(self.fset.position(self.pos), err, false, false);
@@ -174,7 +291,9 @@ impl<'a, S: SourceRead> Importer<'a, S> {
     }
 }
 
-fn read_content(p: &Path, reader: &dyn SourceRead) -> io::Result<Vec<(String, String)>> {
+/// Reads the source files of the package at `p`, which is a directory or a single file,
+/// returns their display names and contents, sorted by path.
+pub fn read_package_files(p: &Path, reader: &dyn SourceRead) -> io::Result<Vec<(String, String)>> {
     let working_dir = reader.working_dir().canonicalize().ok();
     let mut result = vec![];
     let mut read = |path: PathBuf| -> io::Result<()> {
@@ -8,6 +8,7 @@
 //!
 //! # Feature
 //! - `btree_map`: Make it use BTreeMap instead of HashMap
+//! - `serde_borsh`: Borsh support for the packages exported by `PackageData`
 //!
 
 mod constant;
@@ -17,6 +18,7 @@ mod scope;
 #[macro_use]
 mod objects;
 mod display;
+mod export;
 mod importer;
 mod lookup;
 mod operand;
@@ -29,9 +31,11 @@ pub mod check;
 
 pub use constant::Value as ConstValue;
 pub use display::Displayer;
+pub use export::{Extent, Owned, PackageData};
 pub use importer::*;
 pub use obj::*;
 pub use objects::*;
 pub use operand::OperandMode;
+pub use package::Package;
 pub use selection::*;
 pub use universe::*;
@@ -11,16 +11,20 @@
 
 #![allow(dead_code)]
 use super::constant;
+use super::export::Relocation;
 use super::objects::{ObjKey, PackageKey, ScopeKey, TCObjects, TypeKey};
 use super::package::Package;
 use super::typ;
 use super::universe;
 use super::universe::Universe;
+#[cfg(feature = "serde_borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
 use go_parser::{ast, Map, Pos};
 use std::borrow::Cow;
 use std::fmt;
 use std::fmt::Write;
 
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
 #[derive(Clone, Debug, PartialEq)]
 pub struct VarProperty {
     pub embedded: bool,
@@ -40,6 +44,7 @@ impl VarProperty {
 
 /// EntityType defines the types of LangObj entities
 ///
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
 #[derive(Clone, Debug, PartialEq)]
 pub enum EntityType {
     /// A PkgName represents an imported Go package.
@@ -176,6 +181,7 @@ impl EntityType {
     }
 }
 
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub(crate) enum ObjColor {
     White,
@@ -199,6 +205,7 @@ impl fmt::Display for ObjColor {
 /// A LangObj describes a named language entity such as a package,
 /// constant, type, variable, function (incl. methods), or label.
 ///
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
 #[derive(Clone, Debug)]
 pub struct LangObj {
     entity_type: EntityType,
@@ -475,6 +482,25 @@ impl LangObj {
             scope_pos: 0,
         }
     }
+
+    /// Moves the keys and the positions in the object, see `Relocation`
+    pub(crate) fn relocate(&mut self, r: &Relocation) -> Option<()> {
+        if let EntityType::PkgName(pkg, _) = &mut self.entity_type {
+            r.pkg(pkg)?;
+        }
+        if let Some(parent) = &mut self.parent {
+            r.scope(parent)?;
+        }
+        if let Some(pkg) = &mut self.pkg {
+            r.pkg(pkg)?;
+        }
+        if let Some(typ) = &mut self.typ {
+            r.typ(typ)?;
+        }
+        self.pos = r.pos(self.pos);
+        self.scope_pos = r.pos(self.scope_pos);
+        Some(())
+    }
 }
 
 pub(crate) fn type_name_is_alias(okey: ObjKey, objs: &TCObjects) -> bool {
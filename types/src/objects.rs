@@ -16,8 +16,31 @@ use super::package::Package;
 use super::scope::Scope;
 use super::typ::*;
 use super::universe::Universe;
+#[cfg(feature = "serde_borsh")]
+use borsh::{maybestd::io::Result, maybestd::io::Write, BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde_borsh")]
+use go_parser::PiggyVecKey;
 use go_parser::{piggy_key_type, PiggyVec, Pos};
 use std::borrow::Cow;
+use std::rc::Rc;
+
+#[cfg(feature = "serde_borsh")]
+macro_rules! impl_borsh_for_key {
+    ($key:ident) => {
+        impl BorshSerialize for $key {
+            fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
+                self.as_usize().serialize(writer)
+            }
+        }
+
+        impl BorshDeserialize for $key {
+            fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> Result<Self> {
+                let i: usize = usize::deserialize_reader(reader)?;
+                Ok(i.into())
+            }
+        }
+    };
+}
 
 piggy_key_type! {
     pub struct ObjKey;
@@ -27,6 +50,15 @@ piggy_key_type! {
     pub struct ScopeKey;
 }
 
+#[cfg(feature = "serde_borsh")]
+impl_borsh_for_key!(ObjKey);
+#[cfg(feature = "serde_borsh")]
+impl_borsh_for_key!(TypeKey);
+#[cfg(feature = "serde_borsh")]
+impl_borsh_for_key!(PackageKey);
+#[cfg(feature = "serde_borsh")]
+impl_borsh_for_key!(ScopeKey);
+
 pub type LangObjs = PiggyVec<ObjKey, LangObj>;
 pub type Types = PiggyVec<TypeKey, Type>;
 pub type Packages = PiggyVec<PackageKey, Package>;
@@ -35,6 +67,7 @@ pub type Scopes = PiggyVec<ScopeKey, Scope>;
 
 /// The container of all "managed" objects
 /// also works as a "global" variable holder
+#[derive(Clone)]
 pub struct TCObjects {
     pub lobjs: LangObjs,
     pub types: Types,
@@ -43,7 +76,7 @@ pub struct TCObjects {
     pub scopes: Scopes,
     pub universe: Option<Universe>,
     // "global" variable
-    pub fmt_qualifier: Box<dyn Fn(&Package) -> Cow<str>>,
+    pub fmt_qualifier: Rc<dyn Fn(&Package) -> Cow<str>>,
 }
 
 fn default_fmt_qualifier(p: &Package) -> Cow<str> {
@@ -52,7 +85,7 @@ fn default_fmt_qualifier(p: &Package) -> Cow<str> {
 
 impl TCObjects {
     pub fn new() -> TCObjects {
-        let fmtq = Box::new(default_fmt_qualifier);
+        let fmtq = Rc::new(default_fmt_qualifier);
         const CAP: usize = 16;
         let mut objs = TCObjects {
             lobjs: PiggyVec::with_capacity(CAP),
@@ -102,6 +135,17 @@ impl TCObjects {
         self.pkgs.insert(pkg)
     }
 
+    /// Creates a package with its scope nested in the scope of `outer`, instead of
+    /// the universe. It has the path of `outer` too, so that they can access the
+    /// unexported fields and methods of each other.
+    pub fn new_nested_package(&mut self, outer: PackageKey) -> PackageKey {
+        let path = self.pkgs[outer].path().clone();
+        let outer_scope = *self.pkgs[outer].scope();
+        let skey = self.new_scope(Some(outer_scope), 0, 0, format!("package {}", path), false);
+        let pkg = Package::new(path, None, skey);
+        self.pkgs.insert(pkg)
+    }
+
     pub fn new_pkg_name(
         &mut self,
         pos: Pos,
@@ -10,12 +10,16 @@
 // license that can be found in the LICENSE file.
 
 #![allow(dead_code)]
+use super::export::Relocation;
 use super::objects::{PackageKey, ScopeKey};
+#[cfg(feature = "serde_borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
 use std::borrow::Cow;
 use std::fmt;
 
 /// A Package describes a Go package.
-#[derive(Debug)]
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone, Debug)]
 pub struct Package {
     path: String,
     name: Option<String>,
@@ -105,7 +109,17 @@ impl Package {
         f: &mut fmt::Formatter<'_>,
         qf: &dyn Fn(&Package) -> Cow<str>,
     ) -> fmt::Result {
-        write!(f, "{}.", qf(self))
+        // an empty qualifier leaves out the package
+        match qf(self) {
+            q if q.is_empty() => Ok(()),
+            q => write!(f, "{}.", q),
+        }
+    }
+
+    /// Moves the keys in the package, see `Relocation`
+    pub(crate) fn relocate(&mut self, r: &Relocation) -> Option<()> {
+        r.scope(&mut self.scope)?;
+        self.imports.iter_mut().try_for_each(|p| r.pkg(p))
     }
 }
 
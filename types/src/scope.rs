@@ -10,13 +10,18 @@
 // license that can be found in the LICENSE file.
 
 #![allow(dead_code)]
+use super::export::Relocation;
 use super::objects::{ObjKey, ScopeKey, TCObjects};
+#[cfg(feature = "serde_borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
 use go_parser::{Map, Pos};
 use std::fmt;
 
 /// A Scope maintains a set of objects and links to its containing
 /// (parent) and contained (children) scopes. Objects may be inserted
 /// and looked up by name.
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone)]
 pub struct Scope {
     parent: Option<ScopeKey>,
     children: Vec<ScopeKey>,
@@ -173,6 +178,18 @@ impl Scope {
         }
         Ok(())
     }
+
+    /// Moves the keys and the positions in the scope, see `Relocation`
+    pub(crate) fn relocate(&mut self, r: &Relocation) -> Option<()> {
+        if let Some(parent) = &mut self.parent {
+            r.scope(parent)?;
+        }
+        self.children.iter_mut().try_for_each(|s| r.scope(s))?;
+        self.elems.values_mut().try_for_each(|o| r.obj(o))?;
+        self.pos = r.pos(self.pos);
+        self.end = r.pos(self.end);
+        Some(())
+    }
 }
 
 // ----------------------------------------------------------------------------
@@ -10,8 +10,14 @@
 // license that can be found in the LICENSE file.
 
 #![allow(dead_code)]
+use super::export::Relocation;
 use super::obj::{LangObj, ObjSet};
 use super::objects::{ObjKey, ScopeKey, TCObjects, TypeKey};
+#[cfg(feature = "serde_borsh")]
+use borsh::{
+    maybestd::io::Error, maybestd::io::ErrorKind, maybestd::io::Result, BorshDeserialize,
+    BorshSerialize,
+};
 use std::cell::{Ref, RefCell, RefMut};
 use std::collections::HashSet;
 use std::fmt;
@@ -47,7 +53,8 @@ macro_rules! compare_by_type_name {
     }};
 }
 
-#[derive(Debug)]
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone, Debug)]
 pub enum Type {
     Basic(BasicDetail),
     Array(ArrayDetail),
@@ -307,6 +314,51 @@ impl Type {
             _ => false,
         }
     }
+
+    /// Moves the keys in the type, see `Relocation`
+    pub(crate) fn relocate(&mut self, r: &Relocation) -> Option<()> {
+        match self {
+            // basic types are in the universe, they never move
+            Type::Basic(_) => None,
+            Type::Array(a) => r.typ(&mut a.elem),
+            Type::Slice(s) => r.typ(&mut s.elem),
+            Type::Struct(s) => r.objs(&mut s.fields),
+            Type::Pointer(p) => r.typ(&mut p.base),
+            Type::Tuple(t) => r.objs(&mut t.vars),
+            Type::Signature(s) => {
+                if let Some(scope) = &mut s.scope {
+                    r.scope(scope)?;
+                }
+                if let Some(recv) = &mut s.recv {
+                    r.obj(recv)?;
+                }
+                r.typ(&mut s.params)?;
+                r.typ(&mut s.results)
+            }
+            Type::Interface(i) => {
+                r.objs(&mut i.methods)?;
+                i.embeddeds.iter_mut().try_for_each(|t| r.typ(t))?;
+                match &mut *i.all_methods.borrow_mut() {
+                    Some(all) => r.objs(all),
+                    None => Some(()),
+                }
+            }
+            Type::Map(m) => {
+                r.typ(&mut m.key)?;
+                r.typ(&mut m.elem)
+            }
+            Type::Chan(c) => r.typ(&mut c.elem),
+            Type::Named(n) => {
+                if let Some(obj) = &mut n.obj {
+                    r.obj(obj)?;
+                }
+                if let Some(underlying) = &mut n.underlying {
+                    r.typ(underlying)?;
+                }
+                r.objs(&mut n.methods)
+            }
+        }
+    }
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -426,6 +478,26 @@ pub struct BasicDetail {
     name: &'static str,
 }
 
+#[cfg(feature = "serde_borsh")]
+impl BorshSerialize for BasicDetail {
+    fn serialize<W: borsh::maybestd::io::Write>(&self, _: &mut W) -> Result<()> {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "basic types are in the universe",
+        ))
+    }
+}
+
+#[cfg(feature = "serde_borsh")]
+impl BorshDeserialize for BasicDetail {
+    fn deserialize_reader<R: std::io::Read>(_: &mut R) -> Result<Self> {
+        Err(Error::new(
+            ErrorKind::InvalidData,
+            "basic types are in the universe",
+        ))
+    }
+}
+
 impl BasicDetail {
     pub fn new(typ: BasicType, info: BasicInfo, name: &'static str) -> BasicDetail {
         BasicDetail {
@@ -464,7 +536,8 @@ impl BasicDetail {
 }
 
 /// An ArrayDetail represents an array type.
-#[derive(Debug)]
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone, Debug)]
 pub struct ArrayDetail {
     len: Option<u64>,
     elem: TypeKey,
@@ -492,7 +565,8 @@ impl ArrayDetail {
 }
 
 /// A Slice represents a slice type.
-#[derive(Debug)]
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone, Debug)]
 pub struct SliceDetail {
     elem: TypeKey,
 }
@@ -508,7 +582,8 @@ impl SliceDetail {
 }
 
 /// A StructDetail represents a struct type
-#[derive(Debug)]
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone, Debug)]
 pub struct StructDetail {
     fields: Vec<ObjKey>,               // objects of type LangObj::Var
     tags: Option<Vec<Option<String>>>, // None if there are no tags
@@ -558,7 +633,8 @@ impl StructDetail {
 }
 
 /// A PointerDetail represents a pointer type.
-#[derive(Debug)]
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone, Debug)]
 pub struct PointerDetail {
     base: TypeKey, // element type
 }
@@ -576,7 +652,8 @@ impl PointerDetail {
 /// A TupleDetail represents an ordered list of variables
 /// Tuples are used as components of signatures and to represent the type of multiple
 /// assignments; they are not first class types of Go.
-#[derive(Debug)]
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone, Debug)]
 pub struct TupleDetail {
     vars: Vec<ObjKey>, // LangObj::Var
 }
@@ -597,6 +674,7 @@ impl TupleDetail {
 
 /// A SignatureDetail represents a (non-builtin) function or method type.
 /// The receiver is ignored when comparing signatures for identity.
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
 #[derive(Copy, Clone, Debug)]
 pub struct SignatureDetail {
     scope: Option<ScopeKey>, // function scope, present for package-local signatures
@@ -699,6 +777,40 @@ pub struct InterfaceDetail {
     all_methods: Rc<RefCell<Option<Vec<ObjKey>>>>,
 }
 
+impl Clone for InterfaceDetail {
+    /// The clone doesn't share the lazily completed method set with the original
+    fn clone(&self) -> Self {
+        InterfaceDetail {
+            methods: self.methods.clone(),
+            embeddeds: self.embeddeds.clone(),
+            all_methods: Rc::new(RefCell::new(self.all_methods.borrow().clone())),
+        }
+    }
+}
+
+#[cfg(feature = "serde_borsh")]
+impl BorshSerialize for InterfaceDetail {
+    fn serialize<W: borsh::maybestd::io::Write>(&self, writer: &mut W) -> Result<()> {
+        self.methods.serialize(writer)?;
+        self.embeddeds.serialize(writer)?;
+        self.all_methods.borrow().serialize(writer)
+    }
+}
+
+#[cfg(feature = "serde_borsh")]
+impl BorshDeserialize for InterfaceDetail {
+    fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> Result<Self> {
+        let methods = Vec::<ObjKey>::deserialize_reader(reader)?;
+        let embeddeds = Vec::<TypeKey>::deserialize_reader(reader)?;
+        let all_methods = Option::<Vec<ObjKey>>::deserialize_reader(reader)?;
+        Ok(InterfaceDetail {
+            methods,
+            embeddeds,
+            all_methods: Rc::new(RefCell::new(all_methods)),
+        })
+    }
+}
+
 impl InterfaceDetail {
     pub fn new(
         mut methods: Vec<ObjKey>,
@@ -799,7 +911,8 @@ impl InterfaceDetail {
     }
 }
 
-#[derive(Debug)]
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone, Debug)]
 pub struct MapDetail {
     key: TypeKey,
     elem: TypeKey,
@@ -822,6 +935,7 @@ impl MapDetail {
     }
 }
 
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum ChanDir {
     SendRecv,
@@ -829,7 +943,8 @@ pub enum ChanDir {
     RecvOnly,
 }
 
-#[derive(Debug)]
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone, Debug)]
 pub struct ChanDetail {
     dir: ChanDir,
     elem: TypeKey,
@@ -852,7 +967,8 @@ impl ChanDetail {
     }
 }
 
-#[derive(Debug)]
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone, Debug)]
 pub struct NamedDetail {
     obj: Option<ObjKey>,         // corresponding declared object
     underlying: Option<TypeKey>, // possibly a Named during setup; never a Named once set up completely
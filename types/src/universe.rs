@@ -12,11 +12,14 @@
 #![allow(dead_code)]
 
 use super::constant;
+use super::export::Extent;
 use super::obj::*;
 use super::objects::{ObjKey, PackageKey, ScopeKey, TCObjects, TypeKey, Types};
 use super::package::*;
 use super::scope::*;
 use super::typ::*;
+#[cfg(feature = "serde_borsh")]
+use borsh::{BorshDeserialize, BorshSerialize};
 use go_parser::Map;
 
 /// ExprKind describes the kind of an expression; the kind
@@ -29,6 +32,7 @@ pub enum ExprKind {
 }
 
 /// A Builtin is the id of a builtin function.
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
 #[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Builtin {
     Append,
@@ -67,6 +71,7 @@ pub struct BuiltinInfo {
 
 /// Universe sets up the universe scope, the unsafe package
 /// and all the builtin types and functions
+#[derive(Clone)]
 pub struct Universe {
     scope: ScopeKey,
     unsafe_: PackageKey,
@@ -85,6 +90,7 @@ pub struct Universe {
     guard_sig: TypeKey,
     types: Map<BasicType, TypeKey>,
     builtins: Map<Builtin, BuiltinInfo>,
+    extent: Extent,
 }
 
 impl Universe {
@@ -114,6 +120,13 @@ impl Universe {
         let no_value_tuple = objs.new_t_tuple(vec![]);
         let indir = objs.new_type_name(0, None, "*".to_owned(), None);
         let guard_sig = objs.new_t_signature(None, None, no_value_tuple, no_value_tuple, false);
+        let extent = Extent {
+            lobjs: 0..objs.lobjs.vec().len(),
+            types: 0..objs.types.vec().len(),
+            scopes: 0..objs.scopes.vec().len(),
+            pkgs: 0..objs.pkgs.vec().len(),
+            pos: 0..0,
+        };
         Universe {
             scope: uskey,
             unsafe_: unsafe_,
@@ -126,6 +139,7 @@ impl Universe {
             guard_sig: guard_sig,
             types: types,
             builtins: builtins,
+            extent: extent,
         }
     }
 
@@ -169,6 +183,11 @@ impl Universe {
         &self.indir
     }
 
+    /// The objects of the universe, they're the first ones in `TCObjects`
+    pub fn extent(&self) -> &Extent {
+        &self.extent
+    }
+
     pub fn guard_sig(&self) -> &TypeKey {
         &self.guard_sig
     }
@@ -237,6 +256,9 @@ impl Universe {
         unsafe_: &PackageKey,
         objs: &mut TCObjects,
     ) {
+        // in order of the kinds, so that the keys are the same in every run
+        let mut types: Vec<_> = types.iter().collect();
+        types.sort_by_key(|(k, _)| **k);
         for (_, v) in types {
             let name = objs.types[*v].try_as_basic().unwrap().name();
             let t = objs
@@ -390,7 +412,9 @@ impl Universe {
         unsafe_: &PackageKey,
         objs: &mut TCObjects,
     ) {
-        for (f, info) in builtins.iter() {
+        let mut builtins: Vec<_> = builtins.iter().collect();
+        builtins.sort_by_key(|(f, _)| **f);
+        for (f, info) in builtins {
             let fobj = objs
                 .lobjs
                 .insert(LangObj::new_builtin(*f, info.name.to_owned(), typ));
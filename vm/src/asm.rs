@@ -0,0 +1,776 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Builds a `Bytecode` from text, see `Bytecode::assemble`.
+//!
+//! It's for writing programs by hand, to test the VM without the parser, the type
+//! checker and codegen. The instructions are written the way `Bytecode::disassemble`
+//! prints them, the rest of the program is declared with directives:
+//!
+//! ```text
+//! ; the functions before the first package belong to no package,
+//! ; the first function is the entry
+//! func entry
+//!         LOAD_PKG  r0, k0, 1
+//!         CALL<FlagA>  r0, r0, _
+//!         RETURN<FlagA>  _, _, _
+//!
+//! package main
+//! k0 = package main
+//! k1 = int 3
+//!
+//! ; the constructor returns the package, it has to be the first member
+//! func main.init (PkgCtor)
+//!         RETURN<FlagB>  k0, _, _
+//!
+//! func main.main (locals int)
+//!         DUPLICATE  r0, k1, _
+//!   loop: DEC<Int>  r0, _, _
+//!         JUMP_IF  loop, r0, _
+//!         RETURN<FlagA>  _, _, _
+//! ```
+//!
+//! - `package NAME` starts a package, the first one is the main package. Without
+//!   any, an empty `main` package is added.
+//! - `kN = KIND VALUE` is constant N, the constants are numbered in order.
+//!   The kinds are the Go basic types, e.g. `int 42`, `float64 1.5`, `bool true` and
+//!   `string "go"` with Rust escapes, and `func NAME`, `package NAME`, `type TYPE`
+//!   and `nil VALUETYPE`, e.g. `nil Map`.
+//! - `var NAME TYPE` adds a member to the package, with the zero value of the type.
+//! - `func NAME (ATTRS)` starts a function, the attributes are optional and any of
+//!   `params TYPES`, `results TYPES`, `locals TYPES`, `PkgCtor` and `HasDefer`.
+//!   The functions of a package are members of it too, named without the package.
+//! - The types are the Go basic types, `any`, `unsafe.Pointer`, `*T`, `[]T`,
+//!   `[N]T`, `map[K]V` and `chan T`.
+//! - An instruction is `[LABEL:] OP0[.OP1][<T0[,T1]>] [D, S0, S1]`, the fields are
+//!   `rN`, `kN`, `_`, a label or a number, as described in `disasm`. Missing fields
+//!   are `_`. The index the disassembler puts at the front of the lines is ignored.
+//!
+//! Comments start with `;`. Up values are not supported, nor are the instructions
+//! on struct fields and interface methods, as the types of their operands can't be
+//! declared for `Bytecode::verify`.
+
+use crate::gc::GcContainer;
+use crate::instruction::{Instruction, OpIndex, Opcode, ValueType};
+use crate::metadata::{ChannelType, Meta};
+use crate::value::{
+    Bytecode, FuncFlag, FunctionKey, FunctionObj, GosValue, PackageKey, PackageObj, VMObjects,
+};
+use go_parser::{Map, PiggyVecKey};
+use std::fmt;
+use std::str::FromStr;
+
+/// A problem found by `Bytecode::assemble`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AsmError {
+    /// The line number, starting from 1
+    pub line: usize,
+    pub msg: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.msg)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+type AsmResult<T> = Result<T, String>;
+
+impl Bytecode {
+    /// Assembles the text format described in the `asm` module. The result is not
+    /// verified, which allows building malformed bytecode on purpose, `verify` it
+    /// before running it otherwise.
+    pub fn assemble(text: &str) -> Result<Bytecode, AsmError> {
+        let mut asm = Assembler::new(text);
+        for (i, line) in text.lines().enumerate() {
+            asm.line = i + 1;
+            asm.assemble_line(strip_comment(line).trim())?;
+        }
+        asm.finish()
+    }
+}
+
+/// A jump to a label, resolved when the function is done
+struct Fixup<'a> {
+    line: usize,
+    pc: usize,
+    field: usize,
+    label: &'a str,
+    /// The index the offset is from
+    next: usize,
+}
+
+struct FuncState<'a> {
+    key: FunctionKey,
+    code: Vec<Instruction>,
+    labels: Map<&'a str, usize>,
+    fixups: Vec<Fixup<'a>>,
+    /// The extensions the last instruction still needs
+    exts_left: usize,
+    /// The index after the last instruction and its extensions
+    next: usize,
+    last_line: usize,
+}
+
+struct Assembler<'a> {
+    objs: VMObjects,
+    gcc: GcContainer,
+    consts: Vec<GosValue>,
+    /// The functions and the packages by name, they are numbered in the order
+    /// of declaration, so that they can be referred to before that
+    func_keys: Map<&'a str, FunctionKey>,
+    pkg_keys: Map<&'a str, PackageKey>,
+    pkg: PackageKey,
+    func: Option<FuncState<'a>>,
+    line: usize,
+}
+
+impl<'a> Assembler<'a> {
+    fn new(text: &'a str) -> Assembler<'a> {
+        let mut func_keys = Map::new();
+        let mut pkg_keys = Map::new();
+        for line in text.lines() {
+            let line = strip_comment(line).trim();
+            let (first, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            match (first, decl_name(rest.trim())) {
+                ("func", name) => {
+                    let key = FunctionKey::from(func_keys.len());
+                    func_keys.entry(name).or_insert(key);
+                }
+                ("package", name) => {
+                    let key = PackageKey::from(pkg_keys.len());
+                    pkg_keys.entry(name).or_insert(key);
+                }
+                _ => {}
+            }
+        }
+        Assembler {
+            objs: VMObjects::new(),
+            gcc: GcContainer::new(),
+            consts: vec![],
+            func_keys,
+            pkg_keys,
+            pkg: PackageKey::null(),
+            func: None,
+            line: 0,
+        }
+    }
+
+    fn assemble_line(&mut self, line: &'a str) -> Result<(), AsmError> {
+        let (first, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let rest = rest.trim();
+        if first == "package" || first == "func" {
+            self.end_function()?;
+        }
+        match first {
+            "" => Ok(()),
+            "package" => self.package(rest),
+            "func" => self.function(rest),
+            "var" => self.var(rest),
+            _ if is_const_name(first) => self.constant(first, rest),
+            _ => self.instruction(line),
+        }
+        .map_err(|msg| self.error(self.line, msg))
+    }
+
+    fn error(&self, line: usize, msg: String) -> AsmError {
+        AsmError { line, msg }
+    }
+
+    fn package(&mut self, rest: &'a str) -> AsmResult<()> {
+        let name = decl_name(rest);
+        if name.is_empty() || name.len() != rest.len() {
+            return Err("expected 'package NAME'".to_owned());
+        }
+        let key = *self
+            .pkg_keys
+            .get(name)
+            .ok_or_else(|| format!("package {} is not declared", name))?;
+        if key.as_usize() != self.objs.packages.vec().len() {
+            return Err(format!("package {} is already declared", name));
+        }
+        self.pkg = self.objs.packages.insert(PackageObj::new(name.to_owned()));
+        Ok(())
+    }
+
+    fn var(&mut self, rest: &str) -> AsmResult<()> {
+        let (name, typ) = rest
+            .split_once(char::is_whitespace)
+            .ok_or("expected 'var NAME TYPE'")?;
+        let meta = self.parse_type(typ.trim())?;
+        let zero = meta.zero(&self.objs.metas, &self.gcc);
+        self.current_package()?.add_member(name.to_owned(), zero);
+        Ok(())
+    }
+
+    fn function(&mut self, rest: &'a str) -> AsmResult<()> {
+        let name = decl_name(rest);
+        if name.is_empty() {
+            return Err("expected 'func NAME (ATTRS)'".to_owned());
+        }
+        let attrs = match rest[name.len()..].trim() {
+            "" => "",
+            attrs => attrs
+                .strip_prefix('(')
+                .and_then(|a| a.strip_suffix(')'))
+                .ok_or("expected '(ATTRS)' after the name")?,
+        };
+        let key = *self
+            .func_keys
+            .get(name)
+            .ok_or_else(|| format!("function {} is not declared", name))?;
+        if key.as_usize() != self.objs.functions.vec().len() {
+            return Err(format!("function {} is already declared", name));
+        }
+
+        let (mut params, mut results, mut locals) = (vec![], vec![], vec![]);
+        let mut flag = FuncFlag::Default;
+        for attr in attrs.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+            let (attr, types) = attr.split_once(char::is_whitespace).unwrap_or((attr, ""));
+            match attr {
+                "params" => params = self.parse_types(types)?,
+                "results" => results = self.parse_types(types)?,
+                "locals" => locals = self.parse_types(types)?,
+                "PkgCtor" => flag = FuncFlag::PkgCtor,
+                "HasDefer" => flag = FuncFlag::HasDefer,
+                _ => return Err(format!("unknown attribute {}", attr)),
+            }
+        }
+        let meta = Meta::new_sig(None, params, results, None, &mut self.objs.metas);
+        let mut func = FunctionObj::new(self.pkg, meta, &self.objs.metas, &self.gcc, flag);
+        func.name = name.to_owned();
+        func.local_zeros = locals
+            .iter()
+            .map(|m| m.zero(&self.objs.metas, &self.gcc))
+            .collect();
+        self.objs.functions.insert(func);
+
+        if self.pkg != PackageKey::null() {
+            let pkg = &mut self.objs.packages[self.pkg];
+            let member = name
+                .strip_prefix(pkg.name())
+                .and_then(|n| n.strip_prefix('.'))
+                .ok_or_else(|| format!("function {} is not in package {}", name, pkg.name()))?;
+            pkg.add_member(
+                member.to_owned(),
+                GosValue::new_closure_static(key, None, meta),
+            );
+        }
+        self.func = Some(FuncState {
+            key,
+            code: vec![],
+            labels: Map::new(),
+            fixups: vec![],
+            exts_left: 0,
+            next: 0,
+            last_line: 0,
+        });
+        Ok(())
+    }
+
+    fn constant(&mut self, name: &str, rest: &str) -> AsmResult<()> {
+        let index: usize = name[1..].parse().map_err(|_| "bad constant")?;
+        if index != self.consts.len() {
+            return Err(format!("expected k{}", self.consts.len()));
+        }
+        let value = rest.strip_prefix('=').ok_or("expected '='")?.trim();
+        let (kind, value) = value.split_once(char::is_whitespace).unwrap_or((value, ""));
+        let value = value.trim();
+        let val = match kind {
+            "bool" => parse_num::<bool>(value)?.into(),
+            "int" => parse_num::<isize>(value)?.into(),
+            "int8" => parse_num::<i8>(value)?.into(),
+            "int16" => parse_num::<i16>(value)?.into(),
+            "int32" => parse_num::<i32>(value)?.into(),
+            "int64" => parse_num::<i64>(value)?.into(),
+            "uint" => parse_num::<usize>(value)?.into(),
+            "uintptr" => GosValue::new_uint_ptr(parse_num(value)?),
+            "uint8" => parse_num::<u8>(value)?.into(),
+            "uint16" => parse_num::<u16>(value)?.into(),
+            "uint32" => parse_num::<u32>(value)?.into(),
+            "uint64" => parse_num::<u64>(value)?.into(),
+            "float32" => parse_num::<f32>(value)?.into(),
+            "float64" => parse_num::<f64>(value)?.into(),
+            "string" => GosValue::with_str(&parse_string(value)?),
+            "func" => GosValue::new_function(
+                *self
+                    .func_keys
+                    .get(value)
+                    .ok_or_else(|| format!("unknown function {}", value))?,
+            ),
+            "package" => GosValue::new_package(
+                *self
+                    .pkg_keys
+                    .get(value)
+                    .ok_or_else(|| format!("unknown package {}", value))?,
+            ),
+            "type" => GosValue::new_metadata(self.parse_type(value)?),
+            "nil" => match parse_value_type(value)? {
+                t if t.nilable() && t != ValueType::Slice => GosValue::new_nil(t),
+                t => return Err(format!("{} cannot be nil here", t)),
+            },
+            _ => return Err(format!("unknown constant kind {}", kind)),
+        };
+        self.consts.push(val);
+        Ok(())
+    }
+
+    fn instruction(&mut self, line: &'a str) -> AsmResult<()> {
+        let line_no = self.line;
+        let func = self
+            .func
+            .as_mut()
+            .ok_or("instruction outside of a function")?;
+        let pc = func.code.len();
+        let mut tokens = line.trim_start_matches(|c: char| c.is_ascii_digit()).trim();
+        if let Some((label, rest)) = tokens.split_once(':') {
+            let label = label.trim();
+            if is_label(label) {
+                if func.labels.insert(label, pc).is_some() {
+                    return Err(format!("label {} is already defined", label));
+                }
+                tokens = rest.trim();
+            }
+        }
+        let (mnemonic, fields) = tokens
+            .split_once(char::is_whitespace)
+            .unwrap_or((tokens, ""));
+        if mnemonic.is_empty() {
+            return Ok(());
+        }
+        let mut inst = parse_mnemonic(mnemonic)?;
+
+        let fields: Vec<&str> = match fields.trim() {
+            "" => vec![],
+            f => f.split(',').map(str::trim).collect(),
+        };
+        if fields.len() > 3 {
+            return Err("too many fields".to_owned());
+        }
+        let mut ops = [OpIndex::MAX; 3];
+        let mut labels = vec![];
+        for (i, field) in fields.iter().enumerate() {
+            ops[i] = match *field {
+                "_" => OpIndex::MAX,
+                f if is_const_name(f) => -parse_num::<OpIndex>(&f[1..])? - 1,
+                f if f.starts_with('r') && f[1..].parse::<OpIndex>().is_ok() => parse_num(&f[1..])?,
+                f if is_label(f) => {
+                    labels.push((i, f));
+                    0
+                }
+                f => parse_num(f)?,
+            };
+        }
+        inst.d = ops[0];
+        inst.s0 = ops[1];
+        inst.s1 = ops[2];
+
+        // the offsets of the extensions are from the same index as the instruction
+        let next = match func.exts_left {
+            0 => {
+                func.exts_left = inst.ext_count();
+                pc + 1 + func.exts_left
+            }
+            _ => {
+                func.exts_left -= 1;
+                func.next
+            }
+        };
+        func.next = next;
+        func.last_line = line_no;
+        for (field, label) in labels {
+            func.fixups.push(Fixup {
+                line: line_no,
+                pc,
+                field,
+                label,
+                next,
+            });
+        }
+        func.code.push(inst);
+        Ok(())
+    }
+
+    fn end_function(&mut self) -> Result<(), AsmError> {
+        let func = match self.func.take() {
+            Some(f) => f,
+            None => return Ok(()),
+        };
+        if func.exts_left > 0 {
+            return Err(self.error(
+                func.last_line,
+                "the instruction is missing extensions".to_owned(),
+            ));
+        }
+        let mut code = func.code;
+        for fix in func.fixups.iter() {
+            let target = *func
+                .labels
+                .get(fix.label)
+                .ok_or_else(|| self.error(fix.line, format!("undefined label {}", fix.label)))?;
+            let offset = target as OpIndex - fix.next as OpIndex;
+            let inst = &mut code[fix.pc];
+            match fix.field {
+                0 => inst.d = offset,
+                1 => inst.s0 = offset,
+                _ => inst.s1 = offset,
+            }
+        }
+        let obj = &mut self.objs.functions[func.key];
+        obj.max_write_index = match code.is_empty() {
+            true => 0,
+            false => Instruction::max_write_index(&code),
+        };
+        obj.pos = vec![None; code.len()];
+        obj.code = code;
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Bytecode, AsmError> {
+        self.end_function()?;
+        if self.objs.functions.vec().is_empty() {
+            return Err(self.error(self.line, "there are no functions".to_owned()));
+        }
+        if self.objs.packages.vec().is_empty() {
+            self.objs
+                .packages
+                .insert(PackageObj::new("main".to_owned()));
+        }
+        Ok(Bytecode::new(
+            self.objs,
+            self.consts,
+            vec![],
+            vec![],
+            FunctionKey::from(0),
+            PackageKey::from(0),
+            None,
+        ))
+    }
+
+    fn current_package(&mut self) -> AsmResult<&mut PackageObj> {
+        match self.pkg == PackageKey::null() {
+            true => Err("outside of a package".to_owned()),
+            false => Ok(&mut self.objs.packages[self.pkg]),
+        }
+    }
+
+    fn parse_types(&mut self, s: &str) -> AsmResult<Vec<Meta>> {
+        // `chan T` is the only type with a space
+        let mut types = vec![];
+        let mut tokens = s.split_whitespace();
+        while let Some(t) = tokens.next() {
+            let meta = match t {
+                "chan" => {
+                    let elem = tokens.next().ok_or("expected the element type")?;
+                    let elem = self.parse_type(elem)?;
+                    Meta::new_channel(ChannelType::SendRecv, elem, &mut self.objs.metas)
+                }
+                _ => self.parse_type(t)?,
+            };
+            types.push(meta);
+        }
+        Ok(types)
+    }
+
+    fn parse_type(&mut self, s: &str) -> AsmResult<Meta> {
+        let prim = &self.objs.prim_meta;
+        let meta = match s {
+            "bool" => prim.mbool,
+            "int" => prim.mint,
+            "int8" => prim.mint8,
+            "int16" => prim.mint16,
+            "int32" => prim.mint32,
+            "int64" => prim.mint64,
+            "uint" => prim.muint,
+            "uintptr" => prim.muint_ptr,
+            "uint8" => prim.muint8,
+            "uint16" => prim.muint16,
+            "uint32" => prim.muint32,
+            "uint64" => prim.muint64,
+            "float32" => prim.mfloat32,
+            "float64" => prim.mfloat64,
+            "complex64" => prim.mcomplex64,
+            "complex128" => prim.mcomplex128,
+            "string" => prim.mstr,
+            "unsafe.Pointer" => prim.unsafe_ptr,
+            "any" => prim.empty_iface,
+            _ => {
+                if let Some(elem) = s.strip_prefix('*') {
+                    self.parse_type(elem)?.ptr_to()
+                } else if let Some(elem) = s.strip_prefix("[]") {
+                    let elem = self.parse_type(elem)?;
+                    Meta::new_slice(elem, &mut self.objs.metas)
+                } else if let Some(elem) = s.strip_prefix("chan ") {
+                    let elem = self.parse_type(elem.trim())?;
+                    Meta::new_channel(ChannelType::SendRecv, elem, &mut self.objs.metas)
+                } else if let Some(rest) = s.strip_prefix("map[") {
+                    let (key, val) = split_bracket(rest)?;
+                    let key = self.parse_type(key)?;
+                    let val = self.parse_type(val)?;
+                    Meta::new_map(key, val, &mut self.objs.metas)
+                } else if let Some(rest) = s.strip_prefix('[') {
+                    let (len, elem) = split_bracket(rest)?;
+                    let len = parse_num(len)?;
+                    let elem = self.parse_type(elem)?;
+                    Meta::new_array(elem, len, &mut self.objs.metas)
+                } else {
+                    return Err(format!("unknown type {}", s));
+                }
+            }
+        };
+        Ok(meta)
+    }
+}
+
+/// The name declared by `func` or `package`
+fn decl_name(rest: &str) -> &str {
+    rest.split(|c: char| c == '(' || c.is_whitespace())
+        .next()
+        .unwrap_or("")
+}
+
+/// Splits `K]V` at the `]` that closes the `[` before it
+fn split_bracket(s: &str) -> AsmResult<(&str, &str)> {
+    let mut depth = 0;
+    for (i, c) in s.char_indices() {
+        match c {
+            '[' => depth += 1,
+            ']' if depth == 0 => return Ok((&s[..i], &s[i + 1..])),
+            ']' => depth -= 1,
+            _ => {}
+        }
+    }
+    Err(format!("expected ']' in {}", s))
+}
+
+fn parse_mnemonic(s: &str) -> AsmResult<Instruction> {
+    let (ops, types) = match s.split_once('<') {
+        Some((ops, types)) => (ops, types.strip_suffix('>').ok_or("expected '>'")?),
+        None => (s, ""),
+    };
+    let (op0, op1) = ops.split_once('.').unwrap_or((ops, ""));
+    let op0 = Opcode::from_name(op0).ok_or_else(|| format!("unknown opcode {}", op0))?;
+    let op1 = match (op1, op0) {
+        ("", _) => Opcode::VOID,
+        // CAST keeps a value type there
+        (t, Opcode::CAST) => {
+            let t = parse_value_type(t)?;
+            unsafe { std::mem::transmute::<ValueType, Opcode>(t) }
+        }
+        (op, _) => Opcode::from_name(op).ok_or_else(|| format!("unknown opcode {}", op))?,
+    };
+    let (t0, t1) = types.split_once(',').unwrap_or((types, ""));
+    let t = |t: &str| match t.trim() {
+        "" => Ok(ValueType::Void),
+        t => parse_value_type(t),
+    };
+    Ok(Instruction {
+        op0,
+        op1,
+        t0: t(t0)?,
+        t1: t(t1)?,
+        d: OpIndex::MAX,
+        s0: OpIndex::MAX,
+        s1: OpIndex::MAX,
+    })
+}
+
+fn parse_value_type(s: &str) -> AsmResult<ValueType> {
+    ValueType::from_name(s).ok_or_else(|| format!("unknown value type {}", s))
+}
+
+fn parse_num<T: FromStr>(s: &str) -> AsmResult<T> {
+    s.parse().map_err(|_| format!("bad value {}", s))
+}
+
+/// Parses a string in double quotes with the escapes of Rust's `{:?}`
+fn parse_string(s: &str) -> AsmResult<String> {
+    let inner = s
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .ok_or("expected a string in double quotes")?;
+    let mut result = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let c = match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(c @ ('\\' | '"' | '\'')) => c,
+            Some('u') => {
+                let code: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                code.strip_prefix('{')
+                    .and_then(|h| u32::from_str_radix(h, 16).ok())
+                    .and_then(char::from_u32)
+                    .ok_or("bad unicode escape")?
+            }
+            _ => return Err("bad escape".to_owned()),
+        };
+        result.push(c);
+    }
+    Ok(result)
+}
+
+/// Cuts the `;` comment off, a `;` in a string doesn't start one
+fn strip_comment(line: &str) -> &str {
+    let mut in_str = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_str => escaped = true,
+            '"' => in_str = !in_str,
+            ';' if !in_str => return &line[..i],
+            _ => {}
+        }
+    }
+    line
+}
+
+fn is_const_name(s: &str) -> bool {
+    s.starts_with('k') && s.len() > 1 && s[1..].bytes().all(|b| b.is_ascii_digit())
+}
+
+fn is_label(s: &str) -> bool {
+    s.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && s != "_"
+        && !is_const_name(s)
+        && !(s.starts_with('r') && s[1..].parse::<OpIndex>().is_ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ffi::FfiFactory;
+    use crate::metadata::MetadataType;
+
+    const SUM: &str = r#"
+func entry
+        LOAD_PKG  r0, k0, 1
+        CALL<FlagA>  r0, r0, _
+        RETURN<FlagA>  _, _, _
+
+package main
+k0 = package main
+k1 = int 0
+k2 = int 10
+k3 = int 45
+k4 = string "; not a comment"
+
+func main.init (PkgCtor)
+        RETURN<FlagB>  k0, _, _
+
+; sums 0..10 with a loop
+func main.main (locals int int)
+        DUPLICATE  r0, k1, _
+        DUPLICATE  r1, k1, _
+   L0:  LSS<Int>  r2, r1, k2
+        JUMP_IF_NOT  end, r2, _
+        ADD_ASSIGN<Int>  r0, r1, _
+        INC<Int>  r1, _, _
+        JUMP  L0, _, _
+  end:  EQL<Int,Int>  r3, r0, k3
+        ASSERT  _, r3, _
+        RETURN<FlagA>  _, _, _
+
+func main.Sum (params int, results int)
+        ; the result is r0, the parameter r1
+        ADD<Int>  r0, r1, r1
+        RETURN<FlagA>  _, _, _
+"#;
+
+    #[test]
+    fn test_assemble() {
+        let bc = Bytecode::assemble(SUM).unwrap();
+        assert!(bc.verify().is_ok(), "{:?}", bc.verify());
+        assert_eq!(&*bc.consts[4].as_string().as_str(), "; not a comment");
+        let main = &bc.objects.functions[FunctionKey::from(2)];
+        assert_eq!(main.code[3].d, 3);
+        assert_eq!(main.code[6].d, -5);
+
+        let ffi = FfiFactory::new();
+        assert!(crate::run(&bc, &ffi).is_none());
+        let vm = crate::Vm::new(&bc, &ffi);
+        let result = vm.call("Sum", vec![GosValue::from(21isize)]).unwrap();
+        assert_eq!(*result[0].as_int(), 42);
+
+        // the instructions are written the way they are disassembled
+        let text = bc.disassemble();
+        assert!(text.contains("JUMP_IF_NOT  L1, r2, _"));
+    }
+
+    #[test]
+    fn test_verify_packages() {
+        let errors = |text: &str| -> Vec<String> {
+            let bc = Bytecode::assemble(text).unwrap();
+            bc.verify()
+                .unwrap_err()
+                .iter()
+                .map(|e| e.to_string())
+                .collect()
+        };
+        // it would panic in `Vm::init_packages`
+        assert_eq!(
+            errors("func entry\n  RETURN<FlagA>  _, _, _\npackage main\nvar x int\n"),
+            ["package main: member 0 is not a constructor"]
+        );
+        let bc = Bytecode::assemble(SUM).unwrap();
+        let mut bad = bc.clone();
+        bad.objects.packages[bc.main_pkg]
+            .add_member("y".to_owned(), GosValue::new_nil(ValueType::Closure));
+        bad.objects.packages[bc.main_pkg].add_init_func(GosValue::from(1isize));
+        let slice = MetadataType::Slice(Meta::new(1000.into(), 0, false));
+        let key = bad.objects.metas.insert(slice);
+        let msgs: Vec<String> = bad
+            .verify()
+            .unwrap_err()
+            .iter()
+            .map(|e| e.to_string())
+            .collect();
+        assert_eq!(
+            msgs,
+            [
+                "package main: bad init function 0".to_owned(),
+                format!("type {}: bad type MetadataKey(1000)", key.as_usize()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let err = |text: &str| Bytecode::assemble(text).err().unwrap().to_string();
+        assert_eq!(
+            err("func f\n  JUMP  nowhere"),
+            "line 2: undefined label nowhere"
+        );
+        assert_eq!(err("func f\n  ADDD  r0"), "line 2: unknown opcode ADDD");
+        assert_eq!(err("k1 = int 1"), "line 1: expected k0");
+        assert_eq!(err("func f (locals foo)"), "line 1: unknown type foo");
+        assert_eq!(err("k0 = int8 300"), "line 1: bad value 300");
+    }
+
+    #[test]
+    fn test_assemble_spacing() {
+        let text = [
+            "func  entry",
+            "  RETURN<FlagA>",
+            "package \t main",
+            "func\tmain.init  (PkgCtor)",
+            "  RETURN<FlagA>",
+        ];
+        let bc = Bytecode::assemble(&text.join("\n")).unwrap();
+        assert_eq!(bc.objects.packages[bc.main_pkg].name(), "main");
+        let init = &bc.objects.functions[FunctionKey::from(1)];
+        assert_eq!(init.name, "main.init");
+        assert!(init.is_ctor());
+    }
+}
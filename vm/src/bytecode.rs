@@ -82,6 +82,18 @@ impl VMObjects {
     }
 }
 
+impl Clone for VMObjects {
+    fn clone(&self) -> Self {
+        VMObjects {
+            metas: self.metas.clone(),
+            functions: self.functions.clone(),
+            packages: self.packages.clone(),
+            prim_meta: self.prim_meta.clone(),
+            arr_slice_caller: Box::new(ArrCaller::new()),
+        }
+    }
+}
+
 #[cfg(feature = "serde_borsh")]
 impl BorshSerialize for VMObjects {
     fn serialize<W: Write>(&self, writer: &mut W) -> Result<()> {
@@ -102,6 +114,7 @@ impl BorshDeserialize for VMObjects {
 }
 
 #[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone)]
 pub struct Bytecode {
     pub objects: VMObjects,
     pub consts: Vec<GosValue>,
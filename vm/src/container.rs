@@ -0,0 +1,227 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! The on-disk format of `Bytecode`: a header followed by the Borsh encoded bytecode.
+//!
+//! | bytes | content |
+//! |-------|---------|
+//! | 4     | magic number `GOSB` |
+//! | 4     | format version, little endian |
+//! | 4     | the features that affect the layout, see `FeatureFlags` |
+//! | 8     | hash of the FFIs the program needs, as registered by the encoding engine |
+//! | 8     | checksum of the payload |
+//! | ...   | payload, the Borsh encoded `Bytecode` |
+
+use crate::ffi::FfiFactory;
+use crate::instruction::Opcode;
+use crate::value::{Bytecode, ValueType};
+use crate::verifier::VerifyError;
+use borsh::{BorshDeserialize, BorshSerialize};
+use std::fmt;
+
+const MAGIC: [u8; 4] = *b"GOSB";
+const HEADER_LEN: usize = 28;
+
+/// Bumped whenever the encoding of `Bytecode` changes
+pub const FORMAT_VERSION: u32 = 3;
+
+/// The features of go-vm that change the layout of the encoded bytecode
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FeatureFlags(u32);
+
+impl FeatureFlags {
+    pub const INSTRUCTION_POS: u32 = 1;
+    pub const ASYNC: u32 = 1 << 1;
+
+    /// The features this build of go-vm is compiled with
+    pub fn current() -> FeatureFlags {
+        let mut flags = 0;
+        if cfg!(feature = "instruction_pos") {
+            flags |= Self::INSTRUCTION_POS;
+        }
+        if cfg!(feature = "async") {
+            flags |= Self::ASYNC;
+        }
+        FeatureFlags(flags)
+    }
+
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+}
+
+impl fmt::Display for FeatureFlags {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<&str> = [
+            (Self::INSTRUCTION_POS, "instruction_pos"),
+            (Self::ASYNC, "async"),
+        ]
+        .into_iter()
+        .filter_map(|(bit, name)| (self.0 & bit != 0).then_some(name))
+        .collect();
+        write!(f, "[{}]", names.join(", "))
+    }
+}
+
+/// Why an encoded bytecode cannot be loaded
+#[derive(Debug)]
+pub enum BytecodeError {
+    /// It's not an encoded bytecode at all
+    BadMagic,
+    /// It's encoded by a go-vm with a different format version
+    Version { found: u32, expected: u32 },
+    /// It's encoded by a go-vm compiled with different features
+    Features {
+        found: FeatureFlags,
+        expected: FeatureFlags,
+    },
+    /// The payload is truncated or corrupted
+    Checksum,
+    /// The FFIs the program needs are registered with other Go signatures than
+    /// the ones it's built against
+    FfiHash,
+    /// The program needs an FFI that's not registered
+    MissingFfi(String),
+    /// Borsh failed to encode or decode the payload
+    Encoding(String),
+    /// The decoded bytecode is malformed, see `Bytecode::verify`
+    Verify(Vec<VerifyError>),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::BadMagic => f.write_str("not a Goscript bytecode file"),
+            BytecodeError::Version { found, expected } => write!(
+                f,
+                "bytecode format version {} is not supported, expected {}",
+                found, expected
+            ),
+            BytecodeError::Features { found, expected } => write!(
+                f,
+                "bytecode is built with features {}, but the VM has {}",
+                found, expected
+            ),
+            BytecodeError::Checksum => f.write_str("bytecode checksum mismatch"),
+            BytecodeError::FfiHash => f.write_str("bytecode FFI hash mismatch"),
+            BytecodeError::MissingFfi(name) => write!(f, "FFI '{}' is not registered", name),
+            BytecodeError::Encoding(msg) => write!(f, "bad bytecode encoding: {}", msg),
+            BytecodeError::Verify(errors) => {
+                f.write_str("bad bytecode:")?;
+                errors.iter().try_for_each(|e| write!(f, "\n\t{}", e))
+            }
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+impl Bytecode {
+    /// Encodes the bytecode with the header, the result can be loaded with `decode`
+    /// by a go-vm of the same format version and features, with the FFIs the program
+    /// needs registered the same way as in `ffi`.
+    pub fn encode(&self, ffi: &FfiFactory) -> Result<Vec<u8>, BytecodeError> {
+        let payload = self
+            .try_to_vec()
+            .map_err(|e| BytecodeError::Encoding(e.to_string()))?;
+        let mut buf = Vec::with_capacity(HEADER_LEN + payload.len());
+        buf.extend_from_slice(&MAGIC);
+        buf.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        buf.extend_from_slice(&FeatureFlags::current().bits().to_le_bytes());
+        buf.extend_from_slice(&ffi_hash(&self.ffi_names(), ffi).to_le_bytes());
+        buf.extend_from_slice(&fnv1a(&payload).to_le_bytes());
+        buf.extend_from_slice(&payload);
+        Ok(buf)
+    }
+
+    /// Decodes what `encode` returns, checking the header before touching the payload,
+    /// and verifying the bytecode after decoding it. The FFIs the program needs have
+    /// to be registered in `ffi` as they were when it was encoded.
+    pub fn decode(data: &[u8], ffi: &FfiFactory) -> Result<Bytecode, BytecodeError> {
+        if data.len() < HEADER_LEN || data[..4] != MAGIC {
+            return Err(BytecodeError::BadMagic);
+        }
+        let u32_at = |i: usize| u32::from_le_bytes(data[i..i + 4].try_into().unwrap());
+        let u64_at = |i: usize| u64::from_le_bytes(data[i..i + 8].try_into().unwrap());
+        let version = u32_at(4);
+        if version != FORMAT_VERSION {
+            return Err(BytecodeError::Version {
+                found: version,
+                expected: FORMAT_VERSION,
+            });
+        }
+        let features = FeatureFlags(u32_at(8));
+        if features != FeatureFlags::current() {
+            return Err(BytecodeError::Features {
+                found: features,
+                expected: FeatureFlags::current(),
+            });
+        }
+        let payload = &data[HEADER_LEN..];
+        if fnv1a(payload) != u64_at(20) {
+            return Err(BytecodeError::Checksum);
+        }
+        let bc = Bytecode::try_from_slice(payload)
+            .map_err(|e| BytecodeError::Encoding(e.to_string()))?;
+        // the FFI names are read from the constants, which are checked first
+        bc.verify().map_err(BytecodeError::Verify)?;
+        let names = bc.ffi_names();
+        if let Some(name) = names.iter().find(|name| !ffi.is_registered(name)) {
+            return Err(BytecodeError::MissingFfi(name.clone()));
+        }
+        if ffi_hash(&names, ffi) != u64_at(12) {
+            return Err(BytecodeError::FfiHash);
+        }
+        Ok(bc)
+    }
+
+    /// The names of the FFIs the program creates with `ffi(...)`, sorted and deduplicated.
+    /// Only the names given as constants are known.
+    pub fn ffi_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .objects
+            .functions
+            .iter()
+            .flat_map(|f| f.code.iter())
+            .filter(|inst| inst.op0 == Opcode::FFI && inst.s1 < 0)
+            .map(|inst| &self.consts[(-inst.s1 - 1) as usize])
+            .filter(|name| name.typ() == ValueType::String)
+            .map(|name| name.as_string().as_str().to_string())
+            .collect();
+        names.sort();
+        names.dedup();
+        names
+    }
+}
+
+/// Hashes the names of the FFIs along with the Go signatures `ffi` has for them,
+/// so that an FFI whose functions changed doesn't match
+fn ffi_hash(names: &[String], ffi: &FfiFactory) -> u64 {
+    let mut text = String::new();
+    for name in names.iter() {
+        text.push_str(name);
+        text.push('\0');
+        // the functions may be registered in any order
+        let mut sigs: Vec<String> = ffi
+            .go_signatures()
+            .get(name.as_str())
+            .into_iter()
+            .flatten()
+            .map(|sig| {
+                let (params, results) = (sig.params.join(","), sig.results.join(","));
+                format!("{}({}){}\0", sig.name, params, results)
+            })
+            .collect();
+        sigs.sort();
+        text.extend(sigs);
+    }
+    fnv1a(text.as_bytes())
+}
+
+/// 64-bit FNV-1a, which unlike `DefaultHasher` is stable across Rust versions
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |h, b| {
+        (h ^ *b as u64).wrapping_mul(0x100000001b3)
+    })
+}
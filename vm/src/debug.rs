@@ -0,0 +1,421 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Source level debugging.
+//!
+//! A `Debugger` attached with `Vm::set_debugger` is called whenever a fiber
+//! stops, which happens before an instruction is executed if it's at a
+//! breakpoint, if the last step is done, or if a pause is requested.
+//! The debugger inspects the fiber through a `DebugView` and tells it how to
+//! go on with a `DebugAction`.
+//!
+//! Breakpoints and steps work on source lines, so the bytecode needs the
+//! instruction positions and the `FileSet`, i.e. the debug info.
+
+use crate::objects::ClosureObj;
+use crate::stack::Stack;
+use crate::value::*;
+use crate::vm::CallFrame;
+use crate::StackFrame;
+use go_parser::Map;
+use std::cell::RefCell;
+use std::path::Path;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
+
+/// Why a fiber stopped
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint,
+    Step,
+    Pause,
+}
+
+/// What a stopped fiber does next
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DebugAction {
+    /// Runs until the next breakpoint or pause
+    Continue,
+    /// Stops at the next line, including the ones of the functions it calls
+    StepIn,
+    /// Stops at the next line of the current function, or of its caller
+    /// if it returns
+    StepOver,
+    /// Stops when the current function returns
+    StepOut,
+    /// Goes on like before the stop, i.e. the step in progress is kept, for
+    /// debuggers that pause only to do something in between
+    Resume,
+}
+
+pub trait Debugger {
+    /// Called on the fiber that stopped, the other fibers don't run until it returns.
+    fn on_stop(&mut self, reason: StopReason, view: &DebugView) -> DebugAction;
+
+    /// Called when a fiber, i.e. a goroutine, is created.
+    fn on_fiber_start(&mut self, _fiber: usize) {}
+
+    /// Called when a fiber is finished or interrupted.
+    fn on_fiber_end(&mut self, _fiber: usize) {}
+}
+
+/// Lets the host pause a running VM, it can be sent to another thread.
+///
+/// The next fiber that executes an instruction stops with `StopReason::Pause`,
+/// and the request is cleared.
+#[derive(Clone, Debug, Default)]
+pub struct PauseHandle(Arc<AtomicBool>);
+
+impl PauseHandle {
+    pub fn pause(&self) {
+        self.0.store(true, atomic::Ordering::Relaxed);
+    }
+
+    #[inline]
+    fn take(&self) -> bool {
+        self.0.load(atomic::Ordering::Relaxed) && self.0.swap(false, atomic::Ordering::Relaxed)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Step {
+    action: DebugAction,
+    fiber: usize,
+    depth: usize,
+    func: FunctionKey,
+    line: Option<(usize, usize)>,
+}
+
+/// Where in the bytecode a breakpoint is, the function and the pc
+type Location = (FunctionKey, OpIndex);
+
+#[derive(Default)]
+struct Breakpoints {
+    /// The requested file names and lines, and where they are resolved to
+    lines: Vec<(String, usize, Vec<Location>)>,
+    pcs: Map<Location, usize>,
+}
+
+/// The state of a debugging session, shared by all the fibers of a VM
+pub(crate) struct DebugState<'a> {
+    debugger: RefCell<Box<dyn Debugger + 'a>>,
+    breakpoints: RefCell<Breakpoints>,
+    step: RefCell<Option<Step>>,
+    pause: PauseHandle,
+}
+
+impl<'a> DebugState<'a> {
+    pub(crate) fn new(debugger: Box<dyn Debugger + 'a>) -> DebugState<'a> {
+        DebugState {
+            debugger: RefCell::new(debugger),
+            breakpoints: RefCell::new(Breakpoints::default()),
+            step: RefCell::new(None),
+            pause: PauseHandle::default(),
+        }
+    }
+
+    pub(crate) fn pause_handle(&self) -> PauseHandle {
+        self.pause.clone()
+    }
+
+    /// Adds a breakpoint at the first line at or after `line` that has code,
+    /// and returns that line, or `None` if there is no such line in the file.
+    pub(crate) fn set_breakpoint(&self, bc: &Bytecode, file: &str, line: usize) -> Option<usize> {
+        let fs = bc.file_set.as_ref()?;
+        let src = fs.iter().find(|f| same_file(f.name(), file))?;
+        let (begin, end) = (src.base(), src.base() + src.size());
+        // the instructions that start a run of the same line
+        let mut found: Option<(usize, Vec<(FunctionKey, OpIndex)>)> = None;
+        for (i, func) in bc.objects.functions.vec().iter().enumerate() {
+            let mut last = None;
+            for (pc, p) in func.pos.iter().enumerate() {
+                let l = match p.map(|x| x as usize) {
+                    Some(x) if x >= begin && x <= end => Some(src.position(x).line),
+                    _ => None,
+                };
+                if let Some(l) = l.filter(|l| *l >= line && Some(*l) != last) {
+                    let loc = (FunctionKey::from(i), pc as OpIndex);
+                    match &mut found {
+                        Some((fl, locs)) if *fl == l => locs.push(loc),
+                        Some((fl, _)) if *fl < l => {}
+                        _ => found = Some((l, vec![loc])),
+                    }
+                }
+                last = l;
+            }
+        }
+        let (l, locs) = found?;
+        let mut bps = self.breakpoints.borrow_mut();
+        for loc in locs.iter() {
+            *bps.pcs.entry(*loc).or_insert(0) += 1;
+        }
+        bps.lines.push((file.to_owned(), l, locs));
+        Some(l)
+    }
+
+    /// Removes all the breakpoints in `file`
+    pub(crate) fn clear_breakpoints(&self, file: &str) {
+        let mut bps = self.breakpoints.borrow_mut();
+        let (removed, kept) = std::mem::take(&mut bps.lines)
+            .into_iter()
+            .partition::<Vec<_>, _>(|(f, _, _)| same_file(f, file));
+        for loc in removed.iter().flat_map(|(_, _, locs)| locs.iter()) {
+            if let Some(n) = bps.pcs.get_mut(loc) {
+                *n -= 1;
+                if *n == 0 {
+                    bps.pcs.remove(loc);
+                }
+            }
+        }
+        bps.lines = kept;
+    }
+
+    /// Tells if the fiber should stop before executing the instruction at `pc`.
+    #[inline]
+    pub(crate) fn check(
+        &self,
+        bc: &Bytecode,
+        fiber: usize,
+        depth: usize,
+        func: FunctionKey,
+        pc: OpIndex,
+    ) -> Option<StopReason> {
+        if self.pause.take() {
+            return Some(StopReason::Pause);
+        }
+        if self.breakpoints.borrow().pcs.contains_key(&(func, pc)) {
+            return Some(StopReason::Breakpoint);
+        }
+        let step = (*self.step.borrow())?;
+        if step.fiber != fiber {
+            return None;
+        }
+        if depth < step.depth {
+            return Some(StopReason::Step);
+        }
+        let new_line = || {
+            let line = line_of(bc, func, pc);
+            line.is_some() && (func != step.func || line != step.line)
+        };
+        let stop = match step.action {
+            DebugAction::StepIn => new_line(),
+            DebugAction::StepOver => depth == step.depth && new_line(),
+            _ => false,
+        };
+        match stop {
+            true => Some(StopReason::Step),
+            false => None,
+        }
+    }
+
+    pub(crate) fn fiber_started(&self, fiber: usize) {
+        self.debugger.borrow_mut().on_fiber_start(fiber);
+    }
+
+    pub(crate) fn fiber_ended(&self, fiber: usize) {
+        self.debugger.borrow_mut().on_fiber_end(fiber);
+    }
+
+    /// Calls the debugger and sets up the step it asks for.
+    pub(crate) fn stop(&self, reason: StopReason, view: &DebugView) {
+        let action = self.debugger.borrow_mut().on_stop(reason, view);
+        let step = match action {
+            DebugAction::Resume => return,
+            DebugAction::Continue => None,
+            _ => {
+                let (func, pc) = view.call_stack[0];
+                Some(Step {
+                    action,
+                    fiber: view.fiber,
+                    depth: view.frames.len(),
+                    func,
+                    line: line_of(view.code, func, pc),
+                })
+            }
+        };
+        *self.step.borrow_mut() = step;
+    }
+}
+
+/// A stopped fiber as seen by the debugger.
+///
+/// The frames are numbered from the innermost one, which is 0.
+pub struct DebugView<'a, 'b> {
+    code: &'a Bytecode,
+    state: &'b DebugState<'a>,
+    fiber: usize,
+    frames: &'b [CallFrame],
+    stack: &'b Stack,
+    call_stack: Vec<(FunctionKey, OpIndex)>,
+}
+
+impl<'a, 'b> DebugView<'a, 'b> {
+    pub(crate) fn new(
+        code: &'a Bytecode,
+        state: &'b DebugState<'a>,
+        fiber: usize,
+        frames: &'b [CallFrame],
+        stack: &'b Stack,
+    ) -> DebugView<'a, 'b> {
+        // the callers have moved past their CALL instructions
+        let call_stack = frames
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, f)| match i {
+                0 => (f.func(), f.pc),
+                _ => (f.func(), f.pc - 1),
+            })
+            .collect();
+        DebugView {
+            code,
+            state,
+            fiber,
+            frames,
+            stack,
+            call_stack,
+        }
+    }
+
+    pub fn bytecode(&self) -> &'a Bytecode {
+        self.code
+    }
+
+    /// The id of the fiber, i.e. the goroutine
+    pub fn fiber_id(&self) -> usize {
+        self.fiber
+    }
+
+    /// The functions and the instructions of the frames, innermost first
+    pub fn call_stack(&self) -> &[(FunctionKey, OpIndex)] {
+        &self.call_stack
+    }
+
+    /// The frames with their positions resolved, innermost first
+    pub fn stack_frames(&self) -> Vec<StackFrame> {
+        StackFrame::resolve(&self.call_stack, self.code)
+    }
+
+    /// The named locals, parameters and results of a frame that are in scope,
+    /// in the order they are allocated. A shadowed variable is left out.
+    pub fn locals(&self, frame: usize) -> Vec<(String, GosValue)> {
+        let (cf, func, pc) = match self.frame(frame) {
+            Some(x) => x,
+            None => return vec![],
+        };
+        // without the position every variable is visible
+        let pos = func.pos.get(pc as usize).copied().flatten();
+        let visible: Vec<&VarName> = func
+            .var_names
+            .iter()
+            .filter(|v| pos.is_none_or(|p| v.in_scope(p)))
+            .collect();
+        visible
+            .iter()
+            .filter(|v| {
+                !visible
+                    .iter()
+                    .any(|o| o.name == v.name && o.scope.0 > v.scope.0)
+            })
+            .map(|v| {
+                let val = self.stack.get(cf.stack_base + v.index).clone();
+                (v.name.clone(), val)
+            })
+            .collect()
+    }
+
+    /// The variables a frame captures from the functions enclosing it
+    pub fn up_values(&self, frame: usize) -> Vec<(String, GosValue)> {
+        let (cf, func, _) = match self.frame(frame) {
+            Some(x) => x,
+            None => return vec![],
+        };
+        let ptrs = match &cf.var_ptrs {
+            Some(ptrs) => ptrs,
+            None => return vec![],
+        };
+        func.up_ptrs
+            .iter()
+            .zip(func.up_names.iter())
+            .zip(ptrs.iter())
+            // the local ones are pointers to its own locals
+            .filter(|((desc, _), _)| !desc.is_local)
+            .map(|((_, name), uv)| (name.clone(), uv.value(self.stack).into_owned()))
+            .collect()
+    }
+
+    /// The variables and the constants of the package of a frame, sorted by name
+    pub fn globals(&self, frame: usize) -> Vec<(String, GosValue)> {
+        let (_, func, _) = match self.frame(frame) {
+            Some(x) => x,
+            None => return vec![],
+        };
+        let objs = &self.code.objects;
+        let pkg = &objs.packages[func.package];
+        let mut members: Vec<(String, GosValue)> = pkg
+            .member_indices()
+            .iter()
+            .filter(|(name, _)| !name.is_empty()) // the constructor
+            .map(|(name, i)| (name.clone(), pkg.member(*i).clone()))
+            // the functions are the closures of the functions with the same name
+            .filter(|(name, val)| match val.typ() {
+                ValueType::Closure => match val.as_closure() {
+                    Some((ClosureObj::Gos(c), _)) => {
+                        let fname = &objs.functions[c.func].name;
+                        let short = fname
+                            .strip_prefix(pkg.name())
+                            .and_then(|x| x.strip_prefix('.'));
+                        short != Some(name.as_str())
+                    }
+                    _ => true,
+                },
+                _ => true,
+            })
+            .collect();
+        members.sort_by(|a, b| a.0.cmp(&b.0));
+        members
+    }
+
+    /// Finds a variable by name as seen from a frame, i.e. a local first,
+    /// then an up value, and then a package variable.
+    pub fn lookup(&self, frame: usize, name: &str) -> Option<GosValue> {
+        self.locals(frame)
+            .into_iter()
+            .chain(self.up_values(frame))
+            .chain(self.globals(frame))
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v)
+    }
+
+    /// See `Vm::set_breakpoint`
+    pub fn set_breakpoint(&self, file: &str, line: usize) -> Option<usize> {
+        self.state.set_breakpoint(self.code, file, line)
+    }
+
+    /// See `Vm::clear_breakpoints`
+    pub fn clear_breakpoints(&self, file: &str) {
+        self.state.clear_breakpoints(file)
+    }
+
+    fn frame(&self, i: usize) -> Option<(&CallFrame, &'a FunctionObj, OpIndex)> {
+        let (fkey, pc) = *self.call_stack.get(i)?;
+        let cf = &self.frames[self.frames.len() - 1 - i];
+        Some((cf, &self.code.objects.functions[fkey], pc))
+    }
+}
+
+/// The file and the line of an instruction, the file is identified by its base
+fn line_of(bc: &Bytecode, func: FunctionKey, pc: OpIndex) -> Option<(usize, usize)> {
+    let p = bc.objects.functions[func].pos.get(pc as usize).copied()??;
+    let file = bc.file_set.as_ref()?.file(p as usize)?;
+    Some((file.base(), file.position(p as usize).line))
+}
+
+/// The paths are the same, or one is a suffix of the other, e.g. `main.gos`
+/// and `./examples/main.gos`
+fn same_file(a: &str, b: &str) -> bool {
+    let (a, b) = (a.trim_start_matches("./"), b.trim_start_matches("./"));
+    let (pa, pb) = (Path::new(a), Path::new(b));
+    a == b || pa.ends_with(pb) || pb.ends_with(pa)
+}
@@ -0,0 +1,322 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Renders a `Bytecode` as text, see `Bytecode::disassemble`.
+//!
+//! Every function starts with a header with its name, package and frame layout,
+//! followed by one line per instruction:
+//!
+//! ```text
+//!     12  L0:  ADD<Int>  r2, r0, k3  ; main.go:5:9  k3 = 1
+//! ```
+//!
+//! That is the index of the instruction, the label if it's a jump target, the
+//! opcodes and the value types, and the fields `d`, `s0` and `s1` in this order:
+//! - `r3` is register 3 of the frame and `k3` is constant 3, which is how
+//!   `Stack::read` reads the index `3` and `-4`
+//! - `L0` is a jump target, the labels are numbered in the order of the code
+//! - `_` is a field the instruction doesn't use
+//! - any other field, like a member index or a count, is a plain number
+//!
+//! The comment has the source position and the values of the constants read.
+//! The extensions of an instruction, like the cases of a SELECT, are indented
+//! on the lines that follow it.
+
+use crate::instruction::{Instruction, OpIndex, Opcode, ValueType};
+use crate::value::{Bytecode, FuncFlag, FunctionKey, FunctionObj, GosValue, PackageKey};
+use go_parser::{Map, PiggyVecKey};
+use std::fmt::{self, Write};
+
+/// How the disassembler renders a field of an instruction
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Field {
+    /// A register or a constant
+    Reg,
+    /// An offset from the instruction after this one and its extensions
+    Jump,
+    /// Anything else
+    Num,
+}
+
+impl Bytecode {
+    /// Renders all the functions as text, the entry function first.
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "; {} functions, {} constants, main package {}",
+            self.objects.functions.vec().len(),
+            self.consts.len(),
+            self.package_name(self.main_pkg)
+        );
+        let keys = std::iter::once(self.entry).chain(
+            (0..self.objects.functions.vec().len())
+                .map(FunctionKey::from)
+                .filter(|k| *k != self.entry),
+        );
+        for key in keys {
+            out.push('\n');
+            let _ = self.write_function(&mut out, key);
+        }
+        out
+    }
+
+    /// Renders one function as text
+    pub fn disassemble_function(&self, key: FunctionKey) -> String {
+        let mut out = String::new();
+        let _ = self.write_function(&mut out, key);
+        out
+    }
+
+    fn write_function(&self, out: &mut String, key: FunctionKey) -> fmt::Result {
+        let func = &self.objects.functions[key];
+        write!(
+            out,
+            "func {} (package {}",
+            func.name,
+            self.package_name(func.package)
+        )?;
+        write!(
+            out,
+            ", {} params, {} results, {} locals, frame {}",
+            func.param_count,
+            func.ret_count(),
+            func.local_count(),
+            func.frame_size()
+        )?;
+        match func.flag {
+            FuncFlag::Default => {}
+            flag => write!(out, ", {:?}", flag)?,
+        }
+        writeln!(out, ")")?;
+        for (i, uv) in func.up_ptrs.iter().enumerate() {
+            writeln!(
+                out,
+                "  ; up value {}: r{} of {}{}",
+                i,
+                uv.index,
+                self.function_name(uv.func),
+                if uv.is_local { ", local" } else { "" }
+            )?;
+        }
+
+        let labels = labels(func);
+        let mut pc = 0;
+        while pc < func.code.len() {
+            let inst = &func.code[pc];
+            let next = pc + 1 + inst.ext_count();
+            let exts = &func.code[(pc + 1).min(func.code.len())..next.min(func.code.len())];
+            // the comment of the extensions goes with the instruction
+            let mut comment = self.position(func, pc).unwrap_or_default();
+            let fields = [inst.d, inst.s0, inst.s1]
+                .into_iter()
+                .zip(field_kinds(inst))
+                .chain(
+                    exts.iter()
+                        .flat_map(|e| [e.d, e.s0, e.s1].into_iter().zip(ext_field_kinds(inst))),
+                );
+            for (op, kind) in fields {
+                if kind == Field::Reg {
+                    self.describe_const(&mut comment, op);
+                }
+            }
+            self.write_inst(out, pc, 0, inst, field_kinds(inst), next, &labels)?;
+            write_comment(out, &comment)?;
+            for (i, ext) in exts.iter().enumerate() {
+                self.write_inst(
+                    out,
+                    pc + 1 + i,
+                    1,
+                    ext,
+                    ext_field_kinds(inst),
+                    next,
+                    &labels,
+                )?;
+                writeln!(out)?;
+            }
+            pc = next;
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn write_inst(
+        &self,
+        out: &mut String,
+        pc: usize,
+        indent: usize,
+        inst: &Instruction,
+        kinds: [Field; 3],
+        next: usize,
+        labels: &Map<usize, usize>,
+    ) -> fmt::Result {
+        let label = labels
+            .get(&pc)
+            .map(|l| format!("L{}:", l))
+            .unwrap_or_default();
+        write!(out, "{:>6}  {:<5}{}", pc, label, "  ".repeat(indent))?;
+        write!(out, "{}", inst.op0)?;
+        if inst.op1 != Opcode::VOID {
+            match inst.op0 {
+                Opcode::CAST => write!(out, ".{}", inst.op1_as_t())?,
+                _ => write!(out, ".{}", inst.op1)?,
+            }
+        }
+        match (inst.t0, inst.t1) {
+            (ValueType::Void, ValueType::Void) => {}
+            (t0, ValueType::Void) => write!(out, "<{}>", t0)?,
+            (t0, t1) => write!(out, "<{},{}>", t0, t1)?,
+        }
+        for (i, op) in [inst.d, inst.s0, inst.s1].iter().enumerate() {
+            out.push_str(if i == 0 { "  " } else { ", " });
+            match (kinds[i], *op) {
+                (_, OpIndex::MAX) => out.push('_'),
+                (Field::Reg, i) if i < 0 => write!(out, "k{}", -i - 1)?,
+                (Field::Reg, i) => write!(out, "r{}", i)?,
+                (Field::Jump, i) => match labels.get(&jump_target(next, i)) {
+                    Some(l) => write!(out, "L{}", l)?,
+                    None => write!(out, "{}", i)?,
+                },
+                (Field::Num, i) => write!(out, "{}", i)?,
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends the value of the field `op` to `comment` if it's a constant
+    fn describe_const(&self, comment: &mut String, op: OpIndex) {
+        if op >= 0 || op == OpIndex::MAX {
+            return;
+        }
+        let index = (-op - 1) as usize;
+        let desc = match self.consts.get(index) {
+            Some(val) => self.const_desc(val),
+            None => "?".to_owned(),
+        };
+        if !comment.is_empty() {
+            comment.push_str("  ");
+        }
+        let _ = write!(comment, "k{} = {}", index, desc);
+    }
+
+    fn const_desc(&self, val: &GosValue) -> String {
+        match val.typ() {
+            ValueType::String => format!("{:?}", &*val.as_string().as_str()),
+            ValueType::Function => format!("func {}", self.function_name(*val.as_function())),
+            ValueType::Package => format!("package {}", self.package_name(*val.as_package())),
+            ValueType::Metadata => {
+                let meta = val.as_metadata();
+                format!(
+                    "type #{}{}",
+                    meta.key.as_usize(),
+                    "*".repeat(meta.ptr_depth as usize)
+                )
+            }
+            _ => val.to_string(),
+        }
+    }
+
+    fn position(&self, func: &FunctionObj, pc: usize) -> Option<String> {
+        let p = func.pos.get(pc).copied().flatten()?;
+        let pos = self.file_set.as_ref()?.position(p as usize)?;
+        Some(format!("{}:{}:{}", pos.filename, pos.line, pos.column))
+    }
+
+    fn function_name(&self, key: FunctionKey) -> &str {
+        self.objects
+            .functions
+            .vec()
+            .get(key.as_usize())
+            .map_or("?", |f| f.name.as_str())
+    }
+
+    fn package_name(&self, key: PackageKey) -> &str {
+        match key == PackageKey::null() {
+            true => "-",
+            false => self
+                .objects
+                .packages
+                .vec()
+                .get(key.as_usize())
+                .map_or("?", |p| p.name()),
+        }
+    }
+}
+
+fn write_comment(out: &mut String, comment: &str) -> fmt::Result {
+    match comment.is_empty() {
+        true => writeln!(out),
+        false => writeln!(out, "  ; {}", comment),
+    }
+}
+
+fn jump_target(next: usize, offset: OpIndex) -> usize {
+    (next as isize + offset as isize) as usize
+}
+
+/// Numbers the jump targets of the function in the order of the code
+fn labels(func: &FunctionObj) -> Map<usize, usize> {
+    let mut targets = vec![];
+    let mut pc = 0;
+    while pc < func.code.len() {
+        let inst = &func.code[pc];
+        let next = pc + 1 + inst.ext_count();
+        let exts = &func.code[(pc + 1).min(func.code.len())..next.min(func.code.len())];
+        let fields = [inst.d, inst.s0, inst.s1]
+            .into_iter()
+            .zip(field_kinds(inst))
+            .chain(
+                exts.iter()
+                    .flat_map(|e| [e.d, e.s0, e.s1].into_iter().zip(ext_field_kinds(inst))),
+            );
+        for (op, kind) in fields {
+            if kind == Field::Jump && op != OpIndex::MAX {
+                targets.push(jump_target(next, op));
+            }
+        }
+        pc = next;
+    }
+    targets.sort_unstable();
+    targets.dedup();
+    targets
+        .into_iter()
+        .enumerate()
+        .map(|(i, t)| (t, i))
+        .collect()
+}
+
+/// The kinds of `d`, `s0` and `s1` of the instruction
+fn field_kinds(inst: &Instruction) -> [Field; 3] {
+    use Field::*;
+    match inst.op0 {
+        Opcode::LOAD_STRUCT
+        | Opcode::REF_STRUCT_FIELD
+        | Opcode::LOAD_PKG
+        | Opcode::REF_PKG_MEMBER
+        | Opcode::BIND_I_METHOD
+        | Opcode::LOAD_EMBEDDED
+        | Opcode::REF_EMBEDDED
+        | Opcode::LITERAL => [Reg, Reg, Num],
+        Opcode::STORE_STRUCT | Opcode::STORE_PKG | Opcode::STORE_EMBEDDED => [Reg, Num, Reg],
+        Opcode::LOAD_UP_VALUE | Opcode::REF_UPVALUE => [Reg, Num, Num],
+        Opcode::STORE_UP_VALUE => [Num, Reg, Num],
+        Opcode::JUMP => [Jump, Num, Num],
+        Opcode::JUMP_IF | Opcode::JUMP_IF_NOT | Opcode::IMPORT => [Jump, Reg, Num],
+        Opcode::SWITCH => [Jump, Reg, Reg],
+        Opcode::SELECT => [Jump, Num, Num],
+        Opcode::RANGE => [Reg, Jump, Reg],
+        Opcode::CAST => [Reg, Reg, Num],
+        Opcode::PACK_VARIADIC => [Reg, Reg, Reg],
+        _ => [Reg, Reg, Reg],
+    }
+}
+
+/// The kinds of `d`, `s0` and `s1` of the extensions of the instruction
+fn ext_field_kinds(inst: &Instruction) -> [Field; 3] {
+    use Field::*;
+    match inst.op0 {
+        Opcode::SELECT => [Jump, Reg, Reg],
+        _ => [Reg, Reg, Reg],
+    }
+}
@@ -2,9 +2,10 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
-use crate::gc::GcContainer;
+use crate::gc::{GcContainer, HeapSize};
 use crate::value::*;
 use std::hash::{Hash, Hasher};
+use std::rc::Weak;
 
 /// Dispatcher is used to diapatch Array/Slice calls using the vtable.
 pub(crate) trait Dispatcher {
@@ -72,6 +73,12 @@ pub(crate) trait Dispatcher {
     ) -> Option<(usize, GosValue)>;
 
     fn slice_swap(&self, slice: &GosValue, i: usize, j: usize) -> RuntimeResult<()>;
+
+    /// The size of an element in bytes
+    fn elem_size(&self) -> usize;
+
+    /// Returns the array underlying an array, a slice or a string, for the memory limit
+    fn array_heap_obj(&self, val: &GosValue) -> Option<Weak<dyn HeapSize>>;
 }
 
 /// https://users.rust-lang.org/t/workaround-for-hash-trait-not-being-object-safe/53332/5
@@ -292,6 +299,22 @@ macro_rules! define_dispatcher {
             fn slice_swap(&self, slice: &GosValue, i: usize, j: usize) -> RuntimeResult<()> {
                 slice.as_non_nil_slice::<$elem>()?.0.swap(i, j)
             }
+
+            #[inline]
+            fn elem_size(&self) -> usize {
+                std::mem::size_of::<$elem>()
+            }
+
+            fn array_heap_obj(&self, val: &GosValue) -> Option<std::rc::Weak<dyn HeapSize>> {
+                let arr = match val.typ() {
+                    ValueType::Array => val.clone(),
+                    ValueType::Slice => val.as_slice::<$elem>()?.0.array().clone(),
+                    ValueType::String => val.as_string().array().clone(),
+                    _ => return None,
+                };
+                let obj: std::rc::Rc<dyn HeapSize> = arr.into_array::<$elem>();
+                Some(std::rc::Rc::downgrade(&obj))
+            }
         }
     };
 }
@@ -11,7 +11,11 @@ use crate::value::{GosValue, RuntimeResult};
 #[cfg(feature = "async")]
 use futures_lite::future::Future;
 use go_parser::Map;
-use std::cell::Ref;
+use std::any::Any;
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::io;
 #[cfg(feature = "async")]
 use std::pin::Pin;
 use std::rc::Rc;
@@ -19,13 +23,47 @@ use std::rc::Rc;
 pub struct FfiCtx<'a> {
     pub func_name: &'a str,
     pub vm_objs: &'a VMObjects,
-    pub user_data: Option<usize>,
+    pub(crate) user_data: Option<&'a Rc<dyn Any>>,
+    /// The redirections of the standard streams of the engine
+    pub std_io: &'a RefCell<StdIoApi>,
     pub stack: &'a mut Stack,
     pub gcc: &'a GcContainer,
     pub(crate) array_slice_caller: &'a ArrCaller,
 }
 
 impl<'a> FfiCtx<'a> {
+    /// Returns the user data of the engine if it's a `T`
+    #[inline]
+    pub fn user_data<T: Any>(&self) -> Option<&T> {
+        self.user_data.and_then(|d| d.downcast_ref::<T>())
+    }
+
+    /// Returns a reference counted pointer to the user data of the engine if it's a `T`
+    #[inline]
+    pub fn user_data_rc<T: Any>(&self) -> Option<Rc<T>> {
+        self.user_data.and_then(|d| d.clone().downcast::<T>().ok())
+    }
+
+    /// Converts Rust data to a `GosValue` of the Go type `meta` with serde
+    #[cfg(feature = "serde")]
+    pub fn to_gos<T: serde::Serialize + ?Sized>(
+        &self,
+        value: &T,
+        meta: &Meta,
+    ) -> RuntimeResult<GosValue> {
+        crate::value_serde::to_gos_value(value, meta, self.vm_objs, self.gcc)
+    }
+
+    /// Converts a `GosValue` of the Go type `meta` to Rust data with serde
+    #[cfg(feature = "serde")]
+    pub fn from_gos<T: serde::de::DeserializeOwned>(
+        &self,
+        value: &GosValue,
+        meta: &Meta,
+    ) -> RuntimeResult<T> {
+        crate::value_serde::from_gos_value(value, meta, self.vm_objs, self.stack)
+    }
+
     #[inline]
     pub fn new_nil(t: ValueType) -> GosValue {
         GosValue::new_nil(t)
@@ -197,25 +235,53 @@ impl std::fmt::Debug for dyn Ffi {
     }
 }
 
+/// The signature of an FFI function in Go, `params` and `results` are Go types
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FfiSignature {
+    /// The method name in the Go interface of the FFI
+    pub name: &'static str,
+    pub params: Vec<String>,
+    pub results: Vec<String>,
+}
+
+/// The standard streams the Go code reads from and writes to,
+/// `None` means the ones of the process.
+#[derive(Default)]
+pub struct StdIoApi {
+    pub std_in: Option<Box<dyn io::Read + Sync + Send>>,
+    pub std_out: Option<Box<dyn io::Write + Sync + Send>>,
+    pub std_err: Option<Box<dyn io::Write + Sync + Send>>,
+}
+
 pub struct FfiFactory {
     registry: Map<&'static str, Rc<dyn Ffi>>,
-    /// Down-casting only works for 'static types,
-    /// so we just use the good old pointers
-    user_data: Option<usize>,
+    /// The FFIs made of closures, they are in `registry` too
+    fn_ffis: Map<&'static str, Rc<FnFfi>>,
+    /// The Go signatures of the FFI functions, by FFI names
+    signatures: Map<&'static str, Vec<FfiSignature>>,
+    /// The host state for the FFIs, see `FfiCtx::user_data`
+    user_data: Option<Rc<dyn Any>>,
+    std_io: RefCell<StdIoApi>,
 }
 
 impl FfiFactory {
     pub fn new() -> FfiFactory {
         FfiFactory {
             registry: Map::new(),
+            fn_ffis: Map::new(),
+            signatures: Map::new(),
             user_data: None,
+            std_io: RefCell::new(StdIoApi::default()),
         }
     }
 
-    pub fn with_user_data(ptr: usize) -> FfiFactory {
+    pub fn with_user_data(data: Rc<dyn Any>) -> FfiFactory {
         FfiFactory {
             registry: Map::new(),
-            user_data: Some(ptr),
+            fn_ffis: Map::new(),
+            signatures: Map::new(),
+            user_data: Some(data),
+            std_io: RefCell::new(StdIoApi::default()),
         }
     }
 
@@ -223,8 +289,91 @@ impl FfiFactory {
         assert!(self.registry.insert(name, proto).is_none());
     }
 
-    pub(crate) fn user_data(&self) -> Option<usize> {
-        self.user_data
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.registry.contains_key(name)
+    }
+
+    /// Registers an FFI along with the Go signatures of its functions, so that
+    /// a Go package wrapping them can be generated, see `go_signatures`.
+    pub fn register_with_signatures(
+        &mut self,
+        name: &'static str,
+        proto: Rc<dyn Ffi>,
+        signatures: Vec<FfiSignature>,
+    ) {
+        self.register(name, proto);
+        self.signatures.insert(name, signatures);
+    }
+
+    /// Registers a closure as a function of an FFI, `name` is like "mymod.add",
+    /// where "mymod" is the FFI's name and "add" is the method of the Go interface.
+    ///
+    /// The arguments and the return values are converted like in `#[ffi_impl]`.
+    /// Go calls the methods named "async..." with `register_async_fn`'s closures.
+    pub fn register_fn<Args, F: IntoFfiFn<Args>>(&mut self, name: &'static str, f: F) {
+        let (ffi, func) = self.fn_ffi(name);
+        assert!(
+            !func.starts_with("async"),
+            "use register_async_fn for {}",
+            name
+        );
+        assert!(ffi.fns.borrow_mut().insert(func, f.into_ffi_fn()).is_none());
+        self.add_signature(name, F::signature(func));
+    }
+
+    /// Registers a closure that returns a future as a function of an FFI,
+    /// the name of the function has to start with "async", like "mymod.async_fetch".
+    #[cfg(feature = "async")]
+    pub fn register_async_fn<Args, F: IntoAsyncFfiFn<Args>>(&mut self, name: &'static str, f: F) {
+        let (ffi, func) = self.fn_ffi(name);
+        assert!(func.starts_with("async"), "use register_fn for {}", name);
+        assert!(ffi
+            .async_fns
+            .borrow_mut()
+            .insert(func, f.into_async_ffi_fn())
+            .is_none());
+        self.add_signature(name, F::signature(func));
+    }
+
+    fn fn_ffi(&mut self, name: &'static str) -> (Rc<FnFfi>, &'static str) {
+        let (ffi_name, func) = name
+            .rsplit_once('.')
+            .unwrap_or_else(|| panic!("expect a name like 'mymod.func', got {}", name));
+        let registry = &mut self.registry;
+        let ffi = self.fn_ffis.entry(ffi_name).or_insert_with(|| {
+            let ffi = Rc::new(FnFfi::default());
+            assert!(registry.insert(ffi_name, ffi.clone()).is_none());
+            ffi
+        });
+        (ffi.clone(), func)
+    }
+
+    fn add_signature(&mut self, name: &'static str, sig: FfiSignature) {
+        let ffi_name = &name[..name.len() - sig.name.len() - 1];
+        self.signatures.entry(ffi_name).or_default().push(sig);
+    }
+
+    /// The Go signatures of the functions of the FFIs registered with
+    /// `register_with_signatures` or `register_fn`, by the names of the FFIs.
+    pub fn go_signatures(&self) -> &Map<&'static str, Vec<FfiSignature>> {
+        &self.signatures
+    }
+
+    /// Redirects the standard streams of the VMs using this factory
+    pub fn set_std_io(&self, api: StdIoApi) {
+        *self.std_io.borrow_mut() = api;
+    }
+
+    pub fn set_user_data(&mut self, data: Option<Rc<dyn Any>>) {
+        self.user_data = data;
+    }
+
+    pub(crate) fn user_data(&self) -> Option<&Rc<dyn Any>> {
+        self.user_data.as_ref()
+    }
+
+    pub fn std_io(&self) -> &RefCell<StdIoApi> {
+        &self.std_io
     }
 
     pub(crate) fn create(&self, name: &str) -> RuntimeResult<Rc<dyn Ffi>> {
@@ -241,12 +390,497 @@ impl std::fmt::Debug for FfiFactory {
     }
 }
 
+type FfiFn = Box<dyn Fn(&mut FfiCtx, Vec<GosValue>) -> RuntimeResult<Vec<GosValue>>>;
+
+#[cfg(feature = "async")]
+type AsyncFfiFn = Box<
+    dyn Fn(
+        &mut FfiCtx,
+        Vec<GosValue>,
+    ) -> Pin<Box<dyn Future<Output = RuntimeResult<Vec<GosValue>>>>>,
+>;
+
+/// An FFI whose functions are closures, see `FfiFactory::register_fn`
+#[derive(Default)]
+struct FnFfi {
+    fns: RefCell<Map<&'static str, FfiFn>>,
+    #[cfg(feature = "async")]
+    async_fns: RefCell<Map<&'static str, AsyncFfiFn>>,
+}
+
+impl Ffi for FnFfi {
+    fn call(&self, ctx: &mut FfiCtx, params: Vec<GosValue>) -> RuntimeResult<Vec<GosValue>> {
+        match self.fns.borrow().get(ctx.func_name) {
+            Some(f) => f(ctx, params),
+            None => Err(format!("ffi function '{}' not found!", ctx.func_name).into()),
+        }
+    }
+
+    #[cfg(feature = "async")]
+    fn async_call(
+        &self,
+        ctx: &mut FfiCtx,
+        params: Vec<GosValue>,
+    ) -> Pin<Box<dyn Future<Output = RuntimeResult<Vec<GosValue>>> + '_>> {
+        match self.async_fns.borrow().get(ctx.func_name) {
+            Some(f) => f(ctx, params),
+            None => {
+                let err = Err(format!("ffi function '{}' not found!", ctx.func_name).into());
+                Box::pin(async move { err })
+            }
+        }
+    }
+}
+
+/// A Rust type the FFI functions exchange with Go, `go_type` is its Go counterpart
+/// used in the generated Go bindings.
+pub trait GoType {
+    fn go_type() -> String;
+
+    /// The Go type of a parameter, it's `go_type` unless a nil argument has to be possible
+    fn go_param_type() -> String {
+        Self::go_type()
+    }
+}
+
+macro_rules! impl_go_type {
+    ($($t:ty => $go:literal),+ $(,)?) => {
+        $(impl GoType for $t {
+            fn go_type() -> String {
+                $go.to_owned()
+            }
+        })+
+    };
+}
+
+impl_go_type!(
+    bool => "bool",
+    isize => "int",
+    i8 => "int8",
+    i16 => "int16",
+    i32 => "int32",
+    i64 => "int64",
+    usize => "uint",
+    u8 => "uint8",
+    u16 => "uint16",
+    u32 => "uint32",
+    u64 => "uint64",
+    f32 => "float32",
+    f64 => "float64",
+    String => "string",
+    GosValue => "interface{}",
+);
+
+impl<T: GoType> GoType for Vec<T> {
+    fn go_type() -> String {
+        format!("[]{}", T::go_type())
+    }
+}
+
+/// As a result, `None` is only possible for the nilable types, as a parameter,
+/// the types that can't be nil are passed as `interface{}`, nil being `None`.
+impl<T: GoType> GoType for Option<T> {
+    fn go_type() -> String {
+        T::go_type()
+    }
+
+    fn go_param_type() -> String {
+        let t = T::go_type();
+        match is_nilable(&t) {
+            true => t,
+            false => "interface{}".to_owned(),
+        }
+    }
+}
+
+/// Whether nil is a value of the Go type
+fn is_nilable(go_type: &str) -> bool {
+    ["[]", "map[", "*", "func", "chan"]
+        .iter()
+        .any(|x| go_type.starts_with(x))
+        || go_type == "interface{}"
+}
+
+impl<K: GoType, V: GoType, S> GoType for HashMap<K, V, S> {
+    fn go_type() -> String {
+        format!("map[{}]{}", K::go_type(), V::go_type())
+    }
+}
+
+/// Converts a Go value passed to an FFI function to a Rust value.
+/// `#[ffi_impl]` and `register_fn` convert the arguments with it.
+pub trait FromGos: Sized {
+    fn from_gos(val: GosValue) -> RuntimeResult<Self>;
+}
+
+/// Converts a Rust value returned by an FFI function to a Go value.
+/// `#[ffi_impl]` and `register_fn` convert the return values with it.
+pub trait IntoGos {
+    /// The `ValueType` of the converted values, `Void` if it's only known at runtime
+    fn value_type() -> ValueType;
+
+    fn into_gos(self, gcc: &GcContainer) -> RuntimeResult<GosValue>;
+
+    /// The value `None` converts to, if the Go type is nilable
+    fn nil() -> Option<GosValue> {
+        None
+    }
+}
+
+/// The values are read without checking their types, a Go declaration of the FFI
+/// that doesn't match the Rust side must fail here.
+fn expect_type(val: GosValue, typ: ValueType) -> RuntimeResult<GosValue> {
+    let val = unwrap_interface(val);
+    match val.typ() {
+        t if t == typ => Ok(val),
+        t => Err(format!("FFI: expect {}, got {}", typ, t).into()),
+    }
+}
+
+/// The underlying value of a non-nil interface, like the argument of an `Option`
+/// parameter, see `GoType::go_param_type`.
+fn unwrap_interface(val: GosValue) -> GosValue {
+    let underlying = match val.typ() {
+        ValueType::Interface => val
+            .as_interface()
+            .and_then(|x| x.underlying_value().cloned()),
+        _ => None,
+    };
+    underlying.unwrap_or(val)
+}
+
+macro_rules! impl_primitive_conv {
+    ($($t:ty => $vt:ident),+ $(,)?) => {
+        $(
+            impl FromGos for $t {
+                #[inline]
+                fn from_gos(val: GosValue) -> RuntimeResult<Self> {
+                    Ok(expect_type(val, ValueType::$vt)?.as_())
+                }
+            }
+
+            impl IntoGos for $t {
+                #[inline]
+                fn value_type() -> ValueType {
+                    ValueType::$vt
+                }
+
+                #[inline]
+                fn into_gos(self, _gcc: &GcContainer) -> RuntimeResult<GosValue> {
+                    Ok(self.into())
+                }
+            }
+        )+
+    };
+}
+
+impl_primitive_conv!(
+    bool => Bool,
+    isize => Int,
+    i8 => Int8,
+    i16 => Int16,
+    i32 => Int32,
+    i64 => Int64,
+    usize => Uint,
+    u8 => Uint8,
+    u16 => Uint16,
+    u32 => Uint32,
+    u64 => Uint64,
+    f32 => Float32,
+    f64 => Float64,
+    String => String,
+);
+
+impl FromGos for GosValue {
+    #[inline]
+    fn from_gos(val: GosValue) -> RuntimeResult<Self> {
+        Ok(val)
+    }
+}
+
+impl IntoGos for GosValue {
+    #[inline]
+    fn value_type() -> ValueType {
+        ValueType::Void
+    }
+
+    #[inline]
+    fn into_gos(self, _gcc: &GcContainer) -> RuntimeResult<GosValue> {
+        Ok(self)
+    }
+}
+
+/// nil converts to `None`
+impl<T: FromGos> FromGos for Option<T> {
+    fn from_gos(val: GosValue) -> RuntimeResult<Self> {
+        match val.is_nil() {
+            true => Ok(None),
+            false => T::from_gos(val).map(Some),
+        }
+    }
+}
+
+/// `None` converts to nil, which fails if the Go type is not nilable
+impl<T: IntoGos> IntoGos for Option<T> {
+    fn value_type() -> ValueType {
+        T::value_type()
+    }
+
+    fn into_gos(self, gcc: &GcContainer) -> RuntimeResult<GosValue> {
+        match self {
+            Some(v) => v.into_gos(gcc),
+            None => T::nil().ok_or_else(|| "FFI: None for a non-nilable type".to_owned().into()),
+        }
+    }
+
+    fn nil() -> Option<GosValue> {
+        T::nil()
+    }
+}
+
+/// Converts a slice or an array, nil converts to an empty `Vec`
+impl<T: FromGos> FromGos for Vec<T> {
+    fn from_gos(val: GosValue) -> RuntimeResult<Self> {
+        let val = unwrap_interface(val);
+        let elems = match val.typ() {
+            ValueType::Slice => val.caller_slow().slice_get_vec(&val).unwrap_or_default(),
+            ValueType::Array => val.caller_slow().array_get_vec(&val),
+            t => return Err(format!("FFI: expect a slice or an array, got {}", t).into()),
+        };
+        elems.into_iter().map(T::from_gos).collect()
+    }
+}
+
+/// Converts to a slice
+impl<T: IntoGos> IntoGos for Vec<T> {
+    fn value_type() -> ValueType {
+        ValueType::Slice
+    }
+
+    fn into_gos(self, gcc: &GcContainer) -> RuntimeResult<GosValue> {
+        let elems = self
+            .into_iter()
+            .map(|x| x.into_gos(gcc))
+            .collect::<RuntimeResult<Vec<GosValue>>>()?;
+        // with no element to tell the type, the elements of an empty slice are interfaces
+        let t_elem = match (T::value_type(), elems.first()) {
+            (ValueType::Void, Some(first)) => first.typ(),
+            (ValueType::Void, None) => ValueType::Interface,
+            (t, _) => t,
+        };
+        let caller = ArrCaller::get_slow(t_elem);
+        Ok(GosValue::slice_with_data(elems, &caller, gcc))
+    }
+
+    fn nil() -> Option<GosValue> {
+        match T::value_type() {
+            ValueType::Void => None,
+            t => Some(GosValue::new_nil_slice(t)),
+        }
+    }
+}
+
+/// nil converts to an empty map
+impl<K, V, S> FromGos for HashMap<K, V, S>
+where
+    K: FromGos + Eq + Hash,
+    V: FromGos,
+    S: BuildHasher + Default,
+{
+    fn from_gos(val: GosValue) -> RuntimeResult<Self> {
+        let val = expect_type(val, ValueType::Map)?;
+        let entries: Vec<(GosValue, GosValue)> = match val.as_map() {
+            Some((m, _)) => m
+                .borrow_data()
+                .iter()
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+            None => vec![],
+        };
+        entries
+            .into_iter()
+            .map(|(k, v)| Ok((K::from_gos(k)?, V::from_gos(v)?)))
+            .collect()
+    }
+}
+
+impl<K: IntoGos, V: IntoGos, S> IntoGos for HashMap<K, V, S> {
+    fn value_type() -> ValueType {
+        ValueType::Map
+    }
+
+    fn into_gos(self, gcc: &GcContainer) -> RuntimeResult<GosValue> {
+        let mut map = Map::new();
+        for (k, v) in self.into_iter() {
+            map.insert(k.into_gos(gcc)?, v.into_gos(gcc)?);
+        }
+        Ok(GosValue::map_with_data(map, gcc))
+    }
+
+    fn nil() -> Option<GosValue> {
+        Some(GosValue::new_nil(ValueType::Map))
+    }
+}
+
+/// The return value of a closure registered as an FFI function, it can be
+/// `()`, an `IntoGos`, a tuple of those, or a `RuntimeResult` of any of them,
+/// an error makes the Go code panic.
+pub trait FfiReturn {
+    fn into_returns(self, gcc: &GcContainer) -> RuntimeResult<Vec<GosValue>>;
+
+    /// The Go types of the return values
+    fn go_types() -> Vec<String>;
+}
+
+impl FfiReturn for () {
+    #[inline]
+    fn into_returns(self, _gcc: &GcContainer) -> RuntimeResult<Vec<GosValue>> {
+        Ok(vec![])
+    }
+
+    fn go_types() -> Vec<String> {
+        vec![]
+    }
+}
+
+impl<T: IntoGos + GoType> FfiReturn for T {
+    #[inline]
+    fn into_returns(self, gcc: &GcContainer) -> RuntimeResult<Vec<GosValue>> {
+        Ok(vec![self.into_gos(gcc)?])
+    }
+
+    fn go_types() -> Vec<String> {
+        vec![T::go_type()]
+    }
+}
+
+impl<R: FfiReturn> FfiReturn for RuntimeResult<R> {
+    #[inline]
+    fn into_returns(self, gcc: &GcContainer) -> RuntimeResult<Vec<GosValue>> {
+        self.and_then(|r| r.into_returns(gcc))
+    }
+
+    fn go_types() -> Vec<String> {
+        R::go_types()
+    }
+}
+
+macro_rules! impl_ffi_return_tuple {
+    ($($t:ident $v:ident),+) => {
+        impl<$($t: IntoGos + GoType),+> FfiReturn for ($($t,)+) {
+            #[inline]
+            fn into_returns(self, gcc: &GcContainer) -> RuntimeResult<Vec<GosValue>> {
+                let ($($v,)+) = self;
+                Ok(vec![$($v.into_gos(gcc)?),+])
+            }
+
+            fn go_types() -> Vec<String> {
+                vec![$($t::go_type()),+]
+            }
+        }
+    };
+}
+
+impl_ffi_return_tuple!(A a, B b);
+impl_ffi_return_tuple!(A a, B b, C c);
+impl_ffi_return_tuple!(A a, B b, C c, D d);
+
+/// A closure that can be registered as an FFI function,
+/// `Args` is the tuple of its argument types.
+pub trait IntoFfiFn<Args> {
+    fn into_ffi_fn(self) -> FfiFn;
+
+    fn signature(name: &'static str) -> FfiSignature;
+}
+
+/// A closure returning a future that can be registered as an async FFI function.
+#[cfg(feature = "async")]
+pub trait IntoAsyncFfiFn<Args> {
+    fn into_async_ffi_fn(self) -> AsyncFfiFn;
+
+    fn signature(name: &'static str) -> FfiSignature;
+}
+
+macro_rules! impl_into_ffi_fn {
+    ($($t:ident $v:ident),*) => {
+        impl<F, R, $($t),*> IntoFfiFn<($($t,)*)> for F
+        where
+            F: Fn($($t),*) -> R + 'static,
+            R: FfiReturn,
+            $($t: FromGos + GoType,)*
+        {
+            fn signature(name: &'static str) -> FfiSignature {
+                FfiSignature {
+                    name,
+                    params: vec![$($t::go_param_type()),*],
+                    results: R::go_types(),
+                }
+            }
+
+            fn into_ffi_fn(self) -> FfiFn {
+                Box::new(move |ctx, args| {
+                    if args.len() != <[&str]>::len(&[$(stringify!($t)),*]) {
+                        return Err("FFI: bad argument count".to_owned().into());
+                    }
+                    #[allow(unused_mut, unused_variables)]
+                    let mut iter = args.into_iter();
+                    $(let $v = $t::from_gos(iter.next().unwrap())?;)*
+                    self($($v),*).into_returns(ctx.gcc)
+                })
+            }
+        }
+
+        #[cfg(feature = "async")]
+        impl<F, Fut, R, $($t),*> IntoAsyncFfiFn<($($t,)*)> for F
+        where
+            F: Fn($($t),*) -> Fut + 'static,
+            Fut: Future<Output = R> + 'static,
+            R: FfiReturn,
+            $($t: FromGos + GoType,)*
+        {
+            fn signature(name: &'static str) -> FfiSignature {
+                FfiSignature {
+                    name,
+                    params: vec![$($t::go_param_type()),*],
+                    results: R::go_types(),
+                }
+            }
+
+            fn into_async_ffi_fn(self) -> AsyncFfiFn {
+                Box::new(move |ctx, args| {
+                    if args.len() != <[&str]>::len(&[$(stringify!($t)),*]) {
+                        return Box::pin(async { Err("FFI: bad argument count".to_owned().into()) });
+                    }
+                    #[allow(unused_mut, unused_variables)]
+                    let mut iter = args.into_iter();
+                    $(let $v = match $t::from_gos(iter.next().unwrap()) {
+                        Ok(v) => v,
+                        Err(e) => return Box::pin(async move { Err(e) }),
+                    };)*
+                    let fut = self($($v),*);
+                    let gcc = ctx.gcc.clone();
+                    Box::pin(async move { fut.await.into_returns(&gcc) })
+                })
+            }
+        }
+    };
+}
+
+impl_into_ffi_fn!();
+impl_into_ffi_fn!(A a);
+impl_into_ffi_fn!(A a, B b);
+impl_into_ffi_fn!(A a, B b, C c);
+impl_into_ffi_fn!(A a, B b, C c, D d);
+impl_into_ffi_fn!(A a, B b, C c, D d, E e);
+impl_into_ffi_fn!(A a, B b, C c, D d, E e, G g);
+
 /// Used by CodeGen, so that CodeGen can share the API provided by FFI
 pub struct CodeGenVMCtx {
     vm_objs: VMObjects,
     dummy_func_name: &'static str,
     dummy_stack: Stack,
     dummy_gcc: GcContainer,
+    dummy_std_io: RefCell<StdIoApi>,
     caller: ArrCaller,
 }
 
@@ -257,6 +891,7 @@ impl CodeGenVMCtx {
             dummy_func_name: "dummy_name",
             dummy_stack: Stack::new(),
             dummy_gcc: GcContainer::new(),
+            dummy_std_io: RefCell::new(StdIoApi::default()),
             caller: ArrCaller::new(),
         }
     }
@@ -266,6 +901,7 @@ impl CodeGenVMCtx {
             func_name: self.dummy_func_name,
             vm_objs: &self.vm_objs,
             user_data: None,
+            std_io: &self.dummy_std_io,
             stack: &mut self.dummy_stack,
             gcc: &&self.dummy_gcc,
             array_slice_caller: &self.caller,
@@ -2,22 +2,84 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use super::dispatcher::Dispatcher;
 use super::instruction::ValueType;
 use super::objects::*;
-use super::value::{GosValue, RCQueue, RCount, IRC};
-use std::cell::Ref;
+use super::value::{GosValue, RCQueue, RCount, RuntimeResult, IRC};
 use std::cell::RefCell;
+use std::cell::{Cell, Ref};
 use std::convert::TryFrom;
 use std::rc::{Rc, Weak};
 
+/// The estimated size of a map entry, including the hash table overhead
+pub(crate) const MAP_ENTRY_SIZE: usize = 3 * std::mem::size_of::<GosValue>();
+
+/// The number of tracked objects below which the dead ones are not pruned
+const MIN_PRUNE_LEN: usize = 1024;
+
+/// Cloning a GcContainer is cheap, the clones share the same underlying container.
+#[derive(Clone)]
 pub struct GcContainer {
     inner: Rc<RefCell<Vec<GcWeak>>>,
+    memory: Rc<MemoryUsage>,
+    /// The objects added, by kind, for `Vm::stats`
+    #[cfg(feature = "stats")]
+    allocations: Rc<[Cell<u64>; 4]>,
 }
 
 impl GcContainer {
     pub fn new() -> GcContainer {
         GcContainer {
             inner: Rc::new(RefCell::new(Vec::new())),
+            memory: Rc::new(MemoryUsage::default()),
+            #[cfg(feature = "stats")]
+            allocations: Rc::new(Default::default()),
+        }
+    }
+
+    /// Sets the approximate max number of bytes that the arrays, slices, maps and
+    /// strings allocated by the Go code may take, `None` means no limit.
+    /// Going over it makes the Go code panic with "out of memory".
+    pub fn set_memory_limit(&self, limit: Option<usize>) {
+        self.memory.limit.set(limit);
+    }
+
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory.limit.get()
+    }
+
+    /// Returns the approximate number of bytes taken by the objects that are alive.
+    /// The objects are only tracked when there is a memory limit.
+    pub fn memory_used(&self) -> usize {
+        self.memory.recount()
+    }
+
+    /// Makes room for an allocation of `bytes`, fails if it goes over the memory limit.
+    #[inline]
+    pub(crate) fn reserve(&self, bytes: usize) -> RuntimeResult<()> {
+        match self.memory.limit.get() {
+            Some(limit) => self.memory.reserve(bytes, limit),
+            None => Ok(()),
+        }
+    }
+
+    /// Lets the memory limit keep track of the array underlying an array, a slice or a string.
+    #[inline]
+    pub(crate) fn track_array(&self, val: &GosValue, disp: &Box<dyn Dispatcher>) {
+        if self.memory.limit.get().is_some() {
+            if let Some(obj) = disp.array_heap_obj(val) {
+                self.memory.track(obj);
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn track_map(&self, val: &GosValue) {
+        if self.memory.limit.get().is_some() {
+            if let Some(m) = val.clone().into_map() {
+                let obj: Rc<dyn HeapSize> = m;
+                self.memory.track(Rc::downgrade(&obj));
+            }
         }
     }
 
@@ -37,8 +99,27 @@ impl GcContainer {
         self.add_weak(GcWeak::new_struct(s))
     }
 
+    /// The number of objects added so far, by kind
+    #[cfg(feature = "stats")]
+    pub(crate) fn allocations(&self) -> Vec<(&'static str, u64)> {
+        ["array", "closure", "map", "struct"]
+            .into_iter()
+            .zip(self.allocations.iter().map(Cell::get))
+            .collect()
+    }
+
     #[inline]
     pub(crate) fn add_weak(&self, w: GcWeak) {
+        #[cfg(feature = "stats")]
+        {
+            let count = &self.allocations[match &w {
+                GcWeak::Array(_) => 0,
+                GcWeak::Closure(_) => 1,
+                GcWeak::Map(_) => 2,
+                GcWeak::Struct(_) => 3,
+            }];
+            count.set(count.get() + 1);
+        }
         self.inner.borrow_mut().push(w);
     }
 
@@ -47,6 +128,81 @@ impl GcContainer {
     }
 }
 
+/// Something on the heap that counts towards the memory limit
+pub(crate) trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+impl<T: Element> HeapSize for (ArrayObj<T>, RCount) {
+    fn heap_size(&self) -> usize {
+        self.0.capacity() * std::mem::size_of::<T>()
+    }
+}
+
+impl HeapSize for (MapObj, RCount) {
+    fn heap_size(&self) -> usize {
+        self.0.len() * MAP_ENTRY_SIZE
+    }
+}
+
+/// An approximate accounting of the memory allocated by the Go code.
+///
+/// The allocations are added up as they happen, without knowing when the objects
+/// are freed. Only when the sum goes over the limit, it's recounted from the tracked
+/// objects that are still alive.
+/// The dead objects are pruned whenever the number of tracked ones doubles, so
+/// that the tracking itself doesn't grow with the short lived objects.
+#[derive(Default)]
+struct MemoryUsage {
+    limit: Cell<Option<usize>>,
+    used: Cell<usize>,
+    objs: RefCell<Vec<Weak<dyn HeapSize>>>,
+    /// The number of tracked objects at which they are pruned next
+    prune_at: Cell<usize>,
+}
+
+impl MemoryUsage {
+    fn track(&self, obj: Weak<dyn HeapSize>) {
+        let mut objs = self.objs.borrow_mut();
+        objs.push(obj);
+        if objs.len() >= self.prune_at.get().max(MIN_PRUNE_LEN) {
+            self.prune(&mut objs);
+        }
+    }
+
+    fn prune(&self, objs: &mut Vec<Weak<dyn HeapSize>>) {
+        objs.retain(|w| w.strong_count() > 0);
+        // an object grown in place is tracked again
+        objs.sort_by_key(|w| w.as_ptr() as *const () as usize);
+        objs.dedup_by_key(|w| w.as_ptr() as *const () as usize);
+        self.prune_at.set(objs.len() * 2);
+    }
+
+    fn reserve(&self, bytes: usize, limit: usize) -> RuntimeResult<()> {
+        let mut used = self.used.get().saturating_add(bytes);
+        if used > limit {
+            used = self.recount().saturating_add(bytes);
+            if used > limit {
+                return Err("out of memory".to_owned().into());
+            }
+        }
+        self.used.set(used);
+        Ok(())
+    }
+
+    fn recount(&self) -> usize {
+        let mut objs = self.objs.borrow_mut();
+        self.prune(&mut objs);
+        let used = objs
+            .iter()
+            .filter_map(|w| w.upgrade())
+            .map(|o| o.heap_size())
+            .sum();
+        self.used.set(used);
+        used
+    }
+}
+
 #[derive(Clone)]
 pub(crate) enum GcWeak {
     Array(Weak<(GosArrayObj, RCount)>),
@@ -175,23 +331,12 @@ fn break_cycle(val: &GosValue) {
 
 /// put the non-zero-rc on the left, and the others on the right
 fn partition_to_scan(to_scan: &mut Vec<GosValue>) -> usize {
-    let len = to_scan.len();
-    if len == 0 {
-        return 0;
-    }
     let mut p0 = 0;
-    let mut p1 = len - 1;
-    loop {
-        while p0 < len - 1 && to_scan[p0].rc() > 0 {
+    for i in 0..to_scan.len() {
+        if to_scan[i].rc() > 0 {
+            to_scan.swap(p0, i);
             p0 += 1;
         }
-        while p1 > 1 && to_scan[p1].rc() <= 0 {
-            p1 -= 1;
-        }
-        if p0 >= p1 {
-            break;
-        }
-        to_scan.swap(p0, p1);
     }
     p0
 }
@@ -240,3 +385,84 @@ pub(crate) fn collect(objs: &GcContainer) {
         .collect();
     //print!("objs left after GC: {}\n", result.len());
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dispatcher::ArrCaller;
+
+    struct Blob(usize);
+
+    impl HeapSize for Blob {
+        fn heap_size(&self) -> usize {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_memory_tracking_pruned() {
+        let memory = MemoryUsage::default();
+        memory.limit.set(Some(usize::MAX));
+        let kept: Vec<Rc<dyn HeapSize>> = (0..100).map(|_| Rc::new(Blob(8)) as _).collect();
+        kept.iter().for_each(|o| memory.track(Rc::downgrade(o)));
+        // the temporaries are dropped right away, they must not pile up
+        for _ in 0..100_000 {
+            let tmp: Rc<dyn HeapSize> = Rc::new(Blob(8));
+            memory.track(Rc::downgrade(&tmp));
+        }
+        assert!(memory.objs.borrow().len() <= MIN_PRUNE_LEN);
+        assert_eq!(memory.recount(), 800);
+    }
+
+    fn new_struct(fields: Vec<GosValue>, gcc: &GcContainer) -> GosValue {
+        GosValue::new_struct(StructObj::new(fields), gcc)
+    }
+
+    fn alive(gcc: &GcContainer) -> usize {
+        gcc.borrow_data().iter().filter_map(|o| o.to_gosv()).count()
+    }
+
+    #[test]
+    fn test_partition_to_scan() {
+        let gcc = GcContainer::new();
+        let objs: Vec<GosValue> = (0..5).map(|_| new_struct(vec![], &gcc)).collect();
+        let cases = [
+            [1, 1, 1, 1, 1],
+            [0, 0, 0, 0, 0],
+            [1, 0, 1, 0, 1],
+            [0, 1, 0, 1, 1],
+            [1, 1, 0, -1, 0],
+        ];
+        for rcs in cases {
+            for len in 0..=objs.len() {
+                objs.iter().zip(rcs).for_each(|(o, rc)| o.set_rc(rc));
+                let mut to_scan = objs[..len].to_vec();
+                let live = to_scan.iter().filter(|o| o.rc() > 0).count();
+                let boundary = partition_to_scan(&mut to_scan);
+                assert_eq!(boundary, live, "{:?} {}", rcs, len);
+                assert!(to_scan[..boundary].iter().all(|o| o.rc() > 0));
+                assert!(to_scan[boundary..].iter().all(|o| o.rc() <= 0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_collect_untracked_array() {
+        // the array of a []int is not tracked, so the cycles sharing it must
+        // leave its ref count alone
+        let gcc = GcContainer::new();
+        let ints = (0..3isize).map(|x| x.into()).collect();
+        let shared = GosValue::slice_with_data(ints, &ArrCaller::get_slow(ValueType::Int), &gcc);
+        let keep = new_struct(vec![shared.clone(), 0isize.into()], &gcc);
+        for _ in 0..10 {
+            let a = new_struct(vec![shared.clone(), 0isize.into()], &gcc);
+            let b = new_struct(vec![shared.clone(), a.clone()], &gcc);
+            a.as_struct().0.borrow_fields_mut()[1] = b;
+        }
+        assert_eq!(alive(&gcc), 21);
+        collect(&gcc);
+        assert_eq!(alive(&gcc), 1);
+        let fields = keep.as_struct().0.borrow_fields();
+        assert_eq!(fields[0].len(), 3);
+    }
+}
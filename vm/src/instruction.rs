@@ -130,6 +130,15 @@ impl fmt::Display for Opcode {
     }
 }
 
+impl Opcode {
+    /// The opcode `Display` prints as `name`
+    pub(crate) fn from_name(name: &str) -> Option<Opcode> {
+        (0..=Opcode::FFI as u8)
+            .map(|i| unsafe { std::mem::transmute::<u8, Opcode>(i) })
+            .find(|op| op.to_string() == name)
+    }
+}
+
 #[cfg(feature = "serde_borsh")]
 impl BorshSerialize for Opcode {
     #[inline]
@@ -144,6 +153,12 @@ impl BorshDeserialize for Opcode {
     #[inline]
     fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> BorshResult<Self> {
         let val = u8::deserialize_reader(reader)?;
+        if val > Opcode::FFI as u8 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid opcode {}", val),
+            ));
+        }
         Ok(unsafe { std::mem::transmute(val) })
     }
 }
@@ -220,6 +235,12 @@ impl BorshDeserialize for ValueType {
     #[inline]
     fn deserialize_reader<R: std::io::Read>(reader: &mut R) -> BorshResult<Self> {
         let val = u8::deserialize_reader(reader)?;
+        if val > ValueType::FlagE as u8 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("invalid value type {}", val),
+            ));
+        }
         Ok(unsafe { std::mem::transmute(val) })
     }
 }
@@ -230,6 +251,15 @@ impl fmt::Display for ValueType {
     }
 }
 
+impl ValueType {
+    /// The value type `Display` prints as `name`
+    pub(crate) fn from_name(name: &str) -> Option<ValueType> {
+        (0..=ValueType::FlagE as u8)
+            .map(|i| unsafe { std::mem::transmute::<u8, ValueType>(i) })
+            .find(|t| t.to_string() == name)
+    }
+}
+
 #[derive(Clone, Copy)]
 pub struct Instruction {
     pub op0: Opcode,
@@ -246,6 +276,17 @@ impl Instruction {
         unsafe { std::mem::transmute(self.op1) }
     }
 
+    /// The number of instructions following this one that are part of it
+    pub(crate) fn ext_count(&self) -> usize {
+        match self.op0 {
+            Opcode::LOAD_MAP | Opcode::STORE_MAP | Opcode::SLICE | Opcode::LITERAL => 1,
+            Opcode::TYPE_ASSERT if self.t1 == ValueType::FlagB => 1,
+            Opcode::MAKE if self.t0 == ValueType::FlagC => 1,
+            Opcode::SELECT => self.s0.max(0) as usize,
+            _ => 0,
+        }
+    }
+
     // Get the max register index 'instructions' write to
     pub fn max_write_index(instructions: &[Instruction]) -> OpIndex {
         let mut i = 0;
@@ -344,7 +385,7 @@ impl Instruction {
                     })
                 }
                 Opcode::RANGE_INIT => 0,
-                Opcode::RANGE => 0,
+                Opcode::RANGE => std::cmp::max(cur.d, cur.s1),
                 Opcode::LOAD_INIT_FUNC => {
                     i += 2;
                     std::cmp::max(cur.d, cur.s1)
@@ -370,7 +411,7 @@ impl Instruction {
                 }
                 Opcode::CLOSURE => cur.d,
                 Opcode::LITERAL => {
-                    i += 1 + cur.s1 as usize;
+                    i += 1;
                     cur.d
                 }
                 Opcode::NEW => cur.d,
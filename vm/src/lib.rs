@@ -9,19 +9,35 @@
 //! - `btree_map`: Make it use BTreeMap instead of HashMap
 //! - `instruction_pos`: Add instruction position to bytecode for debugging
 //! - `serde_borsh`: Serde support for bytecode using Borsh
+//! - `stats`: Count the executed instructions, clones and allocations, see `Vm::stats`
+//! - `trace`: Execution tracing with `Vm::set_tracer`
 
+mod asm;
 mod instruction;
 #[macro_use]
 mod metadata;
 #[cfg(feature = "async")]
 mod channel;
 mod objects;
+mod profile;
+mod relocate;
 #[macro_use]
 mod dispatcher;
 mod bytecode;
+#[cfg(feature = "serde_borsh")]
+mod container;
+mod debug;
+mod disasm;
 mod ffi;
 mod stack;
+#[cfg(feature = "stats")]
+mod stats;
+#[cfg(feature = "trace")]
+mod trace;
 mod value;
+#[cfg(feature = "serde")]
+mod value_serde;
+mod verifier;
 mod vm;
 
 pub mod gc;
@@ -33,29 +49,108 @@ pub mod parser {
     pub use go_parser::*;
 }
 
+#[cfg(feature = "serde_borsh")]
+pub use container::{BytecodeError, FeatureFlags, FORMAT_VERSION};
+#[cfg(feature = "stats")]
+pub use stats::Stats;
+#[cfg(feature = "trace")]
+pub use trace::{TraceEvent, Tracer};
 pub use {
+    asm::AsmError,
+    debug::{DebugAction, DebugView, Debugger, PauseHandle, StopReason},
     ffi::*,
     go_parser::{Map, MapIter},
     go_pmacro::{ffi_impl, Ffi, UnsafePtr},
+    profile::{Profile, ProfileSample, Sampling},
+    relocate::Relocation,
     value::Bytecode,
+    verifier::VerifyError,
     vm::run,
+    vm::CallError,
+    vm::CancellationHandle,
     vm::PanicData,
+    vm::Vm,
 };
 
+use value::{FunctionKey, OpIndex};
+
+/// A frame of a call stack, with the position resolved from the debug info
+#[derive(Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StackFrame {
+    /// The qualified name of the function, like `main.Foo`
+    pub func: String,
+    /// The file name, empty if the bytecode has no debug info for the frame
+    pub file: String,
+    /// 1-based, 0 if unknown
+    pub line: usize,
+    /// 1-based, 0 if unknown
+    pub column: usize,
+}
+
+impl StackFrame {
+    /// Resolves a call stack like `PanicData::call_stack`, innermost frame first
+    pub fn resolve(call_stack: &[(FunctionKey, OpIndex)], bc: &Bytecode) -> Vec<StackFrame> {
+        call_stack
+            .iter()
+            .map(|(fkey, pc)| {
+                let func = &bc.objects.functions[*fkey];
+                let pos = func
+                    .pos
+                    .get(*pc as usize)
+                    .copied()
+                    .flatten()
+                    .zip(bc.file_set.as_ref())
+                    .and_then(|(p, fs)| fs.position(p as usize));
+                let (file, line, column) = match pos {
+                    Some(p) => (String::clone(&p.filename), p.line, p.column),
+                    None => (String::new(), 0, 0),
+                };
+                StackFrame {
+                    func: func.name.clone(),
+                    file,
+                    line,
+                    column,
+                }
+            })
+            .collect()
+    }
+}
+
+impl std::fmt::Display for StackFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.file.is_empty() {
+            write!(f, "{} <no debug info>", self.func)
+        } else {
+            write!(
+                f,
+                "{} {}:{}:{}",
+                self.func, self.file, self.line, self.column
+            )
+        }
+    }
+}
+
 pub struct CallStackDisplay<'a> {
-    panic_data: &'a PanicData,
+    call_stack: &'a [(FunctionKey, OpIndex)],
     bc: &'a Bytecode,
 }
 
 impl<'a> CallStackDisplay<'a> {
     pub fn new(panic_data: &'a PanicData, bc: &'a Bytecode) -> CallStackDisplay<'a> {
-        Self { panic_data, bc }
+        Self::with_call_stack(&panic_data.call_stack, bc)
+    }
+
+    pub fn with_call_stack(
+        call_stack: &'a [(FunctionKey, OpIndex)],
+        bc: &'a Bytecode,
+    ) -> CallStackDisplay<'a> {
+        Self { call_stack, bc }
     }
 }
 
 impl<'a> std::fmt::Display for CallStackDisplay<'a> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for (fkey, pc) in self.panic_data.call_stack.iter() {
+        for (fkey, pc) in self.call_stack.iter() {
             let func = &self.bc.objects.functions[*fkey];
             if let Some(p) = func.pos[*pc as usize] {
                 if let Some(fs) = &self.bc.file_set {
@@ -6,6 +6,7 @@ use crate::bytecode::{FunctionKey, MetadataKey, MetadataObjs, VMObjects};
 use crate::gc::GcContainer;
 use crate::instruction::{OpIndex, ValueType};
 use crate::objects::{IfaceBinding, StructObj};
+use crate::relocate::Relocation;
 use crate::value::ArrCaller;
 use crate::value::GosValue;
 #[cfg(feature = "serde_borsh")]
@@ -25,7 +26,7 @@ pub enum ChannelType {
     SendRecv,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct PrimitiveMeta {
     pub mbool: Meta,
     pub mint: Meta,
@@ -677,6 +678,44 @@ pub enum MetadataType {
 }
 
 impl MetadataType {
+    /// Moves the keys in the type, see `Relocation`
+    pub(crate) fn relocate(&mut self, r: &Relocation) -> Option<()> {
+        match self {
+            Self::Array(m, _) | Self::Slice(m) | Self::Channel(_, m) => r.meta(m),
+            Self::Map(k, v) => {
+                r.meta(k)?;
+                r.meta(v)
+            }
+            Self::Struct(f) | Self::Interface(f) => {
+                f.fields.iter_mut().try_for_each(|x| r.meta(&mut x.meta))
+            }
+            Self::Signature(sig) => {
+                sig.recv.iter_mut().try_for_each(|m| r.meta(m))?;
+                sig.params.iter_mut().try_for_each(|m| r.meta(m))?;
+                sig.results.iter_mut().try_for_each(|m| r.meta(m))?;
+                match &mut sig.variadic {
+                    Some((a, b)) => {
+                        r.meta(a)?;
+                        r.meta(b)
+                    }
+                    None => Some(()),
+                }
+            }
+            Self::Named(methods, m) => {
+                // the methods are not shared with the type being moved
+                for x in methods.members.iter_mut() {
+                    let mut desc = *x.borrow();
+                    if let Some(f) = &mut desc.func {
+                        r.func(f)?;
+                    }
+                    *x = Rc::new(RefCell::new(desc));
+                }
+                r.meta(m)
+            }
+            _ => Some(()),
+        }
+    }
+
     #[inline]
     pub fn as_signature(&self) -> &SigMetadata {
         match self {
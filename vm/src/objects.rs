@@ -10,6 +10,7 @@ use crate::ffi::Ffi;
 use crate::gc::GcContainer;
 use crate::instruction::{Instruction, OpIndex, ValueType};
 use crate::metadata::*;
+use crate::relocate::Relocation;
 use crate::stack::Stack;
 use crate::value::*;
 
@@ -377,6 +378,11 @@ where
         self.borrow_data().len()
     }
 
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.borrow_data().capacity()
+    }
+
     #[inline(always)]
     pub fn borrow_data_mut(&self) -> std::cell::RefMut<Vec<T>> {
         self.vec.borrow_mut()
@@ -1921,6 +1927,11 @@ impl PackageObj {
         self.var_mapping.borrow().is_none()
     }
 
+    #[inline]
+    pub fn member_count(&self) -> usize {
+        self.members.len()
+    }
+
     #[inline]
     pub fn member(&self, i: OpIndex) -> Ref<GosValue> {
         self.members[i as usize].borrow()
@@ -1936,6 +1947,25 @@ impl PackageObj {
         self.init_funcs.get(i as usize)
     }
 
+    pub(crate) fn init_funcs(&self) -> &[GosValue] {
+        &self.init_funcs
+    }
+
+    /// The member indices of the values the constructor returns, `None` once the
+    /// package is initialized
+    pub(crate) fn var_mapping(&self) -> Ref<Option<Map<OpIndex, OpIndex>>> {
+        self.var_mapping.borrow()
+    }
+
+    /// Moves the keys in the members, see `Relocation`
+    pub(crate) fn relocate(&mut self, r: &Relocation) -> Option<()> {
+        for m in self.members.iter_mut() {
+            let val = r.value(&m.borrow())?;
+            *m = RefCell::new(val);
+        }
+        r.values(&mut self.init_funcs)
+    }
+
     #[inline]
     pub fn init_vars(&self, vals: Vec<GosValue>) {
         let mut borrow = self.var_mapping.borrow_mut();
@@ -1997,11 +2027,31 @@ pub enum FuncFlag {
     HasDefer,
 }
 
+/// The name of a local variable, which is visible within `scope`, a range of
+/// source positions. Debuggers use it to find the locals of a call frame.
+#[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VarName {
+    pub name: String,
+    /// The index of the variable relative to the stack base of the frame
+    pub index: OpIndex,
+    pub scope: (u32, u32),
+}
+
+impl VarName {
+    #[inline]
+    pub fn in_scope(&self, pos: u32) -> bool {
+        self.scope.0 <= pos && pos <= self.scope.1
+    }
+}
+
 /// FunctionObj is the direct container of the Opcode.
 #[cfg_attr(feature = "serde_borsh", derive(BorshDeserialize, BorshSerialize))]
 #[derive(Clone, Debug)]
 pub struct FunctionObj {
     pub package: PackageKey,
+    /// The qualified name like Go's, e.g. `main.Foo`, `main.(*T).Bar` or `main.Foo.func1`
+    pub name: String,
     pub meta: Meta,
     pub flag: FuncFlag,
     pub param_count: OpIndex,
@@ -2016,6 +2066,21 @@ pub struct FunctionObj {
     pub pos: Vec<Option<u32>>,
     pub up_ptrs: Vec<ValueDesc>,
     pub local_zeros: Vec<GosValue>,
+    /// The types of the struct and interface operands of the instructions accessing
+    /// fields and interface methods by index, by pc, for `Bytecode::verify`
+    pub operand_metas: Map<OpIndex, Meta>,
+    /// The named parameters, results and locals, sorted by index
+    #[cfg_attr(
+        all(feature = "serde_borsh", not(feature = "instruction_pos")),
+        borsh_skip
+    )]
+    pub var_names: Vec<VarName>,
+    /// The names of the up values, in the same order as `up_ptrs`
+    #[cfg_attr(
+        all(feature = "serde_borsh", not(feature = "instruction_pos")),
+        borsh_skip
+    )]
+    pub up_names: Vec<String>,
 }
 
 impl FunctionObj {
@@ -2032,6 +2097,7 @@ impl FunctionObj {
         param_count += s.params.len() as OpIndex;
         FunctionObj {
             package,
+            name: String::new(),
             meta,
             flag,
             param_count,
@@ -2041,6 +2107,9 @@ impl FunctionObj {
             pos: Vec::new(),
             up_ptrs: Vec::new(),
             local_zeros: Vec::new(),
+            operand_metas: Map::new(),
+            var_names: Vec::new(),
+            up_names: Vec::new(),
         }
     }
 
@@ -2059,6 +2128,16 @@ impl FunctionObj {
         self.ret_zeros.len() as OpIndex
     }
 
+    /// The number of registers of a call frame, which holds the return values,
+    /// the parameters, the locals and the temporaries.
+    #[inline]
+    pub fn frame_size(&self) -> OpIndex {
+        std::cmp::max(
+            self.max_write_index + 1,
+            self.ret_count() + self.param_count() + self.local_count(),
+        )
+    }
+
     #[inline]
     pub fn is_ctor(&self) -> bool {
         self.flag == FuncFlag::PkgCtor
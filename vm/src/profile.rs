@@ -0,0 +1,435 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! A sampling profiler.
+//!
+//! With `Vm::set_profiler` the call stack of the running fiber is sampled while
+//! the VM runs, `Vm::profile` returns the samples aggregated by function and
+//! source line. The cost is counted in instructions, the time spent in FFI
+//! calls is not seen.
+//!
+//! A `Profile` can be written in the `pprof` protobuf format, for `go tool pprof`
+//! and the likes, or as folded stacks, for flamegraph tools.
+//!
+//! The `Profiler` lives in the VM context and is shared by all the fibers,
+//! without one the main loop pays for a single `None` check per instruction.
+//! Counting instructions rather than wall time keeps the profiles deterministic
+//! and the sampling free of timers and signals.
+//!
+//! A raw sample is a list of `(function, pc)`, the outer frames use the pc of
+//! their `CALL`, i.e. `pc - 1`. Samples are aggregated by raw stack while
+//! running, and resolved to function names, files and lines only once by
+//! `Vm::profile`, which merges the stacks that land on the same lines.
+//!
+//! `Profile::write_pprof` writes an uncompressed protobuf with the "samples" and
+//! "instructions" sample types. Its small encoder covers only the fields pprof
+//! uses, so no protobuf dependency is needed.
+
+use crate::value::*;
+use crate::vm::CallFrame;
+use crate::StackFrame;
+use go_parser::Map;
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+
+/// When the call stack is sampled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sampling {
+    /// Whenever a fiber yields, i.e. at most every 1024 instructions, or ends,
+    /// a sample stands for the instructions since the fiber was resumed
+    Yield,
+    /// Every N instructions of all the fibers
+    Every(u64),
+}
+
+type RawStack = Vec<(FunctionKey, OpIndex)>;
+
+/// The sampling state of a VM, shared by all its fibers
+pub(crate) struct Profiler {
+    sampling: Sampling,
+    /// The instructions left until the next sample with `Sampling::Every`
+    left: Cell<u64>,
+    /// The number of samples and instructions of each call stack
+    samples: RefCell<Map<RawStack, (u64, u64)>>,
+}
+
+impl Profiler {
+    pub(crate) fn new(sampling: Sampling) -> Profiler {
+        let sampling = match sampling {
+            Sampling::Every(n) => Sampling::Every(n.max(1)),
+            s => s,
+        };
+        Profiler {
+            sampling,
+            left: Cell::new(match sampling {
+                Sampling::Every(n) => n,
+                Sampling::Yield => 0,
+            }),
+            samples: RefCell::new(Map::new()),
+        }
+    }
+
+    /// Called before an instruction is executed, returns whether to take a
+    /// sample with `sample`
+    #[inline]
+    pub(crate) fn tick(&self) -> bool {
+        match self.sampling {
+            Sampling::Every(n) => {
+                let left = self.left.get() - 1;
+                self.left.set(if left == 0 { n } else { left });
+                left == 0
+            }
+            Sampling::Yield => false,
+        }
+    }
+
+    pub(crate) fn sample(&self, frames: &[CallFrame]) {
+        if let Sampling::Every(n) = self.sampling {
+            self.record(frames, n);
+        }
+    }
+
+    /// Called when a fiber yields after executing `executed` instructions
+    pub(crate) fn yielded(&self, frames: &[CallFrame], executed: usize) {
+        if self.sampling == Sampling::Yield && executed > 0 {
+            self.record(frames, executed as u64);
+        }
+    }
+
+    fn record(&self, frames: &[CallFrame], instructions: u64) {
+        if frames.is_empty() {
+            return;
+        }
+        // the outer frames are at the calls
+        let stack: RawStack = frames
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, f)| match i {
+                0 => (f.func(), f.pc),
+                _ => (f.func(), f.pc - 1),
+            })
+            .collect();
+        let mut samples = self.samples.borrow_mut();
+        let entry = samples.entry(stack).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += instructions;
+    }
+
+    pub(crate) fn profile(&self, bc: &Bytecode) -> Profile {
+        let mut merged: Map<Vec<StackFrame>, (u64, u64)> = Map::new();
+        for (stack, (count, instructions)) in self.samples.borrow().iter() {
+            let frames = StackFrame::resolve(stack, bc)
+                .into_iter()
+                .map(|f| StackFrame { column: 0, ..f })
+                .collect();
+            let entry = merged.entry(frames).or_insert((0, 0));
+            entry.0 += count;
+            entry.1 += instructions;
+        }
+        let mut samples: Vec<ProfileSample> = merged
+            .into_iter()
+            .map(|(stack, (count, instructions))| ProfileSample {
+                stack,
+                count,
+                instructions,
+            })
+            .collect();
+        // the hottest first
+        samples.sort_by(|a, b| {
+            b.instructions
+                .cmp(&a.instructions)
+                .then_with(|| a.stack.len().cmp(&b.stack.len()))
+                .then_with(|| frame_names(&a.stack).cmp(&frame_names(&b.stack)))
+        });
+        Profile {
+            sampling: self.sampling,
+            samples,
+        }
+    }
+}
+
+/// A call stack that is sampled, the frames are aggregated by line, so the
+/// columns are 0
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProfileSample {
+    /// Innermost frame first
+    pub stack: Vec<StackFrame>,
+    /// The number of times it's sampled
+    pub count: u64,
+    /// The number of instructions the samples stand for
+    pub instructions: u64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Profile {
+    pub sampling: Sampling,
+    /// The hottest stack first
+    pub samples: Vec<ProfileSample>,
+}
+
+impl Profile {
+    /// The instructions spent in each function and line, including the ones
+    /// of the functions it calls, the hottest first.
+    pub fn lines(&self) -> Vec<(String, String, usize, u64)> {
+        let mut lines: Map<(&str, &str, usize), u64> = Map::new();
+        for s in self.samples.iter() {
+            let mut seen: Vec<(&str, &str, usize)> = vec![];
+            for f in s.stack.iter() {
+                let key = (f.func.as_str(), f.file.as_str(), f.line);
+                // recursive calls are counted once
+                if !seen.contains(&key) {
+                    seen.push(key);
+                    *lines.entry(key).or_insert(0) += s.instructions;
+                }
+            }
+        }
+        let mut lines: Vec<(String, String, usize, u64)> = lines
+            .into_iter()
+            .map(|((func, file, line), n)| (func.to_owned(), file.to_owned(), line, n))
+            .collect();
+        lines.sort_by(|a, b| b.3.cmp(&a.3).then_with(|| a.cmp(b)));
+        lines
+    }
+
+    /// Writes the stacks in the folded format of flamegraph tools, one line for
+    /// each stack like `main.main:12;main.add:5 300`, with the outermost frame
+    /// first and weighted by the instructions.
+    pub fn write_folded(&self, w: &mut dyn Write) -> io::Result<()> {
+        for s in self.samples.iter() {
+            writeln!(w, "{} {}", frame_names(&s.stack), s.instructions)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the profile in the `pprof` protobuf format, uncompressed, which
+    /// the `pprof` tools accept as well.
+    pub fn write_pprof(&self, w: &mut dyn Write) -> io::Result<()> {
+        let mut strings = StringTable::default();
+        let mut functions: Map<(&str, &str), u64> = Map::new();
+        let mut locations: Map<(&str, &str, usize), (u64, u64)> = Map::new();
+        let mut func_list: Vec<(u64, &str, &str)> = vec![];
+        let mut loc_list: Vec<(u64, u64, usize)> = vec![];
+        let mut out = Proto::default();
+
+        let samples = strings.index("samples");
+        let instructions = strings.index("instructions");
+        let count = strings.index("count");
+        for typ in [samples, instructions] {
+            out.message(1, |m| {
+                m.uint(1, typ);
+                m.uint(2, count);
+            });
+        }
+        for s in self.samples.iter() {
+            let ids: Vec<u64> = s
+                .stack
+                .iter()
+                .map(|f| {
+                    let next = functions.len() as u64 + 1;
+                    let func_id = *functions
+                        .entry((f.func.as_str(), f.file.as_str()))
+                        .or_insert_with(|| {
+                            func_list.push((next, &f.func, &f.file));
+                            next
+                        });
+                    let next = locations.len() as u64 + 1;
+                    locations
+                        .entry((f.func.as_str(), f.file.as_str(), f.line))
+                        .or_insert_with(|| {
+                            loc_list.push((next, func_id, f.line));
+                            (next, func_id)
+                        })
+                        .0
+                })
+                .collect();
+            out.message(2, |m| {
+                m.packed(1, &ids);
+                m.packed(2, &[s.count, s.instructions]);
+            });
+        }
+        for (id, func_id, line) in loc_list {
+            out.message(4, |m| {
+                m.uint(1, id);
+                m.message(4, |l| {
+                    l.uint(1, func_id);
+                    l.uint(2, line as u64);
+                });
+            });
+        }
+        for (id, name, file) in func_list {
+            let (name, file) = (strings.index(name), strings.index(file));
+            out.message(5, |m| {
+                m.uint(1, id);
+                m.uint(2, name);
+                m.uint(3, name);
+                m.uint(4, file);
+            });
+        }
+        if let Sampling::Every(n) = self.sampling {
+            out.message(11, |m| {
+                m.uint(1, instructions);
+                m.uint(2, count);
+            });
+            out.uint(12, n);
+        }
+        out.uint(14, instructions);
+        // the string table has to come after all the strings are indexed
+        for s in strings.list.iter() {
+            out.bytes(6, s.as_bytes());
+        }
+        w.write_all(&out.0)
+    }
+}
+
+/// Like `main.main:12;main.add:5`, the outermost frame first
+fn frame_names(stack: &[StackFrame]) -> String {
+    stack
+        .iter()
+        .rev()
+        .map(|f| match f.line {
+            0 => f.func.clone(),
+            line => format!("{}:{}", f.func, line),
+        })
+        .collect::<Vec<_>>()
+        .join(";")
+}
+
+/// The strings of a pprof profile are referred to by their index, the first
+/// one has to be empty
+struct StringTable {
+    list: Vec<String>,
+    indices: Map<String, u64>,
+}
+
+impl Default for StringTable {
+    fn default() -> StringTable {
+        let mut table = StringTable {
+            list: vec![],
+            indices: Map::new(),
+        };
+        table.index("");
+        table
+    }
+}
+
+impl StringTable {
+    fn index(&mut self, s: &str) -> u64 {
+        if let Some(i) = self.indices.get(s) {
+            return *i;
+        }
+        let i = self.list.len() as u64;
+        self.list.push(s.to_owned());
+        self.indices.insert(s.to_owned(), i);
+        i
+    }
+}
+
+/// A minimal protobuf encoder, for the fields pprof uses
+#[derive(Default)]
+struct Proto(Vec<u8>);
+
+impl Proto {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.0.push((v as u8) | 0x80);
+            v >>= 7;
+        }
+        self.0.push(v as u8);
+    }
+
+    fn key(&mut self, field: u32, wire_type: u8) {
+        self.varint(((field as u64) << 3) | wire_type as u64);
+    }
+
+    /// Zeros are the default, so they are left out
+    fn uint(&mut self, field: u32, v: u64) {
+        if v != 0 {
+            self.key(field, 0);
+            self.varint(v);
+        }
+    }
+
+    fn bytes(&mut self, field: u32, data: &[u8]) {
+        self.key(field, 2);
+        self.varint(data.len() as u64);
+        self.0.extend_from_slice(data);
+    }
+
+    fn packed(&mut self, field: u32, values: &[u64]) {
+        let mut data = Proto::default();
+        for v in values {
+            data.varint(*v);
+        }
+        self.bytes(field, &data.0);
+    }
+
+    fn message(&mut self, field: u32, f: impl FnOnce(&mut Proto)) {
+        let mut m = Proto::default();
+        f(&mut m);
+        self.bytes(field, &m.0);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_proto() {
+        let mut p = Proto::default();
+        p.uint(1, 150);
+        p.uint(2, 0);
+        p.bytes(3, b"ab");
+        p.packed(4, &[3, 270]);
+        assert_eq!(
+            p.0,
+            vec![0x08, 0x96, 0x01, 0x1a, 2, b'a', b'b', 0x22, 3, 3, 0x8e, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_folded() {
+        let frame = |func: &str, line| StackFrame {
+            func: func.to_owned(),
+            file: "a.gos".to_owned(),
+            line,
+            column: 0,
+        };
+        let profile = Profile {
+            sampling: Sampling::Yield,
+            samples: vec![
+                ProfileSample {
+                    stack: vec![frame("main.add", 5), frame("main.main", 12)],
+                    count: 2,
+                    instructions: 300,
+                },
+                ProfileSample {
+                    stack: vec![frame("main.main", 13)],
+                    count: 1,
+                    instructions: 20,
+                },
+            ],
+        };
+        let mut out = vec![];
+        profile.write_folded(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "main.main:12;main.add:5 300\nmain.main:13 20\n"
+        );
+        let lines: Vec<(String, usize, u64)> = profile
+            .lines()
+            .into_iter()
+            .map(|(func, _, line, n)| (func, line, n))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                ("main.add".to_owned(), 5, 300),
+                ("main.main".to_owned(), 12, 300),
+                ("main.main".to_owned(), 13, 20),
+            ]
+        );
+    }
+}
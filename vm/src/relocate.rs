@@ -0,0 +1,186 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Moves the code generated for a package from a `Bytecode` to another.
+//!
+//! The code refers to the types, functions and packages by their keys, and to
+//! the constants, interface bindings and embedded field paths of the bytecode
+//! by their indices. A `Relocation` maps them from where they are in the old
+//! bytecode to where they are in the new one, the instructions are rewritten
+//! using the operand table of the verifier.
+
+use crate::gc::GcContainer;
+use crate::instruction::{Instruction, OpIndex, ValueType};
+use crate::value::{
+    Binding4Runtime, ClosureObj, FunctionKey, FunctionObj, GosValue, Meta, MetadataKey,
+    MetadataType, PackageKey, PackageObj, UpValue, UpValueState,
+};
+use crate::verifier::{operands, Operand, Slot};
+use go_parser::RangeMap;
+
+/// Relocation maps the keys, the indices into the tables of `Bytecode` and the
+/// source positions in the code of a package, when it's moved between bytecodes.
+#[derive(Clone, Debug, Default)]
+pub struct Relocation {
+    pub metas: RangeMap,
+    pub functions: RangeMap,
+    pub packages: RangeMap,
+    pub consts: RangeMap,
+    pub ifaces: RangeMap,
+    pub indices: RangeMap,
+    /// the positions out of the ranges become unknown
+    pub pos: RangeMap,
+}
+
+impl Relocation {
+    #[inline]
+    pub fn meta(&self, m: &mut Meta) -> Option<()> {
+        m.key = self.metas.key::<MetadataKey>(m.key)?;
+        Some(())
+    }
+
+    #[inline]
+    pub fn func(&self, f: &mut FunctionKey) -> Option<()> {
+        *f = self.functions.key(*f)?;
+        Some(())
+    }
+
+    #[inline]
+    pub fn package(&self, p: &mut PackageKey) -> Option<()> {
+        *p = self.packages.key(*p)?;
+        Some(())
+    }
+
+    /// The relocated value, the values made of other values are not relocated,
+    /// they don't refer to any key when the code is generated
+    pub fn value(&self, v: &GosValue) -> Option<GosValue> {
+        match v.typ() {
+            ValueType::Function => {
+                let mut f = *v.as_function();
+                self.func(&mut f)?;
+                Some(GosValue::new_function(f))
+            }
+            ValueType::Package => {
+                let mut p = *v.as_package();
+                self.package(&mut p)?;
+                Some(GosValue::new_package(p))
+            }
+            ValueType::Metadata => {
+                let mut m = *v.as_metadata();
+                self.meta(&mut m)?;
+                Some(GosValue::new_metadata(m))
+            }
+            ValueType::Closure => match v.as_closure() {
+                Some((ClosureObj::Gos(cls), _)) => {
+                    let mut cls = cls.clone();
+                    self.func(&mut cls.func)?;
+                    self.meta(&mut cls.meta)?;
+                    if let Some(uvs) = &mut cls.uvs {
+                        for uv in uvs.values_mut() {
+                            let mut desc = match &*uv.inner.borrow() {
+                                UpValueState::Open(desc) => desc.clone(),
+                                UpValueState::Closed(_) => return None,
+                            };
+                            self.func(&mut desc.func)?;
+                            *uv = UpValue::new(desc);
+                        }
+                    }
+                    Some(GosValue::new_closure(
+                        ClosureObj::Gos(cls),
+                        &GcContainer::new(),
+                    ))
+                }
+                Some((ClosureObj::Ffi(_), _)) => None,
+                None => Some(v.clone()),
+            },
+            _ => Some(v.clone()),
+        }
+    }
+
+    pub fn values(&self, vals: &mut [GosValue]) -> Option<()> {
+        for v in vals.iter_mut() {
+            *v = self.value(v)?;
+        }
+        Some(())
+    }
+
+    pub fn metadata(&self, t: &mut MetadataType) -> Option<()> {
+        t.relocate(self)
+    }
+
+    pub fn package_obj(&self, p: &mut PackageObj) -> Option<()> {
+        p.relocate(self)
+    }
+
+    pub fn binding(&self, b: &mut Binding4Runtime) -> Option<()> {
+        match b {
+            Binding4Runtime::Struct(f, _, _) => self.func(f),
+            Binding4Runtime::Iface(_, _) => Some(()),
+        }
+    }
+
+    /// Relocates the function, including the constants, the interface bindings
+    /// and the embedded field paths its instructions refer to
+    pub fn function(&self, f: &mut FunctionObj) -> Option<()> {
+        if f.package != PackageKey::null() {
+            self.package(&mut f.package)?;
+        }
+        self.meta(&mut f.meta)?;
+        self.values(&mut f.ret_zeros)?;
+        self.values(&mut f.local_zeros)?;
+        for uv in f.up_ptrs.iter_mut() {
+            self.func(&mut uv.func)?;
+        }
+        for m in f.operand_metas.values_mut() {
+            self.meta(m)?;
+        }
+        let pos = |p: u32| self.pos.get(p as usize).map(|x| x as u32);
+        for p in f.pos.iter_mut() {
+            *p = p.and_then(pos);
+        }
+        for v in f.var_names.iter_mut() {
+            v.scope = (pos(v.scope.0).unwrap_or(0), pos(v.scope.1).unwrap_or(0));
+        }
+        self.code(&mut f.code)
+    }
+
+    fn code(&self, code: &mut [Instruction]) -> Option<()> {
+        let const_index = |i: OpIndex| -(i as i64) - 1;
+        let mut pc = 0;
+        while pc < code.len() {
+            let next = pc + 1 + code[pc].ext_count();
+            if next > code.len() {
+                return None;
+            }
+            let mut bad = false;
+            let ops = operands(&code[pc], &code[pc + 1..next], &mut |_| bad = true);
+            if bad {
+                return None;
+            }
+            for op in ops {
+                let (arg, val) = match op {
+                    Operand::Read(a) | Operand::Const(a, _) | Operand::PkgMember(a, _)
+                        if a.val < 0 =>
+                    {
+                        let c = self.consts.get(const_index(a.val) as usize)?;
+                        (a, -(c as i64) - 1)
+                    }
+                    Operand::Iface(a) => (a, self.ifaces.get(a.val as usize)? as i64),
+                    Operand::Embedded(a) => (a, self.indices.get(a.val as usize)? as i64),
+                    _ => continue,
+                };
+                let (n, slot) = arg.slot?;
+                let val = OpIndex::try_from(val).ok()?;
+                let inst = &mut code[pc + n];
+                match slot {
+                    Slot::D => inst.d = val,
+                    Slot::S0 => inst.s0 = val,
+                    Slot::S1 => inst.s1 = val,
+                }
+            }
+            pc = next;
+        }
+        Some(())
+    }
+}
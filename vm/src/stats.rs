@@ -0,0 +1,155 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Execution statistics, for finding the hot code and the costly code patterns.
+//!
+//! With the `stats` feature every VM counts the instructions it executes and the
+//! values they have to clone to copy them, and its GC container counts the objects
+//! added to it. `Vm::stats` sums them up by opcode, function and source line.
+//! Nothing is printed, the host decides where the report goes.
+
+use crate::value::*;
+use go_parser::{Map, PiggyVecKey};
+use std::cell::RefCell;
+use std::fmt;
+
+/// The number of rows of each table in the report
+const REPORT_ROWS: usize = 20;
+
+/// The raw counts of a VM, shared by all its fibers
+#[derive(Default)]
+pub(crate) struct Counters {
+    /// The executions of each instruction, by function and pc
+    instructions: RefCell<Vec<Vec<u64>>>,
+    clones: RefCell<Map<ValueType, u64>>,
+}
+
+impl Counters {
+    #[inline]
+    pub(crate) fn count_instruction(&self, func: FunctionKey, pc: OpIndex) {
+        let mut instructions = self.instructions.borrow_mut();
+        let (i, pc) = (func.as_usize(), pc as usize);
+        if i >= instructions.len() {
+            instructions.resize(i + 1, vec![]);
+        }
+        let counts = &mut instructions[i];
+        if pc >= counts.len() {
+            counts.resize(pc + 1, 0);
+        }
+        counts[pc] += 1;
+    }
+
+    /// Counts the copy of `val` if it's a clone rather than a copy of the bits
+    #[inline]
+    pub(crate) fn count_copy(&self, val: &GosValue) {
+        if !val.copyable() {
+            *self.clones.borrow_mut().entry(val.typ()).or_insert(0) += 1;
+        }
+    }
+
+    /// `allocations` are the counts of the GC container
+    pub(crate) fn stats(&self, bc: &Bytecode, allocations: Vec<(&'static str, u64)>) -> Stats {
+        let mut opcodes: Map<u8, (Opcode, u64)> = Map::new();
+        let mut functions: Map<&str, u64> = Map::new();
+        let mut lines: Map<(&str, String, usize), u64> = Map::new();
+        let mut total = 0;
+        for (i, counts) in self.instructions.borrow().iter().enumerate() {
+            let func = &bc.objects.functions[FunctionKey::from(i)];
+            for (pc, n) in counts.iter().enumerate().filter(|(_, n)| **n > 0) {
+                total += n;
+                let op = func.code[pc].op0;
+                opcodes.entry(op as u8).or_insert((op, 0)).1 += n;
+                *functions.entry(&func.name).or_insert(0) += n;
+                let pos = func.pos.get(pc).copied().flatten();
+                let pos = pos
+                    .zip(bc.file_set.as_ref())
+                    .and_then(|(p, fs)| fs.position(p as usize));
+                if let Some(p) = pos {
+                    let key = (func.name.as_str(), String::clone(&p.filename), p.line);
+                    *lines.entry(key).or_insert(0) += n;
+                }
+            }
+        }
+        Stats {
+            instructions: total,
+            opcodes: hottest(opcodes.into_values()),
+            functions: hottest(functions.into_iter().map(|(f, n)| (f.to_owned(), n))),
+            lines: {
+                let mut lines: Vec<(String, String, usize, u64)> = lines
+                    .into_iter()
+                    .map(|((func, file, line), n)| (func.to_owned(), file, line, n))
+                    .collect();
+                lines.sort_by(|a, b| b.3.cmp(&a.3).then_with(|| a.cmp(b)));
+                lines
+            },
+            clones: hottest(self.clones.borrow().iter().map(|(t, n)| (*t, *n))),
+            allocations: hottest(allocations.into_iter().filter(|(_, n)| *n > 0)),
+        }
+    }
+}
+
+/// Sorted by the counts, the biggest first
+fn hottest<T: fmt::Display>(counts: impl Iterator<Item = (T, u64)>) -> Vec<(T, u64)> {
+    let mut counts: Vec<(T, u64)> = counts.collect();
+    counts.sort_by(|a, b| {
+        b.1.cmp(&a.1)
+            .then_with(|| a.0.to_string().cmp(&b.0.to_string()))
+    });
+    counts
+}
+
+/// What a VM has done, all the lists have the biggest counts first
+#[derive(Clone, Debug, Default)]
+pub struct Stats {
+    /// The number of instructions executed
+    pub instructions: u64,
+    pub opcodes: Vec<(Opcode, u64)>,
+    /// The instructions executed by each function, not including the functions
+    /// it calls
+    pub functions: Vec<(String, u64)>,
+    /// The instructions executed on each source line, like
+    /// `(function, file, line, count)`, it's empty without the debug info
+    pub lines: Vec<(String, String, usize, u64)>,
+    /// The values the instructions clone to copy them, instead of copying the
+    /// bits, by type
+    pub clones: Vec<(ValueType, u64)>,
+    /// The objects added to the GC container, by kind
+    pub allocations: Vec<(&'static str, u64)>,
+}
+
+impl fmt::Display for Stats {
+    /// Prints the report, the tables are cut to the 20 biggest counts
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |n: u64| n as f64 * 100.0 / self.instructions.max(1) as f64;
+        writeln!(f, "instructions: {}", self.instructions)?;
+        writeln!(f, "by opcode:")?;
+        for (op, n) in self.opcodes.iter().take(REPORT_ROWS) {
+            writeln!(
+                f,
+                "    {:<24} {:>12} {:>6.2}%",
+                op.to_string(),
+                n,
+                percent(*n)
+            )?;
+        }
+        writeln!(f, "by function:")?;
+        for (func, n) in self.functions.iter().take(REPORT_ROWS) {
+            writeln!(f, "    {:<40} {:>12} {:>6.2}%", func, n, percent(*n))?;
+        }
+        writeln!(f, "by line:")?;
+        for (func, file, line, n) in self.lines.iter().take(REPORT_ROWS) {
+            let at = format!("{}:{} {}", file, line, func);
+            writeln!(f, "    {:<56} {:>12} {:>6.2}%", at, n, percent(*n))?;
+        }
+        writeln!(f, "clones by copy_semantic:")?;
+        for (typ, n) in self.clones.iter() {
+            writeln!(f, "    {:<24} {:>12}", typ.to_string(), n)?;
+        }
+        writeln!(f, "GC allocations:")?;
+        for (kind, n) in self.allocations.iter() {
+            writeln!(f, "    {:<24} {:>12}", kind, n)?;
+        }
+        Ok(())
+    }
+}
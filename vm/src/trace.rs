@@ -0,0 +1,61 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Execution tracing, for tools like coverage, tracing or auditing.
+//!
+//! A `Tracer` attached with `Vm::set_tracer` gets the events of all the fibers.
+//! The positions are the ones of `FunctionObj::pos`, they can be resolved with
+//! the `FileSet` of the bytecode, and are `None` without the debug info.
+
+use crate::value::*;
+
+/// What happens in the VM, a fiber is a goroutine.
+#[derive(Clone, Copy)]
+pub enum TraceEvent<'a> {
+    /// An instruction is about to be executed, `pc` is its index in the code
+    /// of `func`
+    Instruction {
+        fiber: usize,
+        func: FunctionKey,
+        pc: OpIndex,
+        inst: &'a Instruction,
+    },
+    /// A Go function is called, including a deferred one, `pos` is where the
+    /// call is made. The first function of a fiber is given by `Spawn` instead.
+    Call {
+        fiber: usize,
+        func: FunctionKey,
+        pos: Option<u32>,
+    },
+    /// A Go function returns, also when a panic unwinds it, `pos` is where it
+    /// returns
+    Return {
+        fiber: usize,
+        func: FunctionKey,
+        pos: Option<u32>,
+    },
+    /// A fiber is created to run `func`
+    Spawn { fiber: usize, func: FunctionKey },
+    /// A fiber is finished or interrupted
+    Exit { fiber: usize },
+    /// A panic starts, `pos` is where it's raised
+    Panic {
+        fiber: usize,
+        func: FunctionKey,
+        pos: Option<u32>,
+        value: &'a GosValue,
+    },
+    /// A panic is stopped by `recover` in `func`
+    Recover {
+        fiber: usize,
+        func: FunctionKey,
+        pos: Option<u32>,
+        value: &'a GosValue,
+    },
+}
+
+pub trait Tracer {
+    /// Called on the fiber where the event happens, the VM waits for it to return.
+    fn on_event(&mut self, event: TraceEvent);
+}
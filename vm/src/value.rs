@@ -8,7 +8,7 @@ pub use crate::bytecode::*;
 #[cfg(feature = "async")]
 use crate::channel::Channel;
 pub(crate) use crate::dispatcher::*;
-use crate::gc::GcContainer;
+use crate::gc::{GcContainer, HeapSize};
 pub use crate::instruction::*;
 pub use crate::metadata::*;
 pub use crate::objects::*;
@@ -2100,7 +2100,9 @@ impl GosValue {
             ValueType::Interface => {
                 self.as_interface().map(|x| x.ref_sub_one());
             }
-            ValueType::Array => self.as_gos_array().1.set(self.as_gos_array().1.get() - 1),
+            ValueType::Array if self.is_gc_array() => {
+                self.as_gos_array().1.set(self.as_gos_array().1.get() - 1)
+            }
             ValueType::Struct => self.as_struct().1.set(self.as_struct().1.get() - 1),
             ValueType::Closure => {
                 self.as_closure().map(|x| x.1.set(x.1.get() - 1));
@@ -2115,10 +2117,18 @@ impl GosValue {
         };
     }
 
+    /// Arrays of basic types are not tracked by gc, their ref counts are not maintained
+    #[inline]
+    fn is_gc_array(&self) -> bool {
+        matches!(ArrCaller::get_elem_type(self.t_elem), ElemType::ElemTypeGos)
+    }
+
     /// for gc
     pub(crate) fn mark_dirty(&self, queue: &mut RCQueue) {
         match &self.typ {
-            ValueType::Array => rcount_mark_and_queue(&self.as_gos_array().1, queue),
+            ValueType::Array if self.is_gc_array() => {
+                rcount_mark_and_queue(&self.as_gos_array().1, queue)
+            }
             ValueType::Pointer => {
                 self.as_pointer().map(|x| x.mark_dirty(queue));
             }
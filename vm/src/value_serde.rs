@@ -0,0 +1,841 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Converts between Rust data and `GosValue` with serde, guided by the `Meta` of
+//! the Go type on the other side.
+//!
+//! A Rust struct maps to a Go struct field by field. The names are matched
+//! ignoring case and underscores, so `max_size` matches `MaxSize`. Go fields
+//! missing on the Rust side are left zero when converting to Go.
+//!
+//! Sequences map to slices and arrays, maps to maps, `Option` to pointers or
+//! nilable values, and unit enum variants to strings. An empty interface
+//! takes bools, numbers and strings, as `bool`, `int`, `uint`, `float64` and `string`.
+
+use crate::bytecode::VMObjects;
+use crate::dispatcher::ArrCaller;
+use crate::gc::GcContainer;
+use crate::metadata::{Meta, MetadataType};
+use crate::objects::{InterfaceObj, PointerObj, StructObj, UpValue};
+use crate::stack::Stack;
+use crate::value::{GosValue, RuntimeError, RuntimeResult, ValueType};
+use go_parser::Map;
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use std::fmt::Display;
+
+impl ser::Error for RuntimeError {
+    fn custom<T: Display>(msg: T) -> Self {
+        RuntimeError::new(msg.to_string())
+    }
+}
+
+impl de::Error for RuntimeError {
+    fn custom<T: Display>(msg: T) -> Self {
+        RuntimeError::new(msg.to_string())
+    }
+}
+
+/// Converts `value` to a `GosValue` of the Go type `meta`
+pub(crate) fn to_gos_value<T: Serialize + ?Sized>(
+    value: &T,
+    meta: &Meta,
+    objs: &VMObjects,
+    gcc: &GcContainer,
+) -> RuntimeResult<GosValue> {
+    value.serialize(ToGos {
+        meta: *meta,
+        objs,
+        gcc,
+    })
+}
+
+/// Converts `value` of the Go type `meta` to a `T`,
+/// `stack` is needed to read the pointers to the variables on it.
+pub(crate) fn from_gos_value<T: DeserializeOwned>(
+    value: &GosValue,
+    meta: &Meta,
+    objs: &VMObjects,
+    stack: &Stack,
+) -> RuntimeResult<T> {
+    T::deserialize(GosDeserializer {
+        val: value.clone(),
+        meta: *meta,
+        objs,
+        stack,
+    })
+}
+
+/// "MaxSize" and "max_size" are the same field
+fn same_field_name(go: &str, rust: &str) -> bool {
+    let norm = |s: &str| {
+        s.chars()
+            .filter(|c| *c != '_')
+            .flat_map(|c| c.to_lowercase())
+            .collect::<String>()
+    };
+    norm(go) == norm(rust)
+}
+
+fn mismatch<T>(rust: &str, meta: &Meta, objs: &VMObjects) -> RuntimeResult<T> {
+    Err(RuntimeError::new(format!(
+        "cannot convert {} to Go type {:?}",
+        rust,
+        meta.value_type(&objs.metas)
+    )))
+}
+
+struct ToGos<'a> {
+    meta: Meta,
+    objs: &'a VMObjects,
+    gcc: &'a GcContainer,
+}
+
+impl<'a> ToGos<'a> {
+    fn with_meta(&self, meta: Meta) -> ToGos<'a> {
+        ToGos {
+            meta,
+            objs: self.objs,
+            gcc: self.gcc,
+        }
+    }
+
+    fn mtype(&self) -> &'a MetadataType {
+        self.meta.mtype_unwraped(&self.objs.metas)
+    }
+
+    fn is_empty_iface(&self) -> bool {
+        self.meta.ptr_depth == 0
+            && matches!(self.mtype(), MetadataType::Interface(f) if f.infos().is_empty())
+    }
+
+    /// Puts a value converted to `meta` into the empty interface
+    fn boxed<T: Serialize + ?Sized>(&self, v: &T, meta: Meta) -> RuntimeResult<GosValue> {
+        let val = v.serialize(self.with_meta(meta))?;
+        Ok(GosValue::new_interface(InterfaceObj::with_value(
+            val,
+            Some((meta, vec![])),
+        )))
+    }
+
+    fn int(self, i: i128) -> RuntimeResult<GosValue> {
+        let prim = &self.objs.prim_meta;
+        if self.is_empty_iface() {
+            return match i < 0 {
+                true => self.boxed(&(i as i64), prim.mint),
+                false => self.boxed(&(i as u64), prim.muint),
+            };
+        }
+        macro_rules! conv {
+            ($t:ty) => {
+                <$t>::try_from(i)
+                    .map(GosValue::from)
+                    .map_err(|_| RuntimeError::new(format!("{} out of range", i)))
+            };
+        }
+        match self.meta.value_type(&self.objs.metas) {
+            ValueType::Int => conv!(isize),
+            ValueType::Int8 => conv!(i8),
+            ValueType::Int16 => conv!(i16),
+            ValueType::Int32 => conv!(i32),
+            ValueType::Int64 => conv!(i64),
+            ValueType::Uint => conv!(usize),
+            ValueType::UintPtr => conv!(usize).map(|x| GosValue::new_uint_ptr(*x.as_uint())),
+            ValueType::Uint8 => conv!(u8),
+            ValueType::Uint16 => conv!(u16),
+            ValueType::Uint32 => conv!(u32),
+            ValueType::Uint64 => conv!(u64),
+            ValueType::Float32 => Ok((i as f32).into()),
+            ValueType::Float64 => Ok((i as f64).into()),
+            _ => mismatch("integer", &self.meta, self.objs),
+        }
+    }
+
+    fn float(self, f: f64) -> RuntimeResult<GosValue> {
+        if self.is_empty_iface() {
+            return self.boxed(&f, self.objs.prim_meta.mfloat64);
+        }
+        match self.meta.value_type(&self.objs.metas) {
+            ValueType::Float32 => Ok((f as f32).into()),
+            ValueType::Float64 => Ok(f.into()),
+            _ => mismatch("float", &self.meta, self.objs),
+        }
+    }
+
+    fn nil(self) -> RuntimeResult<GosValue> {
+        match self.meta.value_type(&self.objs.metas) {
+            ValueType::Slice => match self.mtype() {
+                MetadataType::Slice(m) => {
+                    Ok(GosValue::new_nil_slice(m.value_type(&self.objs.metas)))
+                }
+                _ => unreachable!(),
+            },
+            t @ (ValueType::Pointer
+            | ValueType::Map
+            | ValueType::Interface
+            | ValueType::Closure
+            | ValueType::Channel) => Ok(GosValue::new_nil(t)),
+            _ => mismatch("none", &self.meta, self.objs),
+        }
+    }
+
+    fn seq(&self, len: Option<usize>) -> RuntimeResult<SeqToGos<'a>> {
+        let (elem, size) = match self.mtype() {
+            MetadataType::Slice(m) if self.meta.ptr_depth == 0 => (*m, None),
+            MetadataType::Array(m, size) if self.meta.ptr_depth == 0 => (*m, Some(*size)),
+            _ => return mismatch("sequence", &self.meta, self.objs),
+        };
+        Ok(SeqToGos {
+            to: self.with_meta(elem),
+            size,
+            data: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn fields(&self) -> RuntimeResult<StructToGos<'a>> {
+        match self.mtype() {
+            MetadataType::Struct(fields) if self.meta.ptr_depth == 0 => Ok(StructToGos {
+                to: self.with_meta(self.meta),
+                metas: fields.infos().iter().map(|x| x.meta).collect(),
+                names: fields.infos().iter().map(|x| x.name.as_str()).collect(),
+                values: fields
+                    .infos()
+                    .iter()
+                    .map(|x| x.meta.zero(&self.objs.metas, self.gcc))
+                    .collect(),
+                next: 0,
+            }),
+            _ => mismatch("struct", &self.meta, self.objs),
+        }
+    }
+}
+
+impl<'a> ser::Serializer for ToGos<'a> {
+    type Ok = GosValue;
+    type Error = RuntimeError;
+    type SerializeSeq = SeqToGos<'a>;
+    type SerializeTuple = SeqToGos<'a>;
+    type SerializeTupleStruct = StructToGos<'a>;
+    type SerializeTupleVariant = ser::Impossible<GosValue, RuntimeError>;
+    type SerializeMap = MapToGos<'a>;
+    type SerializeStruct = StructToGos<'a>;
+    type SerializeStructVariant = ser::Impossible<GosValue, RuntimeError>;
+
+    fn serialize_bool(self, v: bool) -> RuntimeResult<GosValue> {
+        if self.is_empty_iface() {
+            return self.boxed(&v, self.objs.prim_meta.mbool);
+        }
+        match self.meta.value_type(&self.objs.metas) {
+            ValueType::Bool => Ok(v.into()),
+            _ => mismatch("bool", &self.meta, self.objs),
+        }
+    }
+
+    fn serialize_i8(self, v: i8) -> RuntimeResult<GosValue> {
+        self.int(v as i128)
+    }
+
+    fn serialize_i16(self, v: i16) -> RuntimeResult<GosValue> {
+        self.int(v as i128)
+    }
+
+    fn serialize_i32(self, v: i32) -> RuntimeResult<GosValue> {
+        self.int(v as i128)
+    }
+
+    fn serialize_i64(self, v: i64) -> RuntimeResult<GosValue> {
+        self.int(v as i128)
+    }
+
+    fn serialize_u8(self, v: u8) -> RuntimeResult<GosValue> {
+        self.int(v as i128)
+    }
+
+    fn serialize_u16(self, v: u16) -> RuntimeResult<GosValue> {
+        self.int(v as i128)
+    }
+
+    fn serialize_u32(self, v: u32) -> RuntimeResult<GosValue> {
+        self.int(v as i128)
+    }
+
+    fn serialize_u64(self, v: u64) -> RuntimeResult<GosValue> {
+        self.int(v as i128)
+    }
+
+    fn serialize_f32(self, v: f32) -> RuntimeResult<GosValue> {
+        self.float(v as f64)
+    }
+
+    fn serialize_f64(self, v: f64) -> RuntimeResult<GosValue> {
+        self.float(v)
+    }
+
+    fn serialize_char(self, v: char) -> RuntimeResult<GosValue> {
+        self.serialize_str(v.encode_utf8(&mut [0; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> RuntimeResult<GosValue> {
+        if self.is_empty_iface() {
+            return self.boxed(v, self.objs.prim_meta.mstr);
+        }
+        match self.meta.value_type(&self.objs.metas) {
+            ValueType::String => Ok(GosValue::with_str(v)),
+            _ => mismatch("string", &self.meta, self.objs),
+        }
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> RuntimeResult<GosValue> {
+        let mut seq = self.seq(Some(v.len()))?;
+        for b in v.iter() {
+            ser::SerializeSeq::serialize_element(&mut seq, b)?;
+        }
+        ser::SerializeSeq::end(seq)
+    }
+
+    fn serialize_none(self) -> RuntimeResult<GosValue> {
+        self.nil()
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> RuntimeResult<GosValue> {
+        match self.meta.ptr_depth {
+            0 => value.serialize(self),
+            _ => {
+                let pointee = value.serialize(self.with_meta(self.meta.unptr_to()))?;
+                let pobj = PointerObj::UpVal(UpValue::new_closed(pointee));
+                Ok(GosValue::new_pointer(pobj))
+            }
+        }
+    }
+
+    fn serialize_unit(self) -> RuntimeResult<GosValue> {
+        self.nil()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> RuntimeResult<GosValue> {
+        match self.mtype() {
+            MetadataType::Struct(_) => ser::SerializeStruct::end(self.fields()?),
+            _ => self.nil(),
+        }
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+    ) -> RuntimeResult<GosValue> {
+        self.serialize_str(variant)
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> RuntimeResult<GosValue> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _value: &T,
+    ) -> RuntimeResult<GosValue> {
+        mismatch(&format!("enum variant {}", variant), &self.meta, self.objs)
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> RuntimeResult<SeqToGos<'a>> {
+        self.seq(len)
+    }
+
+    fn serialize_tuple(self, len: usize) -> RuntimeResult<SeqToGos<'a>> {
+        self.seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> RuntimeResult<StructToGos<'a>> {
+        self.fields()
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> RuntimeResult<Self::SerializeTupleVariant> {
+        mismatch(&format!("enum variant {}", variant), &self.meta, self.objs)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> RuntimeResult<MapToGos<'a>> {
+        match self.mtype() {
+            MetadataType::Map(k, v) if self.meta.ptr_depth == 0 => Ok(MapToGos {
+                key: self.with_meta(*k),
+                val: self.with_meta(*v),
+                data: Map::new(),
+                next_key: None,
+            }),
+            _ => mismatch("map", &self.meta, self.objs),
+        }
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> RuntimeResult<StructToGos<'a>> {
+        self.fields()
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        variant: &'static str,
+        _len: usize,
+    ) -> RuntimeResult<Self::SerializeStructVariant> {
+        mismatch(&format!("enum variant {}", variant), &self.meta, self.objs)
+    }
+}
+
+struct SeqToGos<'a> {
+    to: ToGos<'a>,
+    size: Option<usize>,
+    data: Vec<GosValue>,
+}
+
+impl<'a> ser::SerializeSeq for SeqToGos<'a> {
+    type Ok = GosValue;
+    type Error = RuntimeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> RuntimeResult<()> {
+        self.data
+            .push(value.serialize(self.to.with_meta(self.to.meta))?);
+        Ok(())
+    }
+
+    fn end(self) -> RuntimeResult<GosValue> {
+        let objs = self.to.objs;
+        let caller = objs
+            .arr_slice_caller
+            .get(self.to.meta.value_type(&objs.metas));
+        match self.size {
+            None => Ok(GosValue::slice_with_data(self.data, caller, self.to.gcc)),
+            Some(size) if size == self.data.len() => {
+                Ok(GosValue::array_with_data(self.data, caller, self.to.gcc))
+            }
+            Some(size) => Err(RuntimeError::new(format!(
+                "expect {} elements for the array, got {}",
+                size,
+                self.data.len()
+            ))),
+        }
+    }
+}
+
+impl<'a> ser::SerializeTuple for SeqToGos<'a> {
+    type Ok = GosValue;
+    type Error = RuntimeError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> RuntimeResult<()> {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> RuntimeResult<GosValue> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct MapToGos<'a> {
+    key: ToGos<'a>,
+    val: ToGos<'a>,
+    data: Map<GosValue, GosValue>,
+    next_key: Option<GosValue>,
+}
+
+impl<'a> ser::SerializeMap for MapToGos<'a> {
+    type Ok = GosValue;
+    type Error = RuntimeError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> RuntimeResult<()> {
+        self.next_key = Some(key.serialize(self.key.with_meta(self.key.meta))?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> RuntimeResult<()> {
+        let key = self.next_key.take().unwrap();
+        let val = value.serialize(self.val.with_meta(self.val.meta))?;
+        self.data.insert(key, val);
+        Ok(())
+    }
+
+    fn end(self) -> RuntimeResult<GosValue> {
+        Ok(GosValue::map_with_data(self.data, self.key.gcc))
+    }
+}
+
+/// Fills the fields of a struct by names, or by positions for tuple structs
+struct StructToGos<'a> {
+    to: ToGos<'a>,
+    metas: Vec<Meta>,
+    names: Vec<&'a str>,
+    values: Vec<GosValue>,
+    next: usize,
+}
+
+impl<'a> StructToGos<'a> {
+    fn set<T: Serialize + ?Sized>(&mut self, i: usize, value: &T) -> RuntimeResult<()> {
+        self.values[i] = value.serialize(self.to.with_meta(self.metas[i]))?;
+        Ok(())
+    }
+
+    fn end(self) -> RuntimeResult<GosValue> {
+        Ok(GosValue::new_struct(
+            StructObj::new(self.values),
+            self.to.gcc,
+        ))
+    }
+}
+
+impl<'a> ser::SerializeStruct for StructToGos<'a> {
+    type Ok = GosValue;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> RuntimeResult<()> {
+        match self.names.iter().position(|x| same_field_name(x, key)) {
+            Some(i) => self.set(i, value),
+            None => Err(RuntimeError::new(format!("no Go field for {}", key))),
+        }
+    }
+
+    fn end(self) -> RuntimeResult<GosValue> {
+        StructToGos::end(self)
+    }
+}
+
+impl<'a> ser::SerializeTupleStruct for StructToGos<'a> {
+    type Ok = GosValue;
+    type Error = RuntimeError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> RuntimeResult<()> {
+        if self.next >= self.values.len() {
+            return Err(RuntimeError::new(
+                "too many fields for the Go struct".to_owned(),
+            ));
+        }
+        self.next += 1;
+        self.set(self.next - 1, value)
+    }
+
+    fn end(self) -> RuntimeResult<GosValue> {
+        StructToGos::end(self)
+    }
+}
+
+struct GosDeserializer<'a> {
+    val: GosValue,
+    meta: Meta,
+    objs: &'a VMObjects,
+    stack: &'a Stack,
+}
+
+impl<'a> GosDeserializer<'a> {
+    fn with(&self, val: GosValue, meta: Meta) -> GosDeserializer<'a> {
+        GosDeserializer {
+            val,
+            meta,
+            objs: self.objs,
+            stack: self.stack,
+        }
+    }
+
+    fn mtype(&self) -> &'a MetadataType {
+        self.meta.mtype_unwraped(&self.objs.metas)
+    }
+
+    /// The value is read as the type of the meta, which the value passed by the
+    /// caller may not have
+    fn check_type(&self) -> RuntimeResult<()> {
+        let expected = self.meta.value_type(&self.objs.metas);
+        match self.val.typ() {
+            t if t == expected => Ok(()),
+            t => Err(RuntimeError::new(format!(
+                "expect a value of Go type {:?}, got {:?}",
+                expected, t
+            ))),
+        }
+    }
+
+    /// Follows the pointers, fails on a nil one
+    fn deref(self) -> RuntimeResult<GosDeserializer<'a>> {
+        let mut d = self;
+        d.check_type()?;
+        while d.meta.ptr_depth > 0 {
+            let val = d
+                .val
+                .as_non_nil_pointer()?
+                .deref(d.stack, &d.objs.packages)?;
+            d = d.with(val, d.meta.unptr_to());
+            d.check_type()?;
+        }
+        Ok(d)
+    }
+
+    fn elems(&self, vec: Vec<GosValue>, elem: Meta) -> SeqFromGos<'a> {
+        SeqFromGos {
+            iter: vec.into_iter(),
+            from: self.with(GosValue::new_nil(ValueType::Void), elem),
+        }
+    }
+}
+
+impl<'de, 'a> de::Deserializer<'de> for GosDeserializer<'a> {
+    type Error = RuntimeError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> RuntimeResult<V::Value> {
+        self.check_type()?;
+        if self.meta.ptr_depth > 0 {
+            return match self.val.is_nil() {
+                true => visitor.visit_none(),
+                false => self.deref()?.deserialize_any(visitor),
+            };
+        }
+        let val = &self.val;
+        match self.mtype() {
+            MetadataType::Bool => visitor.visit_bool(*val.as_bool()),
+            MetadataType::Int => visitor.visit_i64(*val.as_int() as i64),
+            MetadataType::Int8 => visitor.visit_i8(*val.as_int8()),
+            MetadataType::Int16 => visitor.visit_i16(*val.as_int16()),
+            MetadataType::Int32 => visitor.visit_i32(*val.as_int32()),
+            MetadataType::Int64 => visitor.visit_i64(*val.as_int64()),
+            MetadataType::Uint => visitor.visit_u64(*val.as_uint() as u64),
+            MetadataType::UintPtr => visitor.visit_u64(*val.as_uint_ptr() as u64),
+            MetadataType::Uint8 => visitor.visit_u8(*val.as_uint8()),
+            MetadataType::Uint16 => visitor.visit_u16(*val.as_uint16()),
+            MetadataType::Uint32 => visitor.visit_u32(*val.as_uint32()),
+            MetadataType::Uint64 => visitor.visit_u64(*val.as_uint64()),
+            MetadataType::Float32 => visitor.visit_f32(val.as_float32().into_inner()),
+            MetadataType::Float64 => visitor.visit_f64(val.as_float64().into_inner()),
+            MetadataType::Str => visitor.visit_str(&val.as_string().as_str()),
+            MetadataType::Slice(m) => match val.is_nil() {
+                true => visitor.visit_seq(self.elems(vec![], *m)),
+                false => {
+                    let caller = ArrCaller::get_slow(m.value_type(&self.objs.metas));
+                    let vec = caller.slice_get_vec(val).unwrap_or_default();
+                    visitor.visit_seq(self.elems(vec, *m))
+                }
+            },
+            MetadataType::Array(m, _) => {
+                let caller = ArrCaller::get_slow(m.value_type(&self.objs.metas));
+                let vec = caller.array_get_vec(val);
+                visitor.visit_seq(self.elems(vec, *m))
+            }
+            MetadataType::Map(k, v) => {
+                let entries: Vec<(GosValue, GosValue)> = match val.as_map() {
+                    Some((m, _)) => m
+                        .borrow_data()
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect(),
+                    None => vec![],
+                };
+                visitor.visit_map(MapFromGos {
+                    iter: entries.into_iter(),
+                    key: self.with(GosValue::new_nil(ValueType::Void), *k),
+                    val: None,
+                    val_meta: *v,
+                })
+            }
+            MetadataType::Struct(fields) => {
+                let entries: Vec<(String, GosValue, Meta)> = fields
+                    .infos()
+                    .iter()
+                    .zip(val.as_struct().0.borrow_fields().iter())
+                    .map(|(info, v)| (info.name.clone(), v.clone(), info.meta))
+                    .collect();
+                visitor.visit_map(StructFromGos {
+                    iter: entries.into_iter(),
+                    from: self.with(GosValue::new_nil(ValueType::Void), self.meta),
+                    val: None,
+                })
+            }
+            MetadataType::Interface(_) => match val.as_interface() {
+                None => visitor.visit_none(),
+                Some(InterfaceObj::Gos(v, Some((m, _)))) => {
+                    self.with(v.clone(), *m).deserialize_any(visitor)
+                }
+                Some(_) => Err(RuntimeError::new(
+                    "cannot convert an interface without type info".to_owned(),
+                )),
+            },
+            _ => Err(RuntimeError::new(format!(
+                "cannot convert Go type {:?}",
+                self.meta.value_type(&self.objs.metas)
+            ))),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> RuntimeResult<V::Value> {
+        match self.val.is_nil() {
+            true => visitor.visit_none(),
+            false => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> RuntimeResult<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        rust_fields: &'static [&'static str],
+        visitor: V,
+    ) -> RuntimeResult<V::Value> {
+        let d = self.deref()?;
+        let fields = match d.mtype() {
+            MetadataType::Struct(fields) => fields,
+            _ => return d.deserialize_any(visitor),
+        };
+        // the keys are the Rust names, so that they are recognized by the visitor
+        let entries: Vec<(String, GosValue, Meta)> = fields
+            .infos()
+            .iter()
+            .zip(d.val.as_struct().0.borrow_fields().iter())
+            .map(|(info, v)| {
+                let name = rust_fields
+                    .iter()
+                    .find(|x| same_field_name(&info.name, x))
+                    .map_or(info.name.clone(), |x| x.to_string());
+                (name, v.clone(), info.meta)
+            })
+            .collect();
+        visitor.visit_map(StructFromGos {
+            iter: entries.into_iter(),
+            from: d.with(GosValue::new_nil(ValueType::Void), d.meta),
+            val: None,
+        })
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> RuntimeResult<V::Value> {
+        let d = self.deref()?;
+        match d.meta.value_type(&d.objs.metas) {
+            ValueType::String => {
+                let s = d.val.as_string().as_str().to_string();
+                visitor.visit_enum(s.into_deserializer())
+            }
+            _ => mismatch("enum", &d.meta, d.objs),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple
+        tuple_struct map identifier ignored_any
+    }
+}
+
+struct SeqFromGos<'a> {
+    iter: std::vec::IntoIter<GosValue>,
+    from: GosDeserializer<'a>,
+}
+
+impl<'de, 'a> de::SeqAccess<'de> for SeqFromGos<'a> {
+    type Error = RuntimeError;
+
+    fn next_element_seed<T: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> RuntimeResult<Option<T::Value>> {
+        match self.iter.next() {
+            Some(v) => seed
+                .deserialize(self.from.with(v, self.from.meta))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapFromGos<'a> {
+    iter: std::vec::IntoIter<(GosValue, GosValue)>,
+    key: GosDeserializer<'a>,
+    val: Option<GosValue>,
+    val_meta: Meta,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for MapFromGos<'a> {
+    type Error = RuntimeError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> RuntimeResult<Option<K::Value>> {
+        match self.iter.next() {
+            Some((k, v)) => {
+                self.val = Some(v);
+                seed.deserialize(self.key.with(k, self.key.meta)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> RuntimeResult<V::Value> {
+        let val = self.val.take().unwrap();
+        seed.deserialize(self.key.with(val, self.val_meta))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct StructFromGos<'a> {
+    iter: std::vec::IntoIter<(String, GosValue, Meta)>,
+    from: GosDeserializer<'a>,
+    val: Option<(GosValue, Meta)>,
+}
+
+impl<'de, 'a> de::MapAccess<'de> for StructFromGos<'a> {
+    type Error = RuntimeError;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> RuntimeResult<Option<K::Value>> {
+        match self.iter.next() {
+            Some((name, v, m)) => {
+                self.val = Some((v, m));
+                seed.deserialize(name.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(&mut self, seed: V) -> RuntimeResult<V::Value> {
+        let (val, meta) = self.val.take().unwrap();
+        seed.deserialize(self.from.with(val, meta))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
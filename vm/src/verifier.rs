@@ -0,0 +1,824 @@
+// Copyright 2022 The Goscript Authors. All rights reserved.
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file.
+
+//! Checks that a `Bytecode` is well-formed before it's run, so that a corrupted
+//! or hand-crafted program cannot make the VM index out of bounds.
+//!
+//! The checks are structural: every register is inside the frame of its function,
+//! every constant, interface binding, embedded field path, function and package
+//! referenced exists, and every jump lands on an instruction. The packages have
+//! their constructor as member 0 and closures as init functions, and the types
+//! are made of types that exist. The types of the values in the registers are not
+//! checked, they are trusted like the compiler's.
+//!
+//! The indices of package members, struct fields and interface methods are checked
+//! against their types: the package has to be a constant, and the types of the
+//! struct and interface operands are the ones recorded by the compiler in
+//! `FunctionObj::operand_metas`, an instruction without one is rejected.
+
+use crate::instruction::{Instruction, OpIndex, Opcode, ValueType};
+use crate::metadata::{Fields, Meta};
+use crate::objects::{ClosureObj, PackageObj};
+use crate::value::{
+    Binding4Runtime, Bytecode, FunctionKey, FunctionObj, GosValue, MetadataType, PackageKey,
+};
+use go_parser::PiggyVecKey;
+use std::fmt;
+
+/// A problem found by `Bytecode::verify`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VerifyError {
+    /// The function with the problem, `None` for the tables of the bytecode
+    pub func: Option<FunctionKey>,
+    /// The qualified name of the function, empty if `func` is `None`
+    pub func_name: String,
+    /// The index of the instruction with the problem
+    pub pc: Option<usize>,
+    pub msg: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.func, self.pc) {
+            (Some(_), Some(pc)) => write!(f, "{} at {}: {}", self.func_name, pc, self.msg),
+            (Some(_), None) => write!(f, "{}: {}", self.func_name, self.msg),
+            _ => f.write_str(&self.msg),
+        }
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl Bytecode {
+    /// Checks the bytecode before running it, this is needed for the bytecode
+    /// that doesn't come from the compiler, e.g. loaded from an untrusted file.
+    /// Returns all the problems found.
+    pub fn verify(&self) -> Result<(), Vec<VerifyError>> {
+        let mut v = Verifier {
+            bc: self,
+            errors: vec![],
+        };
+        v.verify_tables();
+        for (i, func) in self.objects.functions.vec().iter().enumerate() {
+            FuncVerifier::new(&mut v, i.into(), func).verify();
+        }
+        match v.errors.is_empty() {
+            true => Ok(()),
+            false => Err(v.errors),
+        }
+    }
+}
+
+struct Verifier<'a> {
+    bc: &'a Bytecode,
+    errors: Vec<VerifyError>,
+}
+
+impl<'a> Verifier<'a> {
+    fn error(&mut self, msg: String) {
+        self.errors.push(VerifyError {
+            func: None,
+            func_name: String::new(),
+            pc: None,
+            msg,
+        });
+    }
+
+    fn has_func(&self, key: FunctionKey) -> bool {
+        key.as_usize() < self.bc.objects.functions.vec().len()
+    }
+
+    fn has_meta(&self, key: crate::value::MetadataKey) -> bool {
+        key.as_usize() < self.bc.objects.metas.vec().len()
+    }
+
+    /// The function, package and type a constant refers to have to exist
+    fn value_error(&self, val: &GosValue) -> Option<String> {
+        match val.typ() {
+            ValueType::Function if !self.has_func(*val.as_function()) => {
+                Some(format!("bad function {:?}", val.as_function()))
+            }
+            ValueType::Package
+                if val.as_package().as_usize() >= self.bc.objects.packages.vec().len() =>
+            {
+                Some(format!("bad package {:?}", val.as_package()))
+            }
+            ValueType::Metadata if !self.has_meta(val.as_metadata().key) => {
+                Some(format!("bad type {:?}", val.as_metadata().key))
+            }
+            ValueType::Closure => match val.as_closure() {
+                Some((ClosureObj::Gos(cls), _)) if !self.has_func(cls.func) => {
+                    Some(format!("bad function {:?}", cls.func))
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// The function of a closure that's not nil and not an FFI
+    fn closure_func(&self, val: &GosValue) -> Option<&'a FunctionObj> {
+        match val.typ() {
+            ValueType::Closure => match val.as_closure() {
+                Some((ClosureObj::Gos(cls), _)) if self.has_func(cls.func) => {
+                    Some(&self.bc.objects.functions[cls.func])
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Member 0 is the constructor, the values it returns are stored into the members
+    /// in `var_mapping`, and the init functions are called after it
+    fn verify_package(&mut self, pkg: &PackageObj) {
+        let count = pkg.member_count();
+        if count == 0 {
+            return self.error(format!("package {}: no constructor", pkg.name()));
+        }
+        for i in 0..count {
+            if let Some(e) = self.value_error(&pkg.member(i as OpIndex)) {
+                self.error(format!("package {} member {}: {}", pkg.name(), i, e));
+            }
+        }
+        for (name, i) in pkg.member_indices().iter() {
+            if *i < 0 || *i as usize >= count {
+                self.error(format!(
+                    "package {}: bad member index {} of {}",
+                    pkg.name(),
+                    i,
+                    name
+                ));
+            }
+        }
+        match self.closure_func(&pkg.member(0)) {
+            Some(ctor) if ctor.is_ctor() => {
+                if let Some(mapping) = pkg.var_mapping().as_ref() {
+                    for i in 0..ctor.local_count() as OpIndex {
+                        match mapping.get(&i) {
+                            Some(m) if *m >= 0 && (*m as usize) < count => {}
+                            _ => self.error(format!(
+                                "package {}: bad member for the value {} of the constructor",
+                                pkg.name(),
+                                i
+                            )),
+                        }
+                    }
+                }
+            }
+            _ => self.error(format!(
+                "package {}: member 0 is not a constructor",
+                pkg.name()
+            )),
+        }
+        for (i, f) in pkg.init_funcs().iter().enumerate() {
+            if self.closure_func(f).is_none() {
+                self.error(format!("package {}: bad init function {}", pkg.name(), i));
+            }
+        }
+    }
+
+    /// The types a type is made of have to exist
+    fn verify_meta(&mut self, i: usize, mtype: &MetadataType) {
+        let mut metas: Vec<&Meta> = vec![];
+        let mut funcs = vec![];
+        match mtype {
+            MetadataType::Array(m, _) | MetadataType::Slice(m) | MetadataType::Channel(_, m) => {
+                metas.push(m)
+            }
+            MetadataType::Map(k, v) => metas.extend([k, v]),
+            MetadataType::Struct(fields) | MetadataType::Interface(fields) => {
+                metas.extend(fields.infos().iter().map(|f| &f.meta))
+            }
+            MetadataType::Signature(sig) => {
+                metas.extend(sig.recv.iter());
+                metas.extend(sig.params.iter().chain(sig.results.iter()));
+                if let Some((a, b)) = &sig.variadic {
+                    metas.extend([a, b]);
+                }
+            }
+            MetadataType::Named(methods, m) => {
+                metas.push(m);
+                funcs.extend(methods.members.iter().filter_map(|x| x.borrow().func));
+            }
+            _ => {}
+        }
+        for m in metas.into_iter() {
+            if !self.has_meta(m.key) {
+                self.error(format!("type {}: bad type {:?}", i, m.key));
+            }
+        }
+        for f in funcs.into_iter() {
+            if !self.has_func(f) {
+                self.error(format!("type {}: bad method {:?}", i, f));
+            }
+        }
+    }
+
+    fn verify_tables(&mut self) {
+        let bc = self.bc;
+        if !self.has_func(bc.entry) {
+            self.error(format!("bad entry function {:?}", bc.entry));
+        }
+        if bc.main_pkg.as_usize() >= bc.objects.packages.vec().len() {
+            self.error(format!("bad main package {:?}", bc.main_pkg));
+        }
+        for (i, c) in bc.consts.iter().enumerate() {
+            if let Some(e) = self.value_error(c) {
+                self.error(format!("constant {}: {}", i, e));
+            }
+        }
+        for (i, (meta, bindings)) in bc.ifaces.iter().enumerate() {
+            if !self.has_meta(meta.key) {
+                self.error(format!("interface binding {}: bad type {:?}", i, meta.key));
+            }
+            for b in bindings.iter() {
+                match b {
+                    Binding4Runtime::Struct(f, _, _) if !self.has_func(*f) => {
+                        self.error(format!("interface binding {}: bad function {:?}", i, f))
+                    }
+                    _ => {}
+                }
+            }
+        }
+        for (i, path) in bc.indices.iter().enumerate() {
+            if path.is_empty() || path.iter().any(|x| *x < 0) {
+                self.error(format!("bad embedded field path {}: {:?}", i, path));
+            }
+        }
+        for pkg in bc.objects.packages.vec().iter() {
+            self.verify_package(pkg);
+        }
+        for (i, meta) in bc.objects.metas.vec().iter().enumerate() {
+            self.verify_meta(i, meta);
+        }
+    }
+}
+
+/// An operand of an instruction and where it is, the instruction is 0 and its
+/// extensions are 1, 2...
+#[derive(Clone, Copy)]
+pub(crate) struct Arg {
+    pub val: OpIndex,
+    pub slot: Option<(usize, Slot)>,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) enum Slot {
+    D,
+    S0,
+    S1,
+}
+
+impl Arg {
+    /// An operand implied by the instruction
+    fn implied(val: OpIndex) -> Arg {
+        Arg { val, slot: None }
+    }
+}
+
+/// The operands d, s0 and s1 of an instruction
+struct Args {
+    d: Arg,
+    s0: Arg,
+    s1: Arg,
+}
+
+impl Args {
+    fn of(inst: &Instruction, n: usize) -> Args {
+        let arg = |val, slot| Arg {
+            val,
+            slot: Some((n, slot)),
+        };
+        Args {
+            d: arg(inst.d, Slot::D),
+            s0: arg(inst.s0, Slot::S0),
+            s1: arg(inst.s1, Slot::S1),
+        }
+    }
+}
+
+/// How an instruction uses an operand
+#[derive(Clone, Copy)]
+pub(crate) enum Operand {
+    /// A register or a constant
+    Read(Arg),
+    /// A register
+    Write(Arg),
+    /// A constant of the type
+    Const(Arg, ValueType),
+    /// `count` registers from `begin`
+    Registers(Arg, OpIndex),
+    /// An offset from the next instruction
+    Jump(Arg),
+    /// An index into `Bytecode::ifaces`
+    Iface(Arg),
+    /// An index into `Bytecode::indices`
+    Embedded(Arg),
+    /// An index into the up values of the function
+    UpValue(Arg),
+    /// A constant package and the index of a member of it
+    PkgMember(Arg, Arg),
+    /// A field index of the struct operand
+    Field(Arg),
+    /// A method index of the interface operand
+    Method(Arg),
+}
+
+struct FuncVerifier<'a, 'b> {
+    v: &'b mut Verifier<'a>,
+    key: FunctionKey,
+    func: &'a FunctionObj,
+    frame_size: OpIndex,
+    /// Whether an instruction starts at the index, rather than being the
+    /// extension of the previous one
+    starts: Vec<bool>,
+}
+
+impl<'a, 'b> FuncVerifier<'a, 'b> {
+    fn new(v: &'b mut Verifier<'a>, key: FunctionKey, func: &'a FunctionObj) -> Self {
+        FuncVerifier {
+            v,
+            key,
+            func,
+            frame_size: func.frame_size(),
+            starts: vec![false; func.code.len()],
+        }
+    }
+
+    fn error(&mut self, pc: Option<usize>, msg: String) {
+        self.v.errors.push(VerifyError {
+            func: Some(self.key),
+            func_name: self.func.name.clone(),
+            pc,
+            msg,
+        });
+    }
+
+    fn verify(mut self) {
+        let bc = self.v.bc;
+        let func = self.func;
+        if func.package != PackageKey::null()
+            && func.package.as_usize() >= bc.objects.packages.vec().len()
+        {
+            self.error(None, format!("bad package {:?}", func.package));
+        }
+        match self.v.has_meta(func.meta.key) {
+            true => match &bc.objects.metas[func.meta.key] {
+                MetadataType::Signature(_) => {}
+                _ => self.error(None, "the type is not a signature".to_owned()),
+            },
+            false => self.error(None, format!("bad type {:?}", func.meta.key)),
+        }
+        if func.param_count < 0 || func.max_write_index < 0 {
+            self.error(None, "negative frame layout".to_owned());
+        }
+        for (i, uv) in func.up_ptrs.iter().enumerate() {
+            let size = match self.v.has_func(uv.func) {
+                true => bc.objects.functions[uv.func].frame_size(),
+                false => {
+                    self.error(None, format!("up value {}: bad function {:?}", i, uv.func));
+                    continue;
+                }
+            };
+            if uv.index < 0 || uv.index >= size {
+                self.error(None, format!("up value {}: bad register {}", i, uv.index));
+            }
+        }
+        // the debug info is read by debuggers without any further check
+        for v in func.var_names.iter() {
+            if v.index < 0 || v.index >= self.frame_size {
+                self.error(
+                    None,
+                    format!("variable {}: bad register {}", v.name, v.index),
+                );
+            }
+        }
+        if !func.up_names.is_empty() && func.up_names.len() != func.up_ptrs.len() {
+            self.error(
+                None,
+                "the up value names don't match the up values".to_owned(),
+            );
+        }
+        for (i, zero) in func
+            .ret_zeros
+            .iter()
+            .chain(func.local_zeros.iter())
+            .enumerate()
+        {
+            if let Some(e) = self.v.value_error(zero) {
+                self.error(None, format!("zero value {}: {}", i, e));
+            }
+        }
+        // the panics are unwound from the last instruction
+        if func.code.last().map(|x| x.op0) != Some(Opcode::RETURN) {
+            self.error(None, "the code doesn't end with RETURN".to_owned());
+        }
+
+        // first pass for the starts of the instructions, the second one checks
+        // the operands, including the jump targets
+        let code = &func.code;
+        let mut pc = 0;
+        while pc < code.len() {
+            self.starts[pc] = true;
+            pc += 1 + code[pc].ext_count();
+        }
+        if pc > code.len() {
+            self.error(None, "the last instruction is truncated".to_owned());
+        }
+        let mut pc = 0;
+        while pc < code.len() {
+            let inst = &code[pc];
+            let next = pc + 1 + inst.ext_count();
+            let exts = &code[(pc + 1).min(code.len())..next.min(code.len())];
+            let mut errors = vec![];
+            let ops = operands(inst, exts, &mut |msg| errors.push(msg));
+            for msg in errors {
+                self.error(Some(pc), msg);
+            }
+            for op in ops {
+                if let Err(msg) = self.check(pc, next, op) {
+                    self.error(Some(pc), format!("{}: {}", inst.op0, msg));
+                }
+            }
+            pc = next;
+        }
+    }
+
+    fn check(&self, pc: usize, next: usize, op: Operand) -> Result<(), String> {
+        let bc = self.v.bc;
+        let in_frame = |i: OpIndex| i >= 0 && i < self.frame_size;
+        let const_index = |i: OpIndex| -(i as i64) - 1;
+        match op {
+            Operand::Read(Arg { val: i, .. }) if i >= 0 => match in_frame(i) {
+                true => Ok(()),
+                false => Err(self.register_error(i)),
+            },
+            Operand::Read(Arg { val: i, .. }) => match const_index(i) {
+                c if c >= 0 && (c as usize) < bc.consts.len() => Ok(()),
+                c => Err(format!("bad constant {}", c)),
+            },
+            Operand::Const(Arg { val: i, .. }, t) => match const_index(i) {
+                c if c >= 0 && (c as usize) < bc.consts.len() => {
+                    match bc.consts[c as usize].typ() == t {
+                        true => Ok(()),
+                        false => Err(format!("constant {} is not a {}", c, t)),
+                    }
+                }
+                c => Err(format!("bad constant {}", c)),
+            },
+            Operand::Write(Arg { val: i, .. }) => match in_frame(i) {
+                true => Ok(()),
+                false => Err(self.register_error(i)),
+            },
+            Operand::Registers(Arg { val: begin, .. }, count) => {
+                let end = begin as i64 + count as i64;
+                match count >= 0 && begin >= 0 && end <= self.frame_size as i64 {
+                    true => Ok(()),
+                    false => Err(format!(
+                        "registers {}..{} out of the frame of size {}",
+                        begin, end, self.frame_size
+                    )),
+                }
+            }
+            Operand::Jump(Arg { val: offset, .. }) => {
+                let target = next as i64 + offset as i64;
+                match target >= 0
+                    && (target as usize) < self.starts.len()
+                    && self.starts[target as usize]
+                {
+                    true => Ok(()),
+                    false => Err(format!("bad jump target {} from {}", target, pc)),
+                }
+            }
+            Operand::Iface(Arg { val: i, .. }) => match i >= 0 && (i as usize) < bc.ifaces.len() {
+                true => Ok(()),
+                false => Err(format!("bad interface binding {}", i)),
+            },
+            Operand::Embedded(Arg { val: i, .. }) => {
+                let path = match i >= 0 && (i as usize) < bc.indices.len() {
+                    true => &bc.indices[i as usize],
+                    false => return Err(format!("bad embedded field path {}", i)),
+                };
+                let mut fields = self.operand_fields(pc)?;
+                for (n, &x) in path.iter().enumerate() {
+                    let field = fields
+                        .infos()
+                        .get(x as usize)
+                        .ok_or_else(|| format!("bad field {} in embedded field path {}", x, i))?;
+                    if n + 1 < path.len() {
+                        fields = match self.mtype(field.meta)? {
+                            MetadataType::Struct(f) => f,
+                            _ => return Err(format!("embedded field {} is not a struct", x)),
+                        };
+                    }
+                }
+                Ok(())
+            }
+            Operand::UpValue(Arg { val: i, .. }) => {
+                match i >= 0 && (i as usize) < self.func.up_ptrs.len() {
+                    true => Ok(()),
+                    false => Err(format!("bad up value {}", i)),
+                }
+            }
+            Operand::PkgMember(p, Arg { val: i, .. }) => {
+                self.check(pc, next, Operand::Const(p, ValueType::Package))?;
+                let key = *bc.consts[const_index(p.val) as usize].as_package();
+                // a bad package key is reported with the constants
+                match bc.objects.packages.vec().get(key.as_usize()) {
+                    Some(pkg) if i >= 0 && (i as usize) < pkg.member_count() => Ok(()),
+                    Some(_) => Err(format!("bad member {} of package {:?}", i, key)),
+                    None => Err(format!("bad package {:?}", key)),
+                }
+            }
+            Operand::Field(Arg { val: i, .. }) => match self.operand_fields(pc)?.infos().len() {
+                n if i >= 0 && (i as usize) < n => Ok(()),
+                _ => Err(format!("bad field {}", i)),
+            },
+            Operand::Method(Arg { val: i, .. }) => match self.operand_mtype(pc)? {
+                MetadataType::Interface(f) if i >= 0 && (i as usize) < f.infos().len() => Ok(()),
+                MetadataType::Interface(_) => Err(format!("bad method {}", i)),
+                _ => Err("the operand is not an interface".to_owned()),
+            },
+        }
+    }
+
+    /// The type of the struct or interface operand of the instruction at `pc`,
+    /// whether it's a pointer is not relevant
+    fn operand_mtype(&self, pc: usize) -> Result<&'a MetadataType, String> {
+        match self.func.operand_metas.get(&(pc as OpIndex)) {
+            Some(meta) => self.mtype(*meta),
+            None => Err("the type of the operand is unknown".to_owned()),
+        }
+    }
+
+    fn operand_fields(&self, pc: usize) -> Result<&'a Fields, String> {
+        match self.operand_mtype(pc)? {
+            MetadataType::Struct(f) => Ok(f),
+            _ => Err("the operand is not a struct".to_owned()),
+        }
+    }
+
+    /// The underlying type of a named type
+    fn mtype(&self, meta: Meta) -> Result<&'a MetadataType, String> {
+        let metas = &self.v.bc.objects.metas;
+        match self.v.has_meta(meta.key) {
+            true => match &metas[meta.key] {
+                MetadataType::Named(_, u) if !self.v.has_meta(u.key) => {
+                    Err(format!("bad type {:?}", u.key))
+                }
+                t => Ok(t.unwrap_named(metas)),
+            },
+            false => Err(format!("bad type {:?}", meta.key)),
+        }
+    }
+
+    fn register_error(&self, i: OpIndex) -> String {
+        format!(
+            "register {} out of the frame of size {}",
+            i, self.frame_size
+        )
+    }
+}
+
+/// The operands of the instruction and of its extensions, the problems with
+/// the instruction itself are reported to `error`
+pub(crate) fn operands(
+    inst: &Instruction,
+    exts: &[Instruction],
+    error: &mut dyn FnMut(String),
+) -> Vec<Operand> {
+    use Operand::*;
+    if exts.len() < inst.ext_count() {
+        error(format!("{}: truncated", inst.op0));
+        return vec![];
+    }
+    let Args { d, s0, s1 } = Args::of(inst, 0);
+    let ext = |i: usize| Args::of(&exts[i], i + 1);
+    match inst.op0 {
+        Opcode::VOID => {
+            error("VOID is not executable".to_owned());
+            vec![]
+        }
+        Opcode::DUPLICATE
+        | Opcode::LOAD_POINTER
+        | Opcode::UNARY_SUB
+        | Opcode::UNARY_XOR
+        | Opcode::NOT
+        | Opcode::REF
+        | Opcode::NEW
+        | Opcode::REAL
+        | Opcode::IMAG
+        | Opcode::LEN
+        | Opcode::CAP => vec![Write(d), Read(s0)],
+        Opcode::LOAD_SLICE
+        | Opcode::LOAD_ARRAY
+        | Opcode::ADD
+        | Opcode::SUB
+        | Opcode::MUL
+        | Opcode::QUO
+        | Opcode::REM
+        | Opcode::AND
+        | Opcode::OR
+        | Opcode::XOR
+        | Opcode::AND_NOT
+        | Opcode::SHL
+        | Opcode::SHR
+        | Opcode::EQL
+        | Opcode::NEQ
+        | Opcode::LSS
+        | Opcode::GTR
+        | Opcode::LEQ
+        | Opcode::GEQ
+        | Opcode::REF_SLICE_MEMBER
+        | Opcode::BIND_METHOD
+        | Opcode::COMPLEX
+        | Opcode::APPEND
+        | Opcode::COPY
+        | Opcode::FFI => vec![Write(d), Read(s0), Read(s1)],
+        Opcode::STORE_SLICE | Opcode::STORE_ARRAY => {
+            let mut ops = vec![Read(d), Read(s0)];
+            ops.extend(stored_value(inst, s1, error));
+            ops
+        }
+        Opcode::ADD_ASSIGN
+        | Opcode::SUB_ASSIGN
+        | Opcode::MUL_ASSIGN
+        | Opcode::QUO_ASSIGN
+        | Opcode::REM_ASSIGN
+        | Opcode::AND_ASSIGN
+        | Opcode::OR_ASSIGN
+        | Opcode::XOR_ASSIGN
+        | Opcode::AND_NOT_ASSIGN
+        | Opcode::SHL_ASSIGN
+        | Opcode::SHR_ASSIGN => vec![Write(d), Read(s0)],
+        Opcode::INC | Opcode::DEC | Opcode::RECOVER => vec![Write(d)],
+        Opcode::LOAD_MAP => {
+            let mut ops = vec![Write(d), Read(s0), Read(s1), Read(ext(0).s0)];
+            if inst.t1 == ValueType::FlagB {
+                ops.push(Write(ext(0).d));
+            }
+            ops
+        }
+        Opcode::STORE_MAP => {
+            let mut ops = vec![Read(d), Read(s0), Read(ext(0).s0)];
+            ops.extend(stored_value(inst, s1, error));
+            ops
+        }
+        Opcode::LOAD_STRUCT | Opcode::REF_STRUCT_FIELD => {
+            vec![Write(d), Read(s0), Field(s1)]
+        }
+        Opcode::LOAD_PKG | Opcode::REF_PKG_MEMBER => vec![Write(d), PkgMember(s0, s1)],
+        Opcode::BIND_I_METHOD => vec![Write(d), Read(s0), Method(s1)],
+        Opcode::STORE_STRUCT => {
+            let mut ops = vec![Read(d), Field(s0)];
+            ops.extend(stored_value(inst, s1, error));
+            ops
+        }
+        Opcode::STORE_PKG => {
+            let mut ops = vec![PkgMember(d, s0)];
+            ops.extend(stored_value(inst, s1, error));
+            ops
+        }
+        Opcode::LOAD_EMBEDDED | Opcode::REF_EMBEDDED => {
+            vec![Write(d), Read(s0), Embedded(s1)]
+        }
+        Opcode::STORE_EMBEDDED => {
+            let mut ops = vec![Read(d), Embedded(s0)];
+            ops.extend(stored_value(inst, s1, error));
+            ops
+        }
+        Opcode::STORE_POINTER => {
+            let mut ops = vec![Read(d)];
+            ops.extend(stored_value(inst, s0, error));
+            ops
+        }
+        Opcode::LOAD_UP_VALUE | Opcode::REF_UPVALUE => vec![Write(d), UpValue(s0)],
+        Opcode::STORE_UP_VALUE => {
+            let mut ops = vec![UpValue(d)];
+            ops.extend(stored_value(inst, s0, error));
+            ops
+        }
+        Opcode::SEND | Opcode::DELETE => vec![Read(s0), Read(s1)],
+        Opcode::RECV => match inst.t1 {
+            ValueType::FlagB => vec![Write(d), Read(s0), Write(s1)],
+            _ => vec![Write(d), Read(s0)],
+        },
+        Opcode::PACK_VARIADIC => vec![Write(d), Registers(s0, s1.val - s0.val)],
+        Opcode::CALL => {
+            if !matches!(
+                inst.t0,
+                ValueType::FlagA | ValueType::FlagB | ValueType::FlagC
+            ) {
+                error(format!("CALL: bad call style {}", inst.t0));
+            }
+            // the callee's frame starts at s0
+            vec![Read(d), Registers(s0, 0)]
+        }
+        Opcode::RETURN => match inst.t0 {
+            ValueType::FlagA | ValueType::FlagC => vec![],
+            ValueType::FlagB => vec![Read(d)],
+            t => {
+                error(format!("RETURN: bad flag {}", t));
+                vec![]
+            }
+        },
+        Opcode::JUMP => vec![Jump(d)],
+        Opcode::JUMP_IF | Opcode::JUMP_IF_NOT | Opcode::IMPORT => vec![Jump(d), Read(s0)],
+        Opcode::SWITCH => vec![Jump(d), Read(s0), Read(s1)],
+        Opcode::SELECT => {
+            let mut ops = vec![];
+            if inst.t0 == ValueType::FlagE {
+                ops.push(Jump(d));
+            }
+            for (i, e) in exts.iter().enumerate() {
+                let a = ext(i);
+                ops.push(Jump(a.d));
+                ops.push(Read(a.s0));
+                match e.t0 {
+                    ValueType::FlagA => ops.push(Read(a.s1)),
+                    ValueType::FlagB => {}
+                    ValueType::FlagC => ops.push(Write(a.s1)),
+                    ValueType::FlagD => ops.push(Registers(a.s1, 2)),
+                    t => error(format!("SELECT: bad case flag {}", t)),
+                }
+            }
+            ops
+        }
+        Opcode::RANGE_INIT => vec![Read(s0)],
+        Opcode::RANGE => vec![Write(d), Jump(s0), Write(s1)],
+        // jumps over the next 2 instructions when there are no more init functions
+        Opcode::LOAD_INIT_FUNC => vec![Write(d), Read(s0), Write(s1), Jump(Arg::implied(2))],
+        Opcode::CAST => {
+            if (inst.t0 == ValueType::String || inst.t0 == ValueType::Slice)
+                && inst.op1 as u8 > ValueType::FlagE as u8
+            {
+                error("CAST: bad element type".to_owned());
+            }
+            match inst.t0 {
+                ValueType::Interface => vec![Write(d), Read(s0), Iface(s1)],
+                _ => vec![Write(d), Read(s0)],
+            }
+        }
+        Opcode::TYPE_ASSERT => {
+            let mut ops = vec![Write(d), Read(s0), Const(s1, ValueType::Metadata)];
+            if inst.t1 == ValueType::FlagB {
+                ops.push(Write(ext(0).d));
+            }
+            ops
+        }
+        Opcode::TYPE => match inst.t0 {
+            ValueType::FlagA => vec![Write(d), Read(s0), Write(s1)],
+            _ => vec![Write(d), Read(s0)],
+        },
+        Opcode::SLICE => vec![
+            Write(d),
+            Read(s0),
+            Read(s1),
+            Read(ext(0).s0),
+            Read(ext(0).s1),
+        ],
+        Opcode::CLOSURE => vec![Write(d), Const(s0, ValueType::Function)],
+        // s1 pairs of index and value from s0
+        Opcode::LITERAL => vec![
+            Write(d),
+            Registers(s0, s1.val.saturating_mul(2)),
+            Const(ext(0).s0, ValueType::Metadata),
+        ],
+        Opcode::MAKE => match inst.t0 {
+            ValueType::FlagA => vec![Write(d), Read(s0)],
+            ValueType::FlagB => vec![Write(d), Read(s0), Read(s1)],
+            ValueType::FlagC => vec![Write(d), Read(s0), Read(s1), Read(ext(0).s0)],
+            t => {
+                error(format!("MAKE: bad flag {}", t));
+                vec![]
+            }
+        },
+        Opcode::CLOSE | Opcode::PANIC | Opcode::ASSERT => vec![Read(s0)],
+    }
+}
+
+/// The value of a STORE_XXX, which is combined with the old value by the
+/// operator `op1` if it's not VOID, INC and DEC don't have one
+fn stored_value(inst: &Instruction, val: Arg, error: &mut dyn FnMut(String)) -> Option<Operand> {
+    match inst.op1 {
+        Opcode::INC | Opcode::DEC => None,
+        Opcode::VOID
+        | Opcode::ADD
+        | Opcode::SUB
+        | Opcode::MUL
+        | Opcode::QUO
+        | Opcode::REM
+        | Opcode::AND
+        | Opcode::OR
+        | Opcode::XOR
+        | Opcode::AND_NOT
+        | Opcode::SHL
+        | Opcode::SHR => Some(Operand::Read(val)),
+        op => {
+            error(format!("{}: bad operator {}", inst.op0, op));
+            None
+        }
+    }
+}
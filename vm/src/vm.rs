@@ -2,15 +2,25 @@
 // Use of this source code is governed by a BSD-style
 // license that can be found in the LICENSE file.
 
+use crate::debug::{DebugState, DebugView, Debugger, PauseHandle};
 use crate::ffi::{FfiCtx, FfiFactory};
-use crate::gc::{collect, GcContainer};
+use crate::gc::{collect, GcContainer, MAP_ENTRY_SIZE};
 use crate::objects::ClosureObj;
+use crate::profile::{Profile, Profiler, Sampling};
 use crate::stack::{RangeStack, Stack};
+#[cfg(feature = "stats")]
+use crate::stats::{Counters, Stats};
+#[cfg(feature = "trace")]
+use crate::trace::{TraceEvent, Tracer};
 use crate::value::*;
 use go_parser::Map;
 use std::cell::{Cell, RefCell};
 use std::cmp::Ordering;
 use std::rc::Rc;
+#[cfg(feature = "async")]
+use std::rc::Weak;
+use std::sync::atomic::{self, AtomicBool};
+use std::sync::Arc;
 
 #[cfg(feature = "async")]
 use crate::channel;
@@ -20,8 +30,11 @@ use async_executor::LocalExecutor;
 use futures_lite::future;
 
 // restore stack_ref after drop to allow code in block call yield
+// it also marks the fiber as making progress, as it's resuming after being blocked
 macro_rules! restore_stack_ref {
     ($self_:ident, $stack:ident, $stack_ref:ident) => {{
+        #[cfg(feature = "async")]
+        $self_.context.sched.progress();
         $stack_ref = $self_.stack.borrow_mut();
         $stack = &mut $stack_ref;
     }};
@@ -44,6 +57,13 @@ macro_rules! go_panic_str {
     }};
 }
 
+macro_rules! go_panic_ffi {
+    ($panic:ident, $msg:expr, $frame:ident, $code:ident) => {{
+        go_panic_str!($panic, $msg, $frame, $code);
+        $panic.as_mut().unwrap().ffi_error = true;
+    }};
+}
+
 #[cfg(not(feature = "async"))]
 macro_rules! go_panic_no_async {
     ($panic:ident, $frame:ident, $code:ident) => {{
@@ -59,12 +79,32 @@ macro_rules! panic_if_err {
     }};
 }
 
+/// Makes room for an allocation, or panics with "out of memory" and skips the instruction
+macro_rules! reserve_or_panic {
+    ($gcc:ident, $bytes:expr, $panic:ident, $frame:ident, $code:ident) => {{
+        if let Err(e) = $gcc.reserve($bytes) {
+            go_panic_str!($panic, e.as_str(), $frame, $code);
+            continue;
+        }
+    }};
+}
+
+// copy_semantic, which is counted with the `stats` feature
+macro_rules! copy_semantic {
+    ($val:expr, $gcc:expr, $stats:ident) => {{
+        let val = $val;
+        #[cfg(feature = "stats")]
+        $stats.count_copy(val);
+        val.copy_semantic($gcc)
+    }};
+}
+
 #[cfg(feature = "async")]
 macro_rules! unwrap_recv_val {
-    ($chan:expr, $val:expr, $gcc:expr) => {
+    ($chan:expr, $val:expr, $gcc:expr, $stats:ident) => {
         match $val {
             Some(v) => (v, true),
-            None => ($chan.recv_zero.copy_semantic($gcc), false),
+            None => (copy_semantic!(&$chan.recv_zero, $gcc, $stats), false),
         }
     };
 }
@@ -124,30 +164,569 @@ macro_rules! unary_op {
 
 /// Entry point
 pub fn run(code: &Bytecode, ffi: &FfiFactory) -> Option<PanicData> {
-    let gcc = GcContainer::new();
-    let panic_data = Rc::new(RefCell::new(None));
+    let vm = Vm::new(code, ffi);
+    let result = vm.run_main();
+    match result {
+        Err(CallError::Panic(p)) => Some(p),
+        _ => None,
+    }
+}
 
-    #[cfg(not(feature = "async"))]
-    {
-        let ctx = Context::new(code, &gcc, ffi, panic_data.clone());
-        let first_frame = ctx.new_entry_frame(code.entry);
-        Fiber::new(ctx, Stack::new(), first_frame).main_loop();
+/// The reasons a call made by the host into the VM can fail.
+#[derive(Debug)]
+pub enum CallError {
+    /// The function cannot be found, or the arguments don't match its signature.
+    BadCall(String),
+    /// The Go code panicked and the panic was not recovered.
+    Panic(PanicData),
+    /// The call can never return, because all the goroutines are blocked.
+    Deadlock,
+    /// The instruction budget is used up, the call is suspended until the host
+    /// adds more budget and calls `Vm::resume`.
+    BudgetExhausted,
+    /// The run was cancelled by the host, the fiber is stopped without running
+    /// its deferred calls. It holds the call stack of where the fiber was.
+    Interrupted(Vec<(FunctionKey, OpIndex)>),
+}
+
+impl std::fmt::Display for CallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CallError::BadCall(msg) => write!(f, "bad call: {}", msg),
+            CallError::Panic(data) => write!(f, "panic: {}", data.msg),
+            CallError::Deadlock => f.write_str("all goroutines are asleep - deadlock!"),
+            CallError::BudgetExhausted => f.write_str("instruction budget exhausted"),
+            CallError::Interrupted(_) => f.write_str("interrupted"),
+        }
+    }
+}
+
+impl std::error::Error for CallError {}
+
+/// Lets the host stop a running VM, it can be sent to another thread.
+///
+/// The fibers check it every time they yield, once it's cancelled, they stop
+/// at that point and the run ends with `CallError::Interrupted`.
+/// It stays cancelled until `reset` is called.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationHandle(Arc<AtomicBool>);
+
+impl CancellationHandle {
+    pub fn new() -> CancellationHandle {
+        CancellationHandle::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, atomic::Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        self.0.store(false, atomic::Ordering::Relaxed);
+    }
+
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(atomic::Ordering::Relaxed)
     }
+}
+
+/// A VM instance that keeps a loaded program alive between calls.
+///
+/// Unlike `run`, which runs `main.main` and drops everything afterwards, a `Vm`
+/// holds on to the package members, the GC container and the goroutines, so the
+/// host can call exported Go functions as many times as it likes.
+///
+/// Goroutines spawned by a call are not waited for, they make progress during
+/// later calls, or when the host pumps them with `run_until_idle`, which makes it
+/// possible to drive scripts step by step, e.g. once per frame in a game loop.
+///
+/// The number of instructions the VM may execute can be limited with
+/// `set_instruction_budget`. When the budget is used up, the running call is
+/// suspended and returns `CallError::BudgetExhausted`, it continues where it
+/// stopped once the host adds more budget and calls `resume`.
+///
+/// -- Note --
+/// Package members live inside the `Bytecode`, so a `Bytecode` should only be
+/// used by one `Vm` (or one `run`).
+pub struct Vm<'a> {
+    /// The fibers only hold weak references to the executor, so that dropping
+    /// the `Vm` drops all the goroutines that are still alive.
     #[cfg(feature = "async")]
-    {
+    exec: Rc<LocalExecutor<'a>>,
+    /// Without `async`, the fiber of the suspended call is kept here
+    #[cfg(not(feature = "async"))]
+    parked: RefCell<Option<Fiber<'a>>>,
+    context: Context<'a>,
+    suspended: RefCell<Option<Suspended>>,
+    /// How many of the package initializers are done, see `init_packages`
+    init_steps: Cell<OpIndex>,
+}
+
+impl<'a> Vm<'a> {
+    pub fn new(code: &'a Bytecode, ffi: &'a FfiFactory) -> Vm<'a> {
+        Vm::with_gc_container(code, ffi, GcContainer::new())
+    }
+
+    /// Same as `new`, but the objects are allocated in `gcc`, which may be shared
+    /// with the VMs that ran `code` before, as the values they left in the packages
+    /// are allocated there.
+    pub fn with_gc_container(code: &'a Bytecode, ffi: &'a FfiFactory, gcc: GcContainer) -> Vm<'a> {
+        let error = Rc::new(RefCell::new(None));
+        #[cfg(feature = "async")]
         let exec = Rc::new(LocalExecutor::new());
-        let ctx = Context::new(exec.clone(), code, &gcc, ffi, panic_data.clone());
-        let entry = ctx.new_entry_frame(code.entry);
-        ctx.spawn_fiber(Stack::new(), entry);
-        future::block_on(async {
-            loop {
-                if !exec.try_tick() {
-                    break;
+        Vm {
+            context: Context::new(
+                #[cfg(feature = "async")]
+                Rc::downgrade(&exec),
+                code,
+                gcc,
+                ffi,
+                error,
+            ),
+            #[cfg(feature = "async")]
+            exec,
+            #[cfg(not(feature = "async"))]
+            parked: RefCell::new(None),
+            suspended: RefCell::new(None),
+            init_steps: Cell::new(0),
+        }
+    }
+
+    pub fn bytecode(&self) -> &'a Bytecode {
+        self.context.code
+    }
+
+    pub fn gc_container(&self) -> &GcContainer {
+        &self.context.gcc
+    }
+
+    /// Limits the number of instructions the VM may execute from now on,
+    /// `None` means no limit, which is the default.
+    pub fn set_instruction_budget(&self, budget: Option<u64>) {
+        self.context.budget.set(budget);
+    }
+
+    /// Returns what is left of the instruction budget.
+    pub fn instruction_budget(&self) -> Option<u64> {
+        self.context.budget.get()
+    }
+
+    /// Returns the handle that stops this VM when cancelled.
+    pub fn cancellation_handle(&self) -> CancellationHandle {
+        self.context.cancel.clone()
+    }
+
+    /// Replaces the cancellation handle, e.g. to share one between VMs.
+    /// Fibers that are already running keep checking the old one.
+    pub fn set_cancellation_handle(&mut self, handle: CancellationHandle) {
+        self.context.cancel = handle;
+    }
+
+    /// Attaches a debugger, it only sees the fibers started after this.
+    /// Breakpoints set before are dropped.
+    pub fn set_debugger(&mut self, debugger: Box<dyn Debugger + 'a>) {
+        self.context.debug = Some(Rc::new(DebugState::new(debugger)));
+    }
+
+    /// Starts sampling the call stacks of the fibers started after this, the
+    /// samples taken so far are dropped.
+    pub fn set_profiler(&mut self, sampling: Sampling) {
+        self.context.profiler = Some(Rc::new(Profiler::new(sampling)));
+    }
+
+    /// Returns the samples taken so far, `None` if there is no profiler.
+    pub fn profile(&self) -> Option<Profile> {
+        let profiler = self.context.profiler.as_ref()?;
+        Some(profiler.profile(self.context.code))
+    }
+
+    /// Returns the statistics of everything executed so far, by all the fibers
+    /// and the host calls.
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> Stats {
+        let allocations = self.context.gcc.allocations();
+        self.context.stats.stats(self.context.code, allocations)
+    }
+
+    /// Attaches a tracer, it only sees the fibers started after this.
+    #[cfg(feature = "trace")]
+    pub fn set_tracer(&mut self, tracer: Box<dyn Tracer + 'a>) {
+        self.context.tracer = Some(Rc::new(RefCell::new(tracer)));
+    }
+
+    /// Returns the handle that pauses this VM, `None` if no debugger is attached.
+    pub fn pause_handle(&self) -> Option<PauseHandle> {
+        self.context.debug.as_ref().map(|d| d.pause_handle())
+    }
+
+    /// Adds a breakpoint at the first line at or after `line` in `file` that
+    /// has code, and returns that line.
+    ///
+    /// It needs a debugger attached and the debug info, i.e. the instruction
+    /// positions and the `FileSet`, otherwise it returns `None`. A file can be
+    /// given by a suffix of its path, like `main.gos`.
+    pub fn set_breakpoint(&self, file: &str, line: usize) -> Option<usize> {
+        let debug = self.context.debug.as_ref()?;
+        debug.set_breakpoint(self.context.code, file, line)
+    }
+
+    /// Removes all the breakpoints in `file`.
+    pub fn clear_breakpoints(&self, file: &str) {
+        if let Some(debug) = &self.context.debug {
+            debug.clear_breakpoints(file);
+        }
+    }
+
+    /// Runs the entry function, i.e. `main.main`, and then the goroutines until
+    /// they are all finished or blocked.
+    pub fn run_main(&self) -> std::result::Result<(), CallError> {
+        self.check_not_suspended()?;
+        let entry = self.context.new_entry_frame(self.context.code.entry);
+        let fiber = Fiber::new(self.context.clone(), Stack::new(), entry);
+        #[cfg(not(feature = "async"))]
+        self.parked.replace(Some(fiber));
+        #[cfg(feature = "async")]
+        self.context.spawn(fiber);
+        self.drive(Suspended::default()).map(|_| ())
+    }
+
+    /// Runs the constructor and the `init` functions of the main package,
+    /// which in turn initialize all the packages it imports.
+    /// It does nothing if the main package is already initialized.
+    pub fn init_packages(&self) -> std::result::Result<(), CallError> {
+        let code = self.context.code;
+        let pkg = &code.objects.packages[code.main_pkg];
+        // initialized by `run_main`
+        if self.init_steps.get() == 0 && pkg.inited() {
+            return Ok(());
+        }
+        loop {
+            // the 0th member is the constructor, the init functions come after it
+            let step = self.init_steps.get();
+            let cls = if step == 0 {
+                pkg.member(0).as_closure().unwrap().0.clone()
+            } else {
+                match pkg.init_func(step - 1) {
+                    Some(f) => f.as_closure().unwrap().0.clone(),
+                    None => return Ok(()),
                 }
+            };
+            // `drive` counts the step once it's done
+            self.call_closure(cls, vec![], Some(step))?;
+        }
+    }
+
+    /// Calls an exported function and returns its results.
+    ///
+    /// The name is qualified with the package name, like `"plugin.OnEvent"`,
+    /// a name without a qualifier refers to a function in the main package.
+    /// The packages are initialized first if they are not yet.
+    ///
+    /// The arguments must match the parameters of the function, a variadic
+    /// parameter takes a slice.
+    pub fn call(
+        &self,
+        name: &str,
+        args: Vec<GosValue>,
+    ) -> std::result::Result<Vec<GosValue>, CallError> {
+        if let Err(e) = self.init_packages() {
+            // the call itself is made when the initialization is resumed
+            if let Some(s) = self.suspended.borrow_mut().as_mut() {
+                s.then = Some((name.to_owned(), args));
             }
-        });
+            return Err(e);
+        }
+        let cls = self.lookup_function(name)?;
+        self.call_closure(cls, args, None)
+    }
+
+    /// Returns the metas of the parameters and the results of an exported function,
+    /// which guide `to_gos` and `from_gos`.
+    pub fn signature(&self, name: &str) -> std::result::Result<(Vec<Meta>, Vec<Meta>), CallError> {
+        let cls = self.lookup_function(name)?;
+        let objs = &self.context.code.objects;
+        let func = match &cls {
+            ClosureObj::Gos(gos) => &objs.functions[gos.func],
+            ClosureObj::Ffi(_) => unreachable!(),
+        };
+        let sig = objs.metas[func.meta.key].as_signature();
+        Ok((sig.params.clone(), sig.results.clone()))
+    }
+
+    /// Converts Rust data to a `GosValue` of the Go type `meta` with serde,
+    /// e.g. to pass a struct to `call`.
+    #[cfg(feature = "serde")]
+    pub fn to_gos<T: serde::Serialize + ?Sized>(
+        &self,
+        value: &T,
+        meta: &Meta,
+    ) -> RuntimeResult<GosValue> {
+        crate::value_serde::to_gos_value(value, meta, &self.context.code.objects, &self.context.gcc)
+    }
+
+    /// Converts a `GosValue` of the Go type `meta` to Rust data with serde,
+    /// e.g. to read the results of `call`.
+    #[cfg(feature = "serde")]
+    pub fn from_gos<T: serde::de::DeserializeOwned>(
+        &self,
+        value: &GosValue,
+        meta: &Meta,
+    ) -> RuntimeResult<T> {
+        // the pointers in the results cannot point to a stack that is gone
+        let stack = Stack::new();
+        crate::value_serde::from_gos_value(value, meta, &self.context.code.objects, &stack)
+    }
+
+    /// Continues the call that ran out of instruction budget, and returns its
+    /// results. Without a suspended call, it's the same as `run_until_idle`.
+    pub fn resume(&self) -> std::result::Result<Vec<GosValue>, CallError> {
+        let s = self.suspended.take().unwrap_or_default();
+        self.drive(s)
+    }
+
+    /// Runs the goroutines until all of them are either finished or blocked.
+    /// Note that a goroutine that never blocks, like `for {}`, keeps it from returning,
+    /// unless there is an instruction budget.
+    ///
+    /// Returns the panic of a goroutine that was not recovered, if there is one.
+    /// Other goroutines are not affected by it, they can still be pumped later.
+    ///
+    /// A call that is suspended gets resumed, its results are dropped.
+    pub fn run_until_idle(&self) -> std::result::Result<(), CallError> {
+        self.resume().map(|_| ())
+    }
+
+    /// Drops all the goroutines that are still alive and frees the objects
+    /// they were holding.
+    pub fn shutdown(self) {
+        let gcc = self.context.gcc.clone();
+        drop(self);
+        collect(&gcc);
+    }
+
+    /// Ticks the executor until `done` returns true, or until a full round of
+    /// ticks gives no fiber a chance to make progress, i.e. they are all blocked.
+    ///
+    /// Blocked fibers keep yielding instead of sleeping, that's why `try_tick`
+    /// alone cannot tell.
+    #[cfg(feature = "async")]
+    fn pump(&self, done: impl Fn() -> bool) {
+        let sched = &self.context.sched;
+        loop {
+            let progress = sched.progress.get();
+            for _ in 0..sched.live.get().max(1) {
+                if done() || !self.exec.try_tick() {
+                    return;
+                }
+            }
+            if sched.progress.get() == progress {
+                return;
+            }
+        }
+    }
+
+    /// Runs until the call `s` is waiting for is done, or until the goroutines are
+    /// idle if it's not waiting for a call. The call gets suspended if the fibers
+    /// stopped because the instruction budget is used up.
+    fn drive(&self, s: Suspended) -> std::result::Result<Vec<GosValue>, CallError> {
+        #[cfg(not(feature = "async"))]
+        let pending = match self.parked.take() {
+            Some(mut fiber) => {
+                fiber.main_loop();
+                // it returns before the end only when there is no budget left
+                let pending = !fiber.frames.is_empty();
+                if pending {
+                    self.parked.replace(Some(fiber));
+                }
+                pending
+            }
+            None => false,
+        };
+        #[cfg(feature = "async")]
+        let pending = match &s.outcome {
+            Some(outcome) => {
+                self.pump(|| outcome.borrow().is_some());
+                outcome.borrow().is_none()
+            }
+            None => {
+                self.pump(|| false);
+                self.context.sched.live.get() > 0
+            }
+        };
+        if pending && self.context.out_of_budget() {
+            self.suspended.replace(Some(s));
+            return Err(CallError::BudgetExhausted);
+        }
+
+        let results = match s.outcome {
+            Some(outcome) => match outcome.replace(None) {
+                Some(Ok(results)) => results,
+                Some(Err(e)) => return Err(e),
+                None => return Err(CallError::Deadlock),
+            },
+            None => match self.context.error.replace(None) {
+                Some(e) => return Err(e),
+                None => vec![],
+            },
+        };
+        if let Some(step) = s.init_step {
+            self.init_steps.set(step + 1);
+        }
+        match s.then {
+            Some((name, args)) => self.call(&name, args),
+            None => Ok(results),
+        }
+    }
+
+    fn check_not_suspended(&self) -> std::result::Result<(), CallError> {
+        match self.suspended.borrow().is_some() {
+            true => Err(CallError::BadCall(
+                "a suspended call has to be resumed first".to_owned(),
+            )),
+            false => Ok(()),
+        }
+    }
+
+    fn lookup_function(&self, name: &str) -> std::result::Result<ClosureObj, CallError> {
+        let code = self.context.code;
+        let (pkey, func_name) = match name.rsplit_once('.') {
+            Some((pkg_name, func_name)) => (self.lookup_package(pkg_name)?, func_name),
+            None => (code.main_pkg, name),
+        };
+        if !func_name.starts_with(char::is_uppercase) {
+            return Err(CallError::BadCall(format!("{} is not exported", name)));
+        }
+        let pkg = &code.objects.packages[pkey];
+        let val = match pkg.member_index(func_name) {
+            Some(i) => pkg.member(*i).clone(),
+            None => return Err(CallError::BadCall(format!("{} not found", name))),
+        };
+        match val.typ() {
+            ValueType::Closure => match val.as_closure() {
+                Some((cls @ ClosureObj::Gos(_), _)) => Ok(cls.clone()),
+                Some(_) => Err(CallError::BadCall(format!("{} is a FFI function", name))),
+                None => Err(CallError::BadCall(format!("{} is nil", name))),
+            },
+            _ => Err(CallError::BadCall(format!("{} is not a function", name))),
+        }
+    }
+
+    fn lookup_package(&self, name: &str) -> std::result::Result<PackageKey, CallError> {
+        let code = self.context.code;
+        let pkgs = &code.objects.packages;
+        if pkgs[code.main_pkg].name() == name {
+            return Ok(code.main_pkg);
+        }
+        let mut found = pkgs
+            .vec()
+            .iter()
+            .enumerate()
+            .filter(|(_, p)| p.name() == name)
+            .map(|(i, _)| PackageKey::from(i));
+        match (found.next(), found.next()) {
+            (Some(key), None) => Ok(key),
+            (None, _) => Err(CallError::BadCall(format!("package {} not found", name))),
+            (Some(_), Some(_)) => Err(CallError::BadCall(format!(
+                "more than one package named {}",
+                name
+            ))),
+        }
+    }
+
+    fn call_closure(
+        &self,
+        cls: ClosureObj,
+        args: Vec<GosValue>,
+        init_step: Option<OpIndex>,
+    ) -> std::result::Result<Vec<GosValue>, CallError> {
+        self.check_not_suspended()?;
+        let objs = &self.context.code.objects;
+        let gosc = cls.as_gos();
+        let func = &objs.functions[gosc.func];
+        let sig = objs.metas[func.meta.key].as_signature();
+        if args.len() != sig.params.len() {
+            return Err(CallError::BadCall(format!(
+                "expect {} arguments, got {}",
+                sig.params.len(),
+                args.len()
+            )));
+        }
+        for (i, (arg, param)) in args.iter().zip(sig.params.iter()).enumerate() {
+            let typ = param.value_type(&objs.metas);
+            if arg.typ() != typ {
+                return Err(CallError::BadCall(format!(
+                    "argument {} should be {:?}, got {:?}",
+                    i,
+                    typ,
+                    arg.typ()
+                )));
+            }
+        }
+
+        let mut vec = func.ret_zeros.clone();
+        vec.extend(args);
+        let outcome = Rc::new(RefCell::new(None));
+        let host_call = HostCall {
+            ret_count: func.ret_count(),
+            outcome: outcome.clone(),
+        };
+        let frame = CallFrame::with_closure(cls.clone(), 0);
+        let mut fiber = Fiber::new(self.context.clone(), Stack::with_vec(vec), frame);
+        if let Some(uvs) = &gosc.uvs {
+            let stack = fiber.stack.clone();
+            fiber.frames[0].init_var_ptrs(func, uvs, &stack);
+        }
+        fiber.host_call = Some(host_call);
+
+        #[cfg(not(feature = "async"))]
+        self.parked.replace(Some(fiber));
+        #[cfg(feature = "async")]
+        self.context.spawn(fiber);
+        self.drive(Suspended {
+            outcome: Some(outcome),
+            init_step,
+            then: None,
+        })
     }
-    panic_data.replace(None)
+}
+
+type CallOutcome = std::result::Result<Vec<GosValue>, CallError>;
+
+type OutcomeSlot = Rc<RefCell<Option<CallOutcome>>>;
+
+/// What the host is waiting for when the instruction budget runs out
+#[derive(Default)]
+struct Suspended {
+    /// The results of the call, `None` if it's waiting for the goroutines
+    outcome: Option<OutcomeSlot>,
+    /// Set if it's one of the package initializers
+    init_step: Option<OpIndex>,
+    /// The call to make after the package initialization
+    then: Option<(String, Vec<GosValue>)>,
+}
+
+/// Shared by all the fibers of a VM, for telling whether they are all blocked
+#[cfg(feature = "async")]
+#[derive(Default)]
+struct Scheduling {
+    /// The number of fibers alive
+    live: Cell<usize>,
+    /// Bumped whenever a fiber starts, ends or resumes after being blocked
+    progress: Cell<usize>,
+}
+
+#[cfg(feature = "async")]
+impl Scheduling {
+    #[inline]
+    fn progress(&self) {
+        self.progress.set(self.progress.get().wrapping_add(1));
+    }
+}
+
+/// Where a fiber started by the host leaves the results or the panic of the call
+struct HostCall {
+    ret_count: OpIndex,
+    outcome: OutcomeSlot,
 }
 
 #[derive(Clone, Debug)]
@@ -157,11 +736,11 @@ struct Referers {
 }
 
 #[derive(Clone, Debug)]
-struct CallFrame {
+pub(crate) struct CallFrame {
     closure: ClosureObj,
-    pc: OpIndex,
-    stack_base: OpIndex,
-    var_ptrs: Option<Vec<UpValue>>,
+    pub(crate) pc: OpIndex,
+    pub(crate) stack_base: OpIndex,
+    pub(crate) var_ptrs: Option<Vec<UpValue>>,
     // closures that have upvalues pointing to this frame
     referred_by: Option<Map<OpIndex, Referers>>,
 
@@ -180,6 +759,30 @@ impl CallFrame {
         }
     }
 
+    /// Creates the upvalues of a new frame, local ones point to the stack the frame lives on
+    #[inline]
+    fn init_var_ptrs(
+        &mut self,
+        func: &FunctionObj,
+        uvs: &Map<usize, UpValue>,
+        stack: &Rc<RefCell<Stack>>,
+    ) {
+        let mut ptrs: Vec<UpValue> = Vec::with_capacity(func.up_ptrs.len());
+        for (i, p) in func.up_ptrs.iter().enumerate() {
+            ptrs.push(if p.is_local {
+                // local pointers
+                let uv = UpValue::new(
+                    p.clone_with_stack(Rc::downgrade(stack), self.stack_base as OpIndex),
+                );
+                self.add_referred_by(p.index, p.typ, &uv);
+                uv
+            } else {
+                uvs[&i].clone()
+            });
+        }
+        self.var_ptrs = Some(ptrs);
+    }
+
     fn add_referred_by(&mut self, index: OpIndex, typ: ValueType, uv: &UpValue) {
         if self.referred_by.is_none() {
             self.referred_by = Some(Map::new());
@@ -204,7 +807,7 @@ impl CallFrame {
     }
 
     #[inline]
-    fn func(&self) -> FunctionKey {
+    pub(crate) fn func(&self) -> FunctionKey {
         self.closure.as_gos().func
     }
 
@@ -242,12 +845,15 @@ struct DeferredCall {
 enum Result {
     Continue,
     End,
+    Interrupted,
 }
 
 #[derive(Debug)]
 pub struct PanicData {
     pub msg: GosValue,
     pub call_stack: Vec<(FunctionKey, OpIndex)>,
+    /// The panic is caused by an error returned by an FFI
+    pub ffi_error: bool,
 }
 
 impl PanicData {
@@ -255,6 +861,7 @@ impl PanicData {
         PanicData {
             msg: m,
             call_stack: vec![],
+            ffi_error: false,
         }
     }
 }
@@ -262,33 +869,81 @@ impl PanicData {
 #[derive(Clone)]
 struct Context<'a> {
     #[cfg(feature = "async")]
-    exec: Rc<LocalExecutor<'a>>,
+    exec: Weak<LocalExecutor<'a>>,
+    #[cfg(feature = "async")]
+    sched: Rc<Scheduling>,
     code: &'a Bytecode,
-    gcc: &'a GcContainer,
+    gcc: GcContainer,
     ffi_factory: &'a FfiFactory,
-    panic_data: Rc<RefCell<Option<PanicData>>>,
+    /// The panic or the interruption of a fiber that was not started by the host
+    error: Rc<RefCell<Option<CallError>>>,
+    cancel: CancellationHandle,
+    /// The number of instructions that can still be executed, shared by all the fibers
+    budget: Rc<Cell<Option<u64>>>,
     next_id: Cell<usize>,
+    debug: Option<Rc<DebugState<'a>>>,
+    profiler: Option<Rc<Profiler>>,
+    #[cfg(feature = "trace")]
+    tracer: Option<Rc<RefCell<Box<dyn Tracer + 'a>>>>,
+    #[cfg(feature = "stats")]
+    stats: Rc<Counters>,
 }
 
 impl<'a> Context<'a> {
     fn new(
-        #[cfg(feature = "async")] exec: Rc<LocalExecutor<'a>>,
+        #[cfg(feature = "async")] exec: Weak<LocalExecutor<'a>>,
         code: &'a Bytecode,
-        gcc: &'a GcContainer,
+        gcc: GcContainer,
         ffi_factory: &'a FfiFactory,
-        panic_data: Rc<RefCell<Option<PanicData>>>,
+        error: Rc<RefCell<Option<CallError>>>,
     ) -> Context<'a> {
         Context {
             #[cfg(feature = "async")]
             exec,
+            #[cfg(feature = "async")]
+            sched: Rc::new(Scheduling::default()),
             code,
             gcc,
             ffi_factory,
-            panic_data,
+            error,
+            cancel: CancellationHandle::new(),
+            budget: Rc::new(Cell::new(None)),
             next_id: Cell::new(0),
+            debug: None,
+            profiler: None,
+            #[cfg(feature = "trace")]
+            tracer: None,
+            #[cfg(feature = "stats")]
+            stats: Rc::new(Counters::default()),
+        }
+    }
+
+    /// Takes up to `max` instructions from the budget for the next run of a fiber
+    #[inline]
+    fn take_budget(&self, max: usize) -> usize {
+        match self.budget.get() {
+            Some(left) => {
+                let n = left.min(max as u64);
+                self.budget.set(Some(left - n));
+                n as usize
+            }
+            None => max,
+        }
+    }
+
+    /// Gives back what a fiber took but didn't use
+    #[inline]
+    fn return_budget(&self, n: usize) {
+        if let Some(left) = self.budget.get() {
+            self.budget.set(Some(left + n as u64));
         }
     }
 
+    #[inline]
+    fn out_of_budget(&self) -> bool {
+        self.budget.get() == Some(0)
+    }
+
     fn new_entry_frame(&self, entry: FunctionKey) -> CallFrame {
         let cls = ClosureObj::gos_from_func(entry, &self.code.objects.functions, None);
         CallFrame::with_closure(cls, 0)
@@ -296,14 +951,27 @@ impl<'a> Context<'a> {
 
     #[cfg(feature = "async")]
     fn spawn_fiber(&self, stack: Stack, first_frame: CallFrame) {
-        let mut f = Fiber::new(self.clone(), stack, first_frame);
-        self.exec
-            .spawn(async move {
+        self.spawn(Fiber::new(self.clone(), stack, first_frame));
+    }
+
+    #[cfg(feature = "async")]
+    fn spawn(&self, mut f: Fiber<'a>) {
+        // the executor is gone only if the Vm is being shut down
+        if let Some(exec) = self.exec.upgrade() {
+            let sched = self.sched.clone();
+            sched.live.set(sched.live.get() + 1);
+            // so that the pumping goes on until the new fiber gets to run
+            sched.progress();
+            exec.spawn(async move {
+                sched.progress();
                 // let parent fiber go first
                 future::yield_now().await;
                 f.main_loop().await;
+                sched.live.set(sched.live.get() - 1);
+                sched.progress();
             })
             .detach();
+        }
     }
 }
 
@@ -312,6 +980,9 @@ struct Fiber<'a> {
     rstack: RangeStack,
     frames: Vec<CallFrame>,
     context: Context<'a>,
+    host_call: Option<HostCall>,
+    /// The panic that was being unwound when the fiber got suspended
+    unwinding: Option<PanicData>,
     _id: usize,
 }
 
@@ -320,14 +991,33 @@ impl<'a> Fiber<'a> {
         self._id
     }
 
-    fn new(context: Context<'a>, stack: Stack, first_frame: CallFrame) -> Fiber<'a> {
+    fn new(context: Context<'a>, mut stack: Stack, first_frame: CallFrame) -> Fiber<'a> {
         let _id = context.next_id.get();
         context.next_id.set(_id + 1);
+        if let Some(debug) = &context.debug {
+            debug.fiber_started(_id);
+        }
+        #[cfg(feature = "trace")]
+        if let Some(tracer) = &context.tracer {
+            tracer.borrow_mut().on_event(TraceEvent::Spawn {
+                fiber: _id,
+                func: first_frame.func(),
+            });
+        }
+        // allocate local variables
+        let func = first_frame.func_obj(&context.code.objects);
+        stack.set_min_size((first_frame.stack_base + func.frame_size()) as usize);
+        stack.set_vec(
+            first_frame.stack_base + func.ret_count() + func.param_count(),
+            func.local_zeros.clone(),
+        );
         Fiber {
             stack: Rc::new(RefCell::new(stack)),
             rstack: RangeStack::new(),
             frames: vec![first_frame],
             context,
+            host_call: None,
+            unwinding: None,
             _id,
         }
     }
@@ -335,7 +1025,7 @@ impl<'a> Fiber<'a> {
     #[cfg_attr(feature = "async", go_pmacro::async_fn)]
     fn main_loop(&mut self) {
         let ctx = &self.context;
-        let gcc = ctx.gcc;
+        let gcc = &ctx.gcc;
         let objs: &VMObjects = &ctx.code.objects;
         let caller: &ArrCaller = &objs.arr_slice_caller;
         let consts = &ctx.code.consts;
@@ -349,23 +1039,80 @@ impl<'a> Fiber<'a> {
 
         let mut stack_mut_ref = self.stack.borrow_mut();
         let mut stack: &mut Stack = &mut stack_mut_ref;
-        // allocate local variables
-        stack.set_vec(func.param_count(), func.local_zeros.clone());
 
         let mut code = &func.code;
+        let debug = ctx.debug.as_deref();
+        let profiler = ctx.profiler.as_deref();
+        #[cfg(feature = "trace")]
+        let tracer = ctx.tracer.as_deref();
+
+        #[cfg(feature = "stats")]
+        let stats = &*ctx.stats;
 
-        let mut total_inst = 0;
-        //let mut stats: Map<Opcode, usize> = Map::new();
+        let mut total_inst: u64 = 0;
+        // a panic keeps unwinding across yields
+        let mut panic: Option<PanicData> = self.unwinding.take();
+        // where the last traced panic is raised
+        #[cfg(feature = "trace")]
+        let mut traced_panic = panic.as_ref().map(|p| p.call_stack[0]);
         loop {
             let mut frame = self.frames.last_mut().unwrap();
             let mut result: Result = Result::Continue;
-            let mut panic: Option<PanicData> = None;
-            let yield_unit = 1024;
+            let yield_unit = ctx.take_budget(1024);
+            let unit_start = total_inst;
             for _ in 0..yield_unit {
+                if let Some(dbg) = debug {
+                    // a panic is not stepped through while it's unwinding
+                    let stop = match panic.is_none() {
+                        true => dbg.check(ctx.code, self._id, frame_height, frame.func(), frame.pc),
+                        false => None,
+                    };
+                    if let Some(reason) = stop {
+                        let view = DebugView::new(ctx.code, dbg, self._id, &self.frames, &*stack);
+                        dbg.stop(reason, &view);
+                        frame = self.frames.last_mut().unwrap();
+                    }
+                }
+                if let Some(prof) = profiler {
+                    if prof.tick() {
+                        prof.sample(&self.frames);
+                        frame = self.frames.last_mut().unwrap();
+                    }
+                }
+                #[cfg(feature = "trace")]
+                if let Some(tracer) = tracer {
+                    let mut tracer = tracer.borrow_mut();
+                    // unwinding adds the frames to the call stack, so a panic
+                    // with only one frame is just raised, or raised again in a
+                    // deferred call
+                    match &panic {
+                        Some(p) if p.call_stack.len() == 1 => {
+                            let (fkey, pc) = p.call_stack[0];
+                            if traced_panic != Some((fkey, pc)) {
+                                traced_panic = Some((fkey, pc));
+                                tracer.on_event(TraceEvent::Panic {
+                                    fiber: self._id,
+                                    func: fkey,
+                                    pos: inst_pos(&objs.functions[fkey], pc),
+                                    value: &p.msg,
+                                });
+                            }
+                        }
+                        Some(_) => {}
+                        None => traced_panic = None,
+                    }
+                    tracer.on_event(TraceEvent::Instruction {
+                        fiber: self._id,
+                        func: frame.func(),
+                        pc: frame.pc,
+                        inst: &code[frame.pc as usize],
+                    });
+                }
                 let inst = &code[frame.pc as usize];
                 let inst_op = inst.op0;
                 total_inst += 1;
-                //stats.entry(*inst).and_modify(|e| *e += 1).or_insert(1);
+                #[cfg(feature = "stats")]
+                stats.count_instruction(frame.func(), frame.pc);
                 frame.pc += 1;
                 //dbg!(inst);
                 match inst_op {
@@ -375,7 +1122,7 @@ impl<'a> Fiber<'a> {
                         //dbg!(stack.read(inst.s0, sb, consts));
                         stack.set(
                             sb + inst.d,
-                            stack.read(inst.s0, sb, consts).copy_semantic(gcc),
+                            copy_semantic!(stack.read(inst.s0, sb, consts), gcc, stats),
                         )
                     }
                     // desc: local
@@ -401,7 +1148,8 @@ impl<'a> Fiber<'a> {
                         match dest.slice_array_equivalent(index) {
                             Ok((array, i)) => match inst.op1 {
                                 Opcode::VOID => {
-                                    let val = stack.read(inst.s1, sb, consts).copy_semantic(gcc);
+                                    let val =
+                                        copy_semantic!(stack.read(inst.s1, sb, consts), gcc, stats);
                                     let result = array.caller(caller).array_set(&array, &val, i);
                                     panic_if_err!(result, panic, frame, code);
                                 }
@@ -444,7 +1192,8 @@ impl<'a> Fiber<'a> {
                         let index = stack.read(inst.s0, sb, consts).as_index();
                         match inst.op1 {
                             Opcode::VOID => {
-                                let val = stack.read(inst.s1, sb, consts).copy_semantic(gcc);
+                                let val =
+                                    copy_semantic!(stack.read(inst.s1, sb, consts), gcc, stats);
                                 let result = array.caller(caller).array_set(&array, &val, index);
                                 panic_if_err!(result, panic, frame, code);
                             }
@@ -482,7 +1231,10 @@ impl<'a> Fiber<'a> {
                         };
                         let (v, ok) = match val {
                             Some(v) => (v, true),
-                            None => (stack.read(inst_ex.s0, sb, consts).copy_semantic(gcc), false),
+                            None => (
+                                copy_semantic!(stack.read(inst_ex.s0, sb, consts), gcc, stats),
+                                false,
+                            ),
                         };
                         stack.set(inst.d + sb, v);
                         if inst.t1 == ValueType::FlagB {
@@ -499,11 +1251,15 @@ impl<'a> Fiber<'a> {
                         let dest = stack.read(inst.d, sb, consts);
                         match dest.as_non_nil_map() {
                             Ok(map) => {
+                                reserve_or_panic!(gcc, MAP_ENTRY_SIZE, panic, frame, code);
                                 let key = stack.read(inst.s0, sb, consts);
                                 match inst.op1 {
                                     Opcode::VOID => {
-                                        let val =
-                                            stack.read(inst.s1, sb, consts).copy_semantic(gcc);
+                                        let val = copy_semantic!(
+                                            stack.read(inst.s1, sb, consts),
+                                            gcc,
+                                            stats
+                                        );
                                         map.0.insert(key.clone(), val);
                                     }
                                     _ => {
@@ -541,7 +1297,8 @@ impl<'a> Fiber<'a> {
                         let dest = stack.read(inst.d, sb, consts);
                         match inst.op1 {
                             Opcode::VOID => {
-                                let val = stack.read(inst.s1, sb, consts).copy_semantic(gcc);
+                                let val =
+                                    copy_semantic!(stack.read(inst.s1, sb, consts), gcc, stats);
                                 dest.as_struct().0.borrow_fields_mut()[inst.s0 as usize] = val;
                             }
                             _ => {
@@ -592,7 +1349,8 @@ impl<'a> Fiber<'a> {
                         match struct_ {
                             Ok(s) => match inst.op1 {
                                 Opcode::VOID => {
-                                    let val = stack.read(inst.s1, sb, consts).copy_semantic(gcc);
+                                    let val =
+                                        copy_semantic!(stack.read(inst.s1, sb, consts), gcc, stats);
                                     s.as_struct().0.borrow_fields_mut()[index] = val;
                                 }
                                 _ => {
@@ -631,7 +1389,8 @@ impl<'a> Fiber<'a> {
                         let pkg = &objs.packages[*dest.as_package()];
                         match inst.op1 {
                             Opcode::VOID => {
-                                let val = stack.read(inst.s1, sb, consts).copy_semantic(gcc);
+                                let val =
+                                    copy_semantic!(stack.read(inst.s1, sb, consts), gcc, stats);
                                 *pkg.member_mut(index) = val;
                             }
                             _ => {
@@ -666,7 +1425,9 @@ impl<'a> Fiber<'a> {
                         let dest = stack.read(inst.d, sb, consts).clone();
                         let result = dest.as_non_nil_pointer().and_then(|p| {
                             let val = match inst.op1 {
-                                Opcode::VOID => stack.read(inst.s0, sb, consts).copy_semantic(gcc),
+                                Opcode::VOID => {
+                                    copy_semantic!(stack.read(inst.s0, sb, consts), gcc, stats)
+                                }
                                 _ => {
                                     let old = p.deref(stack, &objs.packages)?;
                                     stack.read_and_op(
@@ -714,7 +1475,8 @@ impl<'a> Fiber<'a> {
                         let uv = &uvs[inst.d as usize];
                         match inst.op1 {
                             Opcode::VOID => {
-                                let val = stack.read(inst.s0, sb, consts).copy_semantic(gcc);
+                                let val =
+                                    copy_semantic!(stack.read(inst.s0, sb, consts), gcc, stats);
                                 uv.set_value(val, stack);
                             }
                             _ => {
@@ -731,7 +1493,17 @@ impl<'a> Fiber<'a> {
                             }
                         }
                     }
-                    Opcode::ADD => binary_op!(stack, binary_op_add, inst, sb, consts),
+                    Opcode::ADD => {
+                        if inst.t0 == ValueType::String {
+                            let len = stack.read(inst.s0, sb, consts).len()
+                                + stack.read(inst.s1, sb, consts).len();
+                            reserve_or_panic!(gcc, len, panic, frame, code);
+                            binary_op!(stack, binary_op_add, inst, sb, consts);
+                            gcc.track_array(stack.get(inst.d + sb), caller.get(ValueType::Uint8));
+                        } else {
+                            binary_op!(stack, binary_op_add, inst, sb, consts)
+                        }
+                    }
                     Opcode::SUB => binary_op!(stack, binary_op_sub, inst, sb, consts),
                     Opcode::MUL => binary_op!(stack, binary_op_mul, inst, sb, consts),
                     Opcode::QUO => binary_op!(stack, binary_op_quo, inst, sb, consts),
@@ -742,7 +1514,22 @@ impl<'a> Fiber<'a> {
                     Opcode::AND_NOT => binary_op!(stack, binary_op_and_not, inst, sb, consts),
                     Opcode::SHL => shift_op!(stack, binary_op_shl, inst, sb, consts),
                     Opcode::SHR => shift_op!(stack, binary_op_shr, inst, sb, consts),
-                    Opcode::ADD_ASSIGN => binary_op_assign!(stack, binary_op_add, inst, sb, consts),
+                    Opcode::ADD_ASSIGN => {
+                        if inst.t0 == ValueType::String {
+                            let len = stack.get(inst.d + sb).len()
+                                + stack.read(inst.s0, sb, consts).len();
+                            reserve_or_panic!(gcc, len, panic, frame, code);
+                            // not binary_op_assign!, the old string has to be dropped
+                            let vdata = stack
+                                .get(inst.d + sb)
+                                .data()
+                                .add_str(stack.read(inst.s0, sb, consts).data());
+                            stack.set(inst.d + sb, GosValue::new(ValueType::String, vdata));
+                            gcc.track_array(stack.get(inst.d + sb), caller.get(ValueType::Uint8));
+                        } else {
+                            binary_op_assign!(stack, binary_op_add, inst, sb, consts)
+                        }
+                    }
                     Opcode::SUB_ASSIGN => binary_op_assign!(stack, binary_op_sub, inst, sb, consts),
                     Opcode::MUL_ASSIGN => binary_op_assign!(stack, binary_op_mul, inst, sb, consts),
                     Opcode::QUO_ASSIGN => binary_op_assign!(stack, binary_op_quo, inst, sb, consts),
@@ -914,7 +1701,7 @@ impl<'a> Fiber<'a> {
                                 drop(stack_mut_ref);
                                 let val = chan.recv().await;
                                 restore_stack_ref!(self, stack, stack_mut_ref);
-                                let (unwrapped, ok) = unwrap_recv_val!(chan, val, gcc);
+                                let (unwrapped, ok) = unwrap_recv_val!(chan, val, gcc, stats);
                                 stack.set(inst.d + sb, unwrapped);
                                 if inst.t1 == ValueType::FlagB {
                                     stack.set(inst.s1 + sb, ok.into());
@@ -951,9 +1738,7 @@ impl<'a> Fiber<'a> {
                                     // don't call copy_semantic because BIND_METHOD did it already
                                     returns_recv.push(r.clone());
                                 }
-                                stack.set_min_size(
-                                    (next_sb + next_func.max_write_index + 1) as usize,
-                                );
+                                stack.set_min_size((next_sb + next_func.frame_size()) as usize);
                                 stack.set_vec(next_sb, returns_recv);
                             }
                             _ => {}
@@ -963,27 +1748,20 @@ impl<'a> Fiber<'a> {
                         match cls {
                             ClosureObj::Gos(gosc) => {
                                 let nfunc = &objs.functions[gosc.func];
-                                if let Some(uvs) = gosc.uvs {
-                                    let mut ptrs: Vec<UpValue> =
-                                        Vec::with_capacity(nfunc.up_ptrs.len());
-                                    for (i, p) in nfunc.up_ptrs.iter().enumerate() {
-                                        ptrs.push(if p.is_local {
-                                            // local pointers
-                                            let uv = UpValue::new(p.clone_with_stack(
-                                                Rc::downgrade(&self.stack),
-                                                nframe.stack_base as OpIndex,
-                                            ));
-                                            nframe.add_referred_by(p.index, p.typ, &uv);
-                                            uv
-                                        } else {
-                                            uvs[&i].clone()
-                                        });
-                                    }
-                                    nframe.var_ptrs = Some(ptrs);
+                                if let Some(uvs) = &gosc.uvs {
+                                    nframe.init_var_ptrs(nfunc, uvs, &self.stack);
                                 }
                                 match call_style {
                                     ValueType::FlagA => {
                                         // default call
+                                        #[cfg(feature = "trace")]
+                                        if let Some(tracer) = tracer {
+                                            tracer.borrow_mut().on_event(TraceEvent::Call {
+                                                fiber: self._id,
+                                                func: gosc.func,
+                                                pos: inst_pos(func, frame.pc - 1),
+                                            });
+                                        }
                                         self.frames.push(nframe);
                                         frame_height += 1;
                                         frame = self.frames.last_mut().unwrap();
@@ -1035,6 +1813,7 @@ impl<'a> Fiber<'a> {
                                         func_name: &ffic.func_name,
                                         vm_objs: objs,
                                         user_data: ctx.ffi_factory.user_data(),
+                                        std_io: ctx.ffi_factory.std_io(),
                                         stack: &mut self.stack.borrow_mut(),
                                         gcc,
                                         array_slice_caller: caller,
@@ -1054,7 +1833,7 @@ impl<'a> Fiber<'a> {
                                 match returns {
                                     Ok(result) => stack.set_vec(result_begin, result),
                                     Err(e) => {
-                                        go_panic_str!(panic, e.as_str(), frame, code);
+                                        go_panic_ffi!(panic, e.as_str(), frame, code);
                                     }
                                 }
                             }
@@ -1097,6 +1876,14 @@ impl<'a> Fiber<'a> {
                                         + cur_func.local_count();
                                     stack.set_vec(new_sb, call.vec);
                                     let nframe = call.frame;
+                                    #[cfg(feature = "trace")]
+                                    if let Some(tracer) = tracer {
+                                        tracer.borrow_mut().on_event(TraceEvent::Call {
+                                            fiber: self._id,
+                                            func: nframe.func(),
+                                            pos: inst_pos(func, frame.pc),
+                                        });
+                                    }
 
                                     self.frames.push(nframe);
                                     frame_height += 1;
@@ -1108,6 +1895,7 @@ impl<'a> Fiber<'a> {
                                     code = &func.code;
                                     //dbg!("deferred", &code);
                                     let index = new_sb + call_vec_len;
+                                    stack.set_min_size((new_sb + func.frame_size()) as usize);
                                     stack.set_vec(index, func.local_zeros.clone());
                                     continue;
                                 }
@@ -1131,13 +1919,23 @@ impl<'a> Fiber<'a> {
                             stack.move_vec(begin, end);
                         }
 
+                        #[cfg(feature = "trace")]
+                        if let Some(tracer) = tracer {
+                            tracer.borrow_mut().on_event(TraceEvent::Return {
+                                fiber: self._id,
+                                func: frame.func(),
+                                pos: inst_pos(func, frame.pc - 1),
+                            });
+                        }
+                        if let (Some(prof), 1) = (profiler, frame_height) {
+                            // the fiber ends, so it's the last chance to sample
+                            prof.yielded(&self.frames, (total_inst - unit_start) as usize);
+                        }
                         // We used to need this to make the compiler happy:
                         // drop(frame);
                         self.frames.pop();
                         frame_height -= 1;
                         if self.frames.is_empty() {
-                            dbg!(total_inst);
-
                             result = Result::End;
                             break;
                         }
@@ -1194,7 +1992,11 @@ impl<'a> Fiber<'a> {
                             let flag = entry.t0;
                             let typ = match &flag {
                                 ValueType::FlagA => {
-                                    let val = stack.read(entry.s1, sb, consts).copy_semantic(gcc);
+                                    let val = copy_semantic!(
+                                        stack.read(entry.s1, sb, consts),
+                                        gcc,
+                                        stats
+                                    );
                                     channel::SelectCommType::Send(val)
                                 }
                                 ValueType::FlagB | ValueType::FlagC | ValueType::FlagD => {
@@ -1222,7 +2024,8 @@ impl<'a> Fiber<'a> {
                                             let (unwrapped, ok) = unwrap_recv_val!(
                                                 comm.chan.as_channel().as_ref().unwrap(),
                                                 val,
-                                                gcc
+                                                gcc,
+                                                stats
                                             );
                                             match flag {
                                                 ValueType::FlagC => {
@@ -1278,7 +2081,7 @@ impl<'a> Fiber<'a> {
                         }
                     }
                     Opcode::BIND_METHOD => {
-                        let recv = stack.read(inst.s0, sb, consts).copy_semantic(gcc);
+                        let recv = copy_semantic!(stack.read(inst.s0, sb, consts), gcc, stats);
                         let func = *stack.read(inst.s1, sb, consts).as_function();
                         stack.set(
                             inst.d + sb,
@@ -1320,7 +2123,8 @@ impl<'a> Fiber<'a> {
                                 .cast_copyable(from_type, to_type),
                             ValueType::Interface => {
                                 let binding = ifaces[inst.s1 as usize].clone();
-                                let under = stack.read(inst.s0, sb, consts).copy_semantic(gcc);
+                                let under =
+                                    copy_semantic!(stack.read(inst.s0, sb, consts), gcc, stats);
                                 GosValue::new_interface(InterfaceObj::with_value(
                                     under,
                                     Some(binding),
@@ -1456,7 +2260,7 @@ impl<'a> Fiber<'a> {
                             let (val, meta) = match iface_value.as_interface() {
                                 Some(iface) => match &iface as &InterfaceObj {
                                     InterfaceObj::Gos(v, b) => {
-                                        (v.copy_semantic(gcc), b.as_ref().unwrap().0)
+                                        (copy_semantic!(v, gcc, stats), b.as_ref().unwrap().0)
                                     }
                                     _ => (iface_value.clone(), prim_meta.none),
                                 },
@@ -1505,8 +2309,10 @@ impl<'a> Fiber<'a> {
                                     for (_, uv) in uvs.iter_mut() {
                                         let r: &mut UpValueState = &mut uv.inner.borrow_mut();
                                         if let UpValueState::Open(d) = r {
-                                            // get frame index, and add_referred_by
-                                            for i in 1..frame_height {
+                                            // get frame index, and add_referred_by,
+                                            // the bottom frame included, it's a Go
+                                            // function when called with `Vm::call`
+                                            for i in 1..=frame_height {
                                                 let index = frame_height - i;
                                                 if self.frames[index].func() == d.func {
                                                     let upframe = &mut self.frames[index];
@@ -1533,6 +2339,23 @@ impl<'a> Fiber<'a> {
 
                         let begin = inst.s0 + sb;
                         let count = inst.s1;
+                        if gcc.memory_limit().is_some() {
+                            let bytes = match &objs.metas[md.key] {
+                                MetadataType::Slice(m) | MetadataType::Array(m, _) => {
+                                    // the indices can leave gaps to be filled with zero values
+                                    let (mut len, mut cur_index) = (0, -1);
+                                    for i in 0..count {
+                                        let index = *stack.get(begin + i * 2).as_int32();
+                                        cur_index = if index < 0 { cur_index + 1 } else { index };
+                                        len = len.max(cur_index as usize + 1);
+                                    }
+                                    len * caller.get(m.value_type(&objs.metas)).elem_size()
+                                }
+                                MetadataType::Map(_, _) => count as usize * MAP_ENTRY_SIZE,
+                                _ => 0,
+                            };
+                            reserve_or_panic!(gcc, bytes, panic, frame, code);
+                        }
                         let build_val = |m: &Meta| {
                             let zero_val = m.zero(&objs.metas, gcc);
                             let mut val = vec![];
@@ -1562,11 +2385,15 @@ impl<'a> Fiber<'a> {
                         let new_val = match &objs.metas[md.key] {
                             MetadataType::Slice(m) => {
                                 let (val, typ) = build_val(m);
-                                GosValue::slice_with_data(val, caller.get(typ), gcc)
+                                let slice = GosValue::slice_with_data(val, caller.get(typ), gcc);
+                                gcc.track_array(&slice, caller.get(typ));
+                                slice
                             }
                             MetadataType::Array(m, _) => {
                                 let (val, typ) = build_val(m);
-                                GosValue::array_with_data(val, caller.get(typ), gcc)
+                                let arr = GosValue::array_with_data(val, caller.get(typ), gcc);
+                                gcc.track_array(&arr, caller.get(typ));
+                                arr
                             }
                             MetadataType::Map(_, _) => {
                                 let map_val = GosValue::new_map(gcc);
@@ -1576,6 +2403,7 @@ impl<'a> Fiber<'a> {
                                     let v = stack.get(begin + 1 + i * 2).clone();
                                     map.0.insert(k, v);
                                 }
+                                gcc.track_map(&map_val);
                                 map_val
                             }
                             MetadataType::Struct(_) => {
@@ -1622,15 +2450,23 @@ impl<'a> Fiber<'a> {
                                     _ => unreachable!(),
                                 };
                                 let zero = vmeta.zero(&objs.metas, gcc);
-                                GosValue::slice_with_size(
-                                    len,
-                                    cap,
-                                    &zero,
-                                    caller.get(zero.typ()),
+                                let disp = caller.get(zero.typ());
+                                reserve_or_panic!(
                                     gcc,
-                                )
+                                    cap.saturating_mul(disp.elem_size()),
+                                    panic,
+                                    frame,
+                                    code
+                                );
+                                let slice = GosValue::slice_with_size(len, cap, &zero, disp, gcc);
+                                gcc.track_array(&slice, disp);
+                                slice
+                            }
+                            MetadataType::Map(_, _) => {
+                                let map = GosValue::new_map(gcc);
+                                gcc.track_map(&map);
+                                map
                             }
-                            MetadataType::Map(_, _) => GosValue::new_map(gcc),
                             #[cfg(not(feature = "async"))]
                             MetadataType::Channel(_, _) => {
                                 go_panic_no_async!(panic, frame, code);
@@ -1717,8 +2553,19 @@ impl<'a> Fiber<'a> {
                             GosValue::slice_array(arr, 0, -1, caller.get(ValueType::Uint8)).unwrap()
                         };
 
-                        match caller.get(inst.t1).slice_append(a, b, gcc) {
-                            Ok(slice) => stack.set(inst.d + sb, slice),
+                        let disp = caller.get(inst.t1);
+                        reserve_or_panic!(
+                            gcc,
+                            b.len().saturating_mul(disp.elem_size()),
+                            panic,
+                            frame,
+                            code
+                        );
+                        match disp.slice_append(a, b, gcc) {
+                            Ok(slice) => {
+                                gcc.track_array(&slice, disp);
+                                stack.set(inst.d + sb, slice)
+                            }
                             Err(e) => go_panic_str!(panic, e.as_str(), frame, code),
                         };
                     }
@@ -1758,6 +2605,15 @@ impl<'a> Fiber<'a> {
                     }
                     Opcode::RECOVER => {
                         let p = panic.take();
+                        #[cfg(feature = "trace")]
+                        if let (Some(tracer), Some(p)) = (tracer, &p) {
+                            tracer.borrow_mut().on_event(TraceEvent::Recover {
+                                fiber: self._id,
+                                func: frame.func(),
+                                pos: inst_pos(func, frame.pc - 1),
+                                value: &p.msg,
+                            });
+                        }
                         let val = p.map_or(GosValue::new_nil(ValueType::Void), |x| {
                             GosValue::new_interface(InterfaceObj::with_value(x.msg, None))
                         });
@@ -1782,7 +2638,7 @@ impl<'a> Fiber<'a> {
                                     )))
                                 }
                                 Err(e) => {
-                                    go_panic_str!(panic, e.as_str(), frame, code);
+                                    go_panic_ffi!(panic, e.as_str(), frame, code);
                                     continue;
                                 }
                             }
@@ -1792,20 +2648,85 @@ impl<'a> Fiber<'a> {
                     Opcode::VOID => unreachable!(),
                 }
             } //yield unit
+            let executed = (total_inst - unit_start) as usize;
+            ctx.return_budget(yield_unit - executed);
+            if let Some(prof) = profiler {
+                prof.yielded(&self.frames, executed);
+            }
+            #[cfg(feature = "async")]
+            if executed > 0 {
+                ctx.sched.progress();
+            }
+            if matches!(result, Result::Continue) && ctx.cancel.is_cancelled() {
+                result = Result::Interrupted;
+            }
             match result {
                 Result::End => {
-                    *ctx.panic_data.borrow_mut() = panic.take();
+                    match self.host_call.take() {
+                        Some(call) => {
+                            let outcome = match panic.take() {
+                                Some(p) => Err(CallError::Panic(p)),
+                                None => Ok(stack.move_vec(0, call.ret_count)),
+                            };
+                            *call.outcome.borrow_mut() = Some(outcome);
+                        }
+                        // don't let a fiber that ends normally clear the panic of another one
+                        None => {
+                            if let Some(p) = panic.take() {
+                                *ctx.error.borrow_mut() = Some(CallError::Panic(p));
+                            }
+                        }
+                    }
+                    break;
+                }
+                Result::Interrupted => {
+                    let call_stack = self
+                        .frames
+                        .iter()
+                        .rev()
+                        .map(|f| (f.func(), f.pc.max(1) - 1))
+                        .collect();
+                    for f in self.frames.iter_mut().rev() {
+                        f.on_drop(stack);
+                    }
+                    self.frames.clear();
+                    let err = CallError::Interrupted(call_stack);
+                    match self.host_call.take() {
+                        Some(call) => *call.outcome.borrow_mut() = Some(Err(err)),
+                        None => *ctx.error.borrow_mut() = Some(err),
+                    }
                     break;
                 }
                 Result::Continue => {
                     drop(stack_mut_ref);
                     #[cfg(feature = "async")]
-                    future::yield_now().await;
+                    {
+                        future::yield_now().await;
+                        // wait for the host to add more budget
+                        while ctx.out_of_budget() {
+                            future::yield_now().await;
+                        }
+                    }
+                    #[cfg(not(feature = "async"))]
+                    if ctx.out_of_budget() {
+                        // the host resumes it by calling main_loop again
+                        self.unwinding = panic;
+                        return;
+                    }
                     restore_stack_ref!(self, stack, stack_mut_ref);
                 }
             };
         } //loop
 
+        if let Some(dbg) = debug {
+            dbg.fiber_ended(self._id);
+        }
+        #[cfg(feature = "trace")]
+        if let Some(tracer) = tracer {
+            tracer
+                .borrow_mut()
+                .on_event(TraceEvent::Exit { fiber: self._id });
+        }
         collect(gcc);
     }
 }
@@ -1825,6 +2746,13 @@ fn deref_value(v: &GosValue, stack: &Stack, objs: &VMObjects) -> RuntimeResult<G
     v.as_non_nil_pointer()?.deref(stack, &objs.packages)
 }
 
+/// The position of the instruction at `pc`, if there is the debug info
+#[cfg(feature = "trace")]
+#[inline]
+fn inst_pos(func: &FunctionObj, pc: OpIndex) -> Option<u32> {
+    func.pos.get(pc as usize).copied().flatten()
+}
+
 #[inline(always)]
 fn cst(consts: &Vec<GosValue>, i: OpIndex) -> &GosValue {
     &consts[(-i - 1) as usize]
@@ -1932,7 +2860,12 @@ fn bind_iface_method(
 ) -> RuntimeResult<GosValue> {
     match iface {
         InterfaceObj::Gos(obj, b) => {
-            let binding = &b.as_ref().unwrap().1[index];
+            let binding = b
+                .as_ref()
+                .unwrap()
+                .1
+                .get(index)
+                .ok_or_else(|| format!("interface method {} is not bound", index))?;
             match binding {
                 Binding4Runtime::Struct(func, ptr_recv, indices) => {
                     let obj = match indices {